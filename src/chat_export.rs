@@ -0,0 +1,537 @@
+use crate::helper_functions;
+use crate::login::hash_password;
+use crate::password_hashing;
+use crate::storage::{Storage, StorageResult};
+use crate::structs::chat_room::ChatRoom;
+use crate::structs::message::{ChatMessage, MessageEdit};
+use crate::structs::room_role::RoomRole;
+use crate::structs::user::User;
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// Version of the JSON format, exports of other versions can not be imported
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Number of messages that are read from the storage at once while exporting
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// Format a chatroom is exported in
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum ExportFormat {
+    /// Versioned JSON, which can be imported again
+    #[default]
+    Json,
+    /// Readable transcript with one line per message
+    Transcript,
+}
+
+/// Chatroom with all of its messages, as it is written to an export
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatExport {
+    pub version: u32,
+    /// Seconds since UNIX EPOCH the export was written
+    pub exported_at: u64,
+    /// True if a member exported the decrypted messages, exports of the server contain the encrypted messages
+    pub is_decrypted: bool,
+    pub chatroom: ExportedChatroom,
+    /// Messages in the order they were written
+    pub messages: Vec<ExportedMessage>,
+    /// Friendships between the exported users as pairs of user ids
+    #[serde(default)]
+    pub friendships: Vec<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedChatroom {
+    pub id: u32,
+    pub name: String,
+    /// The password of a public room is not exported, so rooms with a password are exported as rooms only the members can enter
+    pub is_public: bool,
+    pub members: Vec<ExportedMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedMember {
+    pub id: u32,
+    pub name: String,
+    pub role: RoomRole,
+    /// Public identity key, so that the other members can write to the member before the member logs in again
+    #[serde(default)]
+    pub identity_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    pub id: u32,
+    /// Number of the message within its chatroom
+    pub room_message_id: u32,
+    /// None for old messages without a known sender
+    pub sender_id: Option<u32>,
+    pub sender: String,
+    /// Seconds since UNIX EPOCH the server saved the message
+    pub sent_at: u64,
+    /// Seconds since UNIX EPOCH the message was last edited, None if it never was
+    #[serde(default)]
+    pub edited_at: Option<u64>,
+    /// Seconds since UNIX EPOCH the message was deleted, deleted messages have no content
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
+    pub content: String,
+    /// Contents the message had before it was edited, the oldest first
+    #[serde(default)]
+    pub edits: Vec<MessageEdit>,
+}
+
+/// User that is created by an import, because the database does not have the user yet
+pub struct RestoredUser {
+    pub user: User,
+    /// bcrypt hash of the one-time password
+    pub password_hash: String,
+    pub identity_key: Option<String>,
+}
+
+/// Result of an import
+#[derive(Debug)]
+pub struct ImportSummary {
+    pub message_count: usize,
+    /// Name and one-time password of every user that was created, the user has to choose a new password on the next login
+    pub one_time_passwords: Vec<(String, String)>,
+}
+
+/// Only the version is read first, so that exports of other versions get a clear error
+#[derive(Deserialize)]
+struct ExportVersion {
+    version: u32,
+}
+
+impl ChatExport {
+    ///Creates an export of the chatroom, the members keep the roles they have
+    pub fn new(
+        chatroom: &ChatRoom,
+        roles: &[(u32, RoomRole)],
+        messages: Vec<ExportedMessage>,
+        is_decrypted: bool,
+    ) -> ChatExport {
+        let members = chatroom
+            .get_members()
+            .iter()
+            .map(|member| ExportedMember {
+                id: member.get_id(),
+                name: member.get_name().clone(),
+                role: roles
+                    .iter()
+                    .find(|(user_id, _)| *user_id == member.get_id())
+                    .map_or(RoomRole::Member, |(_, role)| *role),
+                identity_key: None,
+            })
+            .collect();
+        ChatExport {
+            version: EXPORT_VERSION,
+            exported_at: helper_functions::get_sys_time_in_secs(),
+            is_decrypted,
+            chatroom: ExportedChatroom {
+                id: *chatroom.get_id(),
+                name: chatroom.get_name().clone(),
+                is_public: chatroom.is_public() && !chatroom.has_password(),
+                members,
+            },
+            messages,
+            friendships: Vec::new(),
+        }
+    }
+
+    ///Reads an export from JSON
+    pub fn from_json(text: &str) -> Result<ChatExport, Box<dyn Error + Send + Sync>> {
+        let ExportVersion { version } = serde_json::from_str(text)
+            .map_err(|e| format!("The file is no JSON export of a chatroom: {}", e))?;
+        if version != EXPORT_VERSION {
+            return Err(format!(
+                "The export has version {}, only version {} can be imported.",
+                version, EXPORT_VERSION
+            )
+            .into());
+        }
+        Ok(serde_json::from_str(text)?)
+    }
+
+    ///Writes the export in the format
+    pub fn render(&self, format: ExportFormat) -> Result<String, serde_json::Error> {
+        match format {
+            ExportFormat::Json => serde_json::to_string_pretty(self),
+            ExportFormat::Transcript => Ok(self.to_transcript()),
+        }
+    }
+
+    /// Times are written in UTC, so that the transcript reads the same in every time zone
+    fn to_transcript(&self) -> String {
+        let member_names = self
+            .chatroom
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect::<Vec<&str>>();
+        let mut transcript = format!(
+            "Chatroom: {} (id {})\nMembers: {}\nExported: {}\n",
+            self.chatroom.name,
+            self.chatroom.id,
+            member_names.join(", "),
+            format_utc_time(self.exported_at)
+        );
+        if !self.is_decrypted {
+            transcript.push_str(
+                "The messages are end-to-end encrypted, only the members can read them.\n",
+            );
+        }
+        transcript.push('\n');
+        for message in &self.messages {
+            let sender = if message.sender.is_empty() {
+                "unknown"
+            } else {
+                message.sender.as_str()
+            };
+            let content = if message.deleted_at.is_some() {
+                "message deleted".to_string()
+            } else if message.edited_at.is_some() {
+                format!("{} (edited)", message.content)
+            } else {
+                message.content.clone()
+            };
+            transcript.push_str(&format!(
+                "[{}] #{} {}: {}\n",
+                format_utc_time(message.sent_at),
+                message.room_message_id,
+                sender,
+                content
+            ));
+        }
+        transcript
+    }
+}
+
+impl ExportedMessage {
+    ///Creates the exported message with the content, None for messages that were not saved by the server
+    pub fn from_message(message: &ChatMessage, content: String) -> Option<ExportedMessage> {
+        Some(ExportedMessage {
+            id: message.get_id()?,
+            room_message_id: message.get_room_message_id()?,
+            sender_id: message.get_sender_id(),
+            sender: message.get_sender_name().clone(),
+            sent_at: message.get_sent_at()?,
+            edited_at: message.get_edited_at(),
+            deleted_at: message.get_deleted_at(),
+            content,
+            edits: Vec::new(),
+        })
+    }
+}
+
+/// Exports the chatroom with all of its messages as they are stored
+/// <br>Besides the roles, the export keeps the edit history, the public identity keys of the members
+/// and their friendships with each other, so that the chatroom can be used again after an import
+pub fn export_chatroom(storage: &dyn Storage, chatroom_id: u32) -> StorageResult<ChatExport> {
+    let chatroom = storage.get_chatroom_by_id(chatroom_id)?;
+    let mut roles = Vec::new();
+    for member in chatroom.get_members() {
+        if let Some(role) = storage.get_chat_member_role(chatroom_id, member.get_id())? {
+            roles.push((member.get_id(), role));
+        }
+    }
+
+    // The pages are read from the newest to the oldest message
+    let mut pages = Vec::new();
+    let mut before_message_id = None;
+    loop {
+        let page =
+            storage.get_chat_messages_before(chatroom_id, before_message_id, EXPORT_PAGE_SIZE)?;
+        before_message_id = page.first().and_then(|message| message.get_id());
+        let is_last_page = (page.len() as u32) < EXPORT_PAGE_SIZE || before_message_id.is_none();
+        pages.push(page);
+        if is_last_page {
+            break;
+        }
+    }
+    let mut messages = Vec::new();
+    for message in pages.iter().rev().flatten() {
+        if let Some(mut exported) = ExportedMessage::from_message(message, message.get_content()) {
+            if message.get_edited_at().is_some() {
+                exported.edits = storage.get_chat_message_edits(exported.id)?;
+            }
+            messages.push(exported);
+        }
+    }
+
+    let mut export = ChatExport::new(&chatroom, &roles, messages, false);
+    for member in &mut export.chatroom.members {
+        member.identity_key = storage.get_identity_key(member.id)?;
+    }
+    let members = &export.chatroom.members;
+    for (index, member) in members.iter().enumerate() {
+        for other in &members[index + 1..] {
+            if storage.check_if_two_users_are_friends(member.id, other.id)? {
+                export.friendships.push((member.id, other.id));
+            }
+        }
+    }
+    Ok(export)
+}
+
+/// Restores an export of the server into a fresh database, with the ids the chatroom, its members and messages had
+/// <br>Everything is saved in one transaction, so a failed import leaves nothing behind.
+/// Passwords are not exported: members that do not exist yet are created with a one-time password,
+/// which has to be replaced on their first login. Bans, mutes, receipts and the read state of the chatroom are not part of the export
+pub async fn import_chatroom(
+    storage: &dyn Storage,
+    export: &ChatExport,
+) -> StorageResult<ImportSummary> {
+    // Decrypted messages would be stored in plaintext and could not be told apart from encrypted ones
+    if export.is_decrypted {
+        return Err(
+            "Exports of a member contain the decrypted messages and can not be imported.".into(),
+        );
+    }
+    let chatroom = &export.chatroom;
+    if storage.get_chatroom_by_id(chatroom.id).is_ok() {
+        return Err(format!(
+            "There is a chatroom with the id {} already, exports can only be imported into a fresh database.",
+            chatroom.id
+        )
+        .into());
+    }
+
+    // Senders who left the chatroom are restored as well
+    let mut users: Vec<(User, Option<String>)> = chatroom
+        .members
+        .iter()
+        .map(|member| {
+            (
+                User::new(member.id, member.name.clone()),
+                member.identity_key.clone(),
+            )
+        })
+        .collect();
+    for message in &export.messages {
+        if let Some(sender_id) = message.sender_id {
+            if !users.iter().any(|(user, _)| user.get_id() == sender_id) {
+                users.push((User::new(sender_id, message.sender.clone()), None));
+            }
+        }
+    }
+    // Nothing is written before it is clear that every user fits into the database
+    let mut new_users = Vec::new();
+    let mut one_time_passwords = Vec::new();
+    for (user, identity_key) in users {
+        // The same rules as on signup, usernames become part of file names on the devices
+        if !User::is_valid_name(user.get_name()) {
+            return Err(format!(
+                "The export contains the invalid username {:?}.",
+                user.get_name()
+            )
+            .into());
+        }
+        if !storage.check_if_username_exists(user.get_name())? {
+            let one_time_password = generate_one_time_password();
+            let password_hash =
+                password_hashing::hash_password_for_storage(&hash_password(&one_time_password))
+                    .await?;
+            one_time_passwords.push((user.get_name().clone(), one_time_password));
+            new_users.push(RestoredUser {
+                user,
+                password_hash,
+                identity_key,
+            });
+        } else if storage
+            .get_user_from_database_by_name(user.get_name())?
+            .get_id()
+            != user.get_id()
+        {
+            return Err(format!(
+                "The user {} has another id in this database, exports can only be imported into a fresh database.",
+                user.get_name()
+            )
+            .into());
+        }
+    }
+    let mut new_friendships = Vec::new();
+    for (user1_id, user2_id) in &export.friendships {
+        if !storage.check_if_two_users_are_friends(*user1_id, *user2_id)? {
+            new_friendships.push((*user1_id, *user2_id));
+        }
+    }
+
+    storage.restore_chat_export(export, &new_users, &new_friendships)?;
+    Ok(ImportSummary {
+        message_count: export.messages.len(),
+        one_time_passwords,
+    })
+}
+
+/// Creates a random password for a restored user, which is handed to the user by the operator of the server
+fn generate_one_time_password() -> String {
+    helper_functions::generate_session_token()[..16].to_string()
+}
+
+/// Formats seconds since UNIX EPOCH as date and time in UTC
+fn format_utc_time(secs: u64) -> String {
+    DateTime::from_timestamp(secs as i64, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_storage::SqliteStorage;
+    use crate::test_support::TestStorageBuilder;
+    use std::sync::Arc;
+
+    /// Anton and rino are friends, anton edited the first message once
+    fn get_test_export() -> ChatExport {
+        let storage = TestStorageBuilder::new()
+            .user("anton")
+            .user("rino")
+            .friends("anton", "rino")
+            .chat_room("anton, rino", &["anton", "rino"])
+            .message(1, "anton", "e2e2.first")
+            .message(1, "rino", "e2e2.second")
+            .build();
+        storage.set_identity_key(1, &"ab".repeat(32)).unwrap();
+        let now = helper_functions::get_sys_time_in_secs();
+        storage
+            .edit_chat_message(1, 1, 0, "e2e2.edited", now)
+            .unwrap()
+            .unwrap();
+        export_chatroom(storage.as_ref(), 1).unwrap()
+    }
+
+    #[test]
+    fn test_export_chatroom() {
+        let export = get_test_export();
+        assert_eq!(export.version, EXPORT_VERSION);
+        assert!(!export.is_decrypted);
+        assert_eq!(export.chatroom.name, "anton, rino");
+        assert_eq!(
+            export
+                .chatroom
+                .members
+                .iter()
+                .map(|member| (member.id, member.name.as_str()))
+                .collect::<Vec<(u32, &str)>>(),
+            vec![(1, "anton"), (2, "rino")]
+        );
+        assert_eq!(
+            export
+                .messages
+                .iter()
+                .map(|message| (
+                    message.room_message_id,
+                    message.sender.as_str(),
+                    message.content.as_str()
+                ))
+                .collect::<Vec<(u32, &str, &str)>>(),
+            vec![(1, "anton", "e2e2.edited"), (2, "rino", "e2e2.second")]
+        );
+        assert_eq!(export.messages[0].edits.len(), 1);
+        assert_eq!(export.messages[0].edits[0].get_content(), "e2e2.first");
+        assert_eq!(
+            export.chatroom.members[0].identity_key,
+            Some("ab".repeat(32))
+        );
+        assert_eq!(export.chatroom.members[1].identity_key, None);
+        assert_eq!(export.friendships, vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let export = get_test_export();
+        let json = export.render(ExportFormat::Json).unwrap();
+        assert_eq!(ChatExport::from_json(&json).unwrap(), export);
+
+        let other_version = json.replacen(
+            &format!("\"version\": {}", EXPORT_VERSION),
+            "\"version\": 99",
+            1,
+        );
+        assert!(ChatExport::from_json(&other_version).is_err());
+        assert!(ChatExport::from_json("anton: hello").is_err());
+    }
+
+    #[test]
+    fn test_transcript() {
+        let mut export = get_test_export();
+        export.exported_at = 0;
+        // The transcript numbers the messages within the chatroom, not by their id in the storage
+        for message in &mut export.messages {
+            message.sent_at = 60;
+            message.id += 40;
+        }
+        export.messages[1].sender = String::new();
+        assert_eq!(
+            export.render(ExportFormat::Transcript).unwrap(),
+            "Chatroom: anton, rino (id 1)\n\
+            Members: anton, rino\n\
+            Exported: 1970-01-01 00:00 UTC\n\
+            The messages are end-to-end encrypted, only the members can read them.\n\
+            \n\
+            [1970-01-01 00:01 UTC] #1 anton: e2e2.edited (edited)\n\
+            [1970-01-01 00:01 UTC] #2 unknown: e2e2.second\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_into_fresh_database() {
+        let export = get_test_export();
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open_in_memory().unwrap());
+        let summary = import_chatroom(storage.as_ref(), &export).await.unwrap();
+        assert_eq!(summary.message_count, 2);
+
+        let mut restored = export_chatroom(storage.as_ref(), 1).unwrap();
+        restored.exported_at = export.exported_at;
+        assert_eq!(restored, export);
+        // The restored users log in with their one-time password and have to change it
+        let (username, one_time_password) = &summary.one_time_passwords[0];
+        assert_eq!(username, "anton");
+        assert!(password_hashing::check_if_password_matches_username(
+            &storage,
+            &hash_password(one_time_password),
+            "anton"
+        )
+        .await
+        .unwrap());
+        assert!(storage.check_if_password_change_required("anton").unwrap());
+        // New messages are numbered after the restored ones
+        let message = storage
+            .save_chat_message_to_database(1, 2, "e2e2.third", 100)
+            .unwrap();
+        assert_eq!(message.get_id(), Some(3));
+        assert_eq!(message.get_room_message_id(), Some(3));
+
+        // The chatroom exists now, so it is not imported twice
+        assert!(import_chatroom(storage.as_ref(), &export).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_needs_fresh_database() {
+        let export = get_test_export();
+        // rino would get another id
+        let storage = TestStorageBuilder::new().user("rino").build();
+        assert!(import_chatroom(storage.as_ref(), &export).await.is_err());
+
+        let mut decrypted = export;
+        decrypted.is_decrypted = true;
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        assert!(import_chatroom(&storage, &decrypted).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_import_leaves_nothing_behind() {
+        let mut export = get_test_export();
+        // The second message can not be saved with the id of the first one
+        export.messages[1].id = export.messages[0].id;
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        assert!(import_chatroom(&storage, &export).await.is_err());
+        assert!(storage.get_chatroom_by_id(1).is_err());
+        assert!(!storage.check_if_username_exists("anton").unwrap());
+    }
+}
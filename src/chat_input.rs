@@ -0,0 +1,401 @@
+use colored::Colorize;
+use crossterm::cursor::{MoveToColumn, MoveToPreviousLine, RestorePosition, SavePosition};
+use crossterm::event::{
+    DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
+use crossterm::terminal::{self, Clear, ClearType};
+use std::io::{self, Write};
+
+/// Seconds after which a typing user tells the chatroom again that it is still typing
+pub const TYPING_RESEND_SECS: u64 = 3;
+
+/// Seconds without a key press after which the user stops typing
+pub const TYPING_PAUSE_SECS: u64 = 5;
+
+/// Seconds after which another member is no longer shown as typing if nothing was heard from it
+/// <br>Covers members whose stop was lost, e.g. because their connection closed
+pub const TYPING_TIMEOUT_SECS: u64 = 8;
+
+/// What the user did in the input line
+#[derive(Debug, PartialEq)]
+pub enum InputEvent {
+    /// The text in the input line was changed
+    Edited,
+    /// The user pressed ENTER, the input line is empty again
+    Submitted(String),
+    /// The user pressed CTRL+C or CTRL+D
+    Closed,
+    /// The user pressed PAGE UP to see older messages
+    ScrollBack,
+    /// The terminal got or lost the focus, only reported by terminals that support it
+    FocusChanged(bool),
+}
+
+/// Line that was printed above the input line, so that it can be changed later
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintedLine {
+    /// Row of the line, counted from the first row printed above the input line
+    row: u64,
+    /// Lines printed before the input line was hidden for other output can not be found anymore
+    generation: u64,
+}
+
+/// Input line at the bottom of the chat room, which reads single key presses
+/// <br>Everything the server sends is printed above it, so that the typed text is never torn apart.
+/// The note about typing members is kept in the line above the input line
+pub struct ChatInput {
+    prompt: String,
+    text: String,
+    typing_note: Option<String>,
+    is_shown: bool,
+    /// Rows printed with `print_line` so far
+    rows_printed: u64,
+    generation: u64,
+}
+
+impl ChatInput {
+    /// Switches the terminal to raw mode and shows the empty input line
+    /// <br>The terminal is switched back when the input line is dropped
+    pub fn new(prompt: String) -> io::Result<Self> {
+        let mut input = ChatInput {
+            prompt,
+            text: String::new(),
+            typing_note: None,
+            is_shown: false,
+            rows_printed: 0,
+            generation: 0,
+        };
+        crossterm::execute!(io::stdout(), EnableFocusChange)?;
+        input.show()?;
+        Ok(input)
+    }
+
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Removes the input line, so that something can be printed in its place
+    /// <br>The terminal is in its normal mode until `show` is called again.
+    /// Lines printed before can not be changed anymore, because it is unknown what is printed now
+    pub fn hide(&mut self) -> io::Result<()> {
+        self.generation += 1;
+        self.hide_input_line()
+    }
+
+    /// Prints a line above the input line
+    /// <br>Returns where the line is, so that it can be changed with `update_line`
+    pub fn print_line(&mut self, line: &str) -> io::Result<PrintedLine> {
+        let printed_line = PrintedLine {
+            row: self.rows_printed,
+            generation: self.generation,
+        };
+        self.hide_input_line()?;
+        println!("{}", line);
+        let columns = terminal::size().map_or(80, |(columns, _)| columns.max(1) as usize);
+        self.rows_printed += visible_width(line).div_ceil(columns).max(1) as u64;
+        self.show()?;
+        Ok(printed_line)
+    }
+
+    /// Replaces a line that was printed with `print_line`
+    /// <br>The new line has to be as wide as the old one.
+    /// Nothing happens if the line scrolled out of the terminal
+    pub fn update_line(&mut self, printed_line: PrintedLine, line: &str) -> io::Result<()> {
+        let rows_up = self.rows_printed - printed_line.row;
+        let (_, terminal_rows) = terminal::size()?;
+        let input_rows = if self.typing_note.is_some() { 2 } else { 1 };
+        if printed_line.generation != self.generation || rows_up + input_rows > terminal_rows as u64
+        {
+            return Ok(());
+        }
+        self.hide_input_line()?;
+        let mut stdout = io::stdout();
+        crossterm::execute!(stdout, SavePosition, MoveToPreviousLine(rows_up as u16))?;
+        write!(stdout, "{}", line)?;
+        crossterm::execute!(stdout, RestorePosition)?;
+        self.show()
+    }
+
+    fn hide_input_line(&mut self) -> io::Result<()> {
+        if !self.is_shown {
+            return Ok(());
+        }
+        let mut stdout = io::stdout();
+        crossterm::execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+        if self.typing_note.is_some() {
+            crossterm::execute!(stdout, MoveToPreviousLine(1), Clear(ClearType::CurrentLine))?;
+        }
+        terminal::disable_raw_mode()?;
+        self.is_shown = false;
+        Ok(())
+    }
+
+    /// Shows the input line with the text typed so far below everything that was printed
+    pub fn show(&mut self) -> io::Result<()> {
+        if self.is_shown {
+            return Ok(());
+        }
+        let mut stdout = io::stdout();
+        if let Some(typing_note) = &self.typing_note {
+            write!(stdout, "{}\r\n", typing_note.dimmed().italic())?;
+        }
+        write!(stdout, "{}{}", self.prompt, self.text)?;
+        stdout.flush()?;
+        terminal::enable_raw_mode()?;
+        self.is_shown = true;
+        Ok(())
+    }
+
+    /// Shows the note about typing members above the input line, None removes it
+    pub fn set_typing_note(&mut self, typing_note: Option<String>) -> io::Result<()> {
+        if typing_note == self.typing_note {
+            return Ok(());
+        }
+        self.hide_input_line()?;
+        self.typing_note = typing_note;
+        self.show()
+    }
+
+    /// Changes the input line according to a key press
+    /// <br>Returns None for everything that does not change the input line
+    pub fn handle_event(&mut self, event: Event) -> io::Result<Option<InputEvent>> {
+        let Some(input_event) = self.apply_event(event) else {
+            return Ok(None);
+        };
+        match &input_event {
+            // The sent line is printed by the chat room, together with the status of the message
+            InputEvent::Edited | InputEvent::Submitted(_) => {
+                let mut stdout = io::stdout();
+                crossterm::execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+                write!(stdout, "{}{}", self.prompt, self.text)?;
+                stdout.flush()?;
+            }
+            InputEvent::Closed => self.hide()?,
+            InputEvent::FocusChanged(_) | InputEvent::ScrollBack => {}
+        }
+        Ok(Some(input_event))
+    }
+
+    // Only changes the text, printing is left to `handle_event`
+    fn apply_event(&mut self, event: Event) -> Option<InputEvent> {
+        match event {
+            Event::Key(key) => self.apply_key(key),
+            Event::FocusGained => Some(InputEvent::FocusChanged(true)),
+            Event::FocusLost => Some(InputEvent::FocusChanged(false)),
+            _ => None,
+        }
+    }
+
+    fn apply_key(&mut self, key: KeyEvent) -> Option<InputEvent> {
+        if key.kind == KeyEventKind::Release {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char('c' | 'd') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(InputEvent::Closed)
+            }
+            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => None,
+            KeyCode::Char(c) => {
+                self.text.push(c);
+                Some(InputEvent::Edited)
+            }
+            KeyCode::Backspace => self.text.pop().map(|_| InputEvent::Edited),
+            KeyCode::Enter => Some(InputEvent::Submitted(std::mem::take(&mut self.text))),
+            KeyCode::PageUp => Some(InputEvent::ScrollBack),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for ChatInput {
+    fn drop(&mut self) {
+        _ = self.hide();
+        _ = crossterm::execute!(io::stdout(), DisableFocusChange);
+    }
+}
+
+/// Returns how many characters of the line are shown, colors are not counted
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Escape sequences like `\x1b[1;35m` end with a letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Decides when the chatroom is told that the user started or stopped typing
+/// <br>While the user keeps typing, the start is repeated every `TYPING_RESEND_SECS` instead of on every key press
+#[derive(Default)]
+pub struct TypingSignal {
+    last_start_sent: Option<u64>,
+    last_key_press: u64,
+}
+
+impl TypingSignal {
+    /// Called after every change of the input line
+    /// <br>Returns the signal to send, if any. `now` is the current time in seconds since UNIX EPOCH
+    pub fn edited(&mut self, text: &str, now: u64) -> Option<bool> {
+        if text.is_empty() {
+            return self.stop();
+        }
+        self.last_key_press = now;
+        match self.last_start_sent {
+            Some(last_start_sent) if now - last_start_sent < TYPING_RESEND_SECS => None,
+            _ => {
+                self.last_start_sent = Some(now);
+                Some(true)
+            }
+        }
+    }
+
+    /// Called regularly, stops typing after a pause of `TYPING_PAUSE_SECS`
+    pub fn tick(&mut self, now: u64) -> Option<bool> {
+        if now.saturating_sub(self.last_key_press) >= TYPING_PAUSE_SECS {
+            self.stop()
+        } else {
+            None
+        }
+    }
+
+    /// Stops typing, e.g. because the message was sent
+    pub fn stop(&mut self) -> Option<bool> {
+        self.last_start_sent.take().map(|_| false)
+    }
+}
+
+/// Other members of the chatroom that are typing at the moment
+#[derive(Default)]
+pub struct TypingMembers {
+    /// Name of the member and the time the member was last heard typing
+    members: Vec<(String, u64)>,
+}
+
+impl TypingMembers {
+    /// Saves a typing signal of a member, `now` is the current time in seconds since UNIX EPOCH
+    pub fn update(&mut self, username: &str, is_typing: bool, now: u64) {
+        self.remove(username);
+        if is_typing {
+            self.members.push((username.to_string(), now));
+        }
+    }
+
+    /// A member that sent a message has stopped typing, even if the stop comes later
+    pub fn remove(&mut self, username: &str) {
+        self.members.retain(|(name, _)| name != username);
+    }
+
+    /// Forgets members that were not heard for `TYPING_TIMEOUT_SECS`
+    pub fn expire(&mut self, now: u64) {
+        self.members
+            .retain(|(_, last_heard)| now.saturating_sub(*last_heard) < TYPING_TIMEOUT_SECS);
+    }
+
+    /// Returns the note that is shown above the input line, None if nobody is typing
+    pub fn describe(&self) -> Option<String> {
+        match self.members.as_slice() {
+            [] => None,
+            [(name, _)] => Some(format!("{} is typing…", name)),
+            [(first, _), (second, _)] => Some(format!("{} and {} are typing…", first, second)),
+            _ => Some("Several people are typing…".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typing_signal_is_throttled() {
+        let mut signal = TypingSignal::default();
+        assert_eq!(signal.edited("H", 100), Some(true));
+        assert_eq!(signal.edited("Ha", 101), None);
+        assert_eq!(signal.tick(102), None);
+        assert_eq!(signal.edited("Hal", 103), Some(true));
+
+        // A pause stops typing once
+        assert_eq!(signal.tick(103 + TYPING_PAUSE_SECS), Some(false));
+        assert_eq!(signal.tick(104 + TYPING_PAUSE_SECS), None);
+
+        // Deleting the whole text and sending stop typing as well
+        assert_eq!(signal.edited("Hall", 200), Some(true));
+        assert_eq!(signal.edited("", 200), Some(false));
+        assert_eq!(signal.edited("H", 201), Some(true));
+        assert_eq!(signal.stop(), Some(false));
+        assert_eq!(signal.stop(), None);
+    }
+
+    #[test]
+    fn test_typing_members() {
+        let mut members = TypingMembers::default();
+        assert_eq!(members.describe(), None);
+        members.update("rino", true, 100);
+        assert_eq!(members.describe().unwrap(), "rino is typing…");
+        members.update("anton", true, 102);
+        members.update("rino", true, 103);
+        assert_eq!(members.describe().unwrap(), "anton and rino are typing…");
+        members.update("antonia", true, 103);
+        assert_eq!(members.describe().unwrap(), "Several people are typing…");
+
+        members.remove("antonia");
+        members.update("rino", false, 104);
+        assert_eq!(members.describe().unwrap(), "anton is typing…");
+        members.expire(102 + TYPING_TIMEOUT_SECS);
+        assert_eq!(members.describe(), None);
+    }
+
+    #[test]
+    fn test_visible_width() {
+        assert_eq!(visible_width("anton: Hallo!"), 13);
+        assert_eq!(
+            visible_width(&format!("{}: ✓✓", "anton".bold().purple())),
+            9
+        );
+    }
+
+    #[test]
+    fn test_apply_key() {
+        let mut input = ChatInput {
+            prompt: "anton: ".to_string(),
+            text: String::new(),
+            typing_note: None,
+            is_shown: false,
+            rows_printed: 0,
+            generation: 0,
+        };
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert_eq!(
+            input.apply_key(key(KeyCode::Char('H'))),
+            Some(InputEvent::Edited)
+        );
+        input.apply_key(key(KeyCode::Char('i')));
+        input.apply_key(key(KeyCode::Char('!')));
+        input.apply_key(key(KeyCode::Backspace));
+        assert_eq!(input.get_text(), "Hi");
+        assert_eq!(input.apply_key(key(KeyCode::Left)), None);
+        assert_eq!(
+            input.apply_key(key(KeyCode::PageUp)),
+            Some(InputEvent::ScrollBack)
+        );
+        assert_eq!(
+            input.apply_key(key(KeyCode::Enter)),
+            Some(InputEvent::Submitted("Hi".to_string()))
+        );
+        assert_eq!(input.get_text(), "");
+        assert_eq!(input.apply_key(key(KeyCode::Backspace)), None);
+        assert_eq!(
+            input.apply_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(InputEvent::Closed)
+        );
+    }
+}
@@ -1,34 +1,82 @@
-use std::{thread::sleep, time::Duration};
+use std::{collections::HashMap, path::PathBuf, thread::sleep, time::Duration};
+
+use rpassword::read_password;
 
 use crate::{
-    helper_functions, sql_interaction,
-    structs::{chat_room::ChatRoom, friend_request::FriendRequest, user::User},
+    chat_export::ExportFormat,
+    client, crypto, helper_functions, key_store, login,
+    message_index::{self, MessageIndex, SearchHit},
+    server_connection::ServerConnection,
+    session_store,
+    structs::{
+        chat_room::ChatRoom, friend_request::FriendRequest, presence::PresenceStatus,
+        session_info::SessionInfo, user::User,
+    },
 };
+use colored::Colorize;
+
+/// Maximum number of messages a search shows, the newest ones come first
+const SEARCH_RESULT_LIMIT: u32 = 50;
 
 /// Main loop for the chat menu
-/// <br>Returns the selected chatroom if the user selected one, None otherwise
-pub async fn chat_menu_loop(current_user: User) -> Option<ChatRoom> {
+/// <br>Returns the selected chatroom if the user selected one, None otherwise.
+/// A chatroom that was opened from a search result comes with the id of the found message.
+/// Right after logging in the menu first tells the user what happened while they were offline
+pub async fn chat_menu_loop(
+    connection: &mut ServerConnection,
+    current_user: User,
+    show_login_summary: bool,
+) -> Option<(ChatRoom, Option<u32>)> {
+    let mut login_summary = if show_login_summary {
+        load_login_summary(connection).await
+    } else {
+        None
+    };
     loop {
-        let welcome_prompt = format!("Hello {}, what do you want to do?", current_user.get_name());
+        let mut welcome_prompt =
+            format!("Hello {}, what do you want to do?", current_user.get_name());
+        if let Some(summary) = login_summary.take() {
+            welcome_prompt = format!("{}\n{}", summary.bold(), welcome_prompt);
+        }
         let list_of_choices = vec![
             "Search for friend".to_string(),
             "Join existing chatroom".to_string(),
+            "Create group chat".to_string(),
+            "Create public room".to_string(),
+            "Search public rooms".to_string(),
             "Check friend requests".to_string(),
+            "Search messages".to_string(),
+            "Export chat".to_string(),
+            "Verify contact".to_string(),
+            "Manage sessions".to_string(),
             "Log out".to_string(),
         ];
         let selection =
             helper_functions::display_multiple_choices(&welcome_prompt, list_of_choices, true);
 
         match selection {
-            0..=2 => {
-                if let Some(chat_room) = chat_menu_selection(selection, current_user.clone()).await
+            0..=5 => {
+                if let Some(chat_room) =
+                    chat_menu_selection(connection, selection, current_user.clone()).await
+                {
+                    return Some((chat_room, None));
+                }
+            }
+            6 => {
+                if let Some((chat_room, message_id)) =
+                    search_messages(connection, &current_user, None).await
                 {
-                    return Some(chat_room);
+                    return Some((chat_room, Some(message_id)));
                 }
             }
-            3 => {
-                //user chose third option - Exit
+            7 => export_chat(connection, &current_user).await,
+            8 => verify_contact(connection, &current_user).await,
+            9 => manage_sessions(connection).await,
+            10 => {
+                //user chose eleventh option - Exit
                 _ = helper_functions::print_info("Logging out...");
+                _ = connection.logout().await;
+                _ = session_store::delete_session_token(&session_store::get_session_file_path());
                 sleep(Duration::from_secs(1));
                 return None;
             }
@@ -37,8 +85,77 @@ pub async fn chat_menu_loop(current_user: User) -> Option<ChatRoom> {
     }
 }
 
+/// Loads the unread messages and open friend requests for the summary after logging in
+/// <br>Messages that arrived while the user was offline count as delivered afterwards
+async fn load_login_summary(connection: &mut ServerConnection) -> Option<String> {
+    let unread_counts = match connection.get_unread_counts().await {
+        Ok(unread_counts) => unread_counts,
+        Err(e) => {
+            eprintln!("Error loading unread messages: {}", e);
+            HashMap::new()
+        }
+    };
+    let friend_request_count = match connection.get_friend_requests().await {
+        Ok(requests) => requests.len(),
+        Err(e) => {
+            eprintln!("Error searching for friend requests: {}", e);
+            0
+        }
+    };
+    format_login_summary(
+        unread_counts.values().sum(),
+        unread_counts.len(),
+        friend_request_count,
+    )
+}
+
+/// Returns the summary that is shown after logging in, None if there is nothing new
+fn format_login_summary(
+    message_count: u32,
+    chatroom_count: usize,
+    friend_request_count: usize,
+) -> Option<String> {
+    let mut parts = Vec::new();
+    if message_count > 0 {
+        parts.push(format!(
+            "{} new {} in {} {}",
+            message_count,
+            if message_count == 1 {
+                "message"
+            } else {
+                "messages"
+            },
+            chatroom_count,
+            if chatroom_count == 1 {
+                "chatroom"
+            } else {
+                "chatrooms"
+            }
+        ));
+    }
+    if friend_request_count > 0 {
+        parts.push(format!(
+            "{} open friend {}",
+            friend_request_count,
+            if friend_request_count == 1 {
+                "request"
+            } else {
+                "requests"
+            }
+        ));
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(format!("While you were away: {}.", parts.join(" and ")))
+}
+
 /// Method for handling the user selection in the chat menu
-async fn chat_menu_selection(selection: usize, current_user: User) -> Option<ChatRoom> {
+async fn chat_menu_selection(
+    connection: &mut ServerConnection,
+    selection: usize,
+    current_user: User,
+) -> Option<ChatRoom> {
     match selection {
         0 => {
             _ = helper_functions::clear_console();
@@ -59,11 +176,9 @@ async fn chat_menu_selection(selection: usize, current_user: User) -> Option<Cha
             );
             _ = helper_functions::print_info(&prompt);
 
-            let found_users = sql_interaction::get_user_by_name_with_contains_search(
-                search_input,
-                current_user.get_id(),
-            )
-            .await;
+            let found_users = connection
+                .get_user_by_name_with_contains_search(search_input)
+                .await;
             match found_users {
                 Ok(users) => {
                     if users.is_empty() {
@@ -76,11 +191,8 @@ async fn chat_menu_selection(selection: usize, current_user: User) -> Option<Cha
                         let selected_user = select_friend_for_list_of_users(users.clone());
                         match selected_user {
                             Some(user) => {
-                                let is_already_friends =
-                                    sql_interaction::check_if_two_users_are_friends(
-                                        current_user.get_id(),
-                                        user.get_id(),
-                                    )
+                                let is_already_friends = connection
+                                    .check_if_friends_with(user.get_id())
                                     .await
                                     .unwrap();
 
@@ -92,11 +204,7 @@ async fn chat_menu_selection(selection: usize, current_user: User) -> Option<Cha
                                 } else {
                                     let msg = format!("Sending friend request to {}. If the request gets accepted, you will see the new chat as an existing chatroom!", user.get_name());
                                     _ = helper_functions::print_confirmation(&msg);
-                                    _ = sql_interaction::create_new_friend_request(
-                                        current_user.get_id(),
-                                        user.get_id(),
-                                    )
-                                    .await;
+                                    _ = connection.create_new_friend_request(user.get_id()).await;
                                     sleep(Duration::from_secs(2));
                                     None
                                 }
@@ -114,16 +222,27 @@ async fn chat_menu_selection(selection: usize, current_user: User) -> Option<Cha
         1 => {
             // User chose to join an existing chatroom
 
-            joining_an_existing_chatroom(current_user.clone()).await
+            joining_an_existing_chatroom(connection, &current_user).await
         }
         2 => {
+            // User chose to create a group chat
+            create_group_chat(connection).await
+        }
+        3 => {
+            // User chose to create a public room
+            create_public_room(connection).await
+        }
+        4 => {
+            // User chose to search for public rooms
+            search_public_rooms(connection).await
+        }
+        5 => {
             // User chose to see friend requests
             _ = helper_functions::print_info(
                 "Please wait while we search for your friend requests...",
             );
             // Get all friend requests for the current user
-            let friend_requests =
-                sql_interaction::get_friend_requests_by_user_id(current_user.get_id()).await;
+            let friend_requests = connection.get_friend_requests().await;
 
             match friend_requests {
                 Ok(requests) => {
@@ -147,12 +266,10 @@ async fn chat_menu_selection(selection: usize, current_user: User) -> Option<Cha
 
                                 if should_accept {
                                     friend_request
-                                        .accept_friend_request(current_user.clone())
+                                        .accept_friend_request(connection, current_user.clone())
                                         .await
                                 } else {
-                                    let _ = friend_request
-                                        .decline_friend_request(current_user.get_id())
-                                        .await;
+                                    let _ = friend_request.decline_friend_request(connection).await;
                                     None
                                 }
                             }
@@ -170,6 +287,520 @@ async fn chat_menu_selection(selection: usize, current_user: User) -> Option<Cha
     }
 }
 
+/// Method for creating a group chat with some of the friends of the current user
+/// <br>Returns the new chatroom, so that the user can start chatting right away
+async fn create_group_chat(connection: &mut ServerConnection) -> Option<ChatRoom> {
+    _ = helper_functions::print_info("Please wait while we search for your friends...");
+    let friends = match connection.get_friends().await {
+        Ok(friends) => friends,
+        Err(e) => {
+            eprintln!("Error searching for friends: {}", e);
+            return None;
+        }
+    };
+    if friends.len() < 2 {
+        _ = helper_functions::print_info(
+            "You need at least two friends for a group chat! Try to search for new friends!",
+        );
+        sleep(Duration::from_secs(2));
+        return None;
+    }
+
+    let list_of_choices = friends
+        .iter()
+        .map(|friend| friend.get_name().clone())
+        .collect::<Vec<String>>();
+    let selection = helper_functions::display_multiple_selection(
+        "Select the friends for the group chat (space to select, enter to confirm):",
+        list_of_choices,
+    );
+    let member_ids = get_member_ids_by_selection(&friends, &selection);
+    if member_ids.len() < 2 {
+        _ = helper_functions::print_error("Please select at least two friends.");
+        sleep(Duration::from_secs(2));
+        return None;
+    }
+
+    _ = helper_functions::print_info(
+        "Please enter a name for the group chat (leave blank to return):",
+    );
+    let name = helper_functions::read_user_input_from_console();
+    if name.is_empty() {
+        return None;
+    }
+
+    match connection.create_group_chat(&name, member_ids).await {
+        Ok(chatroom) => {
+            _ = helper_functions::print_confirmation(&format!(
+                "Group chat {} was created!",
+                chatroom.get_name()
+            ));
+            sleep(Duration::from_secs(1));
+            Some(chatroom)
+        }
+        Err(e) => {
+            _ = helper_functions::print_error(&format!("{}", e));
+            sleep(Duration::from_secs(2));
+            None
+        }
+    }
+}
+
+/// Method for creating a public room that other users can find by its name
+/// <br>Returns the new chatroom, so that the user can start chatting right away
+async fn create_public_room(connection: &mut ServerConnection) -> Option<ChatRoom> {
+    _ = helper_functions::print_info(
+        "Please enter a name for the public room (leave blank to return):",
+    );
+    let name = helper_functions::read_user_input_from_console();
+    if name.is_empty() {
+        return None;
+    }
+
+    _ = helper_functions::print_info(
+        "Please enter a password for the room (leave blank for a room everybody can join):",
+    );
+    let password_hash = read_room_password();
+
+    match connection.create_public_room(&name, password_hash).await {
+        Ok(chatroom) => {
+            _ = helper_functions::print_confirmation(&format!(
+                "Public room {} was created!",
+                chatroom.get_name()
+            ));
+            sleep(Duration::from_secs(1));
+            Some(chatroom)
+        }
+        Err(e) => {
+            _ = helper_functions::print_error(&format!("{}", e));
+            sleep(Duration::from_secs(2));
+            None
+        }
+    }
+}
+
+/// Method for searching public rooms by their name and joining one of them
+/// <br>Returns the joined chatroom, so that the user can start chatting right away
+async fn search_public_rooms(connection: &mut ServerConnection) -> Option<ChatRoom> {
+    _ = helper_functions::print_info(
+        "Please enter the name of the room you want to search for (leave blank to return):",
+    );
+    let search_input = helper_functions::read_user_input_from_console();
+    if search_input.is_empty() {
+        return None;
+    }
+
+    let chatrooms = match connection.search_public_rooms(&search_input).await {
+        Ok(chatrooms) => chatrooms,
+        Err(e) => {
+            eprintln!("Error searching for public rooms: {}", e);
+            return None;
+        }
+    };
+    if chatrooms.is_empty() {
+        _ = helper_functions::print_error(
+            "No public rooms found with that name. Please try again.",
+        );
+        sleep(Duration::from_secs(2));
+        return None;
+    }
+
+    let mut list_of_choices = chatrooms
+        .iter()
+        .map(|chatroom| {
+            if chatroom.has_password() {
+                format!("{} (password)", chatroom.get_name())
+            } else {
+                chatroom.get_name().clone()
+            }
+        })
+        .collect::<Vec<String>>();
+    list_of_choices.push("Go back".to_string());
+    let selection = helper_functions::display_multiple_choices(
+        "Select the room you want to join:",
+        list_of_choices,
+        true,
+    );
+    let chatroom = chatrooms.get(selection)?;
+
+    let password_hash = if chatroom.has_password() {
+        _ = helper_functions::print_info("Please enter the password of the room:");
+        read_room_password()
+    } else {
+        None
+    };
+
+    match connection
+        .join_public_room(*chatroom.get_id(), password_hash)
+        .await
+    {
+        Ok(chatroom) => Some(chatroom),
+        Err(e) => {
+            _ = helper_functions::print_error(&format!("{}", e));
+            sleep(Duration::from_secs(2));
+            None
+        }
+    }
+}
+
+/// Method for searching the messages of all chatrooms of the user
+/// <br>Asks for the words to search for if there are none yet.
+/// Returns the chatroom of the selected hit together with the id of the found message
+pub async fn search_messages(
+    connection: &mut ServerConnection,
+    current_user: &User,
+    search: Option<String>,
+) -> Option<(ChatRoom, u32)> {
+    let search = match search.filter(|search| !search.trim().is_empty()) {
+        Some(search) => search,
+        None => {
+            _ = helper_functions::print_info(
+                "Please enter the words you want to search for (leave blank to return):",
+            );
+            let search_input = helper_functions::read_user_input_from_console();
+            if search_input.trim().is_empty() {
+                return None;
+            }
+            search_input
+        }
+    };
+
+    // Only the chatrooms the user is still a member of are searched
+    let chatrooms = match connection.search_for_chatrooms().await {
+        Ok(chatrooms) => chatrooms,
+        Err(e) => {
+            eprintln!("Error searching for chatrooms: {}", e);
+            return None;
+        }
+    };
+    let chatroom_ids = chatrooms
+        .iter()
+        .map(|chatroom| *chatroom.get_id())
+        .collect::<Vec<u32>>();
+    let message_index_file_path =
+        message_index::get_message_index_file_path(current_user.get_name());
+    let hits = match MessageIndex::open(&message_index_file_path)
+        .and_then(|index| index.search(&search, &chatroom_ids, SEARCH_RESULT_LIMIT))
+    {
+        Ok(hits) => hits,
+        Err(e) => {
+            eprintln!("Error searching for messages: {}", e);
+            return None;
+        }
+    };
+    if hits.is_empty() {
+        _ = helper_functions::print_info("No messages found. Please try other words.");
+        sleep(Duration::from_secs(2));
+        return None;
+    }
+
+    let now = helper_functions::get_sys_time_in_secs();
+    let mut list_of_choices = hits
+        .iter()
+        .map(|hit| {
+            let room_name = chatrooms
+                .iter()
+                .find(|chatroom| *chatroom.get_id() == hit.chatroom_id)
+                .map(|chatroom| chatroom.get_name().as_str())
+                .unwrap_or_default();
+            format_search_hit(hit, room_name, now)
+        })
+        .collect::<Vec<String>>();
+    list_of_choices.push("Go back".to_string());
+    let selection = helper_functions::display_multiple_choices(
+        &format!("Messages with \"{}\":", search.trim()),
+        list_of_choices,
+        true,
+    );
+    let hit = hits.get(selection)?;
+    let chatroom = chatrooms
+        .into_iter()
+        .find(|chatroom| *chatroom.get_id() == hit.chatroom_id)?;
+    Some((chatroom, hit.message_id))
+}
+
+/// Method for exporting the decrypted messages of a chatroom to a file
+async fn export_chat(connection: &mut ServerConnection, current_user: &User) {
+    let chatrooms = match connection.search_for_chatrooms().await {
+        Ok(chatrooms) => chatrooms,
+        Err(e) => {
+            eprintln!("Error searching for chatrooms: {}", e);
+            return;
+        }
+    };
+    if chatrooms.is_empty() {
+        _ = helper_functions::print_info("You do not have any chatrooms yet!");
+        sleep(Duration::from_secs(2));
+        return;
+    }
+    let mut list_of_choices = chatrooms
+        .iter()
+        .map(|chatroom| chatroom.get_name().clone())
+        .collect::<Vec<String>>();
+    list_of_choices.push("Go back".to_string());
+    let selection = helper_functions::display_multiple_choices(
+        "Select the chatroom you want to export:",
+        list_of_choices,
+        true,
+    );
+    let Some(chatroom) = chatrooms.get(selection) else {
+        return;
+    };
+
+    let format = match helper_functions::display_multiple_choices(
+        "Select the format of the export:",
+        vec![
+            "JSON (can be read by other programs)".to_string(),
+            "Transcript (one line per message)".to_string(),
+        ],
+        true,
+    ) {
+        0 => ExportFormat::Json,
+        _ => ExportFormat::Transcript,
+    };
+    let default_path = get_default_export_path(chatroom, format);
+    _ = helper_functions::print_info(&format!(
+        "Please enter the file the chat is exported to (leave blank for {}):",
+        default_path.display()
+    ));
+    let path_input = helper_functions::read_user_input_from_console();
+    let path = if path_input.trim().is_empty() {
+        default_path
+    } else {
+        PathBuf::from(path_input.trim())
+    };
+
+    _ = helper_functions::print_info("Please wait while the messages are exported...");
+    match client::export_chat(connection, current_user, chatroom, format, &path).await {
+        Ok(message_count) => {
+            _ = helper_functions::print_confirmation(&format!(
+                "Exported {} messages to {}.",
+                message_count,
+                path.display()
+            ))
+        }
+        Err(e) => {
+            _ = helper_functions::print_error(&format!("The chat could not be exported: {}", e))
+        }
+    }
+    sleep(Duration::from_secs(2));
+}
+
+/// Returns the file a chatroom is exported to if the user does not name one
+/// <br>Characters that are not allowed in file names on every system are replaced
+fn get_default_export_path(chatroom: &ChatRoom, format: ExportFormat) -> PathBuf {
+    let name = chatroom
+        .get_name()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let extension = match format {
+        ExportFormat::Json => "json",
+        ExportFormat::Transcript => "txt",
+    };
+    PathBuf::from(format!("chat_{}_{}.{}", chatroom.get_id(), name, extension))
+}
+
+/// Returns the line that is shown for a message that was found by a search
+/// <br>The words that matched the search are highlighted in the snippet of the message
+fn format_search_hit(hit: &SearchHit, room_name: &str, now: u64) -> String {
+    let mut snippet = String::new();
+    let mut rest = hit.snippet.as_str();
+    while let Some(start) = rest.find(message_index::HIGHLIGHT_START) {
+        snippet.push_str(&rest[..start]);
+        rest = &rest[start + message_index::HIGHLIGHT_START.len_utf8()..];
+        let end = rest
+            .find(message_index::HIGHLIGHT_END)
+            .unwrap_or(rest.len());
+        snippet.push_str(&rest[..end].yellow().bold().to_string());
+        rest = rest[end..]
+            .strip_prefix(message_index::HIGHLIGHT_END)
+            .unwrap_or_default();
+    }
+    snippet.push_str(rest);
+    format!(
+        "[{}] {} {}: {}",
+        room_name,
+        helper_functions::format_message_time(hit.sent_at, now).dimmed(),
+        hit.sender_name,
+        snippet
+    )
+}
+
+/// Reads a room password without echoing it and returns its hash, None if the user left it blank
+/// <br>The password is hashed like the passwords of users, so the server never sees it in plaintext
+fn read_room_password() -> Option<String> {
+    let password = read_password().expect("Error while reading password");
+    let password = password.trim();
+    if password.is_empty() {
+        None
+    } else {
+        Some(login::hash_password(password))
+    }
+}
+
+fn get_member_ids_by_selection(friends: &[User], selection: &[usize]) -> Vec<u32> {
+    selection
+        .iter()
+        .filter_map(|index| friends.get(*index))
+        .map(|friend| friend.get_id())
+        .collect()
+}
+
+/// Method for letting the user select one of the other members of a group chat
+/// <br>In a chatroom of two friends the other member is returned right away
+fn select_other_member(chatroom: &ChatRoom, current_user: &User) -> Option<User> {
+    let other_members = chatroom.get_other_members(current_user.get_id());
+    if other_members.len() <= 1 {
+        return other_members.into_iter().next();
+    }
+    let mut list_of_choices = other_members
+        .iter()
+        .map(|member| member.get_name().clone())
+        .collect::<Vec<String>>();
+    list_of_choices.push("Go back".to_string());
+    let selection = helper_functions::display_multiple_choices(
+        "Select the member you want to verify:",
+        list_of_choices,
+        true,
+    );
+    other_members.get(selection).cloned()
+}
+
+/// Method for comparing the safety number of a chat partner and marking the partner as verified
+/// <br>Both users see the same number if the server did not replace one of the keys
+async fn verify_contact(connection: &mut ServerConnection, current_user: &User) {
+    let chatroom = match joining_an_existing_chatroom(connection, current_user).await {
+        Some(chatroom) => chatroom,
+        None => return,
+    };
+    let other_user = match select_other_member(&chatroom, current_user) {
+        Some(other_user) => other_user,
+        None => return,
+    };
+    let other_user_id = other_user.get_id();
+
+    let key_pair = key_store::load_identity_key(&key_store::get_identity_key_file_path(
+        current_user.get_name(),
+    ));
+    let other_public_key = connection.get_identity_key(other_user_id).await;
+    let (key_pair, other_public_key) = match (key_pair, other_public_key) {
+        (Some(key_pair), Ok(Some(other_public_key))) => (key_pair, other_public_key),
+        (None, _) => {
+            _ = helper_functions::print_error("Your identity key is missing. Please log in again.");
+            sleep(Duration::from_secs(2));
+            return;
+        }
+        (_, Ok(None)) => {
+            _ = helper_functions::print_error(&format!(
+                "{} has no identity key yet.",
+                other_user.get_name()
+            ));
+            sleep(Duration::from_secs(2));
+            return;
+        }
+        (_, Err(e)) => {
+            eprintln!("Error loading the identity key: {}", e);
+            return;
+        }
+    };
+
+    let verified_contacts_file =
+        key_store::get_verified_contacts_file_path(current_user.get_name());
+    let verified_key = key_store::load_verified_key(&verified_contacts_file, other_user_id);
+    match &verified_key {
+        Some(verified_key) if *verified_key == other_public_key => {
+            _ = helper_functions::print_confirmation("You already verified this contact.")
+        }
+        Some(_) => {
+            _ = helper_functions::print_error(
+                "The key of this contact has changed since you verified it!",
+            )
+        }
+        None => _ = helper_functions::print_info("You did not verify this contact yet."),
+    }
+
+    let safety_number =
+        crypto::get_safety_number(&key_pair.get_public_key_hex(), &other_public_key);
+    _ = helper_functions::print_info(&format!(
+        "Safety number for {}:\n{}\nCompare it with the number on your friend's device, in person or over another trusted channel.",
+        other_user.get_name(),
+        safety_number
+    ));
+
+    if verified_key.as_ref() != Some(&other_public_key)
+        && helper_functions::ask_user_yes_no_question(
+            "Does the safety number match the one on your friend's device?",
+        )
+    {
+        match key_store::save_verified_key(
+            &verified_contacts_file,
+            other_user_id,
+            &other_public_key,
+        ) {
+            Ok(_) => _ = helper_functions::print_confirmation("Contact marked as verified."),
+            Err(e) => _ = helper_functions::print_error(&format!("{}", e)),
+        }
+    }
+    sleep(Duration::from_secs(2));
+}
+
+/// Method for listing the active sessions of the current user and revoking one of them
+async fn manage_sessions(connection: &mut ServerConnection) {
+    loop {
+        _ = helper_functions::print_info("Please wait while we search for your sessions...");
+        let sessions = match connection.get_sessions().await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                eprintln!("Error searching for sessions: {}", e);
+                return;
+            }
+        };
+
+        let selected_session = match select_session_to_revoke(&sessions) {
+            Some(session) => session,
+            None => return,
+        };
+
+        if selected_session.is_current() {
+            _ = helper_functions::print_info(
+                "This is the session of this device. Use \"Log out\" to end it.",
+            );
+            sleep(Duration::from_secs(2));
+            continue;
+        }
+
+        let should_revoke = helper_functions::ask_user_yes_no_question(&format!(
+            "Do you want to log out {}?",
+            selected_session.get_device_name()
+        ));
+        if should_revoke {
+            match connection.revoke_session(selected_session.get_id()).await {
+                Ok(_) => _ = helper_functions::print_confirmation("Session was ended."),
+                Err(e) => _ = helper_functions::print_error(&format!("{}", e)),
+            }
+            sleep(Duration::from_secs(2));
+        }
+    }
+}
+
+/// Method for letting the user select one of the sessions
+/// <br>Returns None if the user wants to go back
+fn select_session_to_revoke(sessions: &[SessionInfo]) -> Option<SessionInfo> {
+    let now = helper_functions::get_sys_time_in_secs();
+    let mut list_of_choices = sessions
+        .iter()
+        .map(|session| session.describe(now))
+        .collect::<Vec<String>>();
+    list_of_choices.push("Go back".to_string());
+    let selection = helper_functions::display_multiple_choices(
+        "Select a session to log it out:",
+        list_of_choices,
+        true,
+    );
+    sessions.get(selection).cloned()
+}
+
 /// Method to search for a friend by username
 /// <br>Returns the user if found, None otherwise
 fn select_friend_for_list_of_users(list_of_users: Vec<User>) -> Option<User> {
@@ -197,9 +828,12 @@ fn get_user_by_selection(users: Vec<User>, selection: usize) -> Option<User> {
 }
 
 /// Method for listing all previous chatrooms for the current user and asking the user to select one
-async fn joining_an_existing_chatroom(current_user: User) -> Option<ChatRoom> {
+async fn joining_an_existing_chatroom(
+    connection: &mut ServerConnection,
+    current_user: &User,
+) -> Option<ChatRoom> {
     _ = helper_functions::print_info("Please wait while we search for your chatrooms...");
-    let user_chatrooms = sql_interaction::search_for_chatrooms_of_user(current_user.get_id()).await;
+    let user_chatrooms = connection.search_for_chatrooms().await;
     _ = helper_functions::clear_console();
 
     match user_chatrooms {
@@ -211,9 +845,34 @@ async fn joining_an_existing_chatroom(current_user: User) -> Option<ChatRoom> {
                 sleep(Duration::from_secs(2));
                 None
             } else {
+                // The presence of new friends is not known yet, afterwards the server sends every change
+                let is_presence_missing = chatrooms
+                    .iter()
+                    .filter(|chatroom| !chatroom.is_group_chat())
+                    .flat_map(|chatroom| chatroom.get_other_members(current_user.get_id()))
+                    .any(|member| connection.get_presence(member.get_id()).is_none());
+                if is_presence_missing {
+                    if let Err(e) = connection.load_presence_of_friends().await {
+                        eprintln!("Error loading the presence of friends: {}", e);
+                    }
+                }
+
+                let unread_counts = match connection.get_unread_counts().await {
+                    Ok(unread_counts) => unread_counts,
+                    Err(e) => {
+                        eprintln!("Error loading unread messages: {}", e);
+                        HashMap::new()
+                    }
+                };
+
+                let now = helper_functions::get_sys_time_in_secs();
                 let mut list_of_choices = chatrooms
                     .iter()
-                    .map(|chatroom| chatroom.get_name().clone())
+                    .map(|chatroom| {
+                        let unread_count =
+                            unread_counts.get(chatroom.get_id()).copied().unwrap_or(0);
+                        describe_chatroom(connection, chatroom, current_user, unread_count, now)
+                    })
                     .collect::<Vec<String>>();
                 // list_of_choices.push("Refresh".to_string());
                 list_of_choices.push("Go back".to_string());
@@ -234,6 +893,48 @@ async fn joining_an_existing_chatroom(current_user: User) -> Option<ChatRoom> {
     }
 }
 
+/// Returns the line that is shown for the chatroom when joining an existing chatroom
+/// <br>Chatrooms of two friends show the presence of the friend, group chats how many other members are online.
+/// Unread messages are counted behind that
+fn describe_chatroom(
+    connection: &ServerConnection,
+    chatroom: &ChatRoom,
+    current_user: &User,
+    unread_count: u32,
+    now: u64,
+) -> String {
+    let other_members = chatroom.get_other_members(current_user.get_id());
+    let mut description = if chatroom.is_group_chat() {
+        let online_count = other_members
+            .iter()
+            .filter_map(|member| connection.get_presence(member.get_id()))
+            .filter(|presence| presence.get_status() != PresenceStatus::Offline)
+            .count();
+        format!("{} ({} online)", chatroom.get_name(), online_count)
+    } else {
+        match other_members
+            .first()
+            .and_then(|member| connection.get_presence(member.get_id()))
+        {
+            Some(presence) => {
+                let presence_description = presence.describe(now);
+                let presence_description = match presence.get_status() {
+                    PresenceStatus::Online => presence_description.green(),
+                    PresenceStatus::Away => presence_description.yellow(),
+                    PresenceStatus::Offline => presence_description.dimmed(),
+                };
+                format!("{} {}", chatroom.get_name(), presence_description)
+            }
+            None => chatroom.get_name().clone(),
+        }
+    };
+    if unread_count > 0 {
+        let unread = format!("({} new)", unread_count);
+        description = format!("{} {}", description, unread.bold());
+    }
+    description
+}
+
 async fn joining_existing_chatroom_selection(
     selected_index: usize,
     chatrooms: Vec<ChatRoom>,
@@ -282,13 +983,14 @@ fn list_all_friend_requests(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{TestServer, TestStorageBuilder};
 
     /// Test if user selected a chatroom in the "List all previous chats" menu
     #[tokio::test]
     async fn test_previous_chat_selection_chatroom_got_selected() {
         let dummy_chatrooms = vec![
-            ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2),
-            ChatRoom::new(2, "Chatroom 2".to_string(), 2, 3),
+            ChatRoom::new(1, "Chatroom 1".to_string(), Vec::new()),
+            ChatRoom::new(2, "Chatroom 2".to_string(), Vec::new()),
         ];
         let res = joining_existing_chatroom_selection(0, dummy_chatrooms).await;
 
@@ -300,8 +1002,8 @@ mod tests {
     #[tokio::test]
     async fn test_previous_chat_selection_go_to_chat_menu() {
         let dummy_chatrooms = vec![
-            ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2),
-            ChatRoom::new(2, "Chatroom 2".to_string(), 2, 3),
+            ChatRoom::new(1, "Chatroom 1".to_string(), Vec::new()),
+            ChatRoom::new(2, "Chatroom 2".to_string(), Vec::new()),
         ];
         let res = joining_existing_chatroom_selection(2, dummy_chatrooms).await;
         assert!(res.is_none());
@@ -338,10 +1040,96 @@ mod tests {
         get_user_by_selection(users, 3);
     }
 
+    #[test]
+    fn test_get_member_ids_by_selection() {
+        let friends = vec![
+            User::new(1, "Alice".to_string()),
+            User::new(2, "Bob".to_string()),
+            User::new(5, "Carol".to_string()),
+        ];
+        assert_eq!(get_member_ids_by_selection(&friends, &[0, 2]), vec![1, 5]);
+        assert!(get_member_ids_by_selection(&friends, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_select_other_member_of_two_person_chat() {
+        let anton = User::new(1, "anton".to_string());
+        let rino = User::new(2, "rino".to_string());
+        let chatroom = ChatRoom::new(
+            1,
+            "Chatroom 1".to_string(),
+            vec![anton.clone(), rino.clone()],
+        );
+        assert_eq!(select_other_member(&chatroom, &anton), Some(rino));
+    }
+
+    #[test]
+    fn test_format_login_summary() {
+        assert_eq!(format_login_summary(0, 0, 0), None);
+        assert_eq!(
+            format_login_summary(1, 1, 0),
+            Some("While you were away: 1 new message in 1 chatroom.".to_string())
+        );
+        assert_eq!(
+            format_login_summary(5, 2, 3),
+            Some(
+                "While you were away: 5 new messages in 2 chatrooms and 3 open friend requests."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            format_login_summary(0, 0, 1),
+            Some("While you were away: 1 open friend request.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_get_default_export_path() {
+        let chatroom = ChatRoom::new(3, "anton, rino".to_string(), Vec::new());
+        assert_eq!(
+            get_default_export_path(&chatroom, ExportFormat::Json),
+            PathBuf::from("chat_3_anton__rino.json")
+        );
+        assert_eq!(
+            get_default_export_path(&chatroom, ExportFormat::Transcript),
+            PathBuf::from("chat_3_anton__rino.txt")
+        );
+    }
+
+    #[test]
+    fn test_format_search_hit() {
+        let sent_at = helper_functions::get_sys_time_in_secs();
+        let hit = SearchHit {
+            message_id: 4,
+            chatroom_id: 1,
+            sender_name: "rino".to_string(),
+            sent_at,
+            snippet: "\u{2}Treffen\u{3} wir uns \u{2}morgen\u{3}?".to_string(),
+        };
+        assert_eq!(
+            format_search_hit(&hit, "anton, rino", sent_at),
+            format!(
+                "[anton, rino] {} rino: {} wir uns {}?",
+                helper_functions::format_message_time(sent_at, sent_at).dimmed(),
+                "Treffen".yellow().bold(),
+                "morgen".yellow().bold()
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_joining_chatroom_with_user_having_no_existing_chatrooms() {
-        let user = User::new(4, "antonia".to_string());
-        let res = joining_an_existing_chatroom(user.clone()).await;
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .user("antonia")
+            .friends("rino", "anton")
+            .chat_room("rino and anton's chat", &["rino", "anton"])
+            .build();
+        let server = TestServer::start(storage).await;
+        let (mut connection, antonia) = server.login("antonia").await;
+
+        let res = joining_an_existing_chatroom(&mut connection, &antonia).await;
         assert!(res.is_none());
     }
 }
@@ -1,142 +1,1086 @@
+use crate::chat_export::{ChatExport, ExportFormat, ExportedMessage};
+use crate::chat_input::{ChatInput, InputEvent, PrintedLine, TypingMembers, TypingSignal};
 use crate::chat_menu;
+use crate::config::ClientConfig;
+use crate::crypto::{self, RoomKey};
 use crate::helper_functions;
+use crate::key_store;
 use crate::login;
+use crate::message_index::{self, MessageIndex};
+use crate::protocol::{ModerationAction, Payload};
+use crate::ratchet::{self, RatchetSession};
+use crate::server_connection::ServerConnection;
+use crate::session_store;
 use crate::structs::chat_room::ChatRoom;
+use crate::structs::message::ChatMessage;
+use crate::structs::receipt::{Receipt, ReceiptStatus};
+use crate::structs::room_role::RoomRole;
 use crate::structs::user::User;
-use colored::Colorize;
-use crossterm::terminal::Clear;
-use futures_util::stream::StreamExt;
-use futures_util::SinkExt;
+use crate::tls::{self, BoxedStream};
+use colored::{ColoredString, Colorize};
+use crossterm::event::EventStream;
+use futures_util::StreamExt;
 use http::Uri;
-use std::io::Write;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio_websockets::{ClientBuilder, MaybeTlsStream, Message, WebSocketStream};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_websockets::{ClientBuilder, WebSocketStream};
 
 pub enum ClientState {
     AuthenticationMenu,
     ChatMenu(User),
-    ChatRoom(User, ChatRoom),
+    /// Chat view of the chatroom, the history is shown up to the message with the id or up to the newest message
+    ChatRoom(User, ChatRoom, Option<u32>),
+    /// Results of searching the messages for the words
+    MessageSearch(User, String),
     Exit,
 }
 
 /// Method for the client to authenticate with the server
-async fn authenticate() -> Result<ClientState, Box<dyn std::error::Error>> {
-    let mut current_user: Option<User>;
-    loop {
-        current_user = login::start_authentication_process_for_client().await;
-        match current_user.clone() {
-            None => {
-                continue;
-            }
-            Some(_user) => {
-                break;
+/// <br>Afterwards the identity key of the user is published, so that friends can send encrypted messages
+async fn authenticate(
+    connection: &mut ServerConnection,
+) -> Result<ClientState, Box<dyn std::error::Error>> {
+    // Try to log in with the session of an earlier start first
+    let current_user = match login::login_with_stored_session(connection).await {
+        Some(user) => user,
+        None => loop {
+            if let Some(user) = login::start_authentication_process_for_client(connection).await {
+                break user;
             }
-        }
-    }
-    Ok(ClientState::ChatMenu(current_user.unwrap().clone()))
+        },
+    };
+
+    key_store::ensure_identity_key(connection, &current_user).await?;
+    Ok(ClientState::ChatMenu(current_user))
 }
 
 // Method for the client to select a chat room
-async fn client_main_menu(current_user: User) -> Result<ClientState, Box<dyn std::error::Error>> {
-    let selected_chatroom = chat_menu::chat_menu_loop(current_user.clone()).await;
+async fn client_main_menu(
+    connection: &mut ServerConnection,
+    current_user: User,
+    is_new_login: bool,
+) -> Result<ClientState, Box<dyn std::error::Error>> {
+    let selected_chatroom =
+        chat_menu::chat_menu_loop(connection, current_user.clone(), is_new_login).await;
     match selected_chatroom {
-        Some(chatroom) => Ok(ClientState::ChatRoom(current_user, chatroom)),
+        Some((chatroom, message_id)) => {
+            Ok(ClientState::ChatRoom(current_user, chatroom, message_id))
+        }
         None => Ok(ClientState::AuthenticationMenu),
     }
 }
 
+// Method for the client to search the messages and open the chatroom of the selected one
+async fn message_search(
+    connection: &mut ServerConnection,
+    current_user: User,
+    search: String,
+) -> Result<ClientState, Box<dyn std::error::Error>> {
+    match chat_menu::search_messages(connection, &current_user, Some(search)).await {
+        Some((chatroom, message_id)) => Ok(ClientState::ChatRoom(
+            current_user,
+            chatroom,
+            Some(message_id),
+        )),
+        None => Ok(ClientState::ChatMenu(current_user)),
+    }
+}
+
 /// Method for the client to join a chat room and chat with a friend
+/// <br>A message that was found by a search is shown as the newest message of the history
 async fn chat_room(
+    connection: &ServerConnection,
     current_user: User,
     selected_chatroom: ChatRoom,
+    found_message_id: Option<u32>,
 ) -> Result<ClientState, Box<dyn std::error::Error>> {
-    let mut ws_stream = create_websocket_connection().await?;
+    let mut chat_connection = connection.open_chat_connection().await?;
 
     // CLear the terminal screen and print the chat room name
     _ = helper_functions::clear_console();
 
+    // Messages are encrypted with keys only the members of the chatroom can derive
+    let (mut chat_keys, other_public_keys) =
+        match get_chat_keys(&mut chat_connection, &current_user, &selected_chatroom).await {
+            Ok(result) => result,
+            Err(e) => {
+                _ = helper_functions::print_error(&e.to_string());
+                return Ok(ClientState::ChatMenu(current_user));
+            }
+        };
+    for (other_user, other_public_key) in &other_public_keys {
+        if !check_verified_key(&current_user, other_user, other_public_key) {
+            return Ok(ClientState::ChatMenu(current_user));
+        }
+    }
+
     // Print the welcoming message
     let welcome_msg = format!(
         "Welcome to the chat room: {}.",
         selected_chatroom.get_name()
     );
     println!("{}", welcome_msg.bold().green());
-    let info_msg = "Type a message and press ENTER to send.\n(leave blank to return)";
+    if selected_chatroom.is_group_chat() {
+        let member_names = selected_chatroom
+            .get_members()
+            .iter()
+            .map(|member| member.get_name().clone())
+            .collect::<Vec<String>>();
+        println!(
+            "{}",
+            format!("Members: {}", member_names.join(", ")).yellow()
+        );
+    }
+    let info_msg = "Type a message and press ENTER to send.\n(leave blank to return, /more or PAGE UP shows older messages, /search <words> searches all chats)\n/edit <#> <text> and /delete <#> change your messages of the last day, /history <#> shows what an edited message said before";
     println!("{}", info_msg.yellow());
+    if selected_chatroom.is_group_chat() {
+        println!("{}", MODERATION_COMMANDS_HELP.yellow());
+    }
 
-    // Restore chat history
-    _ = selected_chatroom
-        .clone()
-        .print_chat_history(current_user.get_name().clone())
-        .await;
-
-    // Send the chat room id to the server
-    ws_stream
-        .send(Message::text(selected_chatroom.get_id().to_string()))
+    // Join the chat room and ask for its history
+    chat_connection
+        .join_chat_room(*selected_chatroom.get_id())
+        .await?;
+    chat_connection
+        .request_history(
+            *selected_chatroom.get_id(),
+            found_message_id.map(|message_id| message_id + 1),
+        )
         .await?;
+    let mut found_message_id = found_message_id;
 
     // Main loop for chat room
-    let stdin = tokio::io::stdin();
-    let mut stdin = BufReader::new(stdin).lines();
+    // Single key presses are read, so that the other members can be told when the user is typing
+    let chatroom_id = *selected_chatroom.get_id();
+    let mut key_events = EventStream::new();
+    let mut typing_signal = TypingSignal::default();
+    let mut typing_members = TypingMembers::default();
+    let mut typing_check = tokio::time::interval(Duration::from_secs(1));
+    let mut input = ChatInput::new(format!(
+        "{}: ",
+        current_user.get_name().clone().bold().purple()
+    ))?;
+    let mut own_messages = OwnMessages::new(current_user.get_name());
+    // Lines of the messages of the other members, so that they can be changed when a message is edited or deleted
+    let mut other_lines: HashMap<u32, PrintedLine> = HashMap::new();
+    // Ids of the messages of the other members by their number in the chatroom, for /history
+    let mut other_numbers: HashMap<u32, u32> = HashMap::new();
+    // Messages that arrived while the terminal had no focus are read when it gets the focus back
+    let mut is_focused = true;
+    let mut unread_ids: Vec<u32> = Vec::new();
+    let mut scrollback = Scrollback::default();
 
     loop {
         tokio::select! {
-            incoming = ws_stream.next() => {
-                match incoming {
-                    Some(Ok(msg)) => {
-                        if let Some(text) = msg.as_text() {
-                            // Handle incoming message
-                            crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveToColumn(0), Clear(crossterm::terminal::ClearType::CurrentLine)).unwrap();
-
-                            // Split msg into username and message
-                            let split_msg: Vec<&str> = text.splitn(2, ": ").collect();
-                            let username = split_msg[0];
-                            let message = split_msg[1];
-                            println!("{}: {}", username.bold().cyan(), message);
-                        }
-                    },
-                    Some(Err(err)) => return Err(err.into()),
+            incoming = chat_connection.receive() => {
+                match incoming? {
+                    Some(Payload::MemberTyping { username, is_typing }) => {
+                        typing_members.update(&username, is_typing, helper_functions::get_sys_time_in_secs());
+                        input.set_typing_note(typing_members.describe())?;
+                    }
+                    Some(Payload::ChatMessage(msg)) => {
+                        let text = chat_keys.decrypt(&msg.get_content());
+                        index_message(&chat_keys.message_index, &msg, &text);
+                        // Messages of other devices of the user are shown as own messages
+                        if msg.get_sender_id() == Some(current_user.get_id()) {
+                            own_messages.print_sent(&mut input, &msg, &text, ReceiptStatus::Stored)?;
+                            continue;
+                        }
+                        typing_members.remove(msg.get_sender_name());
+                        input.set_typing_note(typing_members.describe())?;
+                        let line = input.print_line(&format_message(&msg, &text))?;
+                        // The sender learns that the message arrived, and if it was seen
+                        if let Some(message_id) = msg.get_id() {
+                            other_lines.insert(message_id, line);
+                            if let Some(number) = msg.get_room_message_id() {
+                                other_numbers.insert(number, message_id);
+                            }
+                            let status = if is_focused {
+                                ReceiptStatus::Read
+                            } else {
+                                unread_ids.push(message_id);
+                                ReceiptStatus::Delivered
+                            };
+                            chat_connection.send_receipt(Receipt::new(message_id, status)).await?;
+                        }
+                    }
+                    Some(Payload::MessageStored(msg)) => {
+                        if let Some(text) = own_messages.confirm(&mut input, &msg)? {
+                            index_message(&chat_keys.message_index, &msg, &text);
+                        }
+                    }
+                    Some(Payload::Receipt(receipt)) => {
+                        own_messages.update(&mut input, receipt)?;
+                    }
+                    Some(Payload::MessageChanged(msg)) => {
+                        let text = chat_keys.decrypt(&msg.get_content());
+                        index_message(&chat_keys.message_index, &msg, &text);
+                        if msg.get_sender_id() == Some(current_user.get_id()) {
+                            own_messages.change(&mut input, &msg, &text)?;
+                        } else if let Some(line) = msg.get_id().and_then(|message_id| other_lines.get(&message_id)) {
+                            input.update_line(*line, &format_message(&msg, &text))?;
+                        }
+                    }
+                    Some(Payload::EditHistory { edits, .. }) => {
+                        // The earlier contents are encrypted like the message itself
+                        if edits.is_empty() {
+                            input.print_line(&"The message was not edited.".dimmed().to_string())?;
+                        }
+                        let now = helper_functions::get_sys_time_in_secs();
+                        for edit in edits {
+                            let text = chat_keys.decrypt(edit.get_content());
+                            let time = helper_functions::format_message_time(edit.get_replaced_at(), now);
+                            input.print_line(&format!("  until {}: {}", time, text).dimmed().to_string())?;
+                        }
+                    }
+                    Some(Payload::History { messages, receipts, has_more }) => {
+                        // Restore chat history, the server marks it as read
+                        // Older pages can only be printed below the newer messages, so they are framed
+                        let is_older_page = scrollback.page_loaded(&messages, has_more);
+                        if is_older_page {
+                            input.print_line(&"── older messages ──".dimmed().to_string())?;
+                        }
+                        for message in messages {
+                            let text = chat_keys.decrypt(&message.get_content());
+                            index_message(&chat_keys.message_index, &message, &text);
+                            match message.get_id() {
+                                Some(message_id) if message.get_sender_id() == Some(current_user.get_id()) => {
+                                    let status = receipts
+                                        .iter()
+                                        .find(|receipt| receipt.get_message_id() == message_id)
+                                        .map_or(ReceiptStatus::Stored, |receipt| receipt.get_status());
+                                    own_messages.print_sent(&mut input, &message, &text, status)?;
+                                }
+                                Some(message_id) => {
+                                    let line = input.print_line(&format_message(&message, &text))?;
+                                    other_lines.insert(message_id, line);
+                                    if let Some(number) = message.get_room_message_id() {
+                                        other_numbers.insert(number, message_id);
+                                    }
+                                }
+                                None => _ = input.print_line(&format_message(&message, &text))?,
+                            }
+                        }
+                        if is_older_page {
+                            input.print_line(&"── end of older messages ──".dimmed().to_string())?;
+                        } else if found_message_id.take().is_some() {
+                            input.print_line(&"↑ Search result, join the chat again for newer messages.".dimmed().to_string())?;
+                        } else if has_more {
+                            input.print_line(&"Type /more or press PAGE UP for older messages.".dimmed().to_string())?;
+                        }
+                    }
+                    Some(Payload::Error { message }) => {
+                        input.print_line(&message.red().to_string())?;
+                    }
+                    Some(Payload::Announcement { message }) => {
+                        input.print_line(&message.italic().yellow().to_string())?;
+                    }
+                    Some(Payload::Removed { message }) => {
+                        input.hide()?;
+                        _ = helper_functions::print_error(&message);
+                        return Ok(ClientState::ChatMenu(current_user));
+                    }
+                    Some(_) => {}
                     None => return Ok(ClientState::Exit), // Connection closed
                 }
             }
-            res = {
-                print!("{}: ", current_user.get_name().clone().bold().purple());
-                std::io::stdout().flush().unwrap();
-                stdin.next_line()
-            } => {
-                match res {
-                    Ok(None) => return Ok(ClientState::Exit),
-                    Ok(Some(line)) => {
+            event = key_events.next() => {
+                let Some(event) = event else {
+                    return Ok(ClientState::Exit);
+                };
+                match input.handle_event(event?)? {
+                    Some(InputEvent::Edited) => {
+                        if let Some(is_typing) = typing_signal.edited(input.get_text(), helper_functions::get_sys_time_in_secs()) {
+                            chat_connection.send_typing(chatroom_id, is_typing).await?;
+                        }
+                    }
+                    Some(InputEvent::Submitted(line)) => {
+                        if let Some(is_typing) = typing_signal.stop() {
+                            chat_connection.send_typing(chatroom_id, is_typing).await?;
+                        }
                         if line.is_empty() {
                             return Ok(ClientState::ChatMenu(current_user));
                         }
-                        let formatted_msg = format!("{}: {}", current_user.get_name(), line);
-                        ws_stream.send(Message::text(formatted_msg)).await?;
+                        if line == "/search" || line.starts_with("/search ") {
+                            let search = line["/search".len()..].trim().to_string();
+                            input.hide()?;
+                            return Ok(ClientState::MessageSearch(current_user, search));
+                        }
+                        if line == "/more" {
+                            load_older_messages(&mut chat_connection, &mut input, &mut scrollback, chatroom_id).await?;
+                            continue;
+                        }
+                        match parse_message_command(&line) {
+                            Some(Ok(command)) => {
+                                input.print_line(&format!("{}: {}", current_user.get_name().bold().purple(), line))?;
+                                let room_message_id = command.get_room_message_id();
+                                let own_message_id = own_messages.get_message_id(room_message_id);
+                                match (command, own_message_id) {
+                                    (MessageCommand::History { .. }, own_message_id) => {
+                                        match own_message_id.or_else(|| other_numbers.get(&room_message_id).copied()) {
+                                            Some(message_id) => chat_connection.request_edit_history(chatroom_id, message_id).await?,
+                                            None => _ = input.print_line(&"There is no message with this number in this chat view.".red().to_string())?,
+                                        }
+                                    }
+                                    (_, None) => {
+                                        input.print_line(&"Only your own messages in this chat view can be changed.".red().to_string())?;
+                                    }
+                                    (MessageCommand::Edit { text, .. }, Some(message_id)) => {
+                                        if chat_keys.sessions.is_empty() {
+                                            input.print_line(&"Nobody else has joined this room yet. Please try again later.".yellow().to_string())?;
+                                            continue;
+                                        }
+                                        let content = chat_keys.encrypt(&text);
+                                        chat_connection.edit_message(chatroom_id, message_id, content).await?;
+                                    }
+                                    (MessageCommand::Delete { .. }, Some(message_id)) => chat_connection.delete_message(chatroom_id, message_id).await?,
+                                }
+                                continue;
+                            }
+                            Some(Err(message)) => {
+                                input.print_line(&message.red().to_string())?;
+                                continue;
+                            }
+                            None => {}
+                        }
+                        // Other lines starting with a slash are commands for the moderation of the chatroom
+                        if line.starts_with('/') {
+                            input.print_line(&format!("{}: {}", current_user.get_name().bold().purple(), line))?;
+                            match parse_moderation_command(&line) {
+                                Ok(action) => chat_connection.send_moderation(chatroom_id, action).await?,
+                                Err(message) => _ = input.print_line(&message.red().to_string())?,
+                            }
+                            continue;
+                        }
+                        // Nobody could read a message in a public room without other members
+                        if chat_keys.sessions.is_empty() {
+                            input.print_line(&"Nobody else has joined this room yet. Please try again later.".yellow().to_string())?;
+                            continue;
+                        }
+                        let content = chat_keys.encrypt(&line);
+                        own_messages.print_pending(&mut input, &line, &content)?;
+                        let chat_msg = ChatMessage::new(chatroom_id, current_user.get_name().clone(), content);
+                        chat_connection.send_chat_message(chat_msg).await?;
+                    }
+                    Some(InputEvent::FocusChanged(focused)) => {
+                        is_focused = focused;
+                        if is_focused {
+                            for message_id in unread_ids.drain(..) {
+                                chat_connection.send_receipt(Receipt::new(message_id, ReceiptStatus::Read)).await?;
+                            }
+                        }
+                    }
+                    Some(InputEvent::ScrollBack) => {
+                        load_older_messages(&mut chat_connection, &mut input, &mut scrollback, chatroom_id).await?;
                     }
-                    Err(err) => return Err(err.into()),
+                    Some(InputEvent::Closed) => return Ok(ClientState::Exit),
+                    None => {}
                 }
             }
+            _ = typing_check.tick() => {
+                let now = helper_functions::get_sys_time_in_secs();
+                if let Some(is_typing) = typing_signal.tick(now) {
+                    chat_connection.send_typing(chatroom_id, is_typing).await?;
+                }
+                typing_members.expire(now);
+                input.set_typing_note(typing_members.describe())?;
+            }
+        }
+    }
+}
+
+/// Exports the decrypted history of the chatroom to the file
+/// <br>The history is loaded page by page like in the chat view, but the messages stay unread.
+/// Returns the number of exported messages
+pub async fn export_chat(
+    connection: &ServerConnection,
+    current_user: &User,
+    chatroom: &ChatRoom,
+    format: ExportFormat,
+    path: &Path,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut chat_connection = connection.open_chat_connection().await?;
+    let (mut chat_keys, _) = get_chat_keys(&mut chat_connection, current_user, chatroom).await?;
+    let chatroom_id = *chatroom.get_id();
+    chat_connection.join_chat_room(chatroom_id).await?;
 
+    // The pages come from the newest to the oldest message
+    let mut pages = Vec::new();
+    let mut before_message_id = None;
+    loop {
+        chat_connection
+            .request_history_without_reading(chatroom_id, before_message_id)
+            .await?;
+        let (messages, has_more) = loop {
+            match chat_connection.receive().await? {
+                Some(Payload::History {
+                    messages, has_more, ..
+                }) => break (messages, has_more),
+                Some(Payload::Error { message }) => return Err(message.into()),
+                Some(_) => {}
+                None => return Err("The server closed the connection.".into()),
+            }
+        };
+        before_message_id = messages.first().and_then(|message| message.get_id());
+        pages.push(messages);
+        if !has_more || before_message_id.is_none() {
+            break;
         }
     }
+
+    // Messages are decrypted in the order they were written, like in the chat view
+    let messages = pages
+        .iter()
+        .rev()
+        .flatten()
+        .filter_map(|message| {
+            let text = chat_keys.decrypt(&message.get_content());
+            ExportedMessage::from_message(message, text)
+        })
+        .collect::<Vec<ExportedMessage>>();
+    let export = ChatExport::new(chatroom, &[], messages, true);
+    // The export contains the plaintexts, so only the current user may read it
+    session_store::write_private_file(path, &export.render(format)?)?;
+    Ok(export.messages.len())
 }
 
-/// Method for creating the
-async fn create_websocket_connection(
-) -> Result<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>, Box<dyn std::error::Error>> {
-    let (ws_stream, _) = ClientBuilder::from_uri(Uri::from_static("ws://127.0.0.1:8000"))
-        .connect()
-        .await?;
+/// Adds a message to the search index, messages that could not be decrypted are left out
+/// <br>Deleted messages are removed from the index with their plaintexts, edited messages are found by their new text
+/// and their earlier texts are removed
+fn index_message(message_index: &MessageIndex, message: &ChatMessage, text: &str) {
+    if message.is_deleted() {
+        if let Some(message_id) = message.get_id() {
+            _ = message_index.remove(message_id);
+        }
+    } else if message.get_edited_at().is_some() {
+        _ = message_index.remove_earlier_contents(message);
+    }
+    if text != UNDECRYPTABLE_MESSAGE && !message.is_deleted() {
+        // A message that is missing in the index can not be found, but the chat goes on
+        _ = message_index.add(message, text);
+    }
+}
+
+/// Asks for the page of the history before the oldest message that is shown
+async fn load_older_messages(
+    chat_connection: &mut ServerConnection,
+    input: &mut ChatInput,
+    scrollback: &mut Scrollback,
+    chatroom_id: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match scrollback.start_loading() {
+        Some(before_message_id) => {
+            chat_connection
+                .request_history(chatroom_id, Some(before_message_id))
+                .await?
+        }
+        None if !scrollback.is_loading => {
+            input.print_line(&"There are no older messages.".dimmed().to_string())?;
+        }
+        // The page that is already asked for is still on its way
+        None => {}
+    }
+    Ok(())
+}
+
+/// How far the history in the chat view goes back, older pages are loaded on demand
+#[derive(Default)]
+struct Scrollback {
+    /// Id of the oldest message that was loaded, None before a page with messages arrived
+    oldest_message_id: Option<u32>,
+    has_more: bool,
+    /// True while an older page is asked for, so that it is not asked for twice
+    is_loading: bool,
+}
+
+impl Scrollback {
+    /// Returns the message id the next older page ends before, None if there is nothing to load
+    fn start_loading(&mut self) -> Option<u32> {
+        if self.is_loading || !self.has_more {
+            return None;
+        }
+        let before_message_id = self.oldest_message_id?;
+        self.is_loading = true;
+        Some(before_message_id)
+    }
+
+    /// Remembers where a page of the history begins
+    /// <br>Returns true if it is an older page, false for the newest page that is sent on joining
+    fn page_loaded(&mut self, messages: &[ChatMessage], has_more: bool) -> bool {
+        let is_older_page = std::mem::take(&mut self.is_loading);
+        if let Some(first_id) = messages.first().and_then(|message| message.get_id()) {
+            self.oldest_message_id = Some(first_id);
+        }
+        self.has_more = has_more;
+        is_older_page
+    }
+}
+
+/// Line of a message of another member, with the time the server saved it in front
+/// <br>Edited messages show their number in the chatroom, which is used to read their edit history
+fn format_message(message: &ChatMessage, text: &str) -> String {
+    let time = format_sent_at(message.get_sent_at());
+    let mut text = format_text(message, text);
+    if let (Some(_), Some(number), false) = (
+        message.get_edited_at(),
+        message.get_room_message_id(),
+        message.is_deleted(),
+    ) {
+        text = format!("{} {}", text, format!("#{}", number).dimmed());
+    }
+    if message.get_sender_name().is_empty() {
+        return format!("{} {}", time, text);
+    }
+    format!(
+        "{} {}: {}",
+        time,
+        message.get_sender_name().bold().cyan(),
+        text
+    )
+}
+
+/// Text of a message as it is shown, edited messages are marked and deleted messages leave a note
+fn format_text(message: &ChatMessage, text: &str) -> String {
+    if message.is_deleted() {
+        return "message deleted".italic().dimmed().to_string();
+    }
+    if message.get_edited_at().is_some() {
+        return format!("{} {}", text, "(edited)".dimmed());
+    }
+    text.to_string()
+}
+
+/// Line of an own message with the mark of its status behind it
+/// <br>The mark is blank until the server saved the message, and blue when every other member read it.
+/// Saved messages show their number in the chatroom, which is used to edit or delete them
+fn format_own_message(
+    user_name: &str,
+    text: &str,
+    sent_at: u64,
+    room_message_id: Option<u32>,
+    status: Option<ReceiptStatus>,
+) -> String {
+    let mark = match status {
+        None => "  ".normal(),
+        Some(ReceiptStatus::Read) => ReceiptStatus::Read.get_mark().blue(),
+        Some(status) => status.get_mark().dimmed(),
+    };
+    let number = room_message_id.map_or(String::new(), |number| {
+        format!(" {}", format!("#{}", number).dimmed())
+    });
+    format!(
+        "{} {}: {} {}{}",
+        format_sent_at(Some(sent_at)),
+        user_name.bold().purple(),
+        text,
+        mark,
+        number
+    )
+}
+
+/// Time in front of a message, messages that were not saved yet show the current time
+fn format_sent_at(sent_at: Option<u64>) -> ColoredString {
+    let now = helper_functions::get_sys_time_in_secs();
+    helper_functions::format_message_time(sent_at.unwrap_or(now), now).dimmed()
+}
+
+/// Own messages in the chat view, whose marks are changed when their status changes
+struct OwnMessages {
+    user_name: String,
+    /// Messages that were sent but not saved by the server yet, with their encrypted content
+    pending: Vec<(String, String, PrintedLine)>,
+    /// Saved messages by id
+    sent: HashMap<u32, SentMessage>,
+}
+
+impl OwnMessages {
+    fn new(user_name: &str) -> Self {
+        OwnMessages {
+            user_name: user_name.to_string(),
+            pending: Vec::new(),
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Prints a message that was just sent
+    fn print_pending(
+        &mut self,
+        input: &mut ChatInput,
+        text: &str,
+        content: &str,
+    ) -> io::Result<()> {
+        let now = helper_functions::get_sys_time_in_secs();
+        let line = input.print_line(&format_own_message(&self.user_name, text, now, None, None))?;
+        self.pending
+            .push((content.to_string(), text.to_string(), line));
+        Ok(())
+    }
+
+    /// Prints a message that was saved by the server
+    fn print_sent(
+        &mut self,
+        input: &mut ChatInput,
+        message: &ChatMessage,
+        text: &str,
+        status: ReceiptStatus,
+    ) -> io::Result<()> {
+        let (Some(message_id), Some(sent_at)) = (message.get_id(), message.get_sent_at()) else {
+            return Ok(());
+        };
+        let text = format_text(message, text);
+        let sent_message = SentMessage {
+            room_message_id: message.get_room_message_id(),
+            sent_at,
+            status,
+            line: input.print_line(&format_own_message(
+                &self.user_name,
+                &text,
+                sent_at,
+                message.get_room_message_id(),
+                Some(status),
+            ))?,
+            text,
+        };
+        self.sent.insert(message_id, sent_message);
+        Ok(())
+    }
+
+    /// The server saved a sent message, which is found by its encrypted content
+    /// <br>Returns the text of the message, None if it was not sent from this chat view
+    fn confirm(
+        &mut self,
+        input: &mut ChatInput,
+        message: &ChatMessage,
+    ) -> io::Result<Option<String>> {
+        let (Some(message_id), Some(sent_at)) = (message.get_id(), message.get_sent_at()) else {
+            return Ok(None);
+        };
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|(content, _, _)| *content == message.get_content())
+        else {
+            return Ok(None);
+        };
+        let (_, text, line) = self.pending.remove(index);
+        // A receipt can not be older than the confirmation, so the message is stored now
+        let sent_message = SentMessage {
+            text: text.clone(),
+            room_message_id: message.get_room_message_id(),
+            sent_at,
+            status: ReceiptStatus::Stored,
+            line,
+        };
+        self.redraw(input, &sent_message)?;
+        self.sent.insert(message_id, sent_message);
+        Ok(Some(text))
+    }
+
+    /// Shows the new status of a saved message, statuses never go back
+    fn update(&mut self, input: &mut ChatInput, receipt: Receipt) -> io::Result<()> {
+        let Some(sent_message) = self.sent.get_mut(&receipt.get_message_id()) else {
+            return Ok(());
+        };
+        if receipt.get_status() <= sent_message.status {
+            return Ok(());
+        }
+        sent_message.status = receipt.get_status();
+        let sent_message = sent_message.clone();
+        self.redraw(input, &sent_message)
+    }
+
+    /// Shows the new text of a message that was edited or deleted
+    fn change(
+        &mut self,
+        input: &mut ChatInput,
+        message: &ChatMessage,
+        text: &str,
+    ) -> io::Result<()> {
+        let Some(sent_message) = message
+            .get_id()
+            .and_then(|message_id| self.sent.get_mut(&message_id))
+        else {
+            return Ok(());
+        };
+        sent_message.text = format_text(message, text);
+        let sent_message = sent_message.clone();
+        self.redraw(input, &sent_message)
+    }
+
+    /// Returns the id of the own message with the number in the chatroom
+    fn get_message_id(&self, room_message_id: u32) -> Option<u32> {
+        self.sent
+            .iter()
+            .find(|(_, sent_message)| sent_message.room_message_id == Some(room_message_id))
+            .map(|(message_id, _)| *message_id)
+    }
+
+    fn redraw(&self, input: &mut ChatInput, sent_message: &SentMessage) -> io::Result<()> {
+        input.update_line(
+            sent_message.line,
+            &format_own_message(
+                &self.user_name,
+                &sent_message.text,
+                sent_message.sent_at,
+                sent_message.room_message_id,
+                Some(sent_message.status),
+            ),
+        )
+    }
+}
+
+/// Own message that was saved by the server, as it is shown in the chat view
+#[derive(Clone)]
+struct SentMessage {
+    /// Text as it is shown, with the mark of an edit
+    text: String,
+    room_message_id: Option<u32>,
+    sent_at: u64,
+    status: ReceiptStatus,
+    line: PrintedLine,
+}
+
+/// Shown instead of a message that could not be decrypted
+const UNDECRYPTABLE_MESSAGE: &str = "[message could not be decrypted]";
+
+/// Commands for moderators, as they are shown when entering a group chat or public room
+const MODERATION_COMMANDS_HELP: &str = "Moderators: /kick <name>, /ban <name>, /mute <name> <minutes>\nOwner: /mod <name>, /unmod <name>";
+
+/// Reads a moderation command like `/mute rino 10`
+/// <br>Returns the message to show if the command is unknown or incomplete
+fn parse_moderation_command(line: &str) -> Result<ModerationAction, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let action = match parts.as_slice() {
+        ["/kick", username] => Some(ModerationAction::Kick {
+            username: username.to_string(),
+        }),
+        ["/ban", username] => Some(ModerationAction::Ban {
+            username: username.to_string(),
+        }),
+        ["/mute", username, minutes] => {
+            minutes.parse().ok().map(|minutes| ModerationAction::Mute {
+                username: username.to_string(),
+                minutes,
+            })
+        }
+        ["/mod", username] => Some(ModerationAction::SetRole {
+            username: username.to_string(),
+            role: RoomRole::Moderator,
+        }),
+        ["/unmod", username] => Some(ModerationAction::SetRole {
+            username: username.to_string(),
+            role: RoomRole::Member,
+        }),
+        _ => None,
+    };
+    action.ok_or_else(|| format!("Unknown command. {}", MODERATION_COMMANDS_HELP))
+}
+
+/// Change of an own message or request of the edit history of any message, which is given by its number in the chatroom
+#[derive(Debug, PartialEq)]
+enum MessageCommand {
+    Edit { room_message_id: u32, text: String },
+    Delete { room_message_id: u32 },
+    History { room_message_id: u32 },
+}
+
+impl MessageCommand {
+    fn get_room_message_id(&self) -> u32 {
+        match self {
+            MessageCommand::Edit {
+                room_message_id, ..
+            }
+            | MessageCommand::Delete { room_message_id }
+            | MessageCommand::History { room_message_id } => *room_message_id,
+        }
+    }
+}
+
+/// Reads a command like `/edit 12 new text`, `/delete #12` or `/history 12`
+/// <br>Returns None for lines that are no such command, and the message to show if the command is incomplete
+fn parse_message_command(line: &str) -> Option<Result<MessageCommand, String>> {
+    let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+    let usage = match command {
+        "/edit" => "Usage: /edit <#> <text>",
+        "/delete" => "Usage: /delete <#>",
+        "/history" => "Usage: /history <#>",
+        _ => return None,
+    };
+    let arguments = arguments.trim();
+    let (number, text) = arguments.split_once(' ').unwrap_or((arguments, ""));
+    let text = text.trim();
+    let command = match (number.trim_start_matches('#').parse(), command) {
+        (Ok(room_message_id), "/edit") if !text.is_empty() => MessageCommand::Edit {
+            room_message_id,
+            text: text.to_string(),
+        },
+        (Ok(room_message_id), "/delete") if text.is_empty() => {
+            MessageCommand::Delete { room_message_id }
+        }
+        (Ok(room_message_id), "/history") if text.is_empty() => {
+            MessageCommand::History { room_message_id }
+        }
+        _ => return Some(Err(usage.to_string())),
+    };
+    Some(Ok(command))
+}
+
+/// Ratchet session with one other member of the chatroom
+struct MemberSession {
+    user_id: u32,
+    session: RatchetSession,
+    session_file: PathBuf,
+}
+
+impl MemberSession {
+    // Without the stored session the messages of this device could not be decrypted after a restart
+    fn save(&self) {
+        if let Err(e) = key_store::save_ratchet_session(&self.session_file, &self.session) {
+            _ = helper_functions::print_error(&format!("Could not save the chat keys: {}", e));
+        }
+    }
+}
+
+/// Keys of the chatroom that is open
+struct ChatKeys {
+    current_user_id: u32,
+    /// Messages are encrypted by a ratchet session with every other member, which is stored after every message
+    /// <br>In group chats a message contains one ratchet message for every other member
+    sessions: Vec<MemberSession>,
+    is_group_chat: bool,
+    /// Key of the messages that were encrypted before the ratchet was introduced
+    /// <br>Only chatrooms of two friends have one, group chats always used the ratchet
+    room_key: Option<RoomKey>,
+    /// A ratchet message can only be decrypted once, so the plaintexts are kept in the message index of the device
+    message_index: MessageIndex,
+}
+
+impl ChatKeys {
+    fn encrypt(&mut self, plaintext: &str) -> String {
+        let messages = self
+            .sessions
+            .iter_mut()
+            .map(|member| (member.user_id, member.session.encrypt(plaintext)))
+            .collect::<Vec<(u32, String)>>();
+        for member in &self.sessions {
+            member.save();
+        }
+        // The sender can not decrypt its own messages, so they are remembered for showing them again
+        for (_, message) in &messages {
+            _ = self.message_index.remember_text(message, plaintext);
+        }
+        if self.is_group_chat {
+            ratchet::create_group_message(&messages)
+        } else {
+            messages[0].1.clone()
+        }
+    }
+
+    /// Decrypts the content of a message for printing
+    /// <br>Messages that were written before encryption was introduced are shown as they are
+    fn decrypt(&mut self, content: &str) -> String {
+        let plaintext = if let Some(messages) = ratchet::parse_group_message(content) {
+            self.decrypt_group_message(&messages)
+        } else if ratchet::is_ratchet_message(content) {
+            self.decrypt_with_any_session(content)
+        } else if crypto::is_encrypted_with_room_key(content) {
+            match &self.room_key {
+                Some(room_key) => room_key.decrypt(content).ok(),
+                None => None,
+            }
+        } else {
+            return content.to_string();
+        };
+        plaintext.unwrap_or_else(|| UNDECRYPTABLE_MESSAGE.to_string())
+    }
+
+    /// The message for this user can come from any other member,
+    /// the messages for the other members can only be shown again if this user wrote them
+    fn decrypt_group_message(&mut self, messages: &[(u32, String)]) -> Option<String> {
+        if let Some(plaintext) = messages
+            .iter()
+            .find_map(|(_, message)| self.get_remembered_text(message))
+        {
+            return Some(plaintext);
+        }
+        let (_, message) = messages
+            .iter()
+            .find(|(user_id, _)| *user_id == self.current_user_id)?;
+        self.decrypt_with_any_session(message)
+    }
+
+    // The session is only changed if the message could be decrypted
+    fn decrypt_with_any_session(&mut self, message: &str) -> Option<String> {
+        if let Some(plaintext) = self.get_remembered_text(message) {
+            return Some(plaintext);
+        }
+        let plaintext = self.sessions.iter_mut().find_map(|member| {
+            let plaintext = member.session.decrypt(message).ok()?;
+            member.save();
+            Some(plaintext)
+        })?;
+        _ = self.message_index.remember_text(message, &plaintext);
+        Some(plaintext)
+    }
+
+    fn get_remembered_text(&self, message: &str) -> Option<String> {
+        self.message_index
+            .get_remembered_text(message)
+            .ok()
+            .flatten()
+    }
+}
+
+/// Opens the ratchet sessions of the chatroom with the identity key of the user and the published keys of the other members
+/// <br>Also returns the published keys of the other members
+async fn get_chat_keys(
+    connection: &mut ServerConnection,
+    current_user: &User,
+    chatroom: &ChatRoom,
+) -> Result<(ChatKeys, Vec<(User, String)>), Box<dyn std::error::Error>> {
+    let key_pair = key_store::load_identity_key(&key_store::get_identity_key_file_path(
+        current_user.get_name(),
+    ))
+    .ok_or("Your identity key is missing. Please log in again.")?;
+
+    // Every message that is shown can be found by searching later, and is shown again from there
+    let message_index = MessageIndex::open(&message_index::get_message_index_file_path(
+        current_user.get_name(),
+    ))?;
+
+    let chatroom_id = *chatroom.get_id();
+    let mut sessions = Vec::new();
+    let mut other_public_keys = Vec::new();
+    for other_user in chatroom.get_other_members(current_user.get_id()) {
+        let other_public_key = connection
+            .get_identity_key(other_user.get_id())
+            .await?
+            .ok_or_else(|| format!("{} has no identity key yet, messages can not be encrypted. Please try again after {} logged in.", other_user.get_name(), other_user.get_name()))?;
+
+        let session_file = if chatroom.is_group_chat() {
+            key_store::get_group_ratchet_session_file_path(
+                current_user.get_name(),
+                chatroom_id,
+                other_user.get_id(),
+            )
+        } else {
+            key_store::get_ratchet_session_file_path(current_user.get_name(), chatroom_id)
+        };
+        // Sessions of earlier versions contained the plaintexts, they are moved to the message index
+        let legacy_messages = key_store::load_legacy_decrypted_messages(&session_file);
+        let session = key_store::open_ratchet_session(
+            &session_file,
+            &key_pair,
+            current_user.get_id(),
+            &other_public_key,
+            other_user.get_id(),
+            chatroom_id,
+        )?;
+        if !legacy_messages.is_empty() {
+            message_index.remember_hashed_texts(&legacy_messages)?;
+            key_store::save_ratchet_session(&session_file, &session)?;
+        }
+        sessions.push(MemberSession {
+            user_id: other_user.get_id(),
+            session,
+            session_file,
+        });
+        other_public_keys.push((other_user, other_public_key));
+    }
+    // Public rooms can be entered before anybody else has joined them
+    if sessions.is_empty() && !chatroom.is_public() {
+        return Err("This chatroom has no other members.".into());
+    }
+
+    let room_key = if chatroom.is_group_chat() {
+        None
+    } else {
+        Some(key_pair.derive_room_key(&other_public_keys[0].1, chatroom_id)?)
+    };
+    let chat_keys = ChatKeys {
+        current_user_id: current_user.get_id(),
+        sessions,
+        is_group_chat: chatroom.is_group_chat(),
+        room_key,
+        message_index,
+    };
+    Ok((chat_keys, other_public_keys))
+}
+
+/// Warns the user if the key of a verified contact is not the key that was verified
+/// <br>Returns false if the user does not want to chat with the new key
+fn check_verified_key(current_user: &User, other_user: &User, other_public_key: &str) -> bool {
+    let path = key_store::get_verified_contacts_file_path(current_user.get_name());
+    match key_store::load_verified_key(&path, other_user.get_id()) {
+        Some(verified_key) if verified_key != other_public_key => {
+            let warning = format!(
+                "WARNING: THE IDENTITY KEY OF {} HAS CHANGED!\n\
+                 {} might have logged in on a new device, or someone is trying to read your messages.\n\
+                 Compare the safety numbers again with \"Verify contact\" before you write anything confidential.",
+                other_user.get_name().to_uppercase(),
+                other_user.get_name()
+            );
+            println!("{}", warning.bold().red());
+            helper_functions::ask_user_yes_no_question("Do you want to chat anyway?")
+        }
+        _ => true,
+    }
+}
+
+/// Method for creating the websocket connection to the server
+/// <br>`wss://` connections are encrypted with TLS and the certificate of the server is pinned on first use.
+/// Unencrypted `ws://` connections are only made to this device
+pub async fn create_websocket_connection(
+    config: &ClientConfig,
+) -> Result<WebSocketStream<BoxedStream>, Box<dyn std::error::Error>> {
+    let uri = config.server_uri.parse::<Uri>()?;
+    let host = uri.host().ok_or("The server URI has no host")?;
+    let stream: BoxedStream = match uri.scheme_str() {
+        Some("wss") => {
+            let port = uri.port_u16().unwrap_or(443);
+            tls::connect(host, port, &config.known_servers_file).await?
+        }
+        Some("ws") if tls::is_loopback_host(host) => {
+            Box::new(TcpStream::connect((host, uri.port_u16().unwrap_or(80))).await?)
+        }
+        Some("ws") => {
+            return Err(
+                "Unencrypted ws:// connections are only allowed to this device, use wss://".into(),
+            )
+        }
+        _ => return Err("The server URI has to start with ws:// or wss://".into()),
+    };
+    let (ws_stream, _) = ClientBuilder::from_uri(uri).connect_on(stream).await?;
     Ok(ws_stream)
 }
 
 /// Entry method for the client
-pub async fn run(mut current_state: ClientState) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn run(
+    mut current_state: ClientState,
+    config: ClientConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut connection = ServerConnection::connect(&config).await?;
+    // The summary of what happened while being offline is only shown once after logging in
+    let mut is_new_login = false;
     loop {
         current_state = match current_state {
-            ClientState::AuthenticationMenu => authenticate().await?,
-            ClientState::ChatMenu(user) => client_main_menu(user).await?,
-            ClientState::ChatRoom(user, chatroom) => chat_room(user, chatroom).await?,
+            ClientState::AuthenticationMenu => {
+                is_new_login = true;
+                authenticate(&mut connection).await?
+            }
+            ClientState::ChatMenu(user) => {
+                let is_new_login = std::mem::take(&mut is_new_login);
+                client_main_menu(&mut connection, user, is_new_login).await?
+            }
+            ClientState::ChatRoom(user, chatroom, message_id) => {
+                chat_room(&connection, user, chatroom, message_id).await?
+            }
+            ClientState::MessageSearch(user, search) => {
+                message_search(&mut connection, user, search).await?
+            }
             ClientState::Exit => {
                 println!("Exiting application...");
                 break;
@@ -145,4 +1089,153 @@ pub async fn run(mut current_state: ClientState) -> Result<(), Box<dyn std::erro
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::IdentityKeyPair;
+
+    #[test]
+    fn test_scrollback() {
+        let message = |id| ChatMessage::from_database(id, id, 1, None, 1000, "Hallo".to_string());
+        let mut scrollback = Scrollback::default();
+        assert_eq!(scrollback.start_loading(), None);
+
+        assert!(!scrollback.page_loaded(&[message(51), message(52)], true));
+        assert_eq!(scrollback.start_loading(), Some(51));
+        // The page is only asked for once
+        assert_eq!(scrollback.start_loading(), None);
+        assert!(scrollback.is_loading);
+
+        assert!(scrollback.page_loaded(&[message(1), message(2)], false));
+        assert!(!scrollback.is_loading);
+        assert_eq!(scrollback.start_loading(), None);
+    }
+
+    #[test]
+    fn test_parse_message_command() {
+        assert_eq!(
+            parse_message_command("/edit #12 Bis  morgen"),
+            Some(Ok(MessageCommand::Edit {
+                room_message_id: 12,
+                text: "Bis  morgen".to_string()
+            }))
+        );
+        assert_eq!(
+            parse_message_command("/delete 3"),
+            Some(Ok(MessageCommand::Delete { room_message_id: 3 }))
+        );
+        assert_eq!(
+            parse_message_command("/history #7"),
+            Some(Ok(MessageCommand::History { room_message_id: 7 }))
+        );
+        assert_eq!(
+            parse_message_command("/edit 12"),
+            Some(Err("Usage: /edit <#> <text>".to_string()))
+        );
+        assert_eq!(
+            parse_message_command("/delete last"),
+            Some(Err("Usage: /delete <#>".to_string()))
+        );
+        // Other commands are left to the moderation
+        assert_eq!(parse_message_command("/kick rino"), None);
+        assert_eq!(parse_message_command("/editor"), None);
+    }
+
+    #[test]
+    fn test_parse_moderation_command() {
+        assert_eq!(
+            parse_moderation_command("/kick rino"),
+            Ok(ModerationAction::Kick {
+                username: "rino".to_string()
+            })
+        );
+        assert_eq!(
+            parse_moderation_command("/mute  rino 10"),
+            Ok(ModerationAction::Mute {
+                username: "rino".to_string(),
+                minutes: 10
+            })
+        );
+        assert_eq!(
+            parse_moderation_command("/unmod rino"),
+            Ok(ModerationAction::SetRole {
+                username: "rino".to_string(),
+                role: RoomRole::Member
+            })
+        );
+        for line in [
+            "/kick",
+            "/mute rino",
+            "/mute rino ten",
+            "/ban rino anton",
+            "/help",
+        ] {
+            assert!(parse_moderation_command(line).is_err());
+        }
+    }
+
+    /// Chat keys of the user in a group chat with the other users, the sessions are stored in the temp directory
+    fn get_group_chat_keys(key_pairs: &[IdentityKeyPair], user_index: usize) -> ChatKeys {
+        let user_id = user_index as u32 + 1;
+        let sessions = key_pairs
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != user_index)
+            .map(|(index, other_key_pair)| {
+                let other_user_id = index as u32 + 1;
+                MemberSession {
+                    user_id: other_user_id,
+                    session: RatchetSession::new(
+                        &key_pairs[user_index],
+                        user_id,
+                        &other_key_pair.get_public_key_hex(),
+                        other_user_id,
+                        7,
+                    )
+                    .unwrap(),
+                    session_file: std::env::temp_dir().join(format!(
+                        "chatclient_test_group_{}_{}-{}",
+                        std::process::id(),
+                        user_id,
+                        other_user_id
+                    )),
+                }
+            })
+            .collect();
+        ChatKeys {
+            current_user_id: user_id,
+            sessions,
+            is_group_chat: true,
+            room_key: None,
+            message_index: MessageIndex::open_in_memory().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_group_chat_keys() {
+        let key_pairs: Vec<IdentityKeyPair> = (0..3).map(|_| IdentityKeyPair::generate()).collect();
+        let mut chat_keys: Vec<ChatKeys> = (0..3)
+            .map(|index| get_group_chat_keys(&key_pairs, index))
+            .collect();
+
+        let message = chat_keys[1].encrypt("Hallo zusammen!");
+        assert!(crypto::is_encrypted(&message));
+        assert_eq!(chat_keys[0].decrypt(&message), "Hallo zusammen!");
+        assert_eq!(chat_keys[2].decrypt(&message), "Hallo zusammen!");
+        // The sender can show its own message again, and the members the messages they decrypted
+        assert_eq!(chat_keys[1].decrypt(&message), "Hallo zusammen!");
+        assert_eq!(chat_keys[0].decrypt(&message), "Hallo zusammen!");
+
+        let answer = chat_keys[2].encrypt("Hallo, Anton!");
+        assert_eq!(chat_keys[1].decrypt(&answer), "Hallo, Anton!");
+        assert_eq!(chat_keys[0].decrypt(&answer), "Hallo, Anton!");
+
+        for keys in &chat_keys {
+            for member in &keys.sessions {
+                _ = std::fs::remove_file(&member.session_file);
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::chat_export::ExportFormat;
+use crate::storage::DEFAULT_DATABASE_URL;
+use crate::tls;
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Config file that is read if no other file is given
+pub const DEFAULT_CONFIG_FILE: &str = "chat.toml";
+
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8000";
+/// The client uses TLS unless told otherwise
+pub const DEFAULT_SERVER_URI: &str = "wss://127.0.0.1:8000";
+
+/// Number of wrong passwords in a row before the user is blocked
+pub const DEFAULT_MAX_LOGIN_ATTEMPTS: u32 = 3;
+
+/// Seconds a user is blocked after too many wrong passwords
+pub const DEFAULT_LOCKOUT_SECS: u64 = 20;
+
+/// Maximum number of members of a group chat, including the user who created it
+pub const DEFAULT_MAX_GROUP_MEMBERS: u32 = 10;
+
+/// Settings of the server
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub database_url: String,
+    pub max_login_attempts: u32,
+    pub lockout_secs: u64,
+    /// Maximum number of members of a group chat, including the user who created it
+    pub max_group_members: u32,
+    /// Certificate chain in PEM format, without certificate and key the server does not use TLS
+    pub tls_certificate: Option<PathBuf>,
+    /// Private key of the certificate in PEM format
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
+            database_url: DEFAULT_DATABASE_URL.to_string(),
+            max_login_attempts: DEFAULT_MAX_LOGIN_ATTEMPTS,
+            lockout_secs: DEFAULT_LOCKOUT_SECS,
+            max_group_members: DEFAULT_MAX_GROUP_MEMBERS,
+            tls_certificate: None,
+            tls_key: None,
+        }
+    }
+}
+
+/// Settings of the client
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub server_uri: String,
+    /// File with the pinned certificate fingerprints of the servers the client connected to
+    pub known_servers_file: PathBuf,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            server_uri: DEFAULT_SERVER_URI.to_string(),
+            known_servers_file: tls::get_known_servers_file_path(),
+        }
+    }
+}
+
+/// Content of the config file, every value that is missing keeps its default
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub client: ClientConfig,
+}
+
+impl Config {
+    /// Reads the config from TOML
+    pub fn from_toml(text: &str) -> Result<Config, Box<dyn Error + Send + Sync>> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Reads the config file
+    /// <br>Without a path `chat.toml` in the working directory is used if it exists, otherwise the defaults
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error + Send + Sync>> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Config::default()),
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path.display(), e))?;
+        Config::from_toml(&text)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into())
+    }
+}
+
+/// Command line of the program
+/// <br>Flags override environment variables, which override the config file
+#[derive(Debug, Parser)]
+#[command(name = "src", about = "Secure chat application")]
+pub struct Cli {
+    /// Path of the config file
+    #[arg(long, global = true, env = "CHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the chat server
+    Server(ServerArgs),
+    /// Bring the database schema up to date
+    Migrate(DatabaseArgs),
+    /// Write the messages of a chatroom to a JSON export or a transcript
+    Export(ExportArgs),
+    /// Restore a JSON export into a fresh database
+    Import(ImportArgs),
+    /// Start the chat client (default)
+    Client(ClientArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ServerArgs {
+    /// Address the server listens on
+    #[arg(long, env = "CHAT_BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+
+    /// Number of wrong passwords in a row before a user is blocked
+    #[arg(long, env = "CHAT_MAX_LOGIN_ATTEMPTS")]
+    pub max_login_attempts: Option<u32>,
+
+    /// Seconds a user is blocked after too many wrong passwords
+    #[arg(long, env = "CHAT_LOCKOUT_SECS")]
+    pub lockout_secs: Option<u64>,
+
+    /// Maximum number of members of a group chat
+    #[arg(long, env = "CHAT_MAX_GROUP_MEMBERS")]
+    pub max_group_members: Option<u32>,
+
+    /// Certificate chain of the server in PEM format, enables TLS together with --tls-key
+    #[arg(long, env = "CHAT_TLS_CERTIFICATE")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// Private key of the certificate in PEM format
+    #[arg(long, env = "CHAT_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DatabaseArgs {
+    /// Database URL: mysql://..., sqlite://<path> or sqlite::memory:
+    #[arg(long, env = "CHAT_DATABASE_URL")]
+    pub database_url: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Id of the chatroom that is exported
+    #[arg(long = "chatroom")]
+    pub chatroom_id: u32,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+
+    /// File the export is written to, without a file it is printed
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// JSON export that is restored
+    pub input: PathBuf,
+
+    #[command(flatten)]
+    pub database: DatabaseArgs,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ClientArgs {
+    /// URI of the server, for example wss://127.0.0.1:8000
+    #[arg(long, env = "CHAT_SERVER_URI")]
+    pub server_uri: Option<String>,
+
+    /// File with the pinned certificate fingerprints of known servers
+    #[arg(long, env = "CHAT_KNOWN_SERVERS_FILE")]
+    pub known_servers_file: Option<PathBuf>,
+}
+
+impl ServerArgs {
+    /// Overrides the values of the config that were given on the command line
+    pub fn apply_to(self, config: &mut ServerConfig) {
+        if let Some(bind_address) = self.bind_address {
+            config.bind_address = bind_address;
+        }
+        self.database.apply_to(config);
+        if let Some(max_login_attempts) = self.max_login_attempts {
+            config.max_login_attempts = max_login_attempts;
+        }
+        if let Some(lockout_secs) = self.lockout_secs {
+            config.lockout_secs = lockout_secs;
+        }
+        if let Some(max_group_members) = self.max_group_members {
+            config.max_group_members = max_group_members;
+        }
+        if let Some(tls_certificate) = self.tls_certificate {
+            config.tls_certificate = Some(tls_certificate);
+        }
+        if let Some(tls_key) = self.tls_key {
+            config.tls_key = Some(tls_key);
+        }
+    }
+}
+
+impl DatabaseArgs {
+    /// Overrides the database URL of the config if it was given on the command line
+    pub fn apply_to(self, config: &mut ServerConfig) {
+        if let Some(database_url) = self.database_url {
+            config.database_url = database_url;
+        }
+    }
+}
+
+impl ClientArgs {
+    /// Overrides the values of the config that were given on the command line
+    pub fn apply_to(self, config: &mut ClientConfig) {
+        if let Some(server_uri) = self.server_uri {
+            config.server_uri = server_uri;
+        }
+        if let Some(known_servers_file) = self.known_servers_file {
+            config.known_servers_file = known_servers_file;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config = Config::from_toml(
+            r#"
+            [server]
+            bind_address = "0.0.0.0:9000"
+            database_url = "sqlite://chat.db"
+            max_login_attempts = 5
+            lockout_secs = 60
+            max_group_members = 5
+
+            [client]
+            server_uri = "ws://chat.example.com:9000"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.bind_address, "0.0.0.0:9000");
+        assert_eq!(config.server.database_url, "sqlite://chat.db");
+        assert_eq!(config.server.max_login_attempts, 5);
+        assert_eq!(config.server.lockout_secs, 60);
+        assert_eq!(config.server.max_group_members, 5);
+        assert_eq!(config.client.server_uri, "ws://chat.example.com:9000");
+    }
+
+    #[test]
+    fn test_config_from_toml_missing_values_keep_defaults() {
+        let config = Config::from_toml("[server]\nlockout_secs = 60\n").unwrap();
+        assert_eq!(config.server.lockout_secs, 60);
+        assert_eq!(config.server.bind_address, DEFAULT_BIND_ADDRESS);
+        assert_eq!(config.client, ClientConfig::default());
+
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_config_from_toml_unknown_key() {
+        // Typos should not be ignored silently
+        assert!(Config::from_toml("[server]\nlockout_sec = 60\n").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_config_file() {
+        let path = std::env::temp_dir().join("chat_config_test_missing.toml");
+        assert!(Config::load(Some(&path)).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_flags() {
+        let cli = Cli::try_parse_from([
+            "src",
+            "server",
+            "--bind-address",
+            "0.0.0.0:9000",
+            "--lockout-secs",
+            "60",
+            "--max-group-members",
+            "4",
+        ])
+        .unwrap();
+
+        let mut config = ServerConfig::default();
+        match cli.command {
+            Some(Command::Server(args)) => args.apply_to(&mut config),
+            other => panic!("Expected server command, got {:?}", other),
+        }
+        assert_eq!(config.bind_address, "0.0.0.0:9000");
+        assert_eq!(config.lockout_secs, 60);
+        assert_eq!(config.max_group_members, 4);
+        assert_eq!(config.max_login_attempts, DEFAULT_MAX_LOGIN_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_parse_export_flags() {
+        let cli =
+            Cli::try_parse_from(["src", "export", "--chatroom", "3", "--format", "transcript"])
+                .unwrap();
+        match cli.command {
+            Some(Command::Export(args)) => {
+                assert_eq!(args.chatroom_id, 3);
+                assert_eq!(args.format, ExportFormat::Transcript);
+                assert!(args.output.is_none());
+            }
+            other => panic!("Expected export command, got {:?}", other),
+        }
+
+        // JSON is the default, the chatroom is needed
+        let cli = Cli::try_parse_from(["src", "export", "--chatroom", "3"]).unwrap();
+        assert!(
+            matches!(cli.command, Some(Command::Export(args)) if args.format == ExportFormat::Json)
+        );
+        assert!(Cli::try_parse_from(["src", "export"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_without_subcommand() {
+        let cli = Cli::try_parse_from(["src"]).unwrap();
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn test_parse_unknown_subcommand() {
+        assert!(Cli::try_parse_from(["src", "sever"]).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::helper_functions::{decode_hex, encode_hex};
+use crate::ratchet;
+use rand::rngs::OsRng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hkdf;
+use sha2::{Digest, Sha512};
+use std::error::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Prefix of encrypted message content, the number is the version of the format
+/// <br>Format: `e2e1.<nonce as hex>.<ciphertext with tag as hex>`
+const ENCRYPTED_PREFIX: &str = "e2e1.";
+
+/// Label that is mixed into every room key, so the keys can not be confused with keys of other protocols
+const ROOM_KEY_INFO: &[u8] = b"chatclient room key v1";
+
+/// Label that is mixed into the secret a ratchet session starts with
+const SESSION_SECRET_INFO: &[u8] = b"chatclient ratchet session v1";
+
+/// Label that is hashed together with the public keys to get the safety number
+const SAFETY_NUMBER_INFO: &[u8] = b"chatclient safety number v1";
+
+/// Number of blocks of five digits a safety number consists of
+const SAFETY_NUMBER_BLOCKS: usize = 12;
+
+/// Identity of a user for end-to-end encryption
+/// <br>The secret key never leaves the device, only the public key is published through the server
+pub struct IdentityKeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl IdentityKeyPair {
+    ///Generates a new random key pair
+    pub fn generate() -> IdentityKeyPair {
+        IdentityKeyPair::from_secret(StaticSecret::random_from_rng(OsRng))
+    }
+
+    ///Restores the key pair from its secret key as hex string
+    pub fn from_secret_hex(secret_hex: &str) -> Result<IdentityKeyPair, Box<dyn Error>> {
+        let secret: [u8; 32] = decode_key(secret_hex)?;
+        Ok(IdentityKeyPair::from_secret(StaticSecret::from(secret)))
+    }
+
+    fn from_secret(secret: StaticSecret) -> IdentityKeyPair {
+        let public = PublicKey::from(&secret);
+        IdentityKeyPair { secret, public }
+    }
+
+    pub fn get_secret_hex(&self) -> String {
+        encode_hex(self.secret.as_bytes())
+    }
+
+    pub fn get_public_key_hex(&self) -> String {
+        encode_hex(self.public.as_bytes())
+    }
+
+    pub fn get_secret_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    ///Derives the key of a chatroom from this key pair and the public key of the other member
+    /// <br>Both members get the same key, the server can not compute it
+    /// <br>Only used for messages in the first format, new messages are encrypted with a `RatchetSession`
+    pub fn derive_room_key(
+        &self,
+        other_public_key_hex: &str,
+        chatroom_id: u32,
+    ) -> Result<RoomKey, Box<dyn Error>> {
+        let (prk, info) = self.agree(other_public_key_hex, chatroom_id, ROOM_KEY_INFO)?;
+        let info = [info.as_slice()];
+        let okm = prk
+            .expand(&info, &CHACHA20_POLY1305)
+            .map_err(|_| "Key derivation failed.")?;
+        Ok(RoomKey {
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            chatroom_id,
+        })
+    }
+
+    ///Derives the secret the ratchet session of a chatroom starts with
+    /// <br>Both members get the same secret, the server can not compute it
+    pub fn derive_session_secret(
+        &self,
+        other_public_key_hex: &str,
+        chatroom_id: u32,
+    ) -> Result<[u8; 32], Box<dyn Error>> {
+        let (prk, info) = self.agree(other_public_key_hex, chatroom_id, SESSION_SECRET_INFO)?;
+        let mut secret = [0u8; 32];
+        prk.expand(&[info.as_slice()], OutputLength(secret.len()))
+            .and_then(|okm| okm.fill(&mut secret))
+            .map_err(|_| "Key derivation failed.")?;
+        Ok(secret)
+    }
+
+    // Diffie-Hellman with the public key of the other member
+    // Returns the extracted secret and the info that binds derived keys to both identities and the chatroom
+    fn agree(
+        &self,
+        other_public_key_hex: &str,
+        chatroom_id: u32,
+        label: &[u8],
+    ) -> Result<(hkdf::Prk, Vec<u8>), Box<dyn Error>> {
+        let other_public = PublicKey::from(decode_key(other_public_key_hex)?);
+        let shared_secret = self.secret.diffie_hellman(&other_public);
+        // Public keys of low order would result in a secret everybody knows
+        if !shared_secret.was_contributory() {
+            return Err("Invalid public key.".into());
+        }
+
+        // Bind the key to both identities and the chatroom, in an order both members agree on
+        let mut public_keys = [*self.public.as_bytes(), *other_public.as_bytes()];
+        public_keys.sort();
+        let mut info = label.to_vec();
+        info.extend_from_slice(&public_keys[0]);
+        info.extend_from_slice(&public_keys[1]);
+        info.extend_from_slice(&chatroom_id.to_be_bytes());
+
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(shared_secret.as_bytes());
+        Ok((prk, info))
+    }
+}
+
+/// Length of the output of HKDF, for keys that are no ring key type
+struct OutputLength(usize);
+
+impl hkdf::KeyType for OutputLength {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// Fills `output` with key material derived from `input` by HKDF-SHA256
+pub fn hkdf_sha256(salt: &[u8], input: &[u8], info: &[u8], output: &mut [u8]) {
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(input)
+        .expand(&[info], OutputLength(output.len()))
+        .and_then(|okm| okm.fill(output))
+        .expect("Output of HKDF is too long");
+}
+
+/// Key of a chatroom, shared by its two members
+pub struct RoomKey {
+    key: LessSafeKey,
+    chatroom_id: u32,
+}
+
+impl RoomKey {
+    ///Encrypts a message in the first format
+    /// <br>New messages are encrypted by a `RatchetSession`, so only tests still write this format
+    #[cfg(test)]
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let nonce_bytes: [u8; NONCE_LEN] = rand::random();
+        let mut ciphertext = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce_bytes),
+                self.get_aad(),
+                &mut ciphertext,
+            )
+            .expect("Message is too long to be encrypted");
+        format!(
+            "{}{}.{}",
+            ENCRYPTED_PREFIX,
+            encode_hex(&nonce_bytes),
+            encode_hex(&ciphertext)
+        )
+    }
+
+    ///Decrypts a message, fails if it was changed or encrypted with another key
+    pub fn decrypt(&self, content: &str) -> Result<String, Box<dyn Error>> {
+        let (nonce_hex, ciphertext_hex) = content
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .and_then(|rest| rest.split_once('.'))
+            .ok_or("Message is not encrypted.")?;
+        let nonce =
+            Nonce::try_assume_unique_for_key(&decode_hex(nonce_hex).ok_or("Invalid nonce.")?)
+                .map_err(|_| "Invalid nonce.")?;
+        let mut ciphertext = decode_hex(ciphertext_hex).ok_or("Invalid ciphertext.")?;
+
+        let plaintext = self
+            .key
+            .open_in_place(nonce, self.get_aad(), &mut ciphertext)
+            .map_err(|_| "Message could not be decrypted.")?;
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+
+    // A message of one chatroom can not be replayed in another chatroom
+    fn get_aad(&self) -> Aad<[u8; 4]> {
+        Aad::from(self.chatroom_id.to_be_bytes())
+    }
+}
+
+/// Returns true if the content of a message is encrypted
+/// <br>The server only checks the format, it can not decrypt the message
+pub fn is_encrypted(content: &str) -> bool {
+    is_encrypted_with_room_key(content)
+        || ratchet::is_ratchet_message(content)
+        || ratchet::is_group_message(content)
+}
+
+/// Returns true if the message is in the first format, encrypted with the static key of the chatroom
+pub fn is_encrypted_with_room_key(content: &str) -> bool {
+    content
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .and_then(|rest| rest.split_once('.'))
+        .is_some_and(|(nonce, ciphertext)| {
+            nonce.len() == NONCE_LEN * 2
+                && decode_hex(nonce).is_some()
+                && decode_hex(ciphertext).is_some()
+        })
+}
+
+/// Returns the safety number of two users, both users get the same number
+/// <br>If the numbers shown on both devices match, nobody replaced a key on the way
+pub fn get_safety_number(public_key_hex: &str, other_public_key_hex: &str) -> String {
+    let mut public_keys = [
+        public_key_hex.to_lowercase(),
+        other_public_key_hex.to_lowercase(),
+    ];
+    public_keys.sort();
+    let mut hasher = Sha512::new();
+    hasher.update(SAFETY_NUMBER_INFO);
+    hasher.update(public_keys[0].as_bytes());
+    hasher.update(public_keys[1].as_bytes());
+    let hash = hasher.finalize();
+
+    // Every five bytes of the hash become a block of five digits
+    hash.chunks(5)
+        .take(SAFETY_NUMBER_BLOCKS)
+        .map(|chunk| {
+            let value = chunk
+                .iter()
+                .fold(0u64, |value, byte| (value << 8) | u64::from(*byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Returns true if the string is a public key as hex string
+pub fn is_valid_public_key(public_key_hex: &str) -> bool {
+    decode_key(public_key_hex).is_ok()
+}
+
+fn decode_key(hex: &str) -> Result<[u8; 32], Box<dyn Error>> {
+    decode_hex(hex)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "Invalid key.".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_both_members_derive_the_same_room_key() {
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let rino_key = rino
+            .derive_room_key(&anton.get_public_key_hex(), 1)
+            .unwrap();
+        let anton_key = anton
+            .derive_room_key(&rino.get_public_key_hex(), 1)
+            .unwrap();
+
+        let encrypted = rino_key.encrypt("Hallo, Anton!");
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("Hallo"));
+        assert_eq!(anton_key.decrypt(&encrypted).unwrap(), "Hallo, Anton!");
+    }
+
+    #[test]
+    fn test_encrypting_twice_gives_different_ciphertexts() {
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let key = rino
+            .derive_room_key(&anton.get_public_key_hex(), 1)
+            .unwrap();
+        assert_ne!(key.encrypt("Hallo"), key.encrypt("Hallo"));
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key() {
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let antonia = IdentityKeyPair::generate();
+        let encrypted = rino
+            .derive_room_key(&anton.get_public_key_hex(), 1)
+            .unwrap()
+            .encrypt("Hallo, Anton!");
+
+        // Another user can not read the message
+        let antonia_key = antonia
+            .derive_room_key(&rino.get_public_key_hex(), 1)
+            .unwrap();
+        assert!(antonia_key.decrypt(&encrypted).is_err());
+
+        // The message can not be moved to another chatroom
+        let other_room_key = anton
+            .derive_room_key(&rino.get_public_key_hex(), 2)
+            .unwrap();
+        assert!(other_room_key.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_changed_message() {
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let key = rino
+            .derive_room_key(&anton.get_public_key_hex(), 1)
+            .unwrap();
+        let encrypted = key.encrypt("Hallo, Anton!");
+
+        let last = encrypted.chars().last().unwrap();
+        let changed = format!(
+            "{}{}",
+            &encrypted[..encrypted.len() - 1],
+            if last == '0' { '1' } else { '0' }
+        );
+        assert!(key.decrypt(&changed).is_err());
+        assert!(key.decrypt("Hallo, Anton!").is_err());
+    }
+
+    #[test]
+    fn test_key_pair_from_secret_hex() {
+        let key_pair = IdentityKeyPair::generate();
+        let restored = IdentityKeyPair::from_secret_hex(&key_pair.get_secret_hex()).unwrap();
+        assert_eq!(restored.get_public_key_hex(), key_pair.get_public_key_hex());
+        assert!(IdentityKeyPair::from_secret_hex("abc").is_err());
+    }
+
+    #[test]
+    fn test_derive_room_key_with_invalid_public_key() {
+        let key_pair = IdentityKeyPair::generate();
+        assert!(key_pair.derive_room_key("abc", 1).is_err());
+        // The zero point is a public key of low order
+        assert!(key_pair.derive_room_key(&"0".repeat(64), 1).is_err());
+    }
+
+    #[test]
+    fn test_is_encrypted() {
+        assert!(!is_encrypted("Hallo, Anton!"));
+        assert!(!is_encrypted("e2e1.Hallo"));
+        assert!(!is_encrypted("e2e1.00.00"));
+        assert!(is_encrypted(&format!("e2e1.{}.00", "0".repeat(24))));
+    }
+
+    #[test]
+    fn test_is_valid_public_key() {
+        assert!(is_valid_public_key(
+            &IdentityKeyPair::generate().get_public_key_hex()
+        ));
+        assert!(!is_valid_public_key("abc"));
+        assert!(!is_valid_public_key(&"0".repeat(66)));
+    }
+
+    #[test]
+    fn test_safety_number() {
+        let rino = IdentityKeyPair::generate().get_public_key_hex();
+        let anton = IdentityKeyPair::generate().get_public_key_hex();
+        let antonia = IdentityKeyPair::generate().get_public_key_hex();
+
+        let safety_number = get_safety_number(&rino, &anton);
+        assert_eq!(safety_number, get_safety_number(&anton, &rino));
+        assert_ne!(safety_number, get_safety_number(&rino, &antonia));
+
+        let blocks: Vec<&str> = safety_number.split(' ').collect();
+        assert_eq!(blocks.len(), SAFETY_NUMBER_BLOCKS);
+        assert!(blocks
+            .iter()
+            .all(|block| block.len() == 5 && block.chars().all(|c| c.is_ascii_digit())));
+    }
+}
@@ -1,6 +1,7 @@
+use chrono::{Local, TimeZone};
 use colored::*;
 use dialoguer::theme::ColorfulTheme;
-use dialoguer::Select;
+use dialoguer::{MultiSelect, Select};
 use std::process::Command;
 use std::time::SystemTime;
 
@@ -12,6 +13,59 @@ pub fn get_sys_time_in_secs() -> u64 {
     }
 }
 
+/// Formats a duration in seconds as a human readable "... ago"
+pub fn format_time_ago(secs: u64) -> String {
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", secs / 60),
+        3600..=86399 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+/// Formats the time a message was sent at in the local time zone
+/// <br>Messages of today only show the time, older messages the date as well
+pub fn format_message_time(sent_at: u64, now: u64) -> String {
+    let to_local = |secs: u64| Local.timestamp_opt(secs as i64, 0).single();
+    match (to_local(sent_at), to_local(now)) {
+        (Some(sent_at), Some(now)) if sent_at.date_naive() == now.date_naive() => {
+            sent_at.format("%H:%M").to_string()
+        }
+        (Some(sent_at), _) => sent_at.format("%d.%m.%Y %H:%M").to_string(),
+        (None, _) => String::new(),
+    }
+}
+
+/// Method for generating a random session token as hex string
+pub fn generate_session_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    encode_hex(&bytes)
+}
+
+/// Method for encoding bytes as lowercase hex string
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Method for decoding a hex string, returns None if the string is no valid hex
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Method for getting a name for this device, shown in the list of active sessions
+pub fn get_device_name() -> String {
+    let host = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .unwrap_or_else(|_| "unknown device".to_string());
+    format!("{} ({})", host, std::env::consts::OS)
+}
+
 /// Method for clearing the console
 pub fn clear_console() -> Result<(), Box<dyn std::error::Error>> {
     #[cfg(target_os = "windows")]
@@ -48,6 +102,17 @@ pub fn display_multiple_choices(
     selection
 }
 
+/// Method for letting the user select any number of choices
+/// <br>Returns the indices of the selected choices
+pub fn display_multiple_selection(prompt: &str, list_of_choices: Vec<String>) -> Vec<usize> {
+    _ = clear_console();
+    MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(&list_of_choices)
+        .interact()
+        .unwrap()
+}
+
 /// Method for asking the user a yes/no question
 /// <param name="question">The question to ask the user</param>
 /// <returns>True if the user answers "Yes", False if the user answers "No"</returns>
@@ -117,4 +182,29 @@ mod tests {
         // Check if time2 is time 1 plus 1 since we waited for one second
         assert_eq!(time2, time1 + 1);
     }
+
+    #[test]
+    fn test_format_message_time() {
+        let now = get_sys_time_in_secs();
+        let today = format_message_time(now, now);
+        assert_eq!(today.len(), "12:34".len());
+        let last_year = format_message_time(now - 400 * 86400, now);
+        assert_eq!(last_year.len(), "31.12.2025 12:34".len());
+        assert_eq!(&last_year[2..3], ".");
+    }
+
+    #[test]
+    fn test_encode_and_decode_hex() {
+        let bytes = vec![0x00, 0x0f, 0xab, 0xff];
+        assert_eq!(encode_hex(&bytes), "000fabff");
+        assert_eq!(decode_hex("000fabff"), Some(bytes));
+        assert_eq!(decode_hex("000FABFF"), Some(vec![0x00, 0x0f, 0xab, 0xff]));
+    }
+
+    #[test]
+    fn test_decode_invalid_hex() {
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("äb"), None);
+    }
 }
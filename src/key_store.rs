@@ -0,0 +1,300 @@
+use crate::crypto::IdentityKeyPair;
+use crate::helper_functions;
+use crate::ratchet::RatchetSession;
+use crate::server_connection::ServerConnection;
+use crate::session_store;
+use crate::structs::user::User;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Start of the name of the file the identity key is stored in, the username is appended
+const IDENTITY_KEY_FILE_PREFIX: &str = ".chatclient_identity_";
+
+/// Start of the name of the files the ratchet sessions are stored in, username and chatroom id are appended
+const RATCHET_SESSION_FILE_PREFIX: &str = ".chatclient_ratchet_";
+
+/// Start of the name of the file the verified keys of the contacts are stored in, the username is appended
+const VERIFIED_CONTACTS_FILE_PREFIX: &str = ".chatclient_verified_";
+
+/// Returns the path of the file the identity key of the user is stored in
+/// <br>Every user has its own file, so that several users can use the client on the same device
+pub fn get_identity_key_file_path(username: &str) -> PathBuf {
+    session_store::get_path_in_home_directory(&format!(
+        "{}{}",
+        IDENTITY_KEY_FILE_PREFIX,
+        session_store::get_username_for_file_name(username)
+    ))
+}
+
+/// Returns the stored identity key, None if there is no valid key in the file
+pub fn load_identity_key(path: &Path) -> Option<IdentityKeyPair> {
+    let secret_hex = fs::read_to_string(path).ok()?;
+    IdentityKeyPair::from_secret_hex(secret_hex.trim()).ok()
+}
+
+/// Stores the identity key, the file can only be read by the current user
+pub fn save_identity_key(path: &Path, key_pair: &IdentityKeyPair) -> io::Result<()> {
+    session_store::write_private_file(path, &key_pair.get_secret_hex())
+}
+
+/// Returns the path of the file the keys the user verified are stored in
+pub fn get_verified_contacts_file_path(username: &str) -> PathBuf {
+    session_store::get_path_in_home_directory(&format!(
+        "{}{}",
+        VERIFIED_CONTACTS_FILE_PREFIX,
+        session_store::get_username_for_file_name(username)
+    ))
+}
+
+/// Returns the key of the contact that was verified by comparing safety numbers, if any
+/// <br>Every line of the file is `<user id> <public key>`
+pub fn load_verified_key(path: &Path, user_id: u32) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let (line_user_id, public_key) = line.trim().split_once(' ')?;
+        (line_user_id.parse() == Ok(user_id)).then(|| public_key.trim().to_string())
+    })
+}
+
+/// Marks the key of the contact as verified, replaces a key that was verified before
+pub fn save_verified_key(path: &Path, user_id: u32, public_key: &str) -> io::Result<()> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|line| line.split_once(' ').map(|(id, _)| id) != Some(&user_id.to_string()))
+        .map(|line| line.to_string())
+        .collect();
+    lines.push(format!("{} {}", user_id, public_key));
+    session_store::write_private_file(path, &(lines.join("\n") + "\n"))
+}
+
+/// Returns the path of the file the ratchet session of the user in the chatroom is stored in
+pub fn get_ratchet_session_file_path(username: &str, chatroom_id: u32) -> PathBuf {
+    session_store::get_path_in_home_directory(&format!(
+        "{}{}_{}",
+        RATCHET_SESSION_FILE_PREFIX,
+        session_store::get_username_for_file_name(username),
+        chatroom_id
+    ))
+}
+
+/// Path of the ratchet session with one member of a group chat, every pair of members has its own session
+pub fn get_group_ratchet_session_file_path(
+    username: &str,
+    chatroom_id: u32,
+    other_user_id: u32,
+) -> PathBuf {
+    session_store::get_path_in_home_directory(&format!(
+        "{}{}_{}-{}",
+        RATCHET_SESSION_FILE_PREFIX,
+        session_store::get_username_for_file_name(username),
+        chatroom_id,
+        other_user_id
+    ))
+}
+
+/// Returns the stored ratchet session, None if there is no valid session in the file
+pub fn load_ratchet_session(path: &Path) -> Option<RatchetSession> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// Stores the ratchet session, the file can only be read by the current user
+pub fn save_ratchet_session(path: &Path, session: &RatchetSession) -> io::Result<()> {
+    let json = serde_json::to_string(session).expect("Session is always serializable");
+    session_store::write_private_file(path, &json)
+}
+
+/// Plaintexts that sessions of earlier versions stored together with the ratchet state
+#[derive(Deserialize)]
+struct LegacyDecryptedMessages {
+    #[serde(default)]
+    decrypted_messages: HashMap<String, String>,
+}
+
+/// Returns the plaintexts a session file of an earlier version contains, by SHA-256 of the ciphertext
+/// <br>They are not part of the session anymore and are gone from the file once the session is saved again
+pub fn load_legacy_decrypted_messages(path: &Path) -> HashMap<String, String> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<LegacyDecryptedMessages>(&json).ok())
+        .map(|legacy| legacy.decrypted_messages)
+        .unwrap_or_default()
+}
+
+/// Continues the stored ratchet session of the chatroom or starts a new one
+/// <br>A new session is started if one of the identity keys changed
+pub fn open_ratchet_session(
+    path: &Path,
+    key_pair: &IdentityKeyPair,
+    own_user_id: u32,
+    other_public_key_hex: &str,
+    other_user_id: u32,
+    chatroom_id: u32,
+) -> Result<RatchetSession, Box<dyn Error>> {
+    if let Some(session) = load_ratchet_session(path) {
+        if session.matches_identity_keys(&key_pair.get_public_key_hex(), other_public_key_hex) {
+            return Ok(session);
+        }
+    }
+
+    let session = RatchetSession::new(
+        key_pair,
+        own_user_id,
+        other_public_key_hex,
+        other_user_id,
+        chatroom_id,
+    )?;
+    save_ratchet_session(path, &session)?;
+    Ok(session)
+}
+
+/// Loads the identity key of the user or generates a new one on the first login on this device
+/// <br>The public key is published if the server does not have it yet. A key another device published is only replaced
+/// if the user agrees, because that device can not decrypt new messages afterwards.
+/// To use the same key on several devices, the key file is copied from the first device
+pub async fn ensure_identity_key(
+    connection: &mut ServerConnection,
+    user: &User,
+) -> Result<IdentityKeyPair, Box<dyn Error>> {
+    let path = get_identity_key_file_path(user.get_name());
+    let stored_key_pair = load_identity_key(&path);
+    let is_new = stored_key_pair.is_none();
+    let key_pair = stored_key_pair.unwrap_or_else(IdentityKeyPair::generate);
+
+    let public_key = key_pair.get_public_key_hex();
+    let published_key = connection.get_identity_key(user.get_id()).await?;
+    if published_key.is_some()
+        && published_key != Some(public_key.clone())
+        && !helper_functions::ask_user_yes_no_question(&format!(
+            "Another device already published an identity key for {}. \
+            Replacing it means that the other device can not read new messages anymore \
+            and your friends have to verify your safety number again. \
+            To use the same key, copy {} from the other device instead. \
+            Do you want to replace the key?",
+            user.get_name(),
+            path.display()
+        ))
+    {
+        return Err(format!(
+            "The identity key was not replaced, copy {} from the device that published it.",
+            path.display()
+        )
+        .into());
+    }
+
+    if is_new {
+        save_identity_key(&path, &key_pair)?;
+    }
+    if published_key != Some(public_key.clone()) {
+        connection.publish_identity_key(&public_key).await?;
+    }
+    Ok(key_pair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chatclient_identity_test_{}", name))
+    }
+
+    #[test]
+    fn test_save_and_load_identity_key() {
+        let path = get_test_path("save_and_load");
+        let key_pair = IdentityKeyPair::generate();
+        save_identity_key(&path, &key_pair).unwrap();
+
+        let loaded = load_identity_key(&path).unwrap();
+        assert_eq!(loaded.get_public_key_hex(), key_pair.get_public_key_hex());
+        fs::remove_file(&path).unwrap();
+        assert!(load_identity_key(&path).is_none());
+    }
+
+    #[test]
+    fn test_load_invalid_identity_key() {
+        let path = get_test_path("invalid");
+        fs::write(&path, "no key").unwrap();
+        assert!(load_identity_key(&path).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load_verified_key() {
+        let path = get_test_path("verified");
+        _ = fs::remove_file(&path);
+        assert_eq!(load_verified_key(&path, 2), None);
+
+        save_verified_key(&path, 2, "abc").unwrap();
+        save_verified_key(&path, 3, "def").unwrap();
+        assert_eq!(load_verified_key(&path, 2), Some("abc".to_string()));
+        assert_eq!(load_verified_key(&path, 3), Some("def".to_string()));
+
+        // Verifying a new key of the contact replaces the old one
+        save_verified_key(&path, 2, "ghi").unwrap();
+        assert_eq!(load_verified_key(&path, 2), Some("ghi".to_string()));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_ratchet_session() {
+        let path = get_test_path("ratchet");
+        _ = fs::remove_file(&path);
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let mut anton_session =
+            RatchetSession::new(&anton, 2, &rino.get_public_key_hex(), 1, 1).unwrap();
+
+        // The session of rino is stored and continued after a restart
+        let mut session =
+            open_ratchet_session(&path, &rino, 1, &anton.get_public_key_hex(), 2, 1).unwrap();
+        let message = session.encrypt("Hallo, Anton!");
+        save_ratchet_session(&path, &session).unwrap();
+        let mut restored =
+            open_ratchet_session(&path, &rino, 1, &anton.get_public_key_hex(), 2, 1).unwrap();
+        assert_eq!(restored, session);
+        assert_eq!(anton_session.decrypt(&message).unwrap(), "Hallo, Anton!");
+        let answer = anton_session.encrypt("Hallo, Rino!");
+        assert_eq!(restored.decrypt(&answer).unwrap(), "Hallo, Rino!");
+        save_ratchet_session(&path, &restored).unwrap();
+
+        // A new key of anton starts a new session
+        let new_anton = IdentityKeyPair::generate();
+        let new_session =
+            open_ratchet_session(&path, &rino, 1, &new_anton.get_public_key_hex(), 2, 1).unwrap();
+        assert_ne!(new_session, restored);
+        assert_eq!(load_ratchet_session(&path), Some(new_session));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_legacy_decrypted_messages() {
+        let path = get_test_path("legacy_ratchet");
+        _ = fs::remove_file(&path);
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let session =
+            open_ratchet_session(&path, &rino, 1, &anton.get_public_key_hex(), 2, 1).unwrap();
+        assert!(load_legacy_decrypted_messages(&path).is_empty());
+
+        // Earlier versions stored the plaintexts in the session file
+        let mut json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        json["decrypted_messages"] = serde_json::json!({ "abc": "Hallo, Anton!" });
+        fs::write(&path, json.to_string()).unwrap();
+        assert_eq!(
+            load_legacy_decrypted_messages(&path),
+            HashMap::from([("abc".to_string(), "Hallo, Anton!".to_string())])
+        );
+        assert_eq!(load_ratchet_session(&path), Some(session.clone()));
+
+        save_ratchet_session(&path, &session).unwrap();
+        assert!(load_legacy_decrypted_messages(&path).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use crate::helper_functions;
-use crate::sql_interaction;
+use crate::server_connection::{LoginResult, ServerConnection};
+use crate::session_store;
 use crate::structs::user::User;
 use colored::Colorize;
 use regex::Regex;
@@ -14,13 +15,12 @@ extern crate bcrypt;
 extern crate sha2;
 
 ///Start the authentication process when a client starts the application
-pub async fn start_authentication_process_for_client() -> Option<User> {
+pub async fn start_authentication_process_for_client(
+    connection: &mut ServerConnection,
+) -> Option<User> {
     _ = helper_functions::clear_console();
 
-    println!(
-        "{}",
-        "Welcome to our ChatClient!".bold().purple()
-    );
+    println!("{}", "Welcome to our ChatClient!".bold().purple());
 
     let choices = vec![
         "Login".to_string(),
@@ -34,13 +34,13 @@ pub async fn start_authentication_process_for_client() -> Option<User> {
             // User chose login
             _ = helper_functions::clear_console();
             // return current user
-            user_chose_login().await
+            user_chose_login(connection).await
         }
         1 => {
             // User chose sign up
             _ = helper_functions::clear_console();
             // return current user
-            user_chose_signup().await
+            user_chose_signup(connection).await
         }
         2 => {
             // User chose Exit
@@ -52,6 +52,23 @@ pub async fn start_authentication_process_for_client() -> Option<User> {
     }
 }
 
+///Logs in with the session that was stored on this device, if it is still valid
+pub async fn login_with_stored_session(connection: &mut ServerConnection) -> Option<User> {
+    let session_file = session_store::get_session_file_path();
+    let session_token = session_store::load_session_token(&session_file)?;
+
+    match connection.resume_session(&session_token).await {
+        Ok(user) => Some(user),
+        Err(e) => {
+            // Expired or revoked sessions can not be used again
+            _ = session_store::delete_session_token(&session_file);
+            _ = helper_functions::print_info(&format!("{}", e));
+            thread::sleep(Duration::from_secs(2));
+            None
+        }
+    }
+}
+
 ///Hashes a string<br/>
 ///Used to hash passwords that can be stored in database
 pub fn hash_password(password: &str) -> String {
@@ -65,21 +82,25 @@ pub fn hash_password(password: &str) -> String {
 }
 
 ///Lets the user login
-async fn user_chose_login() -> Option<User> {
+async fn user_chose_login(connection: &mut ServerConnection) -> Option<User> {
     let mut username: String;
     loop {
         //Get valid username from user input
-        username = enter_username_for_login().await;
+        username = enter_username_for_login(connection).await?;
 
         if username.is_empty() {
             //user wants to return
             return None;
         } else {
             //Check if user is blocked
-            if sql_interaction::check_if_user_isblocked(username.clone())
-                .await
-                .unwrap()
-            {
+            let is_blocked = match connection.check_if_user_isblocked(&username).await {
+                Ok(is_blocked) => is_blocked,
+                Err(e) => {
+                    print_connection_error(e.as_ref());
+                    return None;
+                }
+            };
+            if is_blocked {
                 for second in (1..=5).rev() {
                     _ = helper_functions::clear_console();
                     let mut msg = format!(
@@ -94,44 +115,44 @@ async fn user_chose_login() -> Option<User> {
 
                 //Return to main menu
                 return None;
-            } else if !enter_password_for_login(username.clone()).await
-                && !sql_interaction::check_if_user_isblocked(username.clone())
-                    .await
-                    .unwrap()
-            {
-                _ = helper_functions::clear_console();
-                continue;
-            } else {
-                // TODO: Error handling
-                let user: User = sql_interaction::get_user_from_database_by_name(username.clone())
-                    .await
-                    .unwrap();
+            }
 
-                return Some(user);
+            let remember_me = helper_functions::ask_user_yes_no_question(
+                "Do you want to stay logged in on this device?",
+            );
+
+            match enter_password_for_login(connection, username.clone(), remember_me).await {
+                PasswordEntry::LoggedIn(user) => return Some(user),
+                PasswordEntry::Returned => {
+                    _ = helper_functions::clear_console();
+                    continue;
+                }
+                PasswordEntry::Blocked | PasswordEntry::Failed => return None,
             }
         }
     }
 }
 
 ///Allows the user to create a new account
-async fn user_chose_signup() -> Option<User> {
+async fn user_chose_signup(connection: &mut ServerConnection) -> Option<User> {
     // Get valid username from user input
-    let username: String = enter_username_for_signup().await?;
+    let username: String = enter_username_for_signup(connection).await?;
 
     let new_password = enter_new_password_for_signup()?;
     let new_password_str: String = new_password.trim().to_string();
 
     if repeat_password_for_signup(&new_password_str).is_some() {
-        sql_interaction::save_new_user_to_database_after_signup(
-            &username,
-            &hash_password(&new_password_str),
-        )
-        .await
-        .expect("TODO: Could not save user to database");
-
-        let user: User = sql_interaction::get_user_from_database_by_name(username.clone())
+        let user = match connection
+            .sign_up(&username, &hash_password(&new_password_str))
             .await
-            .unwrap();
+        {
+            Ok(user) => user,
+            Err(e) => {
+                _ = helper_functions::print_error(&format!("Could not create account: {}", e));
+                thread::sleep(Duration::from_secs(2));
+                return None;
+            }
+        };
 
         _ = helper_functions::print_confirmation("Account successfully created!");
         thread::sleep(Duration::from_secs(2));
@@ -143,8 +164,15 @@ async fn user_chose_signup() -> Option<User> {
     }
 }
 
+///Tells the user that the server could not be asked, before returning to the main menu
+fn print_connection_error(e: &dyn std::error::Error) {
+    _ = helper_functions::print_error(&format!("Connection to the server failed: {}", e));
+    thread::sleep(Duration::from_secs(2));
+}
+
 ///Ask user to enter a username until user entered a valid username on login
-async fn enter_username_for_login() -> String {
+/// <br>Returns None if the server could not be asked if the username exists
+async fn enter_username_for_login(connection: &mut ServerConnection) -> Option<String> {
     let mut username;
 
     //Enter new username until username is valid --> check if user exists in db to avoid SQL-Injection
@@ -166,9 +194,13 @@ async fn enter_username_for_login() -> String {
             break;
         }
 
-        let username_does_exist = sql_interaction::check_if_username_exists(username.trim())
-            .await
-            .unwrap();
+        let username_does_exist = match connection.check_if_username_exists(username.trim()).await {
+            Ok(username_does_exist) => username_does_exist,
+            Err(e) => {
+                print_connection_error(e.as_ref());
+                return None;
+            }
+        };
 
         if !username_does_exist {
             _ = helper_functions::print_error("Unknown username!\n");
@@ -185,76 +217,122 @@ async fn enter_username_for_login() -> String {
         }
     }
 
-    username
+    Some(username)
+}
+
+/// Result of entering the password on login
+enum PasswordEntry {
+    LoggedIn(User),
+    Returned,
+    Blocked,
+    /// The connection to the server failed
+    Failed,
 }
 
 ///Ask user to enter the password for the account with the username to login
-async fn enter_password_for_login(username: String) -> bool {
-    let mut tries_for_password = 0;
+/// <br>If `remember_me` is set, the session is stored on this device for automatic login
+async fn enter_password_for_login(
+    connection: &mut ServerConnection,
+    username: String,
+    remember_me: bool,
+) -> PasswordEntry {
+    let mut tries_left = None;
 
     loop {
         _ = helper_functions::clear_console();
 
-        if tries_for_password > 0 {
-            let msg = format!(
-                "Password is incorrect (Tries left: {})",
-                3 - tries_for_password
-            );
+        if let Some(tries_left) = tries_left {
+            let msg = format!("Password is incorrect (Tries left: {})", tries_left);
             _ = helper_functions::print_error(&msg);
         }
 
         _ = helper_functions::print_info("Please enter your password: (leave blank to return)");
         let password = read_password().expect("Error while reading password");
-        // let password = "Ifuckingloverust1";
 
         if password.trim() == "" {
-            return false;
+            return PasswordEntry::Returned;
         }
 
-        if is_corresponding_to_the_password_regulations(&password)
-            && is_avoiding_sql_injection(&password)
-            && sql_interaction::check_if_password_matches_username(
-                hash_password(password.trim()).as_str(),
-                username.as_str(),
-            )
-            .await
-            .unwrap()
-        {
-            return true;
-        }
-
-        tries_for_password += 1;
-
-        if tries_for_password == 3 {
-            //Block user -> refresh IsBlocked in database
-            sql_interaction::set_user_isblocked(username.clone(), 20)
-                .await
-                .expect("Could not update IsBlocked value");
-
-            //User needed too many tries --> block account and return to main menu
-            for second in (1..=5).rev().step_by(2) {
-                let remaining_seconds = sql_interaction::get_user_is_blockeduntil(username.clone())
-                    .await
-                    .unwrap()
-                    - helper_functions::get_sys_time_in_secs();
-                _ = helper_functions::clear_console();
-                let mut msg = format!(
-                    "Account {} was blocked for {} more seconds due to too many failed attempts",
-                    username, remaining_seconds
+        // A one-time password is replaced by a new password before the user is logged in
+        let password_hash = hash_password(password.trim());
+        let mut new_password_hash = None;
+        let login_result = loop {
+            let result = connection
+                .login(
+                    &username,
+                    &password_hash,
+                    remember_me,
+                    new_password_hash.as_deref(),
+                )
+                .await;
+            match result {
+                Ok(LoginResult::PasswordChangeRequired) if new_password_hash.is_none() => {
+                    _ = helper_functions::print_info(
+                        "You logged in with a one-time password. Please choose a new password.",
+                    );
+                    thread::sleep(Duration::from_secs(2));
+                    let Some(new_password) = enter_new_password_for_signup() else {
+                        return PasswordEntry::Returned;
+                    };
+                    if repeat_password_for_signup(new_password.trim()).is_none() {
+                        return PasswordEntry::Returned;
+                    }
+                    new_password_hash = Some(hash_password(new_password.trim()));
+                }
+                Ok(login_result) => break login_result,
+                Err(e) => {
+                    print_connection_error(e.as_ref());
+                    return PasswordEntry::Failed;
+                }
+            }
+        };
+        match login_result {
+            LoginResult::LoggedIn(user) => {
+                if remember_me {
+                    store_session_token(connection);
+                }
+                return PasswordEntry::LoggedIn(user);
+            }
+            LoginResult::WrongPassword { tries_left: left } => tries_left = Some(left),
+            LoginResult::PasswordChangeRequired => {
+                _ = helper_functions::print_error(
+                    "The new password has to differ from the one-time password.",
                 );
-                println!("{}", msg.bold().red());
-                msg = format!("Returning to main menu in {}...", second);
-                println!("{}", msg.bold());
-                thread::sleep(Duration::from_secs(1));
+                thread::sleep(Duration::from_secs(2));
             }
+            LoginResult::Blocked { remaining_secs } => {
+                //User needed too many tries --> account got blocked, return to main menu
+                for second in (1..=5).rev().step_by(2) {
+                    _ = helper_functions::clear_console();
+                    let mut msg = format!(
+                        "Account {} was blocked for {} more seconds due to too many failed attempts",
+                        username, remaining_secs
+                    );
+                    println!("{}", msg.bold().red());
+                    msg = format!("Returning to main menu in {}...", second);
+                    println!("{}", msg.bold());
+                    thread::sleep(Duration::from_secs(1));
+                }
 
-            return false;
+                return PasswordEntry::Blocked;
+            }
+        }
+    }
+}
+
+///Stores the session token of the connection for automatic login on the next start
+fn store_session_token(connection: &ServerConnection) {
+    if let Some(session_token) = connection.get_session_token() {
+        let session_file = session_store::get_session_file_path();
+        if let Err(e) = session_store::save_session_token(&session_file, session_token) {
+            _ = helper_functions::print_error(&format!("Could not save session: {}", e));
+            thread::sleep(Duration::from_secs(2));
         }
     }
 }
 
 ///Ask user to enter a username to create a new account on signup
-async fn enter_username_for_signup() -> Option<String> {
+async fn enter_username_for_signup(connection: &mut ServerConnection) -> Option<String> {
     _ = helper_functions::clear_console();
     let mut username;
 
@@ -288,10 +366,15 @@ async fn enter_username_for_signup() -> Option<String> {
             _ = helper_functions::print_error(error_message);
             thread::sleep(Duration::from_secs(2));
             continue;
-        } else if sql_interaction::check_if_username_exists(username.trim())
-            .await
-            .unwrap()
-        {
+        }
+        let username_is_taken = match connection.check_if_username_exists(username.trim()).await {
+            Ok(username_is_taken) => username_is_taken,
+            Err(e) => {
+                print_connection_error(e.as_ref());
+                return None;
+            }
+        };
+        if username_is_taken {
             let error_message = "This username is already taken. Please try again.";
             _ = helper_functions::print_error(error_message);
             thread::sleep(Duration::from_secs(2));
@@ -375,20 +458,9 @@ fn repeat_password_for_signup(password: &str) -> Option<String> {
 }
 
 ///Checks if a string (username) meets all username criterias
+/// <br>The server checks the same rules on signup
 fn is_corresponding_to_the_username_regulations(username: &str) -> bool {
-    //Check if username is long enough
-    if username.len() < 3 {
-        return false;
-    }
-
-    //Check if all characters are alphanumeric
-    for c in username.chars() {
-        if !c.is_alphanumeric() {
-            return false; //username not valid if a char is not alphanumeric
-        }
-    }
-
-    true
+    User::is_valid_name(username)
 }
 
 ///Checks if a string (password) meets all password criterias
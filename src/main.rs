@@ -1,40 +1,169 @@
+use crate::chat_export::{ChatExport, ExportFormat, ImportSummary};
 use crate::client::ClientState;
-use std::{env, process::exit};
+use crate::config::{Cli, ClientArgs, Command, Config};
+use clap::Parser;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
 use tokio::runtime::Runtime;
 
 extern crate block_padding;
 
+mod chat_export;
+mod chat_input;
 mod chat_menu;
 mod client;
+mod config;
+mod crypto;
 mod helper_functions;
+mod key_store;
 mod login;
+mod message_edits;
+mod message_index;
+mod migrations;
+mod moderation;
+mod password_hashing;
+mod presence;
+mod protocol;
+mod ratchet;
+mod receipts;
+mod request_handler;
 mod server;
+mod server_connection;
+mod session_store;
 mod sql_interaction;
+mod sqlite_storage;
+mod storage;
+#[cfg(test)]
+mod test_support;
+mod tls;
 
 mod structs {
     pub mod chat_room;
     pub mod friend_request;
     pub mod message;
+    pub mod presence;
+    pub mod receipt;
+    pub mod room_role;
+    pub mod session_info;
     pub mod user;
 }
 
 fn main() {
+    let cli = Cli::parse();
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
     let rt = Runtime::new().unwrap();
     rt.block_on(async {
-        let args: Vec<String> = env::args().collect();
-        if args.len() > 1 && args[1] == "server" {
-            match server::run().await {
-                Ok(_) => println!("Server exited successfully."),
-                Err(e) => eprintln!("Server exited with error: {}", e),
+        match cli
+            .command
+            .unwrap_or(Command::Client(ClientArgs::default()))
+        {
+            Command::Server(args) => {
+                args.apply_to(&mut config.server);
+                match server::run(config.server).await {
+                    Ok(_) => println!("Server exited successfully."),
+                    Err(e) => {
+                        eprintln!("Server exited with error: {}", e);
+                        exit(1);
+                    }
+                }
+            }
+            Command::Migrate(args) => {
+                args.apply_to(&mut config.server);
+                match migrations::run(&config.server.database_url) {
+                    Ok(_) => println!("Migration finished successfully."),
+                    Err(e) => {
+                        eprintln!("Migration failed: {}", e);
+                        exit(1);
+                    }
+                }
+            }
+            Command::Export(args) => {
+                args.database.apply_to(&mut config.server);
+                if let Err(e) = export_chatroom(
+                    &config.server.database_url,
+                    args.chatroom_id,
+                    args.format,
+                    args.output.as_deref(),
+                ) {
+                    eprintln!("Export failed: {}", e);
+                    exit(1);
+                }
+            }
+            Command::Import(args) => {
+                args.database.apply_to(&mut config.server);
+                match import_chatroom(&config.server.database_url, &args.input).await {
+                    Ok(summary) => {
+                        println!(
+                            "Import finished successfully, {} messages were restored.",
+                            summary.message_count
+                        );
+                        if !summary.one_time_passwords.is_empty() {
+                            println!("These users were created and have to choose a new password when they log in with their one-time password:");
+                            for (username, one_time_password) in &summary.one_time_passwords {
+                                println!("  {}: {}", username, one_time_password);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Import failed: {}", e);
+                        exit(1);
+                    }
+                }
             }
-            exit(0);
-        } else {
-            let current_state = ClientState::AuthenticationMenu;
-            match client::run(current_state).await {
-                Ok(_) => println!("Client exited successfully."),
-                Err(e) => eprintln!("Client exited with error: {}", e),
+            Command::Client(args) => {
+                args.apply_to(&mut config.client);
+                let current_state = ClientState::AuthenticationMenu;
+                match client::run(current_state, config.client).await {
+                    Ok(_) => println!("Client exited successfully."),
+                    Err(e) => eprintln!("Client exited with error: {}", e),
+                }
             }
-            exit(0);
         }
+        exit(0);
     });
 }
+
+/// Writes the export of the chatroom to the file, or to the console without a file
+fn export_chatroom(
+    database_url: &str,
+    chatroom_id: u32,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let storage = storage::open_storage(database_url)?;
+    let export = chat_export::export_chatroom(storage.as_ref(), chatroom_id)?;
+    let text = export.render(format)?;
+    match output {
+        Some(path) => {
+            fs::write(path, text)?;
+            println!(
+                "Exported {} messages to {}.",
+                export.messages.len(),
+                path.display()
+            );
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Restores the JSON export in the file
+async fn import_chatroom(
+    database_url: &str,
+    input: &Path,
+) -> Result<ImportSummary, Box<dyn Error + Send + Sync>> {
+    let text = fs::read_to_string(input)
+        .map_err(|e| format!("Could not read {}: {}", input.display(), e))?;
+    let export = ChatExport::from_json(&text)?;
+    let storage = storage::open_storage(database_url)?;
+    chat_export::import_chatroom(storage.as_ref(), &export).await
+}
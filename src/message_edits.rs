@@ -0,0 +1,233 @@
+use crate::storage::{Storage, StorageResult};
+use crate::structs::message::{ChatMessage, MessageEdit};
+use crate::structs::user::User;
+
+/// Time in seconds after sending in which the sender can edit or delete a message, one day
+pub const EDIT_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// Result of an edit or deletion of a message
+#[derive(Debug, PartialEq)]
+pub enum ChangeOutcome {
+    /// The change was saved, the changed message is sent to everybody in the chatroom
+    Changed(ChatMessage),
+    /// The change is not allowed, the message is sent back to the user
+    Rejected(String),
+}
+
+/// Replaces the content of a message of the user, the content it had before is kept in the edit history
+pub fn edit_message(
+    storage: &dyn Storage,
+    chatroom_id: u32,
+    user: &User,
+    message_id: u32,
+    content: &str,
+    now: u64,
+) -> StorageResult<ChangeOutcome> {
+    if let Some(rejection) = check_own_recent_message(storage, chatroom_id, user, message_id, now)?
+    {
+        return Ok(ChangeOutcome::Rejected(rejection));
+    }
+    let sent_after = now.saturating_sub(EDIT_WINDOW_SECS);
+    let edited = storage.edit_chat_message(message_id, user.get_id(), sent_after, content, now)?;
+    get_outcome(storage, chatroom_id, user, message_id, now, edited)
+}
+
+/// Deletes a message of the user, only a tombstone without content stays in the history
+pub fn delete_message(
+    storage: &dyn Storage,
+    chatroom_id: u32,
+    user: &User,
+    message_id: u32,
+    now: u64,
+) -> StorageResult<ChangeOutcome> {
+    if let Some(rejection) = check_own_recent_message(storage, chatroom_id, user, message_id, now)?
+    {
+        return Ok(ChangeOutcome::Rejected(rejection));
+    }
+    let sent_after = now.saturating_sub(EDIT_WINDOW_SECS);
+    let deleted = storage.delete_chat_message(message_id, user.get_id(), sent_after, now)?;
+    get_outcome(storage, chatroom_id, user, message_id, now, deleted)
+}
+
+/// Returns the contents the message had before it was edited, the oldest first
+/// <br>Every member of the chatroom can read the history, the contents are encrypted like the message
+pub fn get_edit_history(
+    storage: &dyn Storage,
+    chatroom_id: u32,
+    message_id: u32,
+) -> StorageResult<Result<Vec<MessageEdit>, String>> {
+    match storage.get_chat_message_by_id(message_id)? {
+        Some(message) if message.get_chatroom_id() == chatroom_id => {
+            Ok(Ok(storage.get_chat_message_edits(message_id)?))
+        }
+        _ => Ok(Err("There is no such message in this chatroom.".to_string())),
+    }
+}
+
+/// The storage only changes the message if it can still be changed when it is written,
+/// so another change in the meantime is caught and explained like before
+fn get_outcome(
+    storage: &dyn Storage,
+    chatroom_id: u32,
+    user: &User,
+    message_id: u32,
+    now: u64,
+    changed: Option<ChatMessage>,
+) -> StorageResult<ChangeOutcome> {
+    if let Some(message) = changed {
+        return Ok(ChangeOutcome::Changed(message));
+    }
+    let rejection = check_own_recent_message(storage, chatroom_id, user, message_id, now)?
+        .unwrap_or_else(|| {
+            "The message was changed at the same time, please try again.".to_string()
+        });
+    Ok(ChangeOutcome::Rejected(rejection))
+}
+
+/// Returns why the user can not change the message, None if the user sent it to the chatroom recently and it was not deleted
+fn check_own_recent_message(
+    storage: &dyn Storage,
+    chatroom_id: u32,
+    user: &User,
+    message_id: u32,
+    now: u64,
+) -> StorageResult<Option<String>> {
+    let rejection = match storage.get_chat_message_by_id(message_id)? {
+        Some(message) if message.get_chatroom_id() == chatroom_id => {
+            if message.get_sender_id() != Some(user.get_id()) {
+                Some("You can only change your own messages.")
+            } else if message.is_deleted() {
+                Some("The message was deleted already.")
+            } else if message.get_sent_at().unwrap_or(0) + EDIT_WINDOW_SECS < now {
+                Some("Messages can only be changed within a day after sending them.")
+            } else {
+                None
+            }
+        }
+        // Messages of other chatrooms are not revealed
+        _ => Some("There is no such message in this chatroom."),
+    };
+    Ok(rejection.map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper_functions;
+    use crate::test_support::TestStorageBuilder;
+    use std::sync::Arc;
+
+    /// Anton and rino share chatroom 1, anton and antonia chatroom 2
+    /// <br>Message 1 of anton and message 2 of rino are in chatroom 1, message 3 of anton in chatroom 2
+    fn get_test_storage() -> Arc<dyn Storage> {
+        TestStorageBuilder::new()
+            .user("anton")
+            .user("rino")
+            .user("antonia")
+            .chat_room("anton, rino", &["anton", "rino"])
+            .chat_room("anton, antonia", &["anton", "antonia"])
+            .message(1, "anton", "e2e2.first")
+            .message(1, "rino", "e2e2.second")
+            .message(2, "anton", "e2e2.third")
+            .build()
+    }
+
+    fn get_anton() -> User {
+        User::new(1, "anton".to_string())
+    }
+
+    #[test]
+    fn test_edit_own_message() {
+        let storage = get_test_storage();
+        let now = helper_functions::get_sys_time_in_secs();
+        match edit_message(storage.as_ref(), 1, &get_anton(), 1, "e2e2.edited", now).unwrap() {
+            ChangeOutcome::Changed(message) => {
+                assert_eq!(message.get_content(), "e2e2.edited");
+                assert_eq!(message.get_edited_at(), Some(now));
+                assert!(!message.is_deleted());
+            }
+            other => panic!("Expected changed message, got {:?}", other),
+        }
+        // The message can be edited again
+        assert!(matches!(
+            edit_message(storage.as_ref(), 1, &get_anton(), 1, "e2e2.again", now).unwrap(),
+            ChangeOutcome::Changed(_)
+        ));
+    }
+
+    #[test]
+    fn test_delete_own_message() {
+        let storage = get_test_storage();
+        let now = helper_functions::get_sys_time_in_secs();
+        match delete_message(storage.as_ref(), 1, &get_anton(), 1, now).unwrap() {
+            ChangeOutcome::Changed(message) => {
+                assert_eq!(message.get_content(), "");
+                assert_eq!(message.get_deleted_at(), Some(now));
+            }
+            other => panic!("Expected changed message, got {:?}", other),
+        }
+        // Deleted messages can neither be edited nor deleted again
+        assert!(matches!(
+            edit_message(storage.as_ref(), 1, &get_anton(), 1, "e2e2.edited", now).unwrap(),
+            ChangeOutcome::Rejected(_)
+        ));
+        assert!(matches!(
+            delete_message(storage.as_ref(), 1, &get_anton(), 1, now).unwrap(),
+            ChangeOutcome::Rejected(_)
+        ));
+    }
+
+    #[test]
+    fn test_only_own_recent_messages_of_the_chatroom() {
+        let storage = get_test_storage();
+        let now = helper_functions::get_sys_time_in_secs();
+        let anton = get_anton();
+        // Message of rino
+        assert_eq!(
+            delete_message(storage.as_ref(), 1, &anton, 2, now).unwrap(),
+            ChangeOutcome::Rejected("You can only change your own messages.".to_string())
+        );
+        // Message of another chatroom and a message that does not exist
+        for message_id in [3, 99] {
+            assert_eq!(
+                edit_message(storage.as_ref(), 1, &anton, message_id, "e2e2.edited", now).unwrap(),
+                ChangeOutcome::Rejected("There is no such message in this chatroom.".to_string())
+            );
+        }
+        // A day later the message can not be changed anymore
+        assert!(matches!(
+            delete_message(storage.as_ref(), 1, &anton, 1, now + EDIT_WINDOW_SECS + 60).unwrap(),
+            ChangeOutcome::Rejected(_)
+        ));
+        assert_eq!(
+            storage
+                .get_chat_message_by_id(1)
+                .unwrap()
+                .unwrap()
+                .get_content(),
+            "e2e2.first"
+        );
+    }
+
+    #[test]
+    fn test_get_edit_history() {
+        let storage = get_test_storage();
+        let now = helper_functions::get_sys_time_in_secs();
+        edit_message(storage.as_ref(), 1, &get_anton(), 1, "e2e2.edited", now).unwrap();
+        edit_message(storage.as_ref(), 1, &get_anton(), 1, "e2e2.again", now + 1).unwrap();
+        let edits = get_edit_history(storage.as_ref(), 1, 1).unwrap().unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                MessageEdit::new("e2e2.first".to_string(), now),
+                MessageEdit::new("e2e2.edited".to_string(), now + 1)
+            ]
+        );
+        // Messages without edits have an empty history, messages of other chatrooms have none
+        assert_eq!(
+            get_edit_history(storage.as_ref(), 1, 2).unwrap(),
+            Ok(vec![])
+        );
+        assert!(get_edit_history(storage.as_ref(), 1, 3).unwrap().is_err());
+    }
+}
@@ -0,0 +1,489 @@
+use crate::helper_functions::encode_hex;
+use crate::ratchet;
+use crate::session_store;
+use crate::structs::message::ChatMessage;
+use rusqlite::{named_params, params_from_iter, Connection, OptionalExtension, ToSql};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Start of the name of the file the search index of the user is stored in, the username is appended
+const MESSAGE_INDEX_FILE_PREFIX: &str = ".chatclient_messages_";
+
+/// Marks the start of a search term in the snippet of a hit
+pub const HIGHLIGHT_START: char = '\u{2}';
+
+/// Marks the end of a search term in the snippet of a hit
+pub const HIGHLIGHT_END: char = '\u{3}';
+
+/// Number of words around the search terms the snippet of a hit shows
+const SNIPPET_WORDS: u32 = 10;
+
+/// Full-text index of the chat messages this device decrypted
+/// <br>The server only has the encrypted messages, so the messages can only be searched on the devices of the members.
+/// Every message the chat view shows is added
+const CREATE_INDEX_TABLE: &str = r"
+CREATE VIRTUAL TABLE IF NOT EXISTS messages USING fts5(
+    Text,
+    Chat_Id UNINDEXED,
+    SenderName UNINDEXED,
+    SentAt UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+";
+
+/// Plaintexts of the messages this device sent or decrypted, by SHA-256 of the ratchet message
+/// <br>A ratchet session can decrypt every message only once, so the history is shown again from here
+const CREATE_DECRYPTED_MESSAGES_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS decrypted_messages (
+    ContentHash TEXT PRIMARY KEY,
+    Text TEXT NOT NULL
+);
+";
+
+/// Ratchet messages each saved message consists of, by their SHA-256 like in decrypted_messages
+/// <br>A group message has one ratchet message for every other member.
+/// When a message is edited or deleted, the plaintexts of its earlier ratchet messages are removed with these rows
+const CREATE_MESSAGE_CONTENTS_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS message_contents (
+    MessageId INTEGER NOT NULL,
+    ContentHash TEXT NOT NULL,
+    PRIMARY KEY (MessageId, ContentHash)
+);
+";
+
+/// Returns the path of the file the search index of the user is stored in
+/// <br>Every user has its own file, so that several users can use the client on the same device
+pub fn get_message_index_file_path(username: &str) -> PathBuf {
+    session_store::get_path_in_home_directory(&format!(
+        "{}{}.sqlite",
+        MESSAGE_INDEX_FILE_PREFIX,
+        session_store::get_username_for_file_name(username)
+    ))
+}
+
+/// Message that matches a search, the matching terms in the snippet are put between `HIGHLIGHT_START` and `HIGHLIGHT_END`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub message_id: u32,
+    pub chatroom_id: u32,
+    pub sender_name: String,
+    pub sent_at: u64,
+    pub snippet: String,
+}
+
+/// Search index of the decrypted messages of a user, stored in a SQLite database on the device
+/// <br>It also keeps the plaintexts the chat view needs to show the history again
+pub struct MessageIndex {
+    conn: Connection,
+}
+
+impl MessageIndex {
+    /// Opens the index in the file, it is created if it does not exist
+    /// <br>The index contains the decrypted messages, so only the current user can read the file
+    pub fn open(path: &Path) -> Result<MessageIndex, Box<dyn Error>> {
+        session_store::create_private_file(path)?;
+        MessageIndex::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<MessageIndex, Box<dyn Error>> {
+        MessageIndex::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<MessageIndex, Box<dyn Error>> {
+        conn.execute_batch(CREATE_INDEX_TABLE)?;
+        conn.execute_batch(CREATE_DECRYPTED_MESSAGES_TABLE)?;
+        conn.execute_batch(CREATE_MESSAGE_CONTENTS_TABLE)?;
+        Ok(MessageIndex { conn })
+    }
+
+    /// Adds the decrypted text of a saved message, messages that are already in the index are skipped
+    pub fn add(&self, message: &ChatMessage, text: &str) -> Result<(), Box<dyn Error>> {
+        let (Some(message_id), Some(sent_at)) = (message.get_id(), message.get_sent_at()) else {
+            return Ok(());
+        };
+        let mut statement = self.conn.prepare(
+            "INSERT OR IGNORE INTO message_contents (MessageId, ContentHash) VALUES (?1, ?2)",
+        )?;
+        for content_hash in get_content_hashes(&message.get_content()) {
+            statement.execute((message_id, content_hash))?;
+        }
+        // The id of the message is the rowid, so that every message is only found once
+        self.conn.execute(
+            r"INSERT INTO messages (rowid, Text, Chat_Id, SenderName, SentAt)
+            SELECT :message_id, :text, :chatroom_id, :sender_name, :sent_at
+            WHERE NOT EXISTS (SELECT 1 FROM messages WHERE rowid = :message_id)",
+            named_params! {
+                ":message_id": message_id,
+                ":text": text,
+                ":chatroom_id": message.get_chatroom_id(),
+                ":sender_name": message.get_sender_name(),
+                ":sent_at": sent_at,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Keeps the plaintext of a ratchet message this device encrypted or decrypted, so that it can be shown again
+    pub fn remember_text(&self, content: &str, text: &str) -> Result<(), Box<dyn Error>> {
+        self.remember_hashed_texts(&HashMap::from([(hash_content(content), text.to_string())]))
+    }
+
+    /// Keeps plaintexts by the SHA-256 of their ratchet messages, like sessions of earlier versions stored them
+    pub fn remember_hashed_texts(
+        &self,
+        texts: &HashMap<String, String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut statement = self.conn.prepare(
+            "INSERT OR REPLACE INTO decrypted_messages (ContentHash, Text) VALUES (?1, ?2)",
+        )?;
+        for (content_hash, text) in texts {
+            statement.execute([content_hash, text])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the plaintext of a ratchet message this device encrypted or decrypted before
+    pub fn get_remembered_text(&self, content: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let text = self
+            .conn
+            .query_row(
+                "SELECT Text FROM decrypted_messages WHERE ContentHash = ?1",
+                [hash_content(content)],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(text)
+    }
+
+    /// Removes a deleted message together with its plaintexts, so that the text is not kept on the device
+    pub fn remove(&self, message_id: u32) -> Result<(), Box<dyn Error>> {
+        self.remove_contents_except(message_id, &[])
+    }
+
+    /// Removes the earlier text of an edited message together with its plaintexts
+    /// <br>The plaintext of the current content is kept, the message is added again with its new text
+    pub fn remove_earlier_contents(&self, message: &ChatMessage) -> Result<(), Box<dyn Error>> {
+        let Some(message_id) = message.get_id() else {
+            return Ok(());
+        };
+        self.remove_contents_except(message_id, &get_content_hashes(&message.get_content()))
+    }
+
+    fn remove_contents_except(
+        &self,
+        message_id: u32,
+        kept_hashes: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let content_hashes = self
+            .conn
+            .prepare("SELECT ContentHash FROM message_contents WHERE MessageId = ?1")?
+            .query_map([message_id], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        for content_hash in content_hashes
+            .iter()
+            .filter(|content_hash| !kept_hashes.contains(content_hash))
+        {
+            self.conn.execute(
+                "DELETE FROM decrypted_messages WHERE ContentHash = ?1",
+                [content_hash],
+            )?;
+            self.conn.execute(
+                "DELETE FROM message_contents WHERE MessageId = ?1 AND ContentHash = ?2",
+                (message_id, content_hash),
+            )?;
+        }
+        self.conn
+            .execute("DELETE FROM messages WHERE rowid = ?1", [message_id])?;
+        Ok(())
+    }
+
+    /// Returns the newest messages of the chatrooms that contain every word of the search, at most `limit`
+    /// <br>Words match the start of words in the messages, case and accents are ignored
+    pub fn search(
+        &self,
+        search: &str,
+        chatroom_ids: &[u32],
+        limit: u32,
+    ) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        let Some(match_query) = build_match_query(search) else {
+            return Ok(Vec::new());
+        };
+        if chatroom_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let chatroom_placeholders = vec!["?"; chatroom_ids.len()].join(", ");
+        let mut statement = self.conn.prepare(&format!(
+            r"SELECT rowid, Chat_Id, SenderName, SentAt, snippet(messages, 0, ?, ?, '…', ?)
+            FROM messages WHERE messages MATCH ? AND Chat_Id IN ({})
+            ORDER BY rowid DESC LIMIT ?",
+            chatroom_placeholders
+        ))?;
+        let highlight_start = HIGHLIGHT_START.to_string();
+        let highlight_end = HIGHLIGHT_END.to_string();
+        let mut params: Vec<&dyn ToSql> = vec![
+            &highlight_start,
+            &highlight_end,
+            &SNIPPET_WORDS,
+            &match_query,
+        ];
+        params.extend(chatroom_ids.iter().map(|id| id as &dyn ToSql));
+        params.push(&limit);
+
+        let hits = statement
+            .query_map(params_from_iter(params), |row| {
+                Ok(SearchHit {
+                    message_id: row.get(0)?,
+                    chatroom_id: row.get(1)?,
+                    sender_name: row.get(2)?,
+                    sent_at: row.get(3)?,
+                    snippet: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<SearchHit>, _>>()?;
+        Ok(hits)
+    }
+}
+
+/// Turns the words the user typed into a query of the full-text index, None if there are no words
+/// <br>Every word is quoted, so that characters with a meaning in the query syntax are searched for as they are
+fn build_match_query(search: &str) -> Option<String> {
+    let terms: Vec<String> = search
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    Some(terms.join(" "))
+}
+
+fn hash_content(content: &str) -> String {
+    encode_hex(&Sha256::digest(content.as_bytes()))
+}
+
+/// Returns the hashes of the ratchet messages the content of a message consists of
+fn get_content_hashes(content: &str) -> Vec<String> {
+    match ratchet::parse_group_message(content) {
+        Some(messages) => messages
+            .iter()
+            .map(|(_, message)| hash_content(message))
+            .collect(),
+        None => vec![hash_content(content)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn get_message(id: u32, chatroom_id: u32, sender_name: &str) -> ChatMessage {
+        ChatMessage::from_database(
+            id,
+            id,
+            chatroom_id,
+            Some((1, sender_name.to_string())),
+            1000 + id as u64,
+            String::new(),
+        )
+    }
+
+    fn get_test_index() -> MessageIndex {
+        let index = MessageIndex::open_in_memory().unwrap();
+        index
+            .add(
+                &get_message(1, 1, "rino"),
+                "Treffen wir uns morgen im Café?",
+            )
+            .unwrap();
+        index
+            .add(&get_message(2, 1, "anton"), "Ja, morgen um zehn")
+            .unwrap();
+        index
+            .add(&get_message(3, 2, "antonia"), "Das Treffen ist verschoben")
+            .unwrap();
+        index
+    }
+
+    #[test]
+    fn test_search_across_chatrooms() {
+        let index = get_test_index();
+        let hits = index.search("treffen", &[1, 2], 10).unwrap();
+        assert_eq!(
+            hits.iter().map(|hit| hit.message_id).collect::<Vec<u32>>(),
+            vec![3, 1]
+        );
+        assert_eq!(
+            hits[1],
+            SearchHit {
+                message_id: 1,
+                chatroom_id: 1,
+                sender_name: "rino".to_string(),
+                sent_at: 1001,
+                snippet: "\u{2}Treffen\u{3} wir uns morgen im Café?".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_search_only_in_given_chatrooms() {
+        let index = get_test_index();
+        let hits = index.search("treffen", &[1], 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].chatroom_id, 1);
+        assert!(index.search("treffen", &[], 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_needs_every_word() {
+        let index = get_test_index();
+        let hits = index.search("morgen zeh", &[1, 2], 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, 2);
+        // Accents and case are ignored
+        assert_eq!(index.search("CAFE", &[1, 2], 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_search_with_query_syntax() {
+        let index = get_test_index();
+        assert!(index
+            .search("\"Treffen OR", &[1, 2], 10)
+            .unwrap()
+            .is_empty());
+        assert!(index.search("   ", &[1, 2], 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_messages_are_only_added_once() {
+        let index = get_test_index();
+        index
+            .add(
+                &get_message(1, 1, "rino"),
+                "Treffen wir uns morgen im Café?",
+            )
+            .unwrap();
+        assert_eq!(index.search("treffen", &[1, 2], 10).unwrap().len(), 2);
+        // Messages that were not saved by the server yet have no id
+        let unsaved = ChatMessage::new(1, "rino".to_string(), String::new());
+        index.add(&unsaved, "Treffen").unwrap();
+        assert_eq!(index.search("treffen", &[1, 2], 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_remove_changed_messages() {
+        let index = get_test_index();
+        index.remove(1).unwrap();
+        assert!(index.search("café", &[1, 2], 10).unwrap().is_empty());
+        // An edited message is found by its new text
+        index
+            .add(&get_message(1, 1, "rino"), "Treffen wir uns im Park?")
+            .unwrap();
+        assert_eq!(index.search("park", &[1, 2], 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_remove_deleted_message_with_its_plaintext() {
+        let index = MessageIndex::open_in_memory().unwrap();
+        let message = ChatMessage::from_database(
+            1,
+            1,
+            1,
+            Some((1, "rino".to_string())),
+            1001,
+            "ciphertext".to_string(),
+        );
+        index.remember_text("ciphertext", "Geheim").unwrap();
+        index.add(&message, "Geheim").unwrap();
+
+        index.remove(1).unwrap();
+        assert_eq!(index.get_remembered_text("ciphertext").unwrap(), None);
+        assert!(index.search("geheim", &[1], 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_earlier_contents_of_edited_message() {
+        let index = MessageIndex::open_in_memory().unwrap();
+        let message = |content: &str| {
+            ChatMessage::from_database(
+                1,
+                1,
+                1,
+                Some((1, "rino".to_string())),
+                1001,
+                content.to_string(),
+            )
+        };
+        index.remember_text("old ciphertext", "Um zehn").unwrap();
+        index.add(&message("old ciphertext"), "Um zehn").unwrap();
+
+        let edited = message("new ciphertext");
+        index.remember_text("new ciphertext", "Um elf").unwrap();
+        index.remove_earlier_contents(&edited).unwrap();
+        index.add(&edited, "Um elf").unwrap();
+        assert_eq!(index.get_remembered_text("old ciphertext").unwrap(), None);
+        assert!(index.search("zehn", &[1], 10).unwrap().is_empty());
+
+        // Loading the edited message again keeps its text
+        index.remove_earlier_contents(&edited).unwrap();
+        index.add(&edited, "Um elf").unwrap();
+        assert_eq!(
+            index.get_remembered_text("new ciphertext").unwrap(),
+            Some("Um elf".to_string())
+        );
+        assert_eq!(index.search("elf", &[1], 10).unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_index_file_is_only_readable_by_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join("chatclient_message_index_test_permissions.sqlite");
+        _ = fs::remove_file(&path);
+        let index = MessageIndex::open(&path).unwrap();
+        index
+            .add(&get_message(1, 1, "rino"), "Treffen wir uns morgen?")
+            .unwrap();
+        drop(index);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Files of earlier versions are restricted as well, and keep their messages
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let index = MessageIndex::open(&path).unwrap();
+        assert_eq!(index.search("treffen", &[1], 10).unwrap().len(), 1);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(index);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remember_texts() {
+        let index = MessageIndex::open_in_memory().unwrap();
+        assert_eq!(index.get_remembered_text("e2e2.first").unwrap(), None);
+        index.remember_text("e2e2.first", "Hallo, Anton!").unwrap();
+        assert_eq!(
+            index.get_remembered_text("e2e2.first").unwrap(),
+            Some("Hallo, Anton!".to_string())
+        );
+        // Plaintexts of earlier versions are found by the same hash
+        index
+            .remember_hashed_texts(&HashMap::from([(
+                hash_content("e2e2.second"),
+                "Hallo, Rino!".to_string(),
+            )]))
+            .unwrap();
+        assert_eq!(
+            index.get_remembered_text("e2e2.second").unwrap(),
+            Some("Hallo, Rino!".to_string())
+        );
+        // The remembered texts are not searched
+        assert!(index.search("hallo", &[1], 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,459 @@
+use crate::storage::{self, Storage, StorageResult};
+
+/// Change of the database schema
+/// <br>Every backend has its own SQL, because MySQL and SQLite differ in details like auto increment
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub mysql: &'static str,
+    pub sqlite: &'static str,
+}
+
+/// All migrations in the order they are applied
+/// <br>Migrations that were released must never be changed, changes to the schema need a new migration
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        mysql: include_str!("migrations/mysql/0001_initial_schema.sql"),
+        sqlite: include_str!("migrations/sqlite/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "sessions",
+        mysql: include_str!("migrations/mysql/0002_sessions.sql"),
+        sqlite: include_str!("migrations/sqlite/0002_sessions.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "identity_keys",
+        mysql: include_str!("migrations/mysql/0003_identity_keys.sql"),
+        sqlite: include_str!("migrations/sqlite/0003_identity_keys.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "chat_members",
+        mysql: include_str!("migrations/mysql/0004_chat_members.sql"),
+        sqlite: include_str!("migrations/sqlite/0004_chat_members.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "public_rooms",
+        mysql: include_str!("migrations/mysql/0005_public_rooms.sql"),
+        sqlite: include_str!("migrations/sqlite/0005_public_rooms.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "room_roles",
+        mysql: include_str!("migrations/mysql/0006_room_roles.sql"),
+        sqlite: include_str!("migrations/sqlite/0006_room_roles.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "presence",
+        mysql: include_str!("migrations/mysql/0007_presence.sql"),
+        sqlite: include_str!("migrations/sqlite/0007_presence.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "message_receipts",
+        mysql: include_str!("migrations/mysql/0008_message_receipts.sql"),
+        sqlite: include_str!("migrations/sqlite/0008_message_receipts.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "last_read_messages",
+        mysql: include_str!("migrations/mysql/0009_last_read_messages.sql"),
+        sqlite: include_str!("migrations/sqlite/0009_last_read_messages.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "message_columns",
+        mysql: include_str!("migrations/mysql/0010_message_columns.sql"),
+        sqlite: include_str!("migrations/sqlite/0010_message_columns.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "message_edits",
+        mysql: include_str!("migrations/mysql/0011_message_edits.sql"),
+        sqlite: include_str!("migrations/sqlite/0011_message_edits.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "password_changes",
+        mysql: include_str!("migrations/mysql/0012_password_changes.sql"),
+        sqlite: include_str!("migrations/sqlite/0012_password_changes.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "public_room_names",
+        mysql: include_str!("migrations/mysql/0013_public_room_names.sql"),
+        sqlite: include_str!("migrations/sqlite/0013_public_room_names.sql"),
+    },
+];
+
+/// Change of the schema by a statement of a MySQL migration
+/// <br>MySQL commits every change of the schema on its own, so a change may be done already
+/// when an interrupted migration is run again. Changes are checked in `information_schema` before they are made
+#[derive(Debug, PartialEq)]
+pub enum SchemaChange<'a> {
+    /// `ALTER TABLE <table> ADD COLUMN <column> ...`, further columns of the statement are added together with the first
+    AddColumn { table: &'a str, column: &'a str },
+    /// `ALTER TABLE <table> DROP COLUMN <column>, ...`, the foreign keys of the columns are dropped first
+    DropColumns {
+        table: &'a str,
+        columns: Vec<&'a str>,
+    },
+    /// `CREATE [UNIQUE] INDEX <index> ON <table> ...`
+    CreateIndex { table: &'a str, index: &'a str },
+}
+
+/// Splits the SQL of a migration into its statements, without the comments
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let without_comments: String = sql
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    without_comments
+        .split(';')
+        .map(|statement| statement.trim().to_string())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// Returns the change of the schema the statement makes
+/// <br>None for statements that can always run again, like `CREATE TABLE IF NOT EXISTS`, and for changes of the data,
+/// which are run in a transaction
+pub fn get_schema_change(statement: &str) -> Option<SchemaChange<'_>> {
+    let words: Vec<&str> = statement
+        .split_whitespace()
+        .map(|word| word.trim_end_matches(','))
+        .collect();
+    let is_keyword = |index: usize, keyword: &str| {
+        words
+            .get(index)
+            .is_some_and(|word| word.eq_ignore_ascii_case(keyword))
+    };
+
+    if is_keyword(0, "ALTER") && is_keyword(1, "TABLE") {
+        let table = *words.get(2)?;
+        if is_keyword(3, "ADD") && is_keyword(4, "COLUMN") {
+            return Some(SchemaChange::AddColumn {
+                table,
+                column: words.get(5)?,
+            });
+        }
+        if is_keyword(3, "DROP") && is_keyword(4, "COLUMN") {
+            let columns = words[3..]
+                .windows(3)
+                .filter(|clause| {
+                    clause[0].eq_ignore_ascii_case("DROP")
+                        && clause[1].eq_ignore_ascii_case("COLUMN")
+                })
+                .map(|clause| clause[2])
+                .collect();
+            return Some(SchemaChange::DropColumns { table, columns });
+        }
+    }
+    let index_position = if is_keyword(1, "UNIQUE") { 2 } else { 1 };
+    if is_keyword(0, "CREATE")
+        && is_keyword(index_position, "INDEX")
+        && is_keyword(index_position + 2, "ON")
+    {
+        let table = words.get(index_position + 3)?.split('(').next()?;
+        return Some(SchemaChange::CreateIndex {
+            table,
+            index: words.get(index_position + 1)?,
+        });
+    }
+    None
+}
+
+/// Returns the schema version this build of the server needs
+pub fn get_latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Applies all migrations the database is missing
+/// <br>Returns the migrations that were applied
+pub fn migrate(storage: &dyn Storage) -> StorageResult<Vec<&'static Migration>> {
+    let current_version = storage.get_schema_version()?;
+    check_not_newer(current_version)?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current_version)
+    {
+        storage.apply_migration(migration)?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+/// Entry method for `src migrate`, brings the database up to date
+pub fn run(database_url: &str) -> StorageResult<()> {
+    let storage = storage::open_storage(database_url)?;
+    let applied = migrate(storage.as_ref())?;
+    if applied.is_empty() {
+        println!(
+            "Database schema is up to date (version {}).",
+            get_latest_version()
+        );
+    }
+    for migration in applied {
+        println!(
+            "Applied migration {:04}_{}",
+            migration.version, migration.name
+        );
+    }
+    Ok(())
+}
+
+/// Returns an error if the database schema does not match this build of the server
+pub fn check_schema_version(storage: &dyn Storage) -> StorageResult<()> {
+    let current_version = storage.get_schema_version()?;
+    check_not_newer(current_version)?;
+
+    if current_version < get_latest_version() {
+        return Err(format!(
+            "The database schema is out of date (version {}, needed {}). Run `src migrate` first.",
+            current_version,
+            get_latest_version()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns an error if the database was migrated by a newer build of the server
+fn check_not_newer(current_version: u32) -> StorageResult<()> {
+    if current_version > get_latest_version() {
+        return Err(format!(
+            "The database schema (version {}) is newer than this server supports (version {}).",
+            current_version,
+            get_latest_version()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_storage::SqliteStorage;
+    use crate::structs::message::ChatMessage;
+
+    #[test]
+    fn test_migration_versions_are_consecutive() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn test_get_schema_change() {
+        assert_eq!(
+            get_schema_change("ALTER TABLE users ADD COLUMN LastSeen INT NOT NULL DEFAULT 0"),
+            Some(SchemaChange::AddColumn {
+                table: "users",
+                column: "LastSeen"
+            })
+        );
+        assert_eq!(
+            get_schema_change("ALTER TABLE chats DROP COLUMN User1_Id, DROP COLUMN User2_Id"),
+            Some(SchemaChange::DropColumns {
+                table: "chats",
+                columns: vec!["User1_Id", "User2_Id"]
+            })
+        );
+        assert_eq!(
+            get_schema_change(
+                "CREATE UNIQUE INDEX chat_messages_room_message ON chat_messages (Chat_Id, RoomMessageId)"
+            ),
+            Some(SchemaChange::CreateIndex {
+                table: "chat_messages",
+                index: "chat_messages_room_message"
+            })
+        );
+        assert_eq!(
+            get_schema_change("CREATE TABLE IF NOT EXISTS chat_bans (Chat_Id INT NOT NULL)"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_mysql_migrations_can_be_run_again() {
+        // Every statement either runs again without harm, runs in a transaction or is checked before
+        for migration in MIGRATIONS {
+            for statement in split_statements(migration.mysql) {
+                let start = statement.to_uppercase();
+                assert!(
+                    start.starts_with("CREATE TABLE IF NOT EXISTS")
+                        || start.starts_with("INSERT")
+                        || start.starts_with("UPDATE")
+                        || get_schema_change(&statement).is_some(),
+                    "Migration {:04}_{} can not be run again: {}",
+                    migration.version,
+                    migration.name,
+                    statement
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate_empty_database() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        assert_eq!(storage.get_schema_version().unwrap(), 0);
+        assert!(check_schema_version(&storage).is_err());
+
+        let applied = migrate(&storage).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(storage.get_schema_version().unwrap(), get_latest_version());
+        assert!(check_schema_version(&storage).is_ok());
+
+        // Running it again does nothing
+        assert!(migrate(&storage).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_partially_migrated_database() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.apply_migration(&MIGRATIONS[0]).unwrap();
+        assert_eq!(storage.get_schema_version().unwrap(), 1);
+        assert!(check_schema_version(&storage).is_err());
+
+        let applied = migrate(&storage).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len() - 1);
+        assert!(check_schema_version(&storage).is_ok());
+    }
+
+    #[test]
+    fn test_migrate_chatrooms_to_chat_members() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        for migration in &MIGRATIONS[..3] {
+            storage.apply_migration(migration).unwrap();
+        }
+        for name in ["anton", "rino"] {
+            storage
+                .save_new_user_to_database_after_signup(name, "password")
+                .unwrap();
+        }
+        storage
+            .execute_batch(
+                r"INSERT INTO chats (ChatName, User1_Id, User2_Id) VALUES ('TestChat1', 1, 2);
+                INSERT INTO chat_messages (Chat_Id, Message) VALUES (1, 'rino: Hallo, Anton!');",
+            )
+            .unwrap();
+
+        migrate(&storage).unwrap();
+        let chatroom = storage.get_chatroom_by_id(1).unwrap();
+        assert_eq!(*chatroom.get_name(), "TestChat1".to_string());
+        assert_eq!(chatroom.get_member_ids(), vec![1, 2]);
+        assert_eq!(
+            storage
+                .get_chat_messages_before(1, None, 10)
+                .unwrap()
+                .iter()
+                .map(|message| message.to_string())
+                .collect::<Vec<String>>(),
+            vec!["rino: Hallo, Anton!".to_string()]
+        );
+        // New chatrooms do not reuse the ids of the migrated ones
+        let new_chatroom = storage.create_new_chatroom(&[1, 2], "TestChat2").unwrap();
+        assert_eq!(*new_chatroom.get_id(), 2);
+    }
+
+    #[test]
+    fn test_migrate_senders_out_of_messages() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        for migration in &MIGRATIONS[..9] {
+            storage.apply_migration(migration).unwrap();
+        }
+        for name in ["anton", "rino"] {
+            storage
+                .save_new_user_to_database_after_signup(name, "password")
+                .unwrap();
+        }
+        storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        storage.create_new_chatroom(&[1, 2], "TestChat2").unwrap();
+        storage
+            .execute_batch(
+                r"INSERT INTO chat_messages (Chat_Id, Message, Timestamp) VALUES (1, 'rino: Hallo: Anton!', '2024-05-01 12:00:00');
+                INSERT INTO chat_messages (Chat_Id, Message, Timestamp) VALUES (2, 'anton: Hallo, Rino!', '2024-05-01 12:00:00');
+                INSERT INTO chat_messages (Chat_Id, Message, Timestamp) VALUES (1, 'Rino: Wer bin ich?', '2024-05-01 12:01:00');",
+            )
+            .unwrap();
+
+        migrate(&storage).unwrap();
+        assert_eq!(
+            storage.get_chat_messages_before(1, None, 10).unwrap(),
+            vec![
+                ChatMessage::from_database(
+                    1,
+                    1,
+                    1,
+                    Some((2, "rino".to_string())),
+                    1714564800,
+                    "Hallo: Anton!".to_string()
+                ),
+                // Names are compared exactly, unknown senders stay in the message
+                ChatMessage::from_database(
+                    3,
+                    2,
+                    1,
+                    None,
+                    1714564860,
+                    "Rino: Wer bin ich?".to_string()
+                ),
+            ]
+        );
+        let message = storage.get_chat_message_by_id(2).unwrap().unwrap();
+        assert_eq!(message.get_sender_id(), Some(1));
+        assert_eq!(message.get_content(), "Hallo, Rino!");
+    }
+
+    #[test]
+    fn test_migrate_duplicate_public_room_names() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        for migration in &MIGRATIONS[..12] {
+            storage.apply_migration(migration).unwrap();
+        }
+        storage
+            .save_new_user_to_database_after_signup("anton", "password")
+            .unwrap();
+        storage
+            .execute_batch(
+                r"INSERT INTO chats (ChatName, IsPublic) VALUES ('Lobby', TRUE);
+                INSERT INTO chats (ChatName, IsPublic) VALUES ('Lobby', TRUE);
+                INSERT INTO chats (ChatName, IsPublic) VALUES ('Lobby', FALSE);
+                INSERT INTO chat_members (Chat_Id, User_Id) VALUES (1, 1), (2, 1), (3, 1);",
+            )
+            .unwrap();
+
+        migrate(&storage).unwrap();
+        // The older room keeps the name, chatrooms that are not public may share it
+        let names: Vec<String> = (1..=3)
+            .map(|id| storage.get_chatroom_by_id(id).unwrap().get_name().clone())
+            .collect();
+        assert_eq!(names, vec!["Lobby", "Lobby #2", "Lobby"]);
+    }
+
+    #[test]
+    fn test_check_schema_version_of_newer_database() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let newer = Migration {
+            version: get_latest_version() + 1,
+            name: "from_the_future",
+            mysql: "",
+            sqlite: "",
+        };
+        storage.apply_migration(&newer).unwrap();
+
+        assert!(check_schema_version(&storage).is_err());
+        assert!(migrate(&storage).is_err());
+    }
+}
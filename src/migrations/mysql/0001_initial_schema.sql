@@ -0,0 +1,56 @@
+-- Tables of the first release
+-- IF NOT EXISTS, so that databases that were created by the old setup script can be migrated as well
+
+-- Registered users
+-- Password: bcrypt hash of the SHA-256 hash the client sends (or the plain SHA-256 hash of old accounts)
+-- BlockedUntil: seconds since UNIX EPOCH until the user can log in again after too many wrong passwords, 0 if not blocked
+CREATE TABLE IF NOT EXISTS users (
+    Id INT NOT NULL AUTO_INCREMENT,
+    UserName VARCHAR(45) NOT NULL,
+    Password VARCHAR(255) NOT NULL,
+    BlockedUntil INT NOT NULL,
+    PRIMARY KEY (Id)
+);
+
+-- Chatrooms of two friends, created when a friend request is accepted
+CREATE TABLE IF NOT EXISTS chats (
+    Id INT NOT NULL AUTO_INCREMENT,
+    ChatName VARCHAR(45) NOT NULL,
+    User1_Id INT NOT NULL,
+    User2_Id INT NOT NULL,
+    FOREIGN KEY (User1_Id) REFERENCES users(Id),
+    FOREIGN KEY (User2_Id) REFERENCES users(Id),
+    PRIMARY KEY (Id)
+);
+
+-- Friend requests, Accepted is set when the receiver accepts, declined requests are deleted
+CREATE TABLE IF NOT EXISTS friend_requests (
+    Id INT NOT NULL AUTO_INCREMENT,
+    Sender_Id INT NOT NULL,
+    Receiver_Id INT NOT NULL,
+    Accepted BOOLEAN NOT NULL,
+    FOREIGN KEY (Sender_Id) REFERENCES users(Id),
+    FOREIGN KEY (Receiver_Id) REFERENCES users(Id),
+    PRIMARY KEY (Id)
+);
+
+-- Friendships, every pair of friends is saved once in any order
+CREATE TABLE IF NOT EXISTS friends (
+    Id INT NOT NULL AUTO_INCREMENT,
+    User1_Id INT NOT NULL,
+    User2_Id INT NOT NULL,
+    FOREIGN KEY (User1_Id) REFERENCES users(Id),
+    FOREIGN KEY (User2_Id) REFERENCES users(Id),
+    PRIMARY KEY (Id)
+);
+
+-- Messages of the chatrooms
+-- Message: "<sender name>: <content>"
+CREATE TABLE IF NOT EXISTS chat_messages (
+    Id INT NOT NULL AUTO_INCREMENT,
+    Chat_Id INT NOT NULL,
+    Message TEXT NOT NULL,
+    Timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (Chat_Id) REFERENCES chats(Id),
+    PRIMARY KEY (Id)
+);
@@ -0,0 +1,15 @@
+-- Logins of users, one row per device
+-- TokenHash: SHA-256 hash of the session token, the token itself is only known to the client
+-- CreatedAt, LastSeen, ExpiresAt: seconds since UNIX EPOCH
+CREATE TABLE IF NOT EXISTS sessions (
+    Id INT NOT NULL AUTO_INCREMENT,
+    User_Id INT NOT NULL,
+    TokenHash CHAR(64) NOT NULL,
+    DeviceName VARCHAR(100) NOT NULL,
+    CreatedAt INT NOT NULL,
+    LastSeen INT NOT NULL,
+    ExpiresAt INT NOT NULL,
+    FOREIGN KEY (User_Id) REFERENCES users(Id),
+    UNIQUE (TokenHash),
+    PRIMARY KEY (Id)
+);
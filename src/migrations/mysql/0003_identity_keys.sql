@@ -0,0 +1,3 @@
+-- Public identity key of every user for end-to-end encryption
+-- IdentityKey: X25519 public key as hex string, NULL until the client of the user published it
+ALTER TABLE users ADD COLUMN IdentityKey CHAR(64) NULL;
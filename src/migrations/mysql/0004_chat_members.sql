@@ -0,0 +1,15 @@
+-- Members of the chatrooms, so that a chatroom can have more than two members
+-- The members of the existing chatrooms are moved from User1_Id and User2_Id of the chats table
+CREATE TABLE IF NOT EXISTS chat_members (
+    Chat_Id INT NOT NULL,
+    User_Id INT NOT NULL,
+    FOREIGN KEY (Chat_Id) REFERENCES chats(Id),
+    FOREIGN KEY (User_Id) REFERENCES users(Id),
+    PRIMARY KEY (Chat_Id, User_Id)
+);
+
+INSERT IGNORE INTO chat_members (Chat_Id, User_Id) SELECT Id, User1_Id FROM chats;
+INSERT IGNORE INTO chat_members (Chat_Id, User_Id) SELECT Id, User2_Id FROM chats;
+
+-- The foreign keys of the columns are dropped before, MySQL generated their names
+ALTER TABLE chats DROP COLUMN User1_Id, DROP COLUMN User2_Id;
@@ -0,0 +1,5 @@
+-- Public rooms, which every user can find by name and join
+-- IsPublic: TRUE for rooms that were created as public room, chatrooms of friends and group chats are not public
+-- PasswordHash: bcrypt hash of the SHA-256 hash of the room password, NULL if the room has no password
+ALTER TABLE chats ADD COLUMN IsPublic BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE chats ADD COLUMN PasswordHash VARCHAR(255) NULL;
@@ -0,0 +1,14 @@
+-- Roles and moderation in chatrooms
+-- Role: 'owner' for the creator of a group chat or public room, 'moderator' for members the owner made moderators, 'member' for everybody else
+-- MutedUntil: seconds since UNIX EPOCH until the member can write again, 0 if not muted
+ALTER TABLE chat_members ADD COLUMN Role VARCHAR(20) NOT NULL DEFAULT 'member';
+ALTER TABLE chat_members ADD COLUMN MutedUntil INT NOT NULL DEFAULT 0;
+
+-- Users that were banned from a chatroom and can not join it again
+CREATE TABLE IF NOT EXISTS chat_bans (
+    Chat_Id INT NOT NULL,
+    User_Id INT NOT NULL,
+    FOREIGN KEY (Chat_Id) REFERENCES chats(Id),
+    FOREIGN KEY (User_Id) REFERENCES users(Id),
+    PRIMARY KEY (Chat_Id, User_Id)
+);
@@ -0,0 +1,3 @@
+-- Presence of users
+-- LastSeen: seconds since UNIX EPOCH the user was last active, saved when the last connection of the user is closed, 0 if never seen
+ALTER TABLE users ADD COLUMN LastSeen INT NOT NULL DEFAULT 0;
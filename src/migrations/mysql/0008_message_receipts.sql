@@ -0,0 +1,11 @@
+-- Delivery and read receipts of chat messages
+-- One row for every member who received a message, the sender has no row
+-- Status: 'delivered' when the client of the member received the message, 'read' when the member saw it
+CREATE TABLE IF NOT EXISTS message_receipts (
+    Message_Id INT NOT NULL,
+    User_Id INT NOT NULL,
+    Status VARCHAR(20) NOT NULL,
+    FOREIGN KEY (Message_Id) REFERENCES chat_messages(Id),
+    FOREIGN KEY (User_Id) REFERENCES users(Id),
+    PRIMARY KEY (Message_Id, User_Id)
+);
@@ -0,0 +1,3 @@
+-- Unread messages
+-- LastReadMessageId: id of the newest message in chat_messages the member has read, 0 if the member has not read any message
+ALTER TABLE chat_members ADD COLUMN LastReadMessageId INT NOT NULL DEFAULT 0;
@@ -0,0 +1,26 @@
+-- Sender, time and number of chat messages, the sender was saved as "<name>: " in front of the message before
+-- Sender_Id: user who sent the message, NULL for old messages without a known sender
+-- SentAt: seconds since UNIX EPOCH the server saved the message
+-- RoomMessageId: number of the message within its chatroom, counted from 1 without gaps
+ALTER TABLE chat_messages
+    ADD COLUMN Sender_Id INT NULL,
+    ADD COLUMN SentAt BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN RoomMessageId INT NOT NULL DEFAULT 0,
+    ADD FOREIGN KEY (Sender_Id) REFERENCES users(Id);
+
+UPDATE chat_messages
+    JOIN users ON LEFT(chat_messages.Message, CHAR_LENGTH(users.UserName) + 2) = BINARY CONCAT(users.UserName, ': ')
+    SET chat_messages.Sender_Id = users.Id,
+        chat_messages.Message = SUBSTRING(chat_messages.Message, CHAR_LENGTH(users.UserName) + 3);
+
+UPDATE chat_messages SET SentAt = UNIX_TIMESTAMP(Timestamp);
+
+UPDATE chat_messages
+    JOIN (
+        SELECT later.Id, COUNT(*) AS Number FROM chat_messages AS later
+        JOIN chat_messages AS earlier ON earlier.Chat_Id = later.Chat_Id AND earlier.Id <= later.Id
+        GROUP BY later.Id
+    ) AS numbered ON numbered.Id = chat_messages.Id
+    SET chat_messages.RoomMessageId = numbered.Number;
+
+CREATE UNIQUE INDEX chat_messages_room_message ON chat_messages (Chat_Id, RoomMessageId);
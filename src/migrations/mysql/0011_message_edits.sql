@@ -0,0 +1,19 @@
+-- Edited and deleted chat messages
+-- EditedAt: seconds since UNIX EPOCH the sender last edited the message, NULL if it was never edited
+-- DeletedAt: seconds since UNIX EPOCH the sender deleted the message, NULL if it was not deleted. The content of deleted messages is removed
+ALTER TABLE chat_messages
+    ADD COLUMN EditedAt BIGINT NULL,
+    ADD COLUMN DeletedAt BIGINT NULL;
+
+-- Earlier contents of edited messages, one row for every edit
+-- Message: the encrypted content the message had before the edit
+-- EditedAt: seconds since UNIX EPOCH the content was replaced
+-- The rows of a message are removed when it is deleted
+CREATE TABLE IF NOT EXISTS chat_message_edits (
+    Id INT NOT NULL AUTO_INCREMENT,
+    Message_Id INT NOT NULL,
+    Message TEXT NOT NULL,
+    EditedAt BIGINT NOT NULL,
+    FOREIGN KEY (Message_Id) REFERENCES chat_messages(Id),
+    PRIMARY KEY (Id)
+);
@@ -0,0 +1,3 @@
+-- Users that have to choose a new password
+-- MustChangePassword: TRUE for users that were restored from an export with a one-time password, the next login has to replace it
+ALTER TABLE users ADD COLUMN MustChangePassword BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,14 @@
+-- Unique names of public rooms, so that two rooms created at the same time can not get the same name
+-- Public rooms that got the name of an older public room before are renamed to "<name> #<id>"
+UPDATE chats
+    JOIN (
+        SELECT DISTINCT later.Id FROM chats AS later
+        JOIN chats AS earlier ON earlier.IsPublic AND earlier.ChatName = later.ChatName AND earlier.Id < later.Id
+        WHERE later.IsPublic
+    ) AS duplicates ON duplicates.Id = chats.Id
+    SET chats.ChatName = CONCAT(LEFT(chats.ChatName, 33), ' #', chats.Id);
+
+-- PublicName: ChatName of public rooms, NULL for other chatrooms, which may share their names
+ALTER TABLE chats ADD COLUMN PublicName VARCHAR(45) GENERATED ALWAYS AS (IF(IsPublic, ChatName, NULL)) STORED;
+
+CREATE UNIQUE INDEX chats_public_name ON chats (PublicName);
@@ -0,0 +1,35 @@
+-- Tables of the first release, see the MySQL migration for a description of the columns
+
+CREATE TABLE IF NOT EXISTS users (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    UserName VARCHAR(45) NOT NULL,
+    Password VARCHAR(255) NOT NULL,
+    BlockedUntil INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS chats (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ChatName VARCHAR(45) NOT NULL,
+    User1_Id INTEGER NOT NULL REFERENCES users(Id),
+    User2_Id INTEGER NOT NULL REFERENCES users(Id)
+);
+
+CREATE TABLE IF NOT EXISTS friend_requests (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    Sender_Id INTEGER NOT NULL REFERENCES users(Id),
+    Receiver_Id INTEGER NOT NULL REFERENCES users(Id),
+    Accepted BOOLEAN NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS friends (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    User1_Id INTEGER NOT NULL REFERENCES users(Id),
+    User2_Id INTEGER NOT NULL REFERENCES users(Id)
+);
+
+CREATE TABLE IF NOT EXISTS chat_messages (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    Chat_Id INTEGER NOT NULL REFERENCES chats(Id),
+    Message TEXT NOT NULL,
+    Timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
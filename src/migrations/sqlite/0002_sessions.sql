@@ -0,0 +1,10 @@
+-- Logins of users, see the MySQL migration for a description of the columns
+CREATE TABLE IF NOT EXISTS sessions (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    User_Id INTEGER NOT NULL REFERENCES users(Id),
+    TokenHash CHAR(64) NOT NULL UNIQUE,
+    DeviceName VARCHAR(100) NOT NULL,
+    CreatedAt INTEGER NOT NULL,
+    LastSeen INTEGER NOT NULL,
+    ExpiresAt INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Public identity key of every user, see the MySQL migration for a description of the column
+ALTER TABLE users ADD COLUMN IdentityKey CHAR(64) NULL;
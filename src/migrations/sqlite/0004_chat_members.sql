@@ -0,0 +1,21 @@
+-- Members of the chatrooms, see the MySQL migration for a description of the table
+
+CREATE TABLE IF NOT EXISTS chat_members (
+    Chat_Id INTEGER NOT NULL REFERENCES chats(Id),
+    User_Id INTEGER NOT NULL REFERENCES users(Id),
+    PRIMARY KEY (Chat_Id, User_Id)
+);
+
+INSERT OR IGNORE INTO chat_members (Chat_Id, User_Id) SELECT Id, User1_Id FROM chats;
+INSERT OR IGNORE INTO chat_members (Chat_Id, User_Id) SELECT Id, User2_Id FROM chats;
+
+-- SQLite cannot drop columns with a foreign key, so the chats table is rebuilt
+-- The foreign keys are switched off while migrating, so chat_members and chat_messages keep their rows
+
+CREATE TABLE chats_new (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ChatName VARCHAR(45) NOT NULL
+);
+INSERT INTO chats_new (Id, ChatName) SELECT Id, ChatName FROM chats;
+DROP TABLE chats;
+ALTER TABLE chats_new RENAME TO chats;
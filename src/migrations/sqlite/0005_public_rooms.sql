@@ -0,0 +1,3 @@
+-- Public rooms, see the MySQL migration for a description of the columns
+ALTER TABLE chats ADD COLUMN IsPublic BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE chats ADD COLUMN PasswordHash VARCHAR(255) NULL;
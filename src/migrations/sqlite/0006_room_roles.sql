@@ -0,0 +1,9 @@
+-- Roles and moderation in chatrooms, see the MySQL migration for a description of the columns
+ALTER TABLE chat_members ADD COLUMN Role VARCHAR(20) NOT NULL DEFAULT 'member';
+ALTER TABLE chat_members ADD COLUMN MutedUntil INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS chat_bans (
+    Chat_Id INTEGER NOT NULL REFERENCES chats(Id),
+    User_Id INTEGER NOT NULL REFERENCES users(Id),
+    PRIMARY KEY (Chat_Id, User_Id)
+);
@@ -0,0 +1,2 @@
+-- Presence of users, see the MySQL migration for a description of the column
+ALTER TABLE users ADD COLUMN LastSeen INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Delivery and read receipts of chat messages, see the MySQL migration for a description of the table
+CREATE TABLE IF NOT EXISTS message_receipts (
+    Message_Id INTEGER NOT NULL REFERENCES chat_messages(Id),
+    User_Id INTEGER NOT NULL REFERENCES users(Id),
+    Status VARCHAR(20) NOT NULL,
+    PRIMARY KEY (Message_Id, User_Id)
+);
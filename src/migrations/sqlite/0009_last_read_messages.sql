@@ -0,0 +1,2 @@
+-- Unread messages, see the MySQL migration for a description of the column
+ALTER TABLE chat_members ADD COLUMN LastReadMessageId INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,21 @@
+-- Sender, time and number of chat messages, see the MySQL migration for a description of the columns
+ALTER TABLE chat_messages ADD COLUMN Sender_Id INTEGER REFERENCES users(Id);
+ALTER TABLE chat_messages ADD COLUMN SentAt INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE chat_messages ADD COLUMN RoomMessageId INTEGER NOT NULL DEFAULT 0;
+
+UPDATE chat_messages SET Sender_Id = (
+    SELECT users.Id FROM users
+    WHERE SUBSTR(chat_messages.Message, 1, LENGTH(users.UserName) + 2) = users.UserName || ': '
+);
+UPDATE chat_messages
+    SET Message = SUBSTR(Message, LENGTH((SELECT users.UserName FROM users WHERE users.Id = chat_messages.Sender_Id)) + 3)
+    WHERE Sender_Id IS NOT NULL;
+
+UPDATE chat_messages SET SentAt = CAST(strftime('%s', Timestamp) AS INTEGER);
+
+UPDATE chat_messages SET RoomMessageId = (
+    SELECT COUNT(*) FROM chat_messages AS earlier
+    WHERE earlier.Chat_Id = chat_messages.Chat_Id AND earlier.Id <= chat_messages.Id
+);
+
+CREATE UNIQUE INDEX chat_messages_room_message ON chat_messages (Chat_Id, RoomMessageId);
@@ -0,0 +1,10 @@
+-- Edited and deleted chat messages, see the MySQL migration for a description of the columns and the table
+ALTER TABLE chat_messages ADD COLUMN EditedAt INTEGER NULL;
+ALTER TABLE chat_messages ADD COLUMN DeletedAt INTEGER NULL;
+
+CREATE TABLE IF NOT EXISTS chat_message_edits (
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    Message_Id INTEGER NOT NULL REFERENCES chat_messages(Id),
+    Message TEXT NOT NULL,
+    EditedAt INTEGER NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Users that have to choose a new password, see the MySQL migration for a description of the column
+ALTER TABLE users ADD COLUMN MustChangePassword BOOLEAN NOT NULL DEFAULT FALSE;
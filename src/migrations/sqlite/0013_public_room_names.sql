@@ -0,0 +1,8 @@
+-- Unique names of public rooms, see the MySQL migration for a description
+UPDATE chats SET ChatName = substr(ChatName, 1, 33) || ' #' || Id
+WHERE IsPublic AND EXISTS(
+    SELECT 1 FROM chats AS earlier WHERE earlier.IsPublic AND earlier.ChatName = chats.ChatName AND earlier.Id < chats.Id
+);
+
+-- Other chatrooms may share their names
+CREATE UNIQUE INDEX chats_public_name ON chats (ChatName) WHERE IsPublic;
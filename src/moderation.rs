@@ -0,0 +1,276 @@
+use crate::helper_functions;
+use crate::protocol::ModerationAction;
+use crate::storage::{Storage, StorageResult};
+use crate::structs::chat_room::ChatRoom;
+use crate::structs::room_role::RoomRole;
+use crate::structs::user::User;
+
+/// Longest time in minutes a member can be muted for, one week
+pub const MAX_MUTE_MINUTES: u32 = 7 * 24 * 60;
+
+/// Result of a moderation in a chatroom
+#[derive(Debug, PartialEq)]
+pub enum ModerationOutcome {
+    /// The moderation was saved, the announcement is sent to everybody in the chatroom
+    /// <br>`removed_user_id` is the member that has to leave the chatroom after a kick or ban
+    Done {
+        announcement: String,
+        removed_user_id: Option<u32>,
+    },
+    /// The moderation is not allowed, the message is sent back to the moderator
+    Rejected(String),
+}
+
+/// Checks the role of the moderator and the target, saves the moderation and updates the members of the chatroom
+pub fn moderate(
+    storage: &dyn Storage,
+    chatroom: &mut ChatRoom,
+    moderator: &User,
+    action: ModerationAction,
+) -> StorageResult<ModerationOutcome> {
+    let chatroom_id = *chatroom.get_id();
+    // The members are reloaded, so that members who joined a public room can be moderated right away
+    reload_members(storage, chatroom)?;
+
+    let username = match &action {
+        ModerationAction::Kick { username }
+        | ModerationAction::Ban { username }
+        | ModerationAction::Mute { username, .. }
+        | ModerationAction::SetRole { username, .. } => username,
+    };
+    let target = match chatroom
+        .get_members()
+        .iter()
+        .find(|member| member.get_name() == username)
+    {
+        Some(target) => target.clone(),
+        None => {
+            return Ok(ModerationOutcome::Rejected(format!(
+                "{} is no member of this chatroom.",
+                username
+            )))
+        }
+    };
+
+    let moderator_role = storage
+        .get_chat_member_role(chatroom_id, moderator.get_id())?
+        .unwrap_or(RoomRole::Member);
+    let target_role = storage
+        .get_chat_member_role(chatroom_id, target.get_id())?
+        .unwrap_or(RoomRole::Member);
+    if let Err(message) = check_permission(moderator_role, target_role, &action) {
+        return Ok(ModerationOutcome::Rejected(message.to_string()));
+    }
+
+    let (announcement, removed_user_id) = match action {
+        ModerationAction::Kick { .. } => {
+            storage.remove_chat_member(chatroom_id, target.get_id())?;
+            (
+                format!(
+                    "{} was kicked by {}.",
+                    target.get_name(),
+                    moderator.get_name()
+                ),
+                Some(target.get_id()),
+            )
+        }
+        ModerationAction::Ban { .. } => {
+            storage.ban_chat_member(chatroom_id, target.get_id())?;
+            (
+                format!(
+                    "{} was banned by {}.",
+                    target.get_name(),
+                    moderator.get_name()
+                ),
+                Some(target.get_id()),
+            )
+        }
+        ModerationAction::Mute { minutes, .. } => {
+            if minutes == 0 || minutes > MAX_MUTE_MINUTES {
+                return Ok(ModerationOutcome::Rejected(format!(
+                    "Members can be muted for 1 to {} minutes.",
+                    MAX_MUTE_MINUTES
+                )));
+            }
+            storage.mute_chat_member(chatroom_id, target.get_id(), minutes as u64 * 60)?;
+            (
+                format!(
+                    "{} was muted for {} minutes by {}.",
+                    target.get_name(),
+                    minutes,
+                    moderator.get_name()
+                ),
+                None,
+            )
+        }
+        ModerationAction::SetRole { role, .. } => {
+            storage.set_chat_member_role(chatroom_id, target.get_id(), role)?;
+            let announcement = if role == RoomRole::Moderator {
+                format!("{} is now a moderator.", target.get_name())
+            } else {
+                format!("{} is no moderator anymore.", target.get_name())
+            };
+            (announcement, None)
+        }
+    };
+
+    if removed_user_id.is_some() {
+        reload_members(storage, chatroom)?;
+    }
+    Ok(ModerationOutcome::Done {
+        announcement,
+        removed_user_id,
+    })
+}
+
+/// Replaces the members of the chatroom with the members in the storage
+/// <br>The chatroom itself is kept, because the connections are subscribed to its broadcast channel
+fn reload_members(storage: &dyn Storage, chatroom: &mut ChatRoom) -> StorageResult<()> {
+    let stored_chatroom = storage.get_chatroom_by_id(*chatroom.get_id())?;
+    chatroom.set_members(stored_chatroom.get_members().clone());
+    Ok(())
+}
+
+/// Returns why the moderation is not allowed
+/// <br>Moderators can only moderate members with a lower role, only the owner can change roles
+fn check_permission(
+    moderator_role: RoomRole,
+    target_role: RoomRole,
+    action: &ModerationAction,
+) -> Result<(), &'static str> {
+    if let ModerationAction::SetRole { role, .. } = action {
+        if moderator_role != RoomRole::Owner {
+            return Err("Only the owner of the chatroom can change roles.");
+        }
+        if *role == RoomRole::Owner {
+            return Err("A chatroom can only have one owner.");
+        }
+    }
+    if !moderator_role.can_moderate() {
+        return Err("Only moderators can do this.");
+    }
+    if target_role >= moderator_role {
+        return Err("You can only moderate members with a lower role.");
+    }
+    Ok(())
+}
+
+/// Returns how many seconds the member is still muted in the chatroom, 0 if the member can write
+pub fn get_remaining_mute_secs(
+    storage: &dyn Storage,
+    chatroom_id: u32,
+    user_id: u32,
+) -> StorageResult<u64> {
+    let muted_until = storage.get_chat_member_muted_until(chatroom_id, user_id)?;
+    Ok(muted_until.saturating_sub(helper_functions::get_sys_time_in_secs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestStorageBuilder;
+
+    fn kick(username: &str) -> ModerationAction {
+        ModerationAction::Kick {
+            username: username.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_permission() {
+        assert!(check_permission(RoomRole::Moderator, RoomRole::Member, &kick("rino")).is_ok());
+        assert!(check_permission(RoomRole::Owner, RoomRole::Moderator, &kick("rino")).is_ok());
+        assert!(check_permission(RoomRole::Member, RoomRole::Member, &kick("rino")).is_err());
+        assert!(check_permission(RoomRole::Moderator, RoomRole::Moderator, &kick("rino")).is_err());
+        assert!(check_permission(RoomRole::Moderator, RoomRole::Owner, &kick("rino")).is_err());
+
+        let make_moderator = ModerationAction::SetRole {
+            username: "rino".to_string(),
+            role: RoomRole::Moderator,
+        };
+        assert!(check_permission(RoomRole::Owner, RoomRole::Member, &make_moderator).is_ok());
+        assert!(check_permission(RoomRole::Moderator, RoomRole::Member, &make_moderator).is_err());
+        let make_owner = ModerationAction::SetRole {
+            username: "rino".to_string(),
+            role: RoomRole::Owner,
+        };
+        assert!(check_permission(RoomRole::Owner, RoomRole::Member, &make_owner).is_err());
+    }
+
+    #[test]
+    fn test_moderate() {
+        let storage = TestStorageBuilder::new()
+            .user("anton")
+            .user("rino")
+            .user("antonia")
+            .build();
+        let anton = storage.get_user_from_database_by_name("anton").unwrap();
+        let rino = storage.get_user_from_database_by_name("rino").unwrap();
+        let mut chatroom = storage
+            .create_public_chatroom(1, "Lobby", None)
+            .unwrap()
+            .unwrap();
+        let chatroom_id = *chatroom.get_id();
+        assert!(storage.join_public_chatroom(chatroom_id, 2, 10).unwrap());
+        assert!(storage.join_public_chatroom(chatroom_id, 3, 10).unwrap());
+
+        // Rino is no moderator yet
+        assert!(matches!(
+            moderate(storage.as_ref(), &mut chatroom, &rino, kick("antonia")).unwrap(),
+            ModerationOutcome::Rejected(_)
+        ));
+        moderate(
+            storage.as_ref(),
+            &mut chatroom,
+            &anton,
+            ModerationAction::SetRole {
+                username: "rino".to_string(),
+                role: RoomRole::Moderator,
+            },
+        )
+        .unwrap();
+
+        let outcome = moderate(
+            storage.as_ref(),
+            &mut chatroom,
+            &rino,
+            ModerationAction::Mute {
+                username: "antonia".to_string(),
+                minutes: 5,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            outcome,
+            ModerationOutcome::Done {
+                announcement: "antonia was muted for 5 minutes by rino.".to_string(),
+                removed_user_id: None,
+            }
+        );
+        assert!(get_remaining_mute_secs(storage.as_ref(), chatroom_id, 3).unwrap() > 0);
+        assert_eq!(
+            get_remaining_mute_secs(storage.as_ref(), chatroom_id, 2).unwrap(),
+            0
+        );
+
+        let outcome = moderate(storage.as_ref(), &mut chatroom, &rino, kick("antonia")).unwrap();
+        assert_eq!(
+            outcome,
+            ModerationOutcome::Done {
+                announcement: "antonia was kicked by rino.".to_string(),
+                removed_user_id: Some(3),
+            }
+        );
+        assert_eq!(chatroom.get_member_ids(), vec![1, 2]);
+
+        // Moderators can not moderate the owner, and nobody can moderate users outside the chatroom
+        assert!(matches!(
+            moderate(storage.as_ref(), &mut chatroom, &rino, kick("anton")).unwrap(),
+            ModerationOutcome::Rejected(_)
+        ));
+        assert!(matches!(
+            moderate(storage.as_ref(), &mut chatroom, &anton, kick("antonia")).unwrap(),
+            ModerationOutcome::Rejected(_)
+        ));
+    }
+}
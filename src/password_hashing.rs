@@ -0,0 +1,225 @@
+use crate::storage::{self, Storage};
+use std::error::Error;
+use std::sync::Arc;
+
+/// Cost factor for bcrypt, every increase doubles the time needed to check a password
+const BCRYPT_COST: u32 = bcrypt::DEFAULT_COST;
+
+/// Result of comparing a password with the hash stored in the database
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasswordCheck {
+    /// The password is correct
+    Valid,
+    /// The password is correct, but it is still stored as unsalted SHA-256 hash
+    ValidLegacyHash,
+    Invalid,
+}
+
+/// Returns true if the text has the format of the password hash the client sends: SHA-256 as 64 lowercase hex digits
+pub fn is_valid_password_hash(password_hash: &str) -> bool {
+    password_hash.len() == 64
+        && password_hash
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Hashes the password with bcrypt and a random salt, so that it can be stored in the database
+/// <br>The client only sends the SHA-256 hash of the password, which is hashed again here
+pub async fn hash_password_for_storage(
+    password_hash: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let password_hash = password_hash.to_string();
+    // bcrypt is slow on purpose, so do not block the other connections while hashing
+    let hashed =
+        tokio::task::spawn_blocking(move || bcrypt::hash(password_hash, BCRYPT_COST)).await??;
+    Ok(hashed)
+}
+
+/// Compares the password with the hash that is stored in the database
+/// <br>Accounts that were created before bcrypt was used still have the plain SHA-256 hash stored.
+/// An empty stored password never matches
+pub async fn verify_password(
+    password_hash: &str,
+    stored_hash: &str,
+) -> Result<PasswordCheck, Box<dyn Error + Send + Sync>> {
+    if stored_hash.is_empty() {
+        return Ok(PasswordCheck::Invalid);
+    }
+    if !is_bcrypt_hash(stored_hash) {
+        return Ok(
+            if constant_time_eq(password_hash.as_bytes(), stored_hash.as_bytes()) {
+                PasswordCheck::ValidLegacyHash
+            } else {
+                PasswordCheck::Invalid
+            },
+        );
+    }
+
+    let password_hash = password_hash.to_string();
+    let stored_hash = stored_hash.to_string();
+    let is_valid =
+        tokio::task::spawn_blocking(move || bcrypt::verify(password_hash, &stored_hash)).await??;
+    Ok(if is_valid {
+        PasswordCheck::Valid
+    } else {
+        PasswordCheck::Invalid
+    })
+}
+
+/// Checks if the password matches the password of the user with the username
+/// <br>A password that is still stored as SHA-256 hash is replaced by a bcrypt hash on success
+pub async fn check_if_password_matches_username(
+    storage: &Arc<dyn Storage>,
+    password_hash: &str,
+    username: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let name = username.to_string();
+    let stored_hash = match storage::run_blocking(storage, move |storage| {
+        storage.get_password_hash_of_user(&name)
+    })
+    .await?
+    {
+        Some(stored_hash) => stored_hash,
+        None => return Ok(false),
+    };
+
+    match verify_password(password_hash, &stored_hash).await? {
+        PasswordCheck::Valid => Ok(true),
+        PasswordCheck::ValidLegacyHash => {
+            let new_hash = hash_password_for_storage(password_hash).await?;
+            let name = username.to_string();
+            storage::run_blocking(storage, move |storage| {
+                storage.update_password_hash_of_user(&name, &new_hash)
+            })
+            .await?;
+            Ok(true)
+        }
+        PasswordCheck::Invalid => Ok(false),
+    }
+}
+
+/// Returns true if the hash was created by bcrypt
+fn is_bcrypt_hash(hash: &str) -> bool {
+    hash.starts_with("$2")
+}
+
+/// Compares two byte strings in a time that does not depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::login::hash_password;
+    use crate::sqlite_storage::SqliteStorage;
+
+    /// Creates a database with the user rino, whose password is still stored as SHA-256 hash
+    fn get_test_storage() -> Arc<dyn Storage> {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage
+            .save_new_user_to_database_after_signup("rino", &hash_password("Ifuckingloverust1"))
+            .unwrap();
+        Arc::new(storage)
+    }
+
+    #[tokio::test]
+    async fn test_hash_password_for_storage_uses_salt() {
+        let password_hash = hash_password("Ifuckingloverust1");
+        let hash1 = hash_password_for_storage(&password_hash).await.unwrap();
+        let hash2 = hash_password_for_storage(&password_hash).await.unwrap();
+
+        assert!(is_bcrypt_hash(&hash1));
+        assert_ne!(hash1, hash2);
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_positive() {
+        let password_hash = hash_password("Ifuckingloverust1");
+        let stored_hash = hash_password_for_storage(&password_hash).await.unwrap();
+
+        let result = verify_password(&password_hash, &stored_hash).await.unwrap();
+        assert_eq!(result, PasswordCheck::Valid);
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_negative() {
+        let stored_hash = hash_password_for_storage(&hash_password("Ifuckingloverust1"))
+            .await
+            .unwrap();
+
+        let result = verify_password(&hash_password("Ifuckingloverust2"), &stored_hash)
+            .await
+            .unwrap();
+        assert_eq!(result, PasswordCheck::Invalid);
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_with_legacy_hash() {
+        let password_hash = hash_password("Ifuckingloverust1");
+
+        let result = verify_password(&password_hash, &password_hash)
+            .await
+            .unwrap();
+        assert_eq!(result, PasswordCheck::ValidLegacyHash);
+
+        let result = verify_password(&hash_password("Ifuckingloverust2"), &password_hash)
+            .await
+            .unwrap();
+        assert_eq!(result, PasswordCheck::Invalid);
+    }
+
+    #[tokio::test]
+    async fn test_verify_password_without_stored_password() {
+        let result = verify_password("", "").await.unwrap();
+        assert_eq!(result, PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+
+    #[tokio::test]
+    async fn test_check_if_password_matches_username_positive() {
+        let storage = get_test_storage();
+        let password = "Ifuckingloverust1";
+        let result = check_if_password_matches_username(
+            &storage,
+            hash_password(password.trim()).as_str(),
+            "rino",
+        )
+        .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap());
+
+        // The password is stored as bcrypt hash afterwards
+        let stored_hash = storage.get_password_hash_of_user("rino").unwrap().unwrap();
+        assert!(is_bcrypt_hash(&stored_hash));
+
+        // The upgraded password still matches
+        let result = check_if_password_matches_username(
+            &storage,
+            hash_password(password.trim()).as_str(),
+            "rino",
+        )
+        .await;
+        assert!(result.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_check_if_password_matches_username_negative() {
+        let storage = get_test_storage();
+        let result = check_if_password_matches_username(&storage, "password", "rino").await;
+        assert!(result.is_ok());
+        assert!(!result.unwrap());
+
+        let result = check_if_password_matches_username(&storage, "password", "nobody").await;
+        assert!(!result.unwrap());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::helper_functions;
+use crate::structs::presence::{Presence, PresenceStatus};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+
+/// Seconds without any frame from a user after which the user is shown as away
+pub const AWAY_AFTER_SECS: u64 = 5 * 60;
+
+/// Seconds between two checks for users that became away
+pub const AWAY_CHECK_INTERVAL_SECS: u64 = 30;
+
+/// User with at least one open connection
+struct ConnectedUser {
+    connections: usize,
+    last_active: u64,
+    is_away: bool,
+}
+
+/// Everything the connections are told about to keep the presences of the friends of their user up to date
+#[derive(Debug, Clone, PartialEq)]
+pub enum PresenceUpdate {
+    /// The presence of the user changed, only the friends of the user see it
+    Presence(Presence),
+    /// The two users with the ids are friends now
+    Friendship(u32, u32),
+}
+
+/// Keeps track of the users that are connected to the server
+/// <br>Every change of a presence is published, so that the connections of the friends can pass it on.
+/// New friendships go through the same channel, so that a connection knows about a friend before the presence of the friend changes
+pub struct PresenceTracker {
+    users: Mutex<HashMap<u32, ConnectedUser>>,
+    updates: Sender<PresenceUpdate>,
+}
+
+impl PresenceTracker {
+    pub fn new() -> Self {
+        let (updates, _) = channel(64);
+        PresenceTracker {
+            users: Mutex::new(HashMap::new()),
+            updates,
+        }
+    }
+
+    ///Returns a receiver for all changes of presences and new friendships
+    pub fn subscribe(&self) -> Receiver<PresenceUpdate> {
+        self.updates.subscribe()
+    }
+
+    ///Tells the connections of both users that they are friends now
+    pub fn publish_friendship(&self, user1_id: u32, user2_id: u32) {
+        _ = self
+            .updates
+            .send(PresenceUpdate::Friendship(user1_id, user2_id));
+    }
+
+    ///Counts a new connection of the user, the first one makes the user online
+    pub fn connect(&self, user_id: u32) {
+        let now = helper_functions::get_sys_time_in_secs();
+        let mut users = self.users.lock().unwrap();
+        let user = users.entry(user_id).or_insert(ConnectedUser {
+            connections: 0,
+            last_active: now,
+            is_away: false,
+        });
+        user.connections += 1;
+        user.last_active = now;
+        if user.connections == 1 || user.is_away {
+            user.is_away = false;
+            self.publish(user_id, PresenceStatus::Online, now);
+        }
+    }
+
+    ///Counts a closed connection of the user, the last one makes the user offline
+    /// <br>Returns the time the user was last active if the user is offline now.
+    /// The caller saves this time and publishes the change with `publish_offline` afterwards
+    pub fn disconnect(&self, user_id: u32) -> Option<u64> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id)?;
+        user.connections -= 1;
+        if user.connections > 0 {
+            return None;
+        }
+        let last_active = user.last_active;
+        users.remove(&user_id);
+        Some(last_active)
+    }
+
+    ///Tells the friends of the user that the user went offline
+    pub fn publish_offline(&self, user_id: u32, last_seen: u64) {
+        self.publish(user_id, PresenceStatus::Offline, last_seen);
+    }
+
+    ///Saves that the user did something, users that were away are online again
+    pub fn mark_active(&self, user_id: u32) {
+        let now = helper_functions::get_sys_time_in_secs();
+        let mut users = self.users.lock().unwrap();
+        if let Some(user) = users.get_mut(&user_id) {
+            user.last_active = now;
+            if user.is_away {
+                user.is_away = false;
+                self.publish(user_id, PresenceStatus::Online, now);
+            }
+        }
+    }
+
+    ///Marks the users that did not do anything for `AWAY_AFTER_SECS` as away
+    /// <br>`now` is the current time in seconds since UNIX EPOCH
+    pub fn mark_idle_users_away(&self, now: u64) {
+        let mut users = self.users.lock().unwrap();
+        for (user_id, user) in users.iter_mut() {
+            if !user.is_away && now.saturating_sub(user.last_active) >= AWAY_AFTER_SECS {
+                user.is_away = true;
+                self.publish(*user_id, PresenceStatus::Away, user.last_active);
+            }
+        }
+    }
+
+    ///Returns the presence of the user, None if the user is offline
+    pub fn get_presence(&self, user_id: u32) -> Option<Presence> {
+        let users = self.users.lock().unwrap();
+        users.get(&user_id).map(|user| {
+            let status = if user.is_away {
+                PresenceStatus::Away
+            } else {
+                PresenceStatus::Online
+            };
+            Presence::new(user_id, status, user.last_active)
+        })
+    }
+
+    // Nobody may be listening, which is fine
+    fn publish(&self, user_id: u32, status: PresenceStatus, last_seen: u64) {
+        _ = self.updates.send(PresenceUpdate::Presence(Presence::new(
+            user_id, status, last_seen,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_status(update: PresenceUpdate) -> PresenceStatus {
+        match update {
+            PresenceUpdate::Presence(presence) => presence.get_status(),
+            other => panic!("Expected presence, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_online_until_last_connection_is_closed() {
+        let tracker = PresenceTracker::new();
+        let mut updates = tracker.subscribe();
+        assert_eq!(tracker.get_presence(1), None);
+
+        tracker.connect(1);
+        tracker.connect(1);
+        assert_eq!(
+            tracker.get_presence(1).unwrap().get_status(),
+            PresenceStatus::Online
+        );
+        assert_eq!(tracker.disconnect(1), None);
+        let last_seen = tracker.disconnect(1).unwrap();
+        tracker.publish_offline(1, last_seen);
+        assert_eq!(tracker.get_presence(1), None);
+        assert_eq!(tracker.disconnect(1), None);
+
+        // Only the first connection and the last disconnection are published
+        let statuses: Vec<PresenceStatus> = std::iter::from_fn(|| updates.try_recv().ok())
+            .map(get_status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![PresenceStatus::Online, PresenceStatus::Offline]
+        );
+
+        tracker.publish_friendship(1, 2);
+        assert_eq!(
+            updates.try_recv().unwrap(),
+            PresenceUpdate::Friendship(1, 2)
+        );
+    }
+
+    #[test]
+    fn test_idle_users_are_away() {
+        let tracker = PresenceTracker::new();
+        tracker.connect(1);
+        let mut updates = tracker.subscribe();
+        let now = helper_functions::get_sys_time_in_secs();
+
+        tracker.mark_idle_users_away(now);
+        assert!(updates.try_recv().is_err());
+
+        tracker.mark_idle_users_away(now + AWAY_AFTER_SECS);
+        assert_eq!(
+            tracker.get_presence(1).unwrap().get_status(),
+            PresenceStatus::Away
+        );
+        assert_eq!(
+            get_status(updates.try_recv().unwrap()),
+            PresenceStatus::Away
+        );
+
+        tracker.mark_active(1);
+        assert_eq!(
+            tracker.get_presence(1).unwrap().get_status(),
+            PresenceStatus::Online
+        );
+        assert_eq!(
+            get_status(updates.try_recv().unwrap()),
+            PresenceStatus::Online
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use crate::structs::{
+    chat_room::ChatRoom,
+    friend_request::FriendRequest,
+    message::{ChatMessage, MessageEdit},
+    presence::Presence,
+    receipt::Receipt,
+    room_role::RoomRole,
+    session_info::SessionInfo,
+    user::User,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Version of the wire protocol
+/// <br>Has to be increased whenever a change makes old clients and servers incompatible
+pub const PROTOCOL_VERSION: u32 = 13;
+
+/// Requests a client can send to the server
+/// <br>Every request is answered with a `ServerResponse`, an `Ack` or an `Error`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ClientRequest {
+    CheckIfUsernameExists {
+        username: String,
+    },
+    CheckIfUserIsBlocked {
+        username: String,
+    },
+    Login {
+        username: String,
+        password_hash: String,
+        device_name: String,
+        remember_me: bool,
+        /// New password of a user who has to replace a one-time password, see `PasswordChangeRequired`
+        #[serde(default)]
+        new_password_hash: Option<String>,
+    },
+    SignUp {
+        username: String,
+        password_hash: String,
+        device_name: String,
+    },
+    /// Logs in with the session token of an earlier login
+    ResumeSession {
+        session_token: String,
+    },
+    Logout,
+    GetSessions,
+    RevokeSession {
+        session_id: u32,
+    },
+    SearchUsers {
+        search_string: String,
+    },
+    CheckIfFriends {
+        user_id: u32,
+    },
+    SendFriendRequest {
+        receiver_id: u32,
+    },
+    GetFriendRequests,
+    AcceptFriendRequest {
+        sender_id: u32,
+    },
+    DeclineFriendRequest {
+        sender_id: u32,
+    },
+    GetChatRooms,
+    GetFriends,
+    /// Creates a chatroom with the logged in user and the friends as members
+    CreateGroupChat {
+        name: String,
+        member_ids: Vec<u32>,
+    },
+    /// Creates a public room with a unique name, which every user can find and join
+    /// <br>`password_hash` is the SHA-256 hash of the room password, None for rooms without password
+    CreatePublicRoom {
+        name: String,
+        password_hash: Option<String>,
+    },
+    SearchPublicRooms {
+        search_string: String,
+    },
+    /// Makes the logged in user a member of the public room, the password is checked by the server
+    JoinPublicRoom {
+        chatroom_id: u32,
+        password_hash: Option<String>,
+    },
+    /// Publishes the public identity key of the logged in user for end-to-end encryption
+    PublishIdentityKey {
+        public_key: String,
+    },
+    GetIdentityKey {
+        user_id: u32,
+    },
+    /// Asks for the presence of all friends of the logged in user, changes are sent afterwards as `Presence`
+    GetPresence,
+    /// Asks for the number of unread messages in every chatroom of the logged in user
+    /// <br>Messages that arrived while the user was offline count as delivered afterwards
+    GetUnreadCounts,
+}
+
+/// Responses the server sends back to a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ServerResponse {
+    UsernameExists(bool),
+    UserIsBlocked(bool),
+    LoginSucceeded {
+        user: User,
+        session_token: String,
+    },
+    LoginFailed {
+        tries_left: u32,
+    },
+    AccountBlocked {
+        remaining_secs: u64,
+    },
+    /// The password was correct, but it is a one-time password, the login has to be repeated with a new password
+    PasswordChangeRequired,
+    SignedUp {
+        user: User,
+        session_token: String,
+    },
+    Users(Vec<User>),
+    AreFriends(bool),
+    FriendRequests(Vec<FriendRequest>),
+    ChatRooms(Vec<ChatRoom>),
+    /// Chatroom that was created or joined
+    ChatRoom(ChatRoom),
+    Sessions(Vec<SessionInfo>),
+    /// Public identity key of a user, None if the user did not publish a key yet
+    IdentityKey {
+        user_id: u32,
+        public_key: Option<String>,
+    },
+    Presences(Vec<Presence>),
+    /// Number of unread messages by chatroom id, chatrooms without unread messages are left out
+    UnreadCounts(HashMap<u32, u32>),
+}
+
+/// Actions moderators can take against other members of the joined chatroom
+/// <br>Members are identified by their username, as it is typed in the chat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ModerationAction {
+    /// Removes the member from the chatroom, members of public rooms can join again
+    Kick { username: String },
+    /// Removes the member from the chatroom for good
+    Ban { username: String },
+    /// The member can not write for the given number of minutes
+    Mute { username: String, minutes: u32 },
+    /// Makes a member moderator or takes the role away again, only allowed for the owner
+    SetRole { username: String, role: RoomRole },
+}
+
+/// Everything that can be sent between client and server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum Payload {
+    /// First frame of every connection, sent by the client
+    /// <br>Contains the session token of the logged in user, if there is one
+    Hello { session_token: Option<String> },
+    /// Request of a client that is not related to a single chatroom
+    Request(ClientRequest),
+    /// Answer of the server to a `Request`
+    Response(ServerResponse),
+    /// Turns the connection into a chat connection for the chatroom
+    /// <br>Only allowed for members of the chatroom
+    JoinRoom { chatroom_id: u32 },
+    /// Message written in the joined chatroom
+    /// <br>Messages the server sends always have the id they were saved with
+    ChatMessage(ChatMessage),
+    /// Answer of the server to a `ChatMessage` of the client, contains the message with its new id
+    MessageStored(ChatMessage),
+    /// Status of a message
+    /// <br>Clients send it for messages of other members they received or read,
+    /// the server sends it to the sender whenever the status of a message changes
+    Receipt(Receipt),
+    /// Asks for a page of the messages of the joined chatroom, which ends before the message with the id
+    /// <br>Without an id the newest page is sent. The messages of the other members on the page are read afterwards,
+    /// unless `keep_unread` is set
+    HistoryRequest {
+        chatroom_id: u32,
+        #[serde(default)]
+        before_message_id: Option<u32>,
+        #[serde(default)]
+        keep_unread: bool,
+    },
+    /// Answer of the server to a `HistoryRequest`, `receipts` are the statuses of the own messages
+    /// <br>`has_more` is true if there are messages older than the page, the next page is asked for with the id of its first message
+    History {
+        messages: Vec<ChatMessage>,
+        receipts: Vec<Receipt>,
+        has_more: bool,
+    },
+    /// Replaces the content of an own message in the joined chatroom, answered with `MessageChanged` or an `Error`
+    /// <br>The new content is encrypted for every member like a new message
+    EditMessage {
+        chatroom_id: u32,
+        message_id: u32,
+        content: String,
+    },
+    /// Deletes an own message in the joined chatroom, answered with `MessageChanged` or an `Error`
+    DeleteMessage { chatroom_id: u32, message_id: u32 },
+    /// A message of the joined chatroom was edited or deleted, sent to everybody in the chatroom
+    MessageChanged(ChatMessage),
+    /// Asks for the contents a message of the joined chatroom had before it was edited, answered with `EditHistory` or an `Error`
+    EditHistoryRequest { chatroom_id: u32, message_id: u32 },
+    /// Answer of the server to an `EditHistoryRequest`, the oldest content first
+    EditHistory {
+        message_id: u32,
+        edits: Vec<MessageEdit>,
+    },
+    /// Moderation in the joined chatroom, answered with an `Error` if it is not allowed
+    Moderation {
+        chatroom_id: u32,
+        action: ModerationAction,
+    },
+    /// Message of the server to everybody in the joined chatroom, e.g. about a moderation
+    Announcement { message: String },
+    /// The user was removed from the joined chatroom, the chat connection is closed afterwards
+    Removed { message: String },
+    /// A friend of the logged in user came online, went away or went offline
+    Presence(Presence),
+    /// The user started or stopped typing in the joined chatroom
+    /// <br>Clients repeat `is_typing: true` every few seconds while the user keeps typing
+    Typing { chatroom_id: u32, is_typing: bool },
+    /// Another member of the joined chatroom started or stopped typing
+    MemberTyping { username: String, is_typing: bool },
+    /// Confirms that the last frame was accepted
+    Ack,
+    /// The last frame was rejected
+    Error { message: String },
+}
+
+/// Frame as it is sent over the websocket connection
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u32,
+    payload: &'a Payload,
+}
+
+/// Frame as it is received from the websocket connection
+#[derive(Deserialize)]
+struct ReceivedEnvelope {
+    payload: Payload,
+}
+
+/// Only the version of a received frame, which can always be read regardless of the payload
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+/// Errors when reading a received frame
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The peer speaks a different version of the protocol
+    IncompatibleVersion(u32),
+    /// The frame is no valid envelope
+    InvalidFrame(serde_json::Error),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::IncompatibleVersion(version) => write!(
+                f,
+                "Incompatible protocol version {} (expected version {})",
+                version, PROTOCOL_VERSION
+            ),
+            ProtocolError::InvalidFrame(e) => write!(f, "Invalid frame: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl Payload {
+    /// Wraps the payload into an envelope with the current protocol version and serializes it
+    pub fn to_json(&self) -> String {
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION,
+            payload: self,
+        };
+        serde_json::to_string(&envelope).expect("Payload is always serializable")
+    }
+
+    /// Parses a received frame and checks that it was sent with the current protocol version
+    pub fn from_json(text: &str) -> Result<Payload, ProtocolError> {
+        let header: VersionHeader =
+            serde_json::from_str(text).map_err(ProtocolError::InvalidFrame)?;
+        if header.version != PROTOCOL_VERSION {
+            return Err(ProtocolError::IncompatibleVersion(header.version));
+        }
+
+        let envelope: ReceivedEnvelope =
+            serde_json::from_str(text).map_err(ProtocolError::InvalidFrame)?;
+        Ok(envelope.payload)
+    }
+
+    /// Creates an error payload with the message
+    pub fn error(message: &str) -> Payload {
+        Payload::Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_round_trip() {
+        let request = ClientRequest::Login {
+            username: "rino".to_string(),
+            password_hash: "hash".to_string(),
+            device_name: "laptop".to_string(),
+            remember_me: true,
+            new_password_hash: Some("new hash".to_string()),
+        };
+        match Payload::from_json(&Payload::Request(request.clone()).to_json()).unwrap() {
+            Payload::Request(parsed) => assert_eq!(parsed, request),
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_request_without_data_round_trip() {
+        let request = ClientRequest::GetChatRooms;
+        match Payload::from_json(&Payload::Request(request.clone()).to_json()).unwrap() {
+            Payload::Request(parsed) => assert_eq!(parsed, request),
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_chat_message_round_trip() {
+        let message = ChatMessage::new(1, "rino".to_string(), "Hallo: Anton!".to_string());
+        match Payload::from_json(&Payload::ChatMessage(message.clone()).to_json()).unwrap() {
+            Payload::ChatMessage(parsed) => assert_eq!(parsed, message),
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_envelope_contains_version() {
+        let json = Payload::Hello {
+            session_token: None,
+        }
+        .to_json();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], PROTOCOL_VERSION);
+        assert_eq!(value["payload"]["type"], "Hello");
+    }
+
+    #[test]
+    fn test_hello_with_session_token_round_trip() {
+        let hello = Payload::Hello {
+            session_token: Some("token".to_string()),
+        };
+        match Payload::from_json(&hello.to_json()).unwrap() {
+            Payload::Hello { session_token } => {
+                assert_eq!(session_token, Some("token".to_string()))
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_incompatible_version() {
+        let json = r#"{"version":999,"payload":{"type":"SomethingNew"}}"#;
+        match Payload::from_json(json) {
+            Err(ProtocolError::IncompatibleVersion(version)) => assert_eq!(version, 999),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_frame() {
+        assert!(matches!(
+            Payload::from_json("1"),
+            Err(ProtocolError::InvalidFrame(_))
+        ));
+        assert!(matches!(
+            Payload::from_json("rino: Hallo, Anton!"),
+            Err(ProtocolError::InvalidFrame(_))
+        ));
+        let unknown_request = format!(
+            r#"{{"version":{},"payload":{{"type":"Request","data":{{"type":"DropAllTables"}}}}}}"#,
+            PROTOCOL_VERSION
+        );
+        assert!(matches!(
+            Payload::from_json(&unknown_request),
+            Err(ProtocolError::InvalidFrame(_))
+        ));
+    }
+
+    #[test]
+    fn test_join_public_room_round_trip() {
+        let request = ClientRequest::JoinPublicRoom {
+            chatroom_id: 3,
+            password_hash: Some("hash".to_string()),
+        };
+        match Payload::from_json(&Payload::Request(request.clone()).to_json()).unwrap() {
+            Payload::Request(parsed) => assert_eq!(parsed, request),
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_moderation_round_trip() {
+        let action = ModerationAction::SetRole {
+            username: "rino".to_string(),
+            role: RoomRole::Moderator,
+        };
+        let payload = Payload::Moderation {
+            chatroom_id: 3,
+            action: action.clone(),
+        };
+        match Payload::from_json(&payload.to_json()).unwrap() {
+            Payload::Moderation {
+                chatroom_id,
+                action: parsed,
+            } => {
+                assert_eq!(chatroom_id, 3);
+                assert_eq!(parsed, action);
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_history_request_round_trip() {
+        let payload = Payload::HistoryRequest {
+            chatroom_id: 3,
+            before_message_id: Some(40),
+            keep_unread: true,
+        };
+        match Payload::from_json(&payload.to_json()).unwrap() {
+            Payload::HistoryRequest {
+                chatroom_id,
+                before_message_id,
+                keep_unread,
+            } => {
+                assert_eq!(chatroom_id, 3);
+                assert_eq!(before_message_id, Some(40));
+                assert!(keep_unread);
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_edit_message_round_trip() {
+        let payload = Payload::EditMessage {
+            chatroom_id: 3,
+            message_id: 40,
+            content: "e2e2.new".to_string(),
+        };
+        match Payload::from_json(&payload.to_json()).unwrap() {
+            Payload::EditMessage {
+                chatroom_id,
+                message_id,
+                content,
+            } => {
+                assert_eq!((chatroom_id, message_id), (3, 40));
+                assert_eq!(content, "e2e2.new");
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_message_changed_round_trip() {
+        let message = ChatMessage::from_database(
+            40,
+            7,
+            3,
+            Some((1, "rino".to_string())),
+            1000,
+            String::new(),
+        )
+        .with_changes(Some(1100), Some(1200));
+        match Payload::from_json(&Payload::MessageChanged(message.clone()).to_json()).unwrap() {
+            Payload::MessageChanged(parsed) => assert_eq!(parsed, message),
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_edit_history_round_trip() {
+        let edits = vec![
+            MessageEdit::new("e2e2.first".to_string(), 1100),
+            MessageEdit::new("e2e2.second".to_string(), 1200),
+        ];
+        let payload = Payload::EditHistory {
+            message_id: 40,
+            edits: edits.clone(),
+        };
+        match Payload::from_json(&payload.to_json()).unwrap() {
+            Payload::EditHistory {
+                message_id,
+                edits: parsed,
+            } => {
+                assert_eq!(message_id, 40);
+                assert_eq!(parsed, edits);
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_response_with_chatrooms_round_trip() {
+        let members = vec![
+            User::new(1, "anton".to_string()),
+            User::new(2, "rino".to_string()),
+        ];
+        let response = Payload::Response(ServerResponse::ChatRooms(vec![
+            ChatRoom::new(1, "TestChat1".to_string(), members.clone()),
+            ChatRoom::new_public(2, "Lobby".to_string(), members.clone(), true),
+        ]));
+        match Payload::from_json(&response.to_json()).unwrap() {
+            Payload::Response(ServerResponse::ChatRooms(chatrooms)) => {
+                assert_eq!(chatrooms.len(), 2);
+                assert_eq!(*chatrooms[0].get_id(), 1);
+                assert_eq!(*chatrooms[0].get_name(), "TestChat1".to_string());
+                assert_eq!(*chatrooms[0].get_members(), members);
+                assert!(!chatrooms[0].is_public());
+                assert!(chatrooms[1].is_public());
+                assert!(chatrooms[1].has_password());
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use crate::crypto::{self, IdentityKeyPair};
+use crate::helper_functions::{decode_hex, encode_hex};
+use rand::rngs::OsRng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Prefix of messages that are encrypted by a ratchet session
+/// <br>Format: `e2e2.<header as hex>.<ciphertext with tag as hex>`
+const RATCHET_PREFIX: &str = "e2e2.";
+
+/// Prefix of messages in group chats, which contain one ratchet message for every other member
+/// <br>Format: `e2eg.<user id>=<ratchet message>;<user id>=<ratchet message>...`
+const GROUP_PREFIX: &str = "e2eg.";
+
+/// Length of the header: ratchet key of the sender, length of the previous chain and number of the message
+const HEADER_LEN: usize = 32 + 4 + 4;
+
+/// Maximum number of messages of a single chain that may be skipped
+/// <br>Protects against a sender that makes the receiver compute millions of keys
+const MAX_SKIP: u32 = 1000;
+
+/// Maximum number of keys of skipped messages that are kept
+const MAX_SKIPPED_MESSAGE_KEYS: usize = 2000;
+
+const ROOT_KEY_INFO: &[u8] = b"chatclient ratchet root key";
+const INITIAL_CHAIN_INFO: &[u8] = b"chatclient ratchet initial chain";
+const MESSAGE_KEY_INFO: &[u8] = b"chatclient ratchet message key";
+
+/// Key of a message that was skipped, so that it can still be decrypted when it arrives later
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SkippedMessageKey {
+    ratchet_key: [u8; 32],
+    number: u32,
+    message_key: [u8; 32],
+}
+
+/// Double ratchet between the two members of a chatroom
+/// <br>Every message is encrypted with its own key. Keys are deleted after use and every reply
+/// brings in a new Diffie-Hellman key, so a leaked state reveals neither old nor future messages.
+/// Once decrypted, a message can not be decrypted by the session again. To show the history again, the client keeps
+/// the plaintexts apart from the session, in the message store of the device (see `MessageIndex`).
+/// <br>The member with the lower user id starts the ratchet, the other member can already send on
+/// an initial chain that is derived from both identity keys until the first message arrives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RatchetSession {
+    chatroom_id: u32,
+    /// Public identity keys the session was created for, the session is replaced if one of them changes
+    own_identity_key: String,
+    other_identity_key: String,
+    root_key: [u8; 32],
+    sending_ratchet_key: [u8; 32],
+    receiving_ratchet_key: Option<[u8; 32]>,
+    sending_chain_key: [u8; 32],
+    receiving_chain_key: Option<[u8; 32]>,
+    sent_count: u32,
+    received_count: u32,
+    previous_sending_count: u32,
+    skipped_message_keys: Vec<SkippedMessageKey>,
+}
+
+/// Header of an encrypted message, it is authenticated but not encrypted
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    ratchet_key: [u8; 32],
+    previous_chain_length: u32,
+    number: u32,
+}
+
+impl Header {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[..32].copy_from_slice(&self.ratchet_key);
+        bytes[32..36].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        bytes[36..].copy_from_slice(&self.number.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Header> {
+        if bytes.len() != HEADER_LEN {
+            return None;
+        }
+        Some(Header {
+            ratchet_key: bytes[..32].try_into().ok()?,
+            previous_chain_length: u32::from_be_bytes(bytes[32..36].try_into().ok()?),
+            number: u32::from_be_bytes(bytes[36..].try_into().ok()?),
+        })
+    }
+}
+
+impl RatchetSession {
+    ///Starts a new session with the other member of the chatroom
+    pub fn new(
+        key_pair: &IdentityKeyPair,
+        own_user_id: u32,
+        other_public_key_hex: &str,
+        other_user_id: u32,
+        chatroom_id: u32,
+    ) -> Result<RatchetSession, Box<dyn Error>> {
+        let session_secret = key_pair.derive_session_secret(other_public_key_hex, chatroom_id)?;
+        let other_identity_key: [u8; 32] = decode_hex(other_public_key_hex)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("Invalid public key.")?;
+        let initial_chain_key = derive_initial_chain_key(&session_secret);
+
+        let mut session = RatchetSession {
+            chatroom_id,
+            own_identity_key: key_pair.get_public_key_hex(),
+            other_identity_key: other_public_key_hex.to_string(),
+            root_key: session_secret,
+            sending_ratchet_key: key_pair.get_secret_bytes(),
+            receiving_ratchet_key: None,
+            sending_chain_key: initial_chain_key,
+            receiving_chain_key: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_sending_count: 0,
+            skipped_message_keys: Vec::new(),
+        };
+
+        if own_user_id < other_user_id {
+            // The starting member ratchets right away and reads the initial chain of the other member
+            let ratchet_key = StaticSecret::random_from_rng(OsRng);
+            let (root_key, sending_chain_key) = derive_root_key(
+                &session.root_key,
+                &diffie_hellman(&ratchet_key, &other_identity_key),
+            );
+            session.root_key = root_key;
+            session.sending_ratchet_key = ratchet_key.to_bytes();
+            session.sending_chain_key = sending_chain_key;
+            session.receiving_ratchet_key = Some(other_identity_key);
+            session.receiving_chain_key = Some(initial_chain_key);
+        }
+        Ok(session)
+    }
+
+    ///Returns true if the session was created for these identity keys
+    pub fn matches_identity_keys(
+        &self,
+        own_public_key_hex: &str,
+        other_public_key_hex: &str,
+    ) -> bool {
+        self.own_identity_key == own_public_key_hex
+            && self.other_identity_key == other_public_key_hex
+    }
+
+    ///Encrypts a message with the next key of the sending chain
+    pub fn encrypt(&mut self, plaintext: &str) -> String {
+        let (message_key, next_chain_key) = derive_chain_keys(&self.sending_chain_key);
+        let header = Header {
+            ratchet_key: PublicKey::from(&StaticSecret::from(self.sending_ratchet_key)).to_bytes(),
+            previous_chain_length: self.previous_sending_count,
+            number: self.sent_count,
+        };
+        self.sending_chain_key = next_chain_key;
+        self.sent_count += 1;
+
+        let mut ciphertext = plaintext.as_bytes().to_vec();
+        let (key, nonce) = get_message_cipher(&message_key);
+        key.seal_in_place_append_tag(nonce, self.get_aad(&header), &mut ciphertext)
+            .expect("Message is too long to be encrypted");
+        let content = format!(
+            "{}{}.{}",
+            RATCHET_PREFIX,
+            encode_hex(&header.to_bytes()),
+            encode_hex(&ciphertext)
+        );
+        content
+    }
+
+    ///Decrypts a message of the other member, messages may arrive late or out of order
+    /// <br>The session is only changed if the message could be decrypted
+    pub fn decrypt(&mut self, content: &str) -> Result<String, Box<dyn Error>> {
+        let (header, ciphertext) = parse_message(content).ok_or("Message is not encrypted.")?;
+
+        let mut session = self.clone();
+        let message_key = match session.take_skipped_message_key(&header) {
+            Some(message_key) => message_key,
+            None => {
+                if session.receiving_ratchet_key != Some(header.ratchet_key) {
+                    session.skip_message_keys(header.previous_chain_length)?;
+                    session.ratchet(header.ratchet_key)?;
+                }
+                session.skip_message_keys(header.number)?;
+                let chain_key = session
+                    .receiving_chain_key
+                    .ok_or("Message could not be decrypted.")?;
+                let (message_key, next_chain_key) = derive_chain_keys(&chain_key);
+                session.receiving_chain_key = Some(next_chain_key);
+                session.received_count += 1;
+                message_key
+            }
+        };
+
+        let mut ciphertext = ciphertext;
+        let (key, nonce) = get_message_cipher(&message_key);
+        let plaintext = key
+            .open_in_place(nonce, session.get_aad(&header), &mut ciphertext)
+            .map_err(|_| "Message could not be decrypted.")?;
+        let plaintext = String::from_utf8(plaintext.to_vec())?;
+
+        *self = session;
+        Ok(plaintext)
+    }
+
+    // Diffie-Hellman ratchet step with the new ratchet key of the other member
+    fn ratchet(&mut self, other_ratchet_key: [u8; 32]) -> Result<(), Box<dyn Error>> {
+        self.previous_sending_count = self.sent_count;
+        self.sent_count = 0;
+        self.received_count = 0;
+        self.receiving_ratchet_key = Some(other_ratchet_key);
+
+        let shared_secret = diffie_hellman(
+            &StaticSecret::from(self.sending_ratchet_key),
+            &other_ratchet_key,
+        );
+        let (root_key, receiving_chain_key) = derive_root_key(&self.root_key, &shared_secret);
+        self.receiving_chain_key = Some(receiving_chain_key);
+
+        let ratchet_key = StaticSecret::random_from_rng(OsRng);
+        let (root_key, sending_chain_key) =
+            derive_root_key(&root_key, &diffie_hellman(&ratchet_key, &other_ratchet_key));
+        self.root_key = root_key;
+        self.sending_ratchet_key = ratchet_key.to_bytes();
+        self.sending_chain_key = sending_chain_key;
+        Ok(())
+    }
+
+    // Keeps the keys of the messages of the current receiving chain up to `until`
+    fn skip_message_keys(&mut self, until: u32) -> Result<(), Box<dyn Error>> {
+        if until > self.received_count.saturating_add(MAX_SKIP) {
+            return Err("Too many messages were skipped.".into());
+        }
+        let (Some(ratchet_key), Some(mut chain_key)) =
+            (self.receiving_ratchet_key, self.receiving_chain_key)
+        else {
+            return Ok(());
+        };
+
+        while self.received_count < until {
+            let (message_key, next_chain_key) = derive_chain_keys(&chain_key);
+            self.skipped_message_keys.push(SkippedMessageKey {
+                ratchet_key,
+                number: self.received_count,
+                message_key,
+            });
+            chain_key = next_chain_key;
+            self.received_count += 1;
+        }
+        self.receiving_chain_key = Some(chain_key);
+
+        // The oldest messages are given up first
+        let too_many = self
+            .skipped_message_keys
+            .len()
+            .saturating_sub(MAX_SKIPPED_MESSAGE_KEYS);
+        self.skipped_message_keys.drain(..too_many);
+        Ok(())
+    }
+
+    fn take_skipped_message_key(&mut self, header: &Header) -> Option<[u8; 32]> {
+        let index = self.skipped_message_keys.iter().position(|skipped| {
+            skipped.ratchet_key == header.ratchet_key && skipped.number == header.number
+        })?;
+        Some(self.skipped_message_keys.remove(index).message_key)
+    }
+
+    // The header is authenticated together with the chatroom, so that it can not be changed or replayed elsewhere
+    fn get_aad(&self, header: &Header) -> Aad<Vec<u8>> {
+        let mut aad = header.to_bytes().to_vec();
+        aad.extend_from_slice(&self.chatroom_id.to_be_bytes());
+        Aad::from(aad)
+    }
+}
+
+/// Returns true if the content is a message that was encrypted by a ratchet session
+pub fn is_ratchet_message(content: &str) -> bool {
+    parse_message(content).is_some()
+}
+
+/// Joins the ratchet messages for the other members of a group chat into one message
+/// <br>Every member has its own session with every other member, so the server only sees ciphertexts
+pub fn create_group_message(messages: &[(u32, String)]) -> String {
+    let parts = messages
+        .iter()
+        .map(|(user_id, message)| format!("{}={}", user_id, message))
+        .collect::<Vec<String>>();
+    format!("{}{}", GROUP_PREFIX, parts.join(";"))
+}
+
+/// Splits a group message into the ratchet messages and the ids of the members they are for
+/// <br>Returns None if the content is no valid group message
+pub fn parse_group_message(content: &str) -> Option<Vec<(u32, String)>> {
+    content
+        .strip_prefix(GROUP_PREFIX)?
+        .split(';')
+        .map(|part| {
+            let (user_id, message) = part.split_once('=')?;
+            if !is_ratchet_message(message) {
+                return None;
+            }
+            Some((user_id.parse().ok()?, message.to_string()))
+        })
+        .collect()
+}
+
+/// Returns true if the content is a message of a group chat
+pub fn is_group_message(content: &str) -> bool {
+    parse_group_message(content).is_some()
+}
+
+fn parse_message(content: &str) -> Option<(Header, Vec<u8>)> {
+    let (header_hex, ciphertext_hex) = content.strip_prefix(RATCHET_PREFIX)?.split_once('.')?;
+    let header = Header::from_bytes(&decode_hex(header_hex)?)?;
+    Some((header, decode_hex(ciphertext_hex)?))
+}
+
+fn diffie_hellman(secret: &StaticSecret, public_key: &[u8; 32]) -> [u8; 32] {
+    secret
+        .diffie_hellman(&PublicKey::from(*public_key))
+        .to_bytes()
+}
+
+/// Returns the next root key and a new chain key
+fn derive_root_key(root_key: &[u8; 32], shared_secret: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut output = [0u8; 64];
+    crypto::hkdf_sha256(root_key, shared_secret, ROOT_KEY_INFO, &mut output);
+    split_keys(&output)
+}
+
+/// Chain the member that did not start the session sends on until the first message arrives
+fn derive_initial_chain_key(session_secret: &[u8; 32]) -> [u8; 32] {
+    let mut chain_key = [0u8; 32];
+    crypto::hkdf_sha256(&[], session_secret, INITIAL_CHAIN_INFO, &mut chain_key);
+    chain_key
+}
+
+/// Returns the key of the next message and the next chain key
+fn derive_chain_keys(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, chain_key);
+    let message_key = hmac::sign(&key, &[1]);
+    let next_chain_key = hmac::sign(&key, &[2]);
+    (
+        message_key
+            .as_ref()
+            .try_into()
+            .expect("HMAC-SHA256 has 32 bytes"),
+        next_chain_key
+            .as_ref()
+            .try_into()
+            .expect("HMAC-SHA256 has 32 bytes"),
+    )
+}
+
+/// Every message key is only used once, so the nonce can be derived from it
+fn get_message_cipher(message_key: &[u8; 32]) -> (LessSafeKey, Nonce) {
+    let mut output = [0u8; 32 + NONCE_LEN];
+    crypto::hkdf_sha256(&[], message_key, MESSAGE_KEY_INFO, &mut output);
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &output[..32]).expect("Key has 32 bytes");
+    let nonce = Nonce::try_assume_unique_for_key(&output[32..]).expect("Nonce has 12 bytes");
+    (LessSafeKey::new(key), nonce)
+}
+
+fn split_keys(output: &[u8; 64]) -> ([u8; 32], [u8; 32]) {
+    let (first, second) = output.split_at(32);
+    (
+        first.try_into().expect("Half of 64 bytes"),
+        second.try_into().expect("Half of 64 bytes"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sessions of rino (id 1, starts the ratchet) and anton (id 2) in chatroom 1
+    fn get_test_sessions() -> (RatchetSession, RatchetSession) {
+        let rino = IdentityKeyPair::generate();
+        let anton = IdentityKeyPair::generate();
+        let rino_session =
+            RatchetSession::new(&rino, 1, &anton.get_public_key_hex(), 2, 1).unwrap();
+        let anton_session =
+            RatchetSession::new(&anton, 2, &rino.get_public_key_hex(), 1, 1).unwrap();
+        (rino_session, anton_session)
+    }
+
+    #[test]
+    fn test_conversation() {
+        let (mut rino, mut anton) = get_test_sessions();
+
+        let message = rino.encrypt("Hallo, Anton!");
+        assert!(is_ratchet_message(&message));
+        assert!(crypto::is_encrypted(&message));
+        assert_eq!(anton.decrypt(&message).unwrap(), "Hallo, Anton!");
+
+        let answer = anton.encrypt("Guten Morgen, Rino!");
+        assert_eq!(rino.decrypt(&answer).unwrap(), "Guten Morgen, Rino!");
+
+        let message = rino.encrypt("Wie geht's?");
+        assert_eq!(anton.decrypt(&message).unwrap(), "Wie geht's?");
+    }
+
+    #[test]
+    fn test_member_that_did_not_start_can_write_first() {
+        let (mut rino, mut anton) = get_test_sessions();
+
+        let first = anton.encrypt("Bist du da?");
+        let second = anton.encrypt("Rino?");
+        assert_eq!(rino.decrypt(&first).unwrap(), "Bist du da?");
+        assert_eq!(rino.decrypt(&second).unwrap(), "Rino?");
+
+        let answer = rino.encrypt("Ja!");
+        assert_eq!(anton.decrypt(&answer).unwrap(), "Ja!");
+        let message = anton.encrypt("Super");
+        assert_eq!(rino.decrypt(&message).unwrap(), "Super");
+    }
+
+    #[test]
+    fn test_every_message_uses_a_new_key() {
+        let (mut rino, _) = get_test_sessions();
+        let first = rino.encrypt("Hallo");
+        let second = rino.encrypt("Hallo");
+        assert_ne!(first.split('.').nth(2), second.split('.').nth(2));
+    }
+
+    #[test]
+    fn test_out_of_order_and_skipped_messages() {
+        let (mut rino, mut anton) = get_test_sessions();
+
+        let messages: Vec<String> = (0..5).map(|i| rino.encrypt(&format!("{}", i))).collect();
+        assert_eq!(anton.decrypt(&messages[3]).unwrap(), "3");
+        assert_eq!(anton.decrypt(&messages[0]).unwrap(), "0");
+
+        // A reply starts a new chain, messages of the old chain can still be read afterwards
+        let answer = anton.encrypt("Antwort");
+        assert_eq!(rino.decrypt(&answer).unwrap(), "Antwort");
+        let next = rino.encrypt("5");
+        assert_eq!(anton.decrypt(&next).unwrap(), "5");
+
+        assert_eq!(anton.decrypt(&messages[4]).unwrap(), "4");
+        assert_eq!(anton.decrypt(&messages[1]).unwrap(), "1");
+        assert_eq!(anton.decrypt(&messages[2]).unwrap(), "2");
+    }
+
+    #[test]
+    fn test_message_of_new_chain_before_old_chain() {
+        let (mut rino, mut anton) = get_test_sessions();
+        let hello = rino.encrypt("Hallo");
+        anton.decrypt(&hello).unwrap();
+
+        // The first message of anton arrives after the next chain of anton was started
+        let first = anton.encrypt("1");
+        let second = anton.encrypt("2");
+        assert_eq!(rino.decrypt(&second).unwrap(), "2");
+        let answer = rino.encrypt("?");
+        assert_eq!(anton.decrypt(&answer).unwrap(), "?");
+        let third = anton.encrypt("3");
+
+        assert_eq!(rino.decrypt(&third).unwrap(), "3");
+        assert_eq!(rino.decrypt(&first).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_stored_session_does_not_reveal_decrypted_messages() {
+        let (mut rino, mut anton) = get_test_sessions();
+        let message = rino.encrypt("Hallo, Anton!");
+        assert_eq!(anton.decrypt(&message).unwrap(), "Hallo, Anton!");
+
+        // The message key is gone after use and no plaintext is stored with the session
+        let stored = serde_json::to_string(&anton).unwrap();
+        assert!(!stored.contains("Hallo"));
+        let mut restored: RatchetSession = serde_json::from_str(&stored).unwrap();
+        assert!(restored.decrypt(&message).is_err());
+        assert!(rino.decrypt(&message).is_err());
+    }
+
+    #[test]
+    fn test_changed_message_does_not_change_session() {
+        let (mut rino, mut anton) = get_test_sessions();
+        let message = rino.encrypt("Hallo, Anton!");
+
+        let last = message.chars().last().unwrap();
+        let changed = format!(
+            "{}{}",
+            &message[..message.len() - 1],
+            if last == '0' { '1' } else { '0' }
+        );
+        let before = anton.clone();
+        assert!(anton.decrypt(&changed).is_err());
+        assert_eq!(anton, before);
+        assert_eq!(anton.decrypt(&message).unwrap(), "Hallo, Anton!");
+    }
+
+    #[test]
+    fn test_message_of_other_session_can_not_be_decrypted() {
+        let (mut rino, _) = get_test_sessions();
+        let (_, mut other_anton) = get_test_sessions();
+        let message = rino.encrypt("Hallo, Anton!");
+        assert!(other_anton.decrypt(&message).is_err());
+    }
+
+    #[test]
+    fn test_too_many_skipped_messages() {
+        let (mut rino, mut anton) = get_test_sessions();
+        let mut last = String::new();
+        for i in 0..=MAX_SKIP + 1 {
+            last = rino.encrypt(&format!("{}", i));
+        }
+        assert!(anton.decrypt(&last).is_err());
+    }
+
+    #[test]
+    fn test_session_survives_serialization() {
+        let (mut rino, anton) = get_test_sessions();
+        let mut anton: RatchetSession =
+            serde_json::from_str(&serde_json::to_string(&anton).unwrap()).unwrap();
+        let message = rino.encrypt("Hallo, Anton!");
+        assert_eq!(anton.decrypt(&message).unwrap(), "Hallo, Anton!");
+    }
+
+    #[test]
+    fn test_is_ratchet_message() {
+        assert!(!is_ratchet_message("Hallo, Anton!"));
+        assert!(!is_ratchet_message("e2e2.00.00"));
+        assert!(is_ratchet_message(&format!(
+            "e2e2.{}.00",
+            "0".repeat(HEADER_LEN * 2)
+        )));
+    }
+
+    #[test]
+    fn test_group_message() {
+        let (mut rino, mut anton) = get_test_sessions();
+        let message = create_group_message(&[
+            (2, rino.encrypt("Hallo, Anton!")),
+            (3, rino.encrypt("Hallo, Antonia!")),
+        ]);
+        assert!(is_group_message(&message));
+
+        let parts = parse_group_message(&message).unwrap();
+        assert_eq!(
+            parts
+                .iter()
+                .map(|(user_id, _)| *user_id)
+                .collect::<Vec<u32>>(),
+            vec![2, 3]
+        );
+        assert_eq!(anton.decrypt(&parts[0].1).unwrap(), "Hallo, Anton!");
+    }
+
+    #[test]
+    fn test_is_group_message() {
+        let ratchet_message = format!("e2e2.{}.00", "0".repeat(HEADER_LEN * 2));
+        assert!(is_group_message(&format!("e2eg.2={}", ratchet_message)));
+        assert!(!is_group_message(&ratchet_message));
+        assert!(!is_group_message("e2eg.2=Hallo, Anton!"));
+        assert!(!is_group_message(&format!(
+            "e2eg.anton={}",
+            ratchet_message
+        )));
+        assert!(!is_group_message(&format!(
+            "e2eg.2={};3=Hallo, Antonia!",
+            ratchet_message
+        )));
+        assert!(!is_group_message("e2eg."));
+    }
+}
@@ -0,0 +1,323 @@
+use crate::storage::{Storage, StorageResult};
+use crate::structs::chat_room::ChatRoom;
+use crate::structs::message::ChatMessage;
+use crate::structs::receipt::{Receipt, ReceiptStatus};
+use crate::structs::user::User;
+use std::collections::HashMap;
+
+/// Number of messages in a page of the history
+pub const HISTORY_PAGE_SIZE: u32 = 50;
+
+/// Page of the history of a chatroom as it is sent to a member
+pub struct LoadedHistory {
+    pub messages: Vec<ChatMessage>,
+    /// True if there are messages older than the ones of the page
+    pub has_more: bool,
+    /// Statuses of the messages the member wrote
+    pub receipts: Vec<Receipt>,
+    /// New statuses for the senders of the messages the member read now
+    pub read: Vec<(String, Receipt)>,
+}
+
+/// Returns the status of a message for its sender, which is the lowest status of all other members
+/// <br>Members without a receipt only have the message stored
+pub fn combine_statuses(
+    receipts: &[(u32, ReceiptStatus)],
+    other_member_ids: &[u32],
+) -> ReceiptStatus {
+    other_member_ids
+        .iter()
+        .map(|member_id| {
+            receipts
+                .iter()
+                .find(|(user_id, _)| user_id == member_id)
+                .map_or(ReceiptStatus::Stored, |(_, status)| *status)
+        })
+        .min()
+        .unwrap_or(ReceiptStatus::Stored)
+}
+
+/// Saves the receipt a member sent for a message of another member
+/// <br>Returns the name of the sender and the new status of the message, None if the status did not change.
+/// Receipts for messages of other chatrooms and for own messages are ignored
+pub fn save_receipt(
+    storage: &dyn Storage,
+    chatroom: &ChatRoom,
+    user: &User,
+    receipt: Receipt,
+) -> StorageResult<Option<(String, Receipt)>> {
+    let message_id = receipt.get_message_id();
+    let message = match storage.get_chat_message_by_id(message_id)? {
+        Some(message) => message,
+        None => return Ok(None),
+    };
+    if message.get_chatroom_id() != *chatroom.get_id()
+        || message.get_sender_id() == Some(user.get_id())
+        || receipt.get_status() == ReceiptStatus::Stored
+    {
+        return Ok(None);
+    }
+
+    if receipt.get_status() == ReceiptStatus::Read {
+        storage.set_last_read_message(*chatroom.get_id(), user.get_id(), message_id)?;
+    }
+
+    let other_member_ids = get_other_member_ids(chatroom, message.get_sender_id());
+    let old_status = combine_statuses(
+        &storage.get_message_receipts(message_id)?,
+        &other_member_ids,
+    );
+    storage.save_message_receipt(message_id, user.get_id(), receipt.get_status())?;
+    let new_status = combine_statuses(
+        &storage.get_message_receipts(message_id)?,
+        &other_member_ids,
+    );
+    if new_status == old_status {
+        return Ok(None);
+    }
+    Ok(Some((
+        message.get_sender_name().clone(),
+        Receipt::new(message_id, new_status),
+    )))
+}
+
+/// Loads a page of the history of the chatroom for a member, who reads every message of the other members on the page
+/// <br>The page ends before the message with the id, without an id it is the newest page.
+/// Afterwards the chatroom has no unread messages for the member. With `mark_as_read` false the page is only loaded,
+/// no receipt is saved and nothing is read
+pub fn read_history(
+    storage: &dyn Storage,
+    chatroom: &ChatRoom,
+    user: &User,
+    before_message_id: Option<u32>,
+    mark_as_read: bool,
+) -> StorageResult<LoadedHistory> {
+    // One message more than the page shows whether there are older messages
+    let mut messages = storage.get_chat_messages_before(
+        *chatroom.get_id(),
+        before_message_id,
+        HISTORY_PAGE_SIZE + 1,
+    )?;
+    let has_more = messages.len() > HISTORY_PAGE_SIZE as usize;
+    if has_more {
+        messages.remove(0);
+    }
+    // Only the receipts of the messages on the page are loaded
+    let mut receipts_by_message: HashMap<u32, Vec<(u32, ReceiptStatus)>> = HashMap::new();
+    let first_message_id = messages.first().and_then(|message| message.get_id());
+    let last_message_id = messages.last().and_then(|message| message.get_id());
+    if let (Some(first_message_id), Some(last_message_id)) = (first_message_id, last_message_id) {
+        for (message_id, user_id, status) in storage.get_message_receipts_for_messages(
+            *chatroom.get_id(),
+            first_message_id,
+            last_message_id,
+        )? {
+            receipts_by_message
+                .entry(message_id)
+                .or_default()
+                .push((user_id, status));
+        }
+    }
+
+    let mut own_receipts = Vec::new();
+    let mut read = Vec::new();
+    for message in &messages {
+        let Some(message_id) = message.get_id() else {
+            continue;
+        };
+        let receipts = receipts_by_message.entry(message_id).or_default();
+        let other_member_ids = get_other_member_ids(chatroom, message.get_sender_id());
+        if message.get_sender_id() == Some(user.get_id()) {
+            own_receipts.push(Receipt::new(
+                message_id,
+                combine_statuses(receipts, &other_member_ids),
+            ));
+            continue;
+        }
+        if !mark_as_read || receipts.contains(&(user.get_id(), ReceiptStatus::Read)) {
+            continue;
+        }
+
+        let old_status = combine_statuses(receipts, &other_member_ids);
+        storage.save_message_receipt(message_id, user.get_id(), ReceiptStatus::Read)?;
+        receipts.retain(|(user_id, _)| *user_id != user.get_id());
+        receipts.push((user.get_id(), ReceiptStatus::Read));
+        let new_status = combine_statuses(receipts, &other_member_ids);
+        if new_status != old_status {
+            read.push((
+                message.get_sender_name().clone(),
+                Receipt::new(message_id, new_status),
+            ));
+        }
+    }
+
+    if let Some(last_message_id) = messages
+        .last()
+        .and_then(|message| message.get_id())
+        .filter(|_| mark_as_read)
+    {
+        storage.set_last_read_message(*chatroom.get_id(), user.get_id(), last_message_id)?;
+    }
+
+    Ok(LoadedHistory {
+        messages,
+        has_more,
+        receipts: own_receipts,
+        read,
+    })
+}
+
+// Members who left the chatroom are not waited for
+fn get_other_member_ids(chatroom: &ChatRoom, sender_id: Option<u32>) -> Vec<u32> {
+    chatroom
+        .get_member_ids()
+        .into_iter()
+        .filter(|member_id| Some(*member_id) != sender_id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestStorageBuilder;
+
+    #[test]
+    fn test_combine_statuses() {
+        let receipts = [(2, ReceiptStatus::Read), (3, ReceiptStatus::Delivered)];
+        assert_eq!(combine_statuses(&receipts, &[2]), ReceiptStatus::Read);
+        assert_eq!(
+            combine_statuses(&receipts, &[2, 3]),
+            ReceiptStatus::Delivered
+        );
+        assert_eq!(
+            combine_statuses(&receipts, &[2, 3, 4]),
+            ReceiptStatus::Stored
+        );
+        assert_eq!(combine_statuses(&receipts, &[]), ReceiptStatus::Stored);
+    }
+
+    #[test]
+    fn test_receipts_of_group_chat() {
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .user("antonia")
+            .friends("rino", "anton")
+            .friends("rino", "antonia")
+            .chat_room("Group", &["rino", "anton", "antonia"])
+            .message(1, "rino", "Hallo zusammen!")
+            .message(1, "anton", "Hallo, Rino!")
+            .build();
+        let chatroom = storage.get_chatroom_by_id(1).unwrap();
+        let rino = storage.get_user_from_database_by_name("rino").unwrap();
+        let anton = storage.get_user_from_database_by_name("anton").unwrap();
+        let antonia = storage.get_user_from_database_by_name("antonia").unwrap();
+
+        // Own messages and messages of other chatrooms get no receipts
+        let delivered = Receipt::new(1, ReceiptStatus::Delivered);
+        assert_eq!(
+            save_receipt(storage.as_ref(), &chatroom, &rino, delivered).unwrap(),
+            None
+        );
+        assert_eq!(
+            save_receipt(
+                storage.as_ref(),
+                &chatroom,
+                &anton,
+                Receipt::new(9, ReceiptStatus::Read)
+            )
+            .unwrap(),
+            None
+        );
+
+        // The status changes when every other member got the message
+        assert_eq!(
+            save_receipt(storage.as_ref(), &chatroom, &anton, delivered).unwrap(),
+            None
+        );
+        assert_eq!(
+            save_receipt(storage.as_ref(), &chatroom, &antonia, delivered).unwrap(),
+            Some(("rino".to_string(), delivered))
+        );
+
+        // Reading the history reads every message of the other members
+        let history = read_history(storage.as_ref(), &chatroom, &anton, None, true).unwrap();
+        assert_eq!(history.messages.len(), 2);
+        assert_eq!(
+            history.receipts,
+            vec![Receipt::new(2, ReceiptStatus::Stored)]
+        );
+        assert!(history.read.is_empty());
+        let history = read_history(storage.as_ref(), &chatroom, &antonia, None, true).unwrap();
+        assert_eq!(
+            history.read,
+            vec![("rino".to_string(), Receipt::new(1, ReceiptStatus::Read))]
+        );
+        assert_eq!(storage.get_unread_counts(antonia.get_id()).unwrap(), vec![]);
+        assert_eq!(
+            storage.get_unread_counts(rino.get_id()).unwrap(),
+            vec![(1, 1)]
+        );
+        let history = read_history(storage.as_ref(), &chatroom, &rino, None, true).unwrap();
+        assert_eq!(history.receipts, vec![Receipt::new(1, ReceiptStatus::Read)]);
+        assert_eq!(
+            history.read,
+            vec![("anton".to_string(), Receipt::new(2, ReceiptStatus::Read))]
+        );
+        assert_eq!(storage.get_unread_counts(rino.get_id()).unwrap(), vec![]);
+        assert!(!history.has_more);
+    }
+
+    #[test]
+    fn test_read_history_in_pages() {
+        let mut builder = TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .friends("rino", "anton")
+            .chat_room("rino and anton's chat", &["rino", "anton"]);
+        for number in 1..=HISTORY_PAGE_SIZE + 3 {
+            builder = builder.message(1, "anton", &format!("Nachricht {}", number));
+        }
+        let storage = builder.build();
+        let chatroom = storage.get_chatroom_by_id(1).unwrap();
+        let rino = storage.get_user_from_database_by_name("rino").unwrap();
+
+        let newest = read_history(storage.as_ref(), &chatroom, &rino, None, true).unwrap();
+        assert_eq!(newest.messages.len(), HISTORY_PAGE_SIZE as usize);
+        assert_eq!(newest.messages[0].get_id(), Some(4));
+        assert!(newest.has_more);
+        // Only the messages on the page were read
+        assert_eq!(newest.read.len(), HISTORY_PAGE_SIZE as usize);
+        assert_eq!(storage.get_message_receipts(3).unwrap(), vec![]);
+
+        let older = read_history(storage.as_ref(), &chatroom, &rino, Some(4), true).unwrap();
+        assert_eq!(older.messages.len(), 3);
+        assert_eq!(older.messages[0].get_content(), "Nachricht 1");
+        assert!(!older.has_more);
+        assert_eq!(
+            storage.get_message_receipts(3).unwrap(),
+            vec![(rino.get_id(), ReceiptStatus::Read)]
+        );
+    }
+    #[test]
+    fn test_read_history_without_reading() {
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .friends("rino", "anton")
+            .chat_room("rino and anton's chat", &["rino", "anton"])
+            .message(1, "anton", "Hallo Rino")
+            .build();
+        let chatroom = storage.get_chatroom_by_id(1).unwrap();
+        let rino = storage.get_user_from_database_by_name("rino").unwrap();
+
+        let history = read_history(storage.as_ref(), &chatroom, &rino, None, false).unwrap();
+        assert_eq!(history.messages.len(), 1);
+        assert!(history.read.is_empty());
+        assert_eq!(storage.get_message_receipts(1).unwrap(), vec![]);
+        assert_eq!(
+            storage.get_unread_counts(rino.get_id()).unwrap(),
+            vec![(1, 1)]
+        );
+    }
+}
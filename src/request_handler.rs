@@ -0,0 +1,1403 @@
+use crate::crypto;
+use crate::helper_functions;
+use crate::password_hashing::{self, PasswordCheck};
+use crate::protocol::{ClientRequest, Payload, ServerResponse};
+use crate::receipts;
+use crate::server::{ServerState, Session};
+use crate::storage::{self, Storage, StorageResult};
+use crate::structs::chat_room::{ChatRoom, RoomEvent};
+use crate::structs::presence::{Presence, PresenceStatus};
+use crate::structs::receipt::{Receipt, ReceiptStatus};
+use crate::structs::room_role::RoomRole;
+use crate::structs::user::User;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+type HandlerResult = Result<Payload, Box<dyn Error + Send + Sync>>;
+
+/// Length of the ChatName column of the chats table
+const MAX_CHATROOM_NAME_LENGTH: usize = 45;
+
+/// Handles a single request of a client and returns the payload to answer with
+/// <br>`current_session` is the session of the user that is logged in on the connection, if any.
+/// Every call of the storage goes through `storage::run_blocking`
+pub async fn handle_request(
+    state: &ServerState,
+    current_session: &mut Option<Session>,
+    request: ClientRequest,
+) -> Payload {
+    let storage = &state.storage;
+    let result = match request {
+        ClientRequest::CheckIfUsernameExists { username } => {
+            storage::run_blocking(storage, move |storage| {
+                storage.check_if_username_exists(&username)
+            })
+            .await
+            .map(|exists| Payload::Response(ServerResponse::UsernameExists(exists)))
+        }
+        ClientRequest::CheckIfUserIsBlocked { username } => {
+            storage::run_blocking(storage, move |storage| {
+                storage.check_if_user_isblocked(&username)
+            })
+            .await
+            .map(|is_blocked| Payload::Response(ServerResponse::UserIsBlocked(is_blocked)))
+        }
+        ClientRequest::Login {
+            username,
+            password_hash,
+            device_name,
+            remember_me,
+            new_password_hash,
+        } => {
+            login(
+                state,
+                current_session,
+                username,
+                password_hash,
+                device_name,
+                remember_me,
+                new_password_hash,
+            )
+            .await
+        }
+        ClientRequest::SignUp {
+            username,
+            password_hash,
+            device_name,
+        } => {
+            sign_up(
+                storage,
+                current_session,
+                username,
+                password_hash,
+                device_name,
+            )
+            .await
+        }
+        ClientRequest::ResumeSession { session_token } => {
+            resume_session(storage, current_session, session_token).await
+        }
+        ClientRequest::Logout => logout(storage, current_session).await,
+        request => handle_request_of_session(state, current_session, request).await,
+    };
+
+    result.unwrap_or_else(|e| {
+        // Do not leak details about the database to the client
+        eprintln!("Error while handling request: {}", e);
+        Payload::error("Internal server error.")
+    })
+}
+
+/// Checks that the session of the connection is still valid before handling the request
+/// <br>Sessions can expire or be revoked from another device while the connection is open
+async fn handle_request_of_session(
+    state: &ServerState,
+    current_session: &mut Option<Session>,
+    request: ClientRequest,
+) -> HandlerResult {
+    let storage = &state.storage;
+    let session = match current_session {
+        Some(session) => match get_session(storage, &session.token).await? {
+            Some(session) => session,
+            None => {
+                *current_session = None;
+                return Ok(Payload::error(
+                    "Your session has expired. Please log in again.",
+                ));
+            }
+        },
+        None => return Ok(Payload::error("You need to log in first.")),
+    };
+
+    let (user_id, current_session_id) = (session.user.get_id(), session.id);
+    match request {
+        ClientRequest::GetSessions => storage::run_blocking(storage, move |storage| {
+            storage.get_sessions_of_user(user_id, current_session_id)
+        })
+        .await
+        .map(|sessions| Payload::Response(ServerResponse::Sessions(sessions))),
+        ClientRequest::RevokeSession { session_id } => {
+            let is_deleted = storage::run_blocking(storage, move |storage| {
+                storage.delete_session(session_id, user_id)
+            })
+            .await?;
+            if is_deleted {
+                if session_id == session.id {
+                    *current_session = None;
+                }
+                Ok(Payload::Ack)
+            } else {
+                Ok(Payload::error("Session not found."))
+            }
+        }
+        request => handle_request_of_user(state, session.user, request).await,
+    }
+}
+
+/// Handles the requests that are only allowed for logged in users
+async fn handle_request_of_user(
+    state: &ServerState,
+    user: User,
+    request: ClientRequest,
+) -> HandlerResult {
+    let storage = &state.storage;
+    let user_id = user.get_id();
+    match request {
+        ClientRequest::SearchUsers { search_string } => {
+            storage::run_blocking(storage, move |storage| {
+                storage.get_user_by_name_with_contains_search(&search_string, user_id)
+            })
+            .await
+            .map(|users| Payload::Response(ServerResponse::Users(users)))
+        }
+        ClientRequest::CheckIfFriends {
+            user_id: other_user_id,
+        } => storage::run_blocking(storage, move |storage| {
+            storage.check_if_two_users_are_friends(user_id, other_user_id)
+        })
+        .await
+        .map(|are_friends| Payload::Response(ServerResponse::AreFriends(are_friends))),
+        ClientRequest::SendFriendRequest { receiver_id } => {
+            storage::run_blocking(storage, move |storage| {
+                send_friend_request(storage, user, receiver_id)
+            })
+            .await
+        }
+        ClientRequest::GetFriendRequests => storage::run_blocking(storage, move |storage| {
+            storage.get_friend_requests_by_user_id(user_id)
+        })
+        .await
+        .map(|requests| Payload::Response(ServerResponse::FriendRequests(requests))),
+        ClientRequest::AcceptFriendRequest { sender_id } => {
+            let response = storage::run_blocking(storage, move |storage| {
+                accept_friend_request(storage, user, sender_id)
+            })
+            .await?;
+            // From now on the two users see the presence of each other
+            if matches!(response, Payload::Ack) {
+                state.presence.publish_friendship(sender_id, user_id);
+            }
+            Ok(response)
+        }
+        ClientRequest::DeclineFriendRequest { sender_id } => {
+            storage::run_blocking(storage, move |storage| {
+                storage.delete_friend_request(sender_id, user_id)
+            })
+            .await?;
+            Ok(Payload::Ack)
+        }
+        ClientRequest::GetChatRooms => storage::run_blocking(storage, move |storage| {
+            storage.search_for_chatrooms_of_user(user_id)
+        })
+        .await
+        .map(|chatrooms| Payload::Response(ServerResponse::ChatRooms(chatrooms))),
+        ClientRequest::GetFriends => {
+            storage::run_blocking(storage, move |storage| storage.get_friends_of_user(user_id))
+                .await
+                .map(|friends| Payload::Response(ServerResponse::Users(friends)))
+        }
+        ClientRequest::CreateGroupChat { name, member_ids } => {
+            let max_group_members = state.config.max_group_members;
+            storage::run_blocking(storage, move |storage| {
+                create_group_chat(storage, max_group_members, user, name, member_ids)
+            })
+            .await
+        }
+        ClientRequest::CreatePublicRoom {
+            name,
+            password_hash,
+        } => create_public_room(storage, user, name, password_hash).await,
+        ClientRequest::SearchPublicRooms { search_string } => {
+            storage::run_blocking(storage, move |storage| {
+                storage.search_public_chatrooms(&search_string, user_id)
+            })
+            .await
+            .map(|chatrooms| Payload::Response(ServerResponse::ChatRooms(chatrooms)))
+        }
+        ClientRequest::JoinPublicRoom {
+            chatroom_id,
+            password_hash,
+        } => {
+            join_public_room(
+                storage,
+                state.config.max_group_members,
+                user,
+                chatroom_id,
+                password_hash,
+            )
+            .await
+        }
+        ClientRequest::PublishIdentityKey { public_key } => {
+            if !crypto::is_valid_public_key(&public_key) {
+                return Ok(Payload::error("Invalid identity key."));
+            }
+            storage::run_blocking(storage, move |storage| {
+                storage.set_identity_key(user_id, &public_key)
+            })
+            .await?;
+            Ok(Payload::Ack)
+        }
+        ClientRequest::GetIdentityKey {
+            user_id: key_user_id,
+        } => storage::run_blocking(storage, move |storage| {
+            storage.get_identity_key(key_user_id)
+        })
+        .await
+        .map(|public_key| {
+            Payload::Response(ServerResponse::IdentityKey {
+                user_id: key_user_id,
+                public_key,
+            })
+        }),
+        ClientRequest::GetPresence => get_presence_of_friends(state, user).await,
+        ClientRequest::GetUnreadCounts => get_unread_counts(state, user).await,
+        _ => Ok(Payload::error("Invalid request.")),
+    }
+}
+
+/// Checks the password of a user and blocks the account after too many wrong passwords
+/// <br>Users with a one-time password are only logged in together with a new password
+async fn login(
+    state: &ServerState,
+    current_session: &mut Option<Session>,
+    username: String,
+    password_hash: String,
+    device_name: String,
+    remember_me: bool,
+    new_password_hash: Option<String>,
+) -> HandlerResult {
+    let storage = &state.storage;
+    let name = username.clone();
+    let blocked_until = storage::run_blocking(storage, move |storage| {
+        if storage.check_if_user_isblocked(&name)? {
+            storage.get_user_is_blockeduntil(&name).map(Some)
+        } else {
+            Ok(None)
+        }
+    })
+    .await?;
+    if let Some(blocked_until) = blocked_until {
+        return Ok(Payload::Response(ServerResponse::AccountBlocked {
+            remaining_secs: blocked_until.saturating_sub(helper_functions::get_sys_time_in_secs()),
+        }));
+    }
+
+    if password_hashing::check_if_password_matches_username(storage, &password_hash, &username)
+        .await?
+    {
+        state.failed_logins.lock().await.remove(&username);
+        let name = username.clone();
+        let is_change_required = storage::run_blocking(storage, move |storage| {
+            storage.check_if_password_change_required(&name)
+        })
+        .await?;
+        if is_change_required {
+            match new_password_hash {
+                Some(new_password_hash)
+                    if new_password_hash != password_hash
+                        && password_hashing::is_valid_password_hash(&new_password_hash) =>
+                {
+                    let stored_hash =
+                        password_hashing::hash_password_for_storage(&new_password_hash).await?;
+                    let name = username.clone();
+                    storage::run_blocking(storage, move |storage| {
+                        storage.change_required_password(&name, &stored_hash)
+                    })
+                    .await?;
+                }
+                _ => return Ok(Payload::Response(ServerResponse::PasswordChangeRequired)),
+            }
+        }
+        let user = storage::run_blocking(storage, move |storage| {
+            storage.get_user_from_database_by_name(&username)
+        })
+        .await?;
+        let session =
+            start_session(storage, current_session, user, &device_name, remember_me).await?;
+        return Ok(Payload::Response(ServerResponse::LoginSucceeded {
+            user: session.user,
+            session_token: session.token,
+        }));
+    }
+
+    let max_login_attempts = state.config.max_login_attempts;
+    let tries = {
+        let mut failed_logins = state.failed_logins.lock().await;
+        let tries = failed_logins.entry(username.clone()).or_insert(0);
+        *tries += 1;
+        let tries = *tries;
+        if tries >= max_login_attempts {
+            failed_logins.remove(&username);
+        }
+        tries
+    };
+
+    if tries >= max_login_attempts {
+        //User needed too many tries --> block account
+        let lockout_secs = state.config.lockout_secs;
+        storage::run_blocking(storage, move |storage| {
+            storage.set_user_isblocked(&username, lockout_secs)
+        })
+        .await?;
+        return Ok(Payload::Response(ServerResponse::AccountBlocked {
+            remaining_secs: state.config.lockout_secs,
+        }));
+    }
+
+    Ok(Payload::Response(ServerResponse::LoginFailed {
+        tries_left: max_login_attempts - tries,
+    }))
+}
+
+/// Creates a new account and logs in as the new user
+/// <br>The client checks the username too, but other clients could send anything
+async fn sign_up(
+    storage: &Arc<dyn Storage>,
+    current_session: &mut Option<Session>,
+    username: String,
+    password_hash: String,
+    device_name: String,
+) -> HandlerResult {
+    if !User::is_valid_name(&username) {
+        return Ok(Payload::error(
+            "Usernames have to be 3 to 32 letters or digits.",
+        ));
+    }
+    if !password_hashing::is_valid_password_hash(&password_hash) {
+        return Ok(Payload::error("The password was not sent as hash."));
+    }
+    let name = username.clone();
+    let is_taken = storage::run_blocking(storage, move |storage| {
+        storage.check_if_username_exists(&name)
+    })
+    .await?;
+    if is_taken {
+        return Ok(Payload::error("This username is already taken."));
+    }
+
+    let stored_hash = password_hashing::hash_password_for_storage(&password_hash).await?;
+    let user = storage::run_blocking(storage, move |storage| {
+        storage.save_new_user_to_database_after_signup(&username, &stored_hash)?;
+        storage.get_user_from_database_by_name(&username)
+    })
+    .await?;
+    let session = start_session(storage, current_session, user, &device_name, false).await?;
+    Ok(Payload::Response(ServerResponse::SignedUp {
+        user: session.user,
+        session_token: session.token,
+    }))
+}
+
+/// Logs in with the token of an existing session
+async fn resume_session(
+    storage: &Arc<dyn Storage>,
+    current_session: &mut Option<Session>,
+    session_token: String,
+) -> HandlerResult {
+    match get_session(storage, &session_token).await? {
+        Some(session) => {
+            *current_session = Some(session.clone());
+            Ok(Payload::Response(ServerResponse::LoginSucceeded {
+                user: session.user,
+                session_token: session.token,
+            }))
+        }
+        None => Ok(Payload::error(
+            "Your session has expired. Please log in again.",
+        )),
+    }
+}
+
+/// Returns the session of the token if it is neither expired nor revoked
+async fn get_session(storage: &Arc<dyn Storage>, token: &str) -> StorageResult<Option<Session>> {
+    let token = token.to_string();
+    storage::run_blocking(storage, move |storage| Session::from_token(storage, &token)).await
+}
+
+/// Ends the session of the connection
+async fn logout(
+    storage: &Arc<dyn Storage>,
+    current_session: &mut Option<Session>,
+) -> HandlerResult {
+    if let Some(session) = current_session.take() {
+        storage::run_blocking(storage, move |storage| session.end(storage)).await?;
+    }
+    Ok(Payload::Ack)
+}
+
+/// Creates a new session for the user and uses it for the connection
+/// <br>A previous session of the connection is ended
+async fn start_session(
+    storage: &Arc<dyn Storage>,
+    current_session: &mut Option<Session>,
+    user: User,
+    device_name: &str,
+    remember_me: bool,
+) -> Result<Session, Box<dyn Error + Send + Sync>> {
+    let previous_session = current_session.take();
+    let device_name = device_name.to_string();
+    let session = storage::run_blocking(storage, move |storage| {
+        if let Some(previous_session) = previous_session {
+            previous_session.end(storage)?;
+        }
+        Session::create(storage, user, &device_name, remember_me)
+    })
+    .await?;
+    *current_session = Some(session.clone());
+    Ok(session)
+}
+
+/// Sends a friend request if the users are not friends already
+fn send_friend_request(storage: &dyn Storage, user: User, receiver_id: u32) -> HandlerResult {
+    if receiver_id == user.get_id() {
+        return Ok(Payload::error(
+            "You can not send a friend request to yourself.",
+        ));
+    }
+
+    if storage.check_if_two_users_are_friends(user.get_id(), receiver_id)? {
+        return Ok(Payload::error("You are already friends with this user."));
+    }
+
+    storage.create_new_friend_request(user.get_id(), receiver_id)?;
+    Ok(Payload::Ack)
+}
+
+/// Accepts a pending friend request and creates the chatroom of the new friends
+fn accept_friend_request(
+    storage: &dyn Storage,
+    receiving_user: User,
+    sender_id: u32,
+) -> HandlerResult {
+    let friend_requests = storage.get_friend_requests_by_user_id(receiving_user.get_id())?;
+    let friend_request = match friend_requests
+        .into_iter()
+        .find(|request| request.get_sender_id() == sender_id)
+    {
+        Some(friend_request) => friend_request,
+        None => return Ok(Payload::error("There is no friend request from this user.")),
+    };
+
+    storage.accept_friend_request_for_user(sender_id, receiving_user.get_id())?;
+    storage.create_friends(sender_id, receiving_user.get_id())?;
+
+    let chatroom_name = format!(
+        "{} and {}'s chat",
+        receiving_user.get_name(),
+        friend_request.get_display_name()
+    );
+    storage.create_new_chatroom(&[receiving_user.get_id(), sender_id], &chatroom_name)?;
+
+    Ok(Payload::Ack)
+}
+
+/// Creates a group chat of the user and some of the friends of the user
+/// <br>`max_group_members` includes the user who creates the group chat
+fn create_group_chat(
+    storage: &dyn Storage,
+    max_group_members: u32,
+    user: User,
+    name: String,
+    mut member_ids: Vec<u32>,
+) -> HandlerResult {
+    let name = name.trim();
+    if let Some(error) = check_chatroom_name(name) {
+        return Ok(error);
+    }
+
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    member_ids.retain(|member_id| *member_id != user.get_id());
+    if member_ids.len() < 2 {
+        return Ok(Payload::error(
+            "A group chat needs at least two of your friends.",
+        ));
+    }
+    if member_ids.len() + 1 > max_group_members as usize {
+        return Ok(Payload::error(&format!(
+            "A group chat can have at most {} members.",
+            max_group_members
+        )));
+    }
+
+    for member_id in &member_ids {
+        if !storage.check_if_two_users_are_friends(user.get_id(), *member_id)? {
+            return Ok(Payload::error(
+                "You can only add your friends to a group chat.",
+            ));
+        }
+    }
+
+    member_ids.push(user.get_id());
+    let chatroom = storage.create_new_chatroom(&member_ids, name)?;
+    // The creator manages the group chat
+    storage.set_chat_member_role(*chatroom.get_id(), user.get_id(), RoomRole::Owner)?;
+    Ok(Payload::Response(ServerResponse::ChatRoom(chatroom)))
+}
+
+/// Creates a public room with the user as first member
+/// <br>The name has to be unique among the public rooms, so that users can find the room by its name
+async fn create_public_room(
+    storage: &Arc<dyn Storage>,
+    user: User,
+    name: String,
+    password_hash: Option<String>,
+) -> HandlerResult {
+    let name = name.trim().to_string();
+    if let Some(error) = check_chatroom_name(&name) {
+        return Ok(error);
+    }
+    let room_name = name.clone();
+    let name_exists = storage::run_blocking(storage, move |storage| {
+        storage.check_if_public_chatroom_name_exists(&room_name)
+    })
+    .await?;
+    if name_exists {
+        return Ok(Payload::error(
+            "There is already a public room with this name.",
+        ));
+    }
+
+    // The room password is stored like the passwords of users
+    let stored_hash = match password_hash {
+        Some(password_hash) => {
+            Some(password_hashing::hash_password_for_storage(&password_hash).await?)
+        }
+        None => None,
+    };
+    let chatroom = storage::run_blocking(storage, move |storage| {
+        storage.create_public_chatroom(user.get_id(), &name, stored_hash.as_deref())
+    })
+    .await?;
+    // Another user may have created a room with the name since it was checked
+    match chatroom {
+        Some(chatroom) => Ok(Payload::Response(ServerResponse::ChatRoom(chatroom))),
+        None => Ok(Payload::error(
+            "There is already a public room with this name.",
+        )),
+    }
+}
+
+/// Makes the user a member of the public room, if the password is correct and the room is not full
+/// <br>Only members can join the chat of a room, so the password is checked before the user can read or write anything
+async fn join_public_room(
+    storage: &Arc<dyn Storage>,
+    max_group_members: u32,
+    user: User,
+    chatroom_id: u32,
+    password_hash: Option<String>,
+) -> HandlerResult {
+    let user_id = user.get_id();
+    let stored_hash = match storage::run_blocking(storage, move |storage| {
+        check_public_room_join(storage, max_group_members, user_id, chatroom_id)
+    })
+    .await?
+    {
+        Ok(stored_hash) => stored_hash,
+        Err(answer) => return Ok(answer),
+    };
+
+    if let Some(stored_hash) = stored_hash {
+        let is_valid = match password_hash {
+            Some(password_hash) => {
+                password_hashing::verify_password(&password_hash, &stored_hash).await?
+                    == PasswordCheck::Valid
+            }
+            None => false,
+        };
+        if !is_valid {
+            return Ok(Payload::error("Wrong password for this room."));
+        }
+    }
+
+    // The room may have become full while the password was checked
+    let chatroom = storage::run_blocking(storage, move |storage| {
+        if !storage.join_public_chatroom(chatroom_id, user_id, max_group_members)? {
+            return Ok(None);
+        }
+        storage.get_chatroom_by_id(chatroom_id).map(Some)
+    })
+    .await?;
+    match chatroom {
+        Some(chatroom) => Ok(Payload::Response(ServerResponse::ChatRoom(chatroom))),
+        None => Ok(get_room_full_error(max_group_members)),
+    }
+}
+
+/// Checks if the user may join the public room before the password is checked
+/// <br>Returns the password hash of the room, None for rooms without password, or the payload to answer with
+fn check_public_room_join(
+    storage: &dyn Storage,
+    max_group_members: u32,
+    user_id: u32,
+    chatroom_id: u32,
+) -> StorageResult<Result<Option<String>, Payload>> {
+    // Chatrooms that are not public are treated like chatrooms that do not exist
+    let chatroom = match storage.get_chatroom_by_id(chatroom_id) {
+        Ok(chatroom) if chatroom.is_public() => chatroom,
+        _ => return Ok(Err(Payload::error("Public room not found."))),
+    };
+    if chatroom.is_member(user_id) {
+        return Ok(Err(Payload::Response(ServerResponse::ChatRoom(chatroom))));
+    }
+    if storage.check_if_banned_from_chatroom(chatroom_id, user_id)? {
+        return Ok(Err(Payload::error("You were banned from this room.")));
+    }
+    if chatroom.get_members().len() >= max_group_members as usize {
+        return Ok(Err(get_room_full_error(max_group_members)));
+    }
+    storage.get_chatroom_password_hash(chatroom_id).map(Ok)
+}
+
+fn get_room_full_error(max_group_members: u32) -> Payload {
+    Payload::error(&format!(
+        "This room is full, it can have at most {} members.",
+        max_group_members
+    ))
+}
+
+/// Returns the presence of every friend of the user
+/// <br>Friends without an open connection are offline since the time that was saved on their last disconnect
+async fn get_presence_of_friends(state: &ServerState, user: User) -> HandlerResult {
+    // The times are read together with the friends, a friend may go offline in the meantime
+    let friends = storage::run_blocking(&state.storage, move |storage| {
+        storage
+            .get_friends_of_user(user.get_id())?
+            .into_iter()
+            .map(|friend| {
+                let last_seen = storage.get_last_seen(friend.get_id())?;
+                Ok((friend, last_seen))
+            })
+            .collect::<StorageResult<Vec<(User, u64)>>>()
+    })
+    .await?;
+    let presences = friends
+        .into_iter()
+        .map(|(friend, last_seen)| {
+            state
+                .presence
+                .get_presence(friend.get_id())
+                .unwrap_or_else(|| {
+                    Presence::new(friend.get_id(), PresenceStatus::Offline, last_seen)
+                })
+        })
+        .collect();
+    Ok(Payload::Response(ServerResponse::Presences(presences)))
+}
+
+/// Returns the number of unread messages in every chatroom of the user
+/// <br>Messages that arrived while the user was offline are delivered now, senders who are in the chat are told right away
+async fn get_unread_counts(state: &ServerState, user: User) -> HandlerResult {
+    let user_id = user.get_id();
+    let messages = storage::run_blocking(&state.storage, move |storage| {
+        storage.get_undelivered_messages(user_id)
+    })
+    .await?;
+    let mut undelivered: HashMap<u32, Vec<u32>> = HashMap::new();
+    for message in messages {
+        if let Some(message_id) = message.get_id() {
+            undelivered
+                .entry(message.get_chatroom_id())
+                .or_default()
+                .push(message_id);
+        }
+    }
+
+    // Only chatrooms somebody is chatting in are loaded, nobody else needs to be told
+    let loaded_chatrooms: HashMap<u32, ChatRoom> = {
+        let chat_rooms = state.chat_rooms.lock().await;
+        undelivered
+            .keys()
+            .filter_map(|chatroom_id| {
+                let chatroom = chat_rooms.get(chatroom_id)?;
+                Some((*chatroom_id, chatroom.clone()))
+            })
+            .collect()
+    };
+    storage::run_blocking(&state.storage, move |storage| {
+        for (chatroom_id, message_ids) in undelivered {
+            let loaded_chatroom = loaded_chatrooms.get(&chatroom_id);
+            let chatroom = match loaded_chatroom {
+                Some(chatroom) => chatroom.clone(),
+                None => storage.get_chatroom_by_id(chatroom_id)?,
+            };
+            for message_id in message_ids {
+                let receipt = Receipt::new(message_id, ReceiptStatus::Delivered);
+                let Some((sender_name, receipt)) =
+                    receipts::save_receipt(storage, &chatroom, &user, receipt)?
+                else {
+                    continue;
+                };
+                if let Some(chatroom) = loaded_chatroom {
+                    // The receipt does not come from a chat connection, so no address is left out
+                    let no_address = SocketAddr::from(([0, 0, 0, 0], 0));
+                    _ = chatroom.broadcast_event(
+                        no_address,
+                        RoomEvent::Receipt {
+                            sender_name,
+                            receipt,
+                        },
+                    );
+                }
+            }
+        }
+
+        let unread_counts = storage.get_unread_counts(user_id)?;
+        Ok(Payload::Response(ServerResponse::UnreadCounts(
+            unread_counts.into_iter().collect(),
+        )))
+    })
+    .await
+}
+
+/// Returns the error to answer with if the name can not be used for a chatroom
+fn check_chatroom_name(name: &str) -> Option<Payload> {
+    if name.is_empty() || name.chars().count() > MAX_CHATROOM_NAME_LENGTH {
+        return Some(Payload::error(&format!(
+            "The name of a chatroom needs 1 to {} characters.",
+            MAX_CHATROOM_NAME_LENGTH
+        )));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_export;
+    use crate::config::ServerConfig;
+    use crate::login::hash_password;
+    use crate::sqlite_storage::SqliteStorage;
+    use crate::test_support::TestStorageBuilder;
+    use std::sync::Arc;
+
+    /// Creates a server state with an empty database that only contains the user anton
+    fn get_test_state() -> ServerState {
+        ServerState::new(
+            TestStorageBuilder::new().user("anton").build(),
+            ServerConfig::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_request_without_login_is_rejected() {
+        let state = get_test_state();
+        let mut current_session = None;
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::GetFriendRequests,
+        )
+        .await;
+
+        assert!(matches!(response, Payload::Error { .. }));
+        assert!(current_session.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_logout_without_login() {
+        let state = get_test_state();
+        let mut current_session = None;
+
+        let response = handle_request(&state, &mut current_session, ClientRequest::Logout).await;
+
+        assert!(matches!(response, Payload::Ack));
+        assert!(current_session.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_logout() {
+        let state = get_test_state();
+        let storage = state.storage.as_ref();
+        let session =
+            Session::create(storage, User::new(1, "anton".to_string()), "laptop", false).unwrap();
+        let mut current_session = Some(session.clone());
+
+        let response = handle_request(&state, &mut current_session, ClientRequest::Logout).await;
+
+        assert!(matches!(response, Payload::Ack));
+        assert!(current_session.is_none());
+        // The token can not be used for new connections anymore
+        assert!(Session::from_token(storage, &session.token)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sign_up_rejects_invalid_names_and_hashes() {
+        let state = get_test_state();
+        let mut current_session = None;
+        let sign_up = |username: &str, password_hash: &str| ClientRequest::SignUp {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            device_name: "laptop".to_string(),
+        };
+
+        for username in ["../../.bashrc", "bob/alice", "bob\u{7}", "bo"] {
+            let response = handle_request(
+                &state,
+                &mut current_session,
+                sign_up(username, &hash_password("password")),
+            )
+            .await;
+            assert!(matches!(response, Payload::Error { .. }));
+        }
+        let response =
+            handle_request(&state, &mut current_session, sign_up("bob", "password")).await;
+        assert!(matches!(response, Payload::Error { .. }));
+        assert!(current_session.is_none());
+        assert!(!state.storage.check_if_username_exists("bob").unwrap());
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            sign_up("bob", &hash_password("password")),
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Payload::Response(ServerResponse::SignedUp { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_restored_user_has_to_change_password() {
+        let export = chat_export::export_chatroom(
+            TestStorageBuilder::new()
+                .user("anton")
+                .chat_room("anton", &["anton"])
+                .build()
+                .as_ref(),
+            1,
+        )
+        .unwrap();
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let summary = chat_export::import_chatroom(&storage, &export)
+            .await
+            .unwrap();
+        let one_time_password_hash = hash_password(&summary.one_time_passwords[0].1);
+        let state = ServerState::new(Arc::new(storage), ServerConfig::default());
+        let mut current_session = None;
+        let login = |new_password_hash: Option<&str>| ClientRequest::Login {
+            username: "anton".to_string(),
+            password_hash: one_time_password_hash.clone(),
+            device_name: "laptop".to_string(),
+            remember_me: false,
+            new_password_hash: new_password_hash.map(str::to_string),
+        };
+
+        // The one-time password alone or as new password is not enough
+        for new_password_hash in [None, Some(one_time_password_hash.as_str())] {
+            let response =
+                handle_request(&state, &mut current_session, login(new_password_hash)).await;
+            assert!(matches!(
+                response,
+                Payload::Response(ServerResponse::PasswordChangeRequired)
+            ));
+            assert!(current_session.is_none());
+        }
+
+        let new_password_hash = hash_password("new password");
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            login(Some(&new_password_hash)),
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Payload::Response(ServerResponse::LoginSucceeded { .. })
+        ));
+        assert!(!state
+            .storage
+            .check_if_password_change_required("anton")
+            .unwrap());
+        assert!(password_hashing::check_if_password_matches_username(
+            &state.storage,
+            &new_password_hash,
+            "anton"
+        )
+        .await
+        .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_other_session() {
+        let state = get_test_state();
+        let storage = state.storage.as_ref();
+        let user = User::new(1, "anton".to_string());
+        let session = Session::create(storage, user.clone(), "laptop", false).unwrap();
+        let other_session = Session::create(storage, user, "phone", true).unwrap();
+        let mut current_session = Some(session.clone());
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::RevokeSession {
+                session_id: other_session.id,
+            },
+        )
+        .await;
+
+        assert!(matches!(response, Payload::Ack));
+        assert_eq!(current_session, Some(session));
+        assert!(Session::from_token(storage, &other_session.token)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_session_can_not_be_used() {
+        let state = get_test_state();
+        let storage = state.storage.as_ref();
+        let session =
+            Session::create(storage, User::new(1, "anton".to_string()), "laptop", false).unwrap();
+        let mut current_session = Some(session.clone());
+        session.end(storage).unwrap();
+
+        let response =
+            handle_request(&state, &mut current_session, ClientRequest::GetChatRooms).await;
+
+        assert!(matches!(response, Payload::Error { .. }));
+        assert!(current_session.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_publish_and_get_identity_key() {
+        let state = get_test_state();
+        let session = Session::create(
+            state.storage.as_ref(),
+            User::new(1, "anton".to_string()),
+            "laptop",
+            false,
+        )
+        .unwrap();
+        let mut current_session = Some(session);
+        let public_key = crypto::IdentityKeyPair::generate().get_public_key_hex();
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::GetIdentityKey { user_id: 1 },
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Payload::Response(ServerResponse::IdentityKey {
+                public_key: None,
+                ..
+            })
+        ));
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::PublishIdentityKey {
+                public_key: public_key.clone(),
+            },
+        )
+        .await;
+        assert!(matches!(response, Payload::Ack));
+
+        match handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::GetIdentityKey { user_id: 1 },
+        )
+        .await
+        {
+            Payload::Response(ServerResponse::IdentityKey {
+                user_id,
+                public_key: published,
+            }) => {
+                assert_eq!(user_id, 1);
+                assert_eq!(published, Some(public_key));
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_invalid_identity_key() {
+        let state = get_test_state();
+        let session = Session::create(
+            state.storage.as_ref(),
+            User::new(1, "anton".to_string()),
+            "laptop",
+            false,
+        )
+        .unwrap();
+        let mut current_session = Some(session);
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::PublishIdentityKey {
+                public_key: "not a key".to_string(),
+            },
+        )
+        .await;
+
+        assert!(matches!(response, Payload::Error { .. }));
+        assert_eq!(state.storage.get_identity_key(1).unwrap(), None);
+    }
+
+    /// Creates a server state where anton is friends with rino, antonia and antonius, but not with berta
+    fn get_group_chat_test_state(max_group_members: u32) -> (ServerState, Option<Session>) {
+        let storage = TestStorageBuilder::new()
+            .user("anton")
+            .user("rino")
+            .user("antonia")
+            .user("antonius")
+            .user("berta")
+            .friends("anton", "rino")
+            .friends("anton", "antonia")
+            .friends("antonius", "anton")
+            .build();
+        let session = Session::create(
+            storage.as_ref(),
+            User::new(1, "anton".to_string()),
+            "laptop",
+            false,
+        )
+        .unwrap();
+        let config = ServerConfig {
+            max_group_members,
+            ..ServerConfig::default()
+        };
+        (ServerState::new(storage, config), Some(session))
+    }
+
+    #[tokio::test]
+    async fn test_create_group_chat() {
+        let (state, mut current_session) = get_group_chat_test_state(10);
+
+        let response = handle_request(
+            &state,
+            &mut current_session,
+            ClientRequest::CreateGroupChat {
+                name: " Team ".to_string(),
+                member_ids: vec![3, 2, 3],
+            },
+        )
+        .await;
+
+        match response {
+            Payload::Response(ServerResponse::ChatRoom(chatroom)) => {
+                assert_eq!(*chatroom.get_name(), "Team".to_string());
+                assert_eq!(chatroom.get_member_ids(), vec![1, 2, 3]);
+                // The creator is the owner of the group chat
+                assert_eq!(
+                    state
+                        .storage
+                        .get_chat_member_role(*chatroom.get_id(), 1)
+                        .unwrap(),
+                    Some(RoomRole::Owner)
+                );
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+        assert_eq!(
+            state.storage.search_for_chatrooms_of_user(3).unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_presence_of_friends() {
+        let (state, mut current_session) = get_group_chat_test_state(10);
+        state.presence.connect(2);
+        state.storage.set_last_seen(3, 1000).unwrap();
+
+        let response =
+            handle_request(&state, &mut current_session, ClientRequest::GetPresence).await;
+
+        match response {
+            Payload::Response(ServerResponse::Presences(presences)) => {
+                // Friends are ordered by name: antonia, antonius, rino
+                assert_eq!(presences.len(), 3);
+                assert_eq!(
+                    presences[0],
+                    Presence::new(3, PresenceStatus::Offline, 1000)
+                );
+                assert_eq!(presences[1], Presence::new(4, PresenceStatus::Offline, 0));
+                assert_eq!(presences[2].get_user_id(), 2);
+                assert_eq!(presences[2].get_status(), PresenceStatus::Online);
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_unread_counts_delivers_offline_messages() {
+        let storage = TestStorageBuilder::new()
+            .user("anton")
+            .user("rino")
+            .friends("anton", "rino")
+            .chat_room("anton and rino's chat", &["anton", "rino"])
+            .message(1, "rino", "Hallo Anton!")
+            .message(1, "rino", "Bist du da?")
+            .message(1, "anton", "Ja!")
+            .build();
+        let mut current_session = Some(
+            Session::create(
+                storage.as_ref(),
+                User::new(1, "anton".to_string()),
+                "laptop",
+                false,
+            )
+            .unwrap(),
+        );
+        let state = ServerState::new(storage, ServerConfig::default());
+        let chatroom = state.storage.get_chatroom_by_id(1).unwrap();
+        let mut events = chatroom.get_sender().subscribe();
+        state.chat_rooms.lock().await.insert(1, chatroom);
+
+        let response =
+            handle_request(&state, &mut current_session, ClientRequest::GetUnreadCounts).await;
+
+        match response {
+            Payload::Response(ServerResponse::UnreadCounts(unread_counts)) => {
+                assert_eq!(unread_counts, HashMap::from([(1, 2)]));
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+        // Rino is told about the delivery while chatting
+        for message_id in [1, 2] {
+            match events.try_recv() {
+                Ok((
+                    _,
+                    RoomEvent::Receipt {
+                        sender_name,
+                        receipt,
+                    },
+                )) => {
+                    assert_eq!(sender_name, "rino");
+                    assert_eq!(receipt, Receipt::new(message_id, ReceiptStatus::Delivered));
+                }
+                _ => panic!("Expected a receipt for message {}", message_id),
+            }
+        }
+        assert!(state
+            .storage
+            .get_undelivered_messages(1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_friends() {
+        let (state, mut current_session) = get_group_chat_test_state(10);
+
+        let response =
+            handle_request(&state, &mut current_session, ClientRequest::GetFriends).await;
+
+        match response {
+            Payload::Response(ServerResponse::Users(friends)) => {
+                let friend_names: Vec<&String> =
+                    friends.iter().map(|friend| friend.get_name()).collect();
+                assert_eq!(friend_names, vec!["antonia", "antonius", "rino"]);
+            }
+            other => panic!("Unexpected payload: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_invalid_group_chat() {
+        let (state, mut current_session) = get_group_chat_test_state(3);
+        let invalid_requests = [
+            // Not enough friends
+            ("Team", vec![2]),
+            ("Team", vec![1, 2]),
+            // berta is no friend of anton
+            ("Team", vec![2, 5]),
+            // More members than allowed
+            ("Team", vec![2, 3, 4]),
+            ("", vec![2, 3]),
+            ("   ", vec![2, 3]),
+        ];
+
+        for (name, member_ids) in invalid_requests {
+            let response = handle_request(
+                &state,
+                &mut current_session,
+                ClientRequest::CreateGroupChat {
+                    name: name.to_string(),
+                    member_ids,
+                },
+            )
+            .await;
+            assert!(matches!(response, Payload::Error { .. }));
+        }
+        assert!(state
+            .storage
+            .search_for_chatrooms_of_user(1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_public_room_with_password() {
+        let (state, mut anton_session) = get_group_chat_test_state(3);
+        let mut rino_session = Some(
+            Session::create(
+                state.storage.as_ref(),
+                User::new(2, "rino".to_string()),
+                "laptop",
+                false,
+            )
+            .unwrap(),
+        );
+
+        let response = handle_request(
+            &state,
+            &mut anton_session,
+            ClientRequest::CreatePublicRoom {
+                name: " Lobby ".to_string(),
+                password_hash: Some("secret".to_string()),
+            },
+        )
+        .await;
+        let chatroom_id = match response {
+            Payload::Response(ServerResponse::ChatRoom(chatroom)) => {
+                assert_eq!(*chatroom.get_name(), "Lobby".to_string());
+                assert!(chatroom.is_public());
+                assert!(chatroom.has_password());
+                *chatroom.get_id()
+            }
+            other => panic!("Expected chatroom, got {:?}", other),
+        };
+
+        // The name of a public room has to be unique
+        let response = handle_request(
+            &state,
+            &mut rino_session,
+            ClientRequest::CreatePublicRoom {
+                name: "Lobby".to_string(),
+                password_hash: None,
+            },
+        )
+        .await;
+        assert!(matches!(response, Payload::Error { .. }));
+
+        let response = handle_request(
+            &state,
+            &mut rino_session,
+            ClientRequest::SearchPublicRooms {
+                search_string: "lob".to_string(),
+            },
+        )
+        .await;
+        match response {
+            Payload::Response(ServerResponse::ChatRooms(chatrooms)) => {
+                assert_eq!(chatrooms.len(), 1);
+                assert_eq!(*chatrooms[0].get_id(), chatroom_id);
+            }
+            other => panic!("Expected chatrooms, got {:?}", other),
+        }
+
+        for password_hash in [None, Some("wrong".to_string())] {
+            let response = handle_request(
+                &state,
+                &mut rino_session,
+                ClientRequest::JoinPublicRoom {
+                    chatroom_id,
+                    password_hash,
+                },
+            )
+            .await;
+            assert!(matches!(response, Payload::Error { .. }));
+        }
+        assert!(!state
+            .storage
+            .get_chatroom_by_id(chatroom_id)
+            .unwrap()
+            .is_member(2));
+
+        let response = handle_request(
+            &state,
+            &mut rino_session,
+            ClientRequest::JoinPublicRoom {
+                chatroom_id,
+                password_hash: Some("secret".to_string()),
+            },
+        )
+        .await;
+        match response {
+            Payload::Response(ServerResponse::ChatRoom(chatroom)) => {
+                assert_eq!(chatroom.get_member_ids(), vec![1, 2]);
+            }
+            other => panic!("Expected chatroom, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_full_private_or_banned_room() {
+        let (state, mut anton_session) = get_group_chat_test_state(1);
+        let chatroom = state
+            .storage
+            .create_public_chatroom(1, "Lobby", None)
+            .unwrap()
+            .unwrap();
+        let private_chatroom = state
+            .storage
+            .create_new_chatroom(&[1, 2], "Private")
+            .unwrap();
+        let mut antonia_session = Some(
+            Session::create(
+                state.storage.as_ref(),
+                User::new(3, "antonia".to_string()),
+                "laptop",
+                false,
+            )
+            .unwrap(),
+        );
+
+        // Banned users can not join again
+        let banned_chatroom = state
+            .storage
+            .create_public_chatroom(1, "Banned", None)
+            .unwrap()
+            .unwrap();
+        state
+            .storage
+            .ban_chat_member(*banned_chatroom.get_id(), 3)
+            .unwrap();
+
+        for chatroom_id in [
+            *chatroom.get_id(),
+            *private_chatroom.get_id(),
+            *banned_chatroom.get_id(),
+            42,
+        ] {
+            let response = handle_request(
+                &state,
+                &mut antonia_session,
+                ClientRequest::JoinPublicRoom {
+                    chatroom_id,
+                    password_hash: None,
+                },
+            )
+            .await;
+            assert!(matches!(response, Payload::Error { .. }));
+        }
+
+        // Members can join their room again
+        let response = handle_request(
+            &state,
+            &mut anton_session,
+            ClientRequest::JoinPublicRoom {
+                chatroom_id: *chatroom.get_id(),
+                password_hash: None,
+            },
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Payload::Response(ServerResponse::ChatRoom(_))
+        ));
+    }
+}
@@ -1,66 +1,209 @@
-use crate::structs::chat_room::ChatRoom;
-use crate::structs::message::ChatMessage;
-use crate::sql_interaction;
+use crate::config::ServerConfig;
+use crate::crypto;
+use crate::helper_functions;
+use crate::message_edits::{self, ChangeOutcome};
+use crate::migrations;
+use crate::moderation::{self, ModerationOutcome};
+use crate::presence::{self, PresenceTracker, PresenceUpdate};
+use crate::protocol::{Payload, ProtocolError};
+use crate::ratchet;
+use crate::receipts;
+use crate::request_handler;
+use crate::storage::{self, Storage, StorageResult};
+use crate::structs::chat_room::{ChatRoom, RoomEvent};
+use crate::structs::user::User;
+use crate::tls::{self, BoxedStream};
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
+use tokio_rustls::TlsAcceptor;
 use tokio_tungstenite::accept_async;
 use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 use tokio_tungstenite::WebSocketStream;
 
+/// Duration in seconds a session is valid after login
+const SESSION_DURATION_SECS: u64 = 12 * 60 * 60;
+
+/// Duration in seconds a session is valid if the user wants to stay logged in on the device
+const REMEMBERED_SESSION_DURATION_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Login of a user on one device, identified by the session token the client received
+/// <br>Sessions are saved in the database, only the hash of the token is stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: u32,
+    pub token: String,
+    pub user: User,
+}
+
+impl Session {
+    /// Creates a new session for the user and returns it
+    pub fn create(
+        storage: &dyn Storage,
+        user: User,
+        device_name: &str,
+        remember_me: bool,
+    ) -> StorageResult<Session> {
+        let duration = if remember_me {
+            REMEMBERED_SESSION_DURATION_SECS
+        } else {
+            SESSION_DURATION_SECS
+        };
+        let token = helper_functions::generate_session_token();
+        let id = storage.create_session(
+            user.get_id(),
+            &hash_session_token(&token),
+            device_name,
+            helper_functions::get_sys_time_in_secs() + duration,
+        )?;
+        Ok(Session { id, token, user })
+    }
+
+    /// Returns the session of the token if it is neither expired nor revoked
+    /// <br>Also updates the time the session was last seen
+    pub fn from_token(storage: &dyn Storage, token: &str) -> StorageResult<Option<Session>> {
+        let session = storage.get_session_by_token_hash(&hash_session_token(token))?;
+        match session {
+            Some((id, user)) => {
+                storage.update_session_last_seen(id)?;
+                Ok(Some(Session {
+                    id,
+                    token: token.to_string(),
+                    user,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Ends the session, the token can not be used anymore afterwards
+    pub fn end(&self, storage: &dyn Storage) -> StorageResult<()> {
+        storage.delete_session(self.id, self.user.get_id())?;
+        Ok(())
+    }
+}
+
+/// Hashes a session token, so that a leaked database does not contain usable tokens
+fn hash_session_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// State that is shared by all connections of the server
+pub struct ServerState {
+    pub storage: Arc<dyn Storage>,
+    pub config: ServerConfig,
+    pub chat_rooms: Mutex<HashMap<u32, ChatRoom>>,
+    pub failed_logins: Mutex<HashMap<String, u32>>,
+    pub presence: PresenceTracker,
+}
+
+impl ServerState {
+    pub fn new(storage: Arc<dyn Storage>, config: ServerConfig) -> Self {
+        ServerState {
+            storage,
+            config,
+            chat_rooms: Mutex::new(HashMap::new()),
+            failed_logins: Mutex::new(HashMap::new()),
+            presence: PresenceTracker::new(),
+        }
+    }
+}
+
 /// Main Function for running the ChatRoom Server
-pub async fn run() -> Result<(), Box<dyn Error + Send + Sync>> {
+pub async fn run(config: ServerConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Open the configured database, which has to be migrated already
+    let storage = storage::open_storage(&config.database_url)?;
+    storage::run_blocking(&storage, migrations::check_schema_version).await?;
+
+    // Without certificate the connections are not encrypted, which is only fine for local testing
+    let tls_acceptor = match (&config.tls_certificate, &config.tls_key) {
+        (Some(certificate), Some(key)) => Some(tls::load_acceptor(certificate, key)?),
+        (None, None) => {
+            let bind_host = config
+                .bind_address
+                .rsplit_once(':')
+                .map_or(config.bind_address.as_str(), |(host, _)| host);
+            if !tls::is_loopback_host(bind_host) {
+                return Err(format!(
+                    "Without TLS the server only listens on this device, configure tls_certificate and tls_key to listen on {}.",
+                    config.bind_address
+                )
+                .into());
+            }
+            println!("Warning: TLS is not configured, only clients on this device can connect!");
+            None
+        }
+        _ => return Err("TLS needs both a certificate and a key.".into()),
+    };
+
     // Bind the server to the address
-    let addr = "127.0.0.1:8000".to_string();
-    let listener = TcpListener::bind(addr).await?;
-    println!("Server listening on port 8000");
+    let listener = TcpListener::bind(&config.bind_address).await?;
+    println!(
+        "Server listening on {}{}",
+        config.bind_address,
+        if tls_acceptor.is_some() { " (TLS)" } else { "" }
+    );
+
+    serve(listener, storage, config, tls_acceptor).await
+}
+
+/// Accepts clients on the listener until an error occurs
+/// <br>Tests call this directly with a listener on a random port and a database in memory
+pub async fn serve(
+    listener: TcpListener,
+    storage: Arc<dyn Storage>,
+    config: ServerConfig,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Use Arc to share the state safely across threads
+    let state = Arc::new(ServerState::new(storage, config));
 
-    // Use Arc and Mutex to share the chat_rooms safely across threads
-    let chat_rooms = Arc::new(Mutex::new(HashMap::<u32, ChatRoom>::new()));
+    // Users that stopped doing anything are shown as away to their friends
+    let away_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(presence::AWAY_CHECK_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            away_state
+                .presence
+                .mark_idle_users_away(helper_functions::get_sys_time_in_secs());
+        }
+    });
 
     loop {
         let (stream, addr) = listener.accept().await?;
         println!("New connection from {:?}", addr);
 
-        let chat_rooms: Arc<Mutex<HashMap<u32, ChatRoom>>> = Arc::clone(&chat_rooms);
+        let state = Arc::clone(&state);
+        let tls_acceptor = tls_acceptor.clone();
 
         tokio::spawn(async move {
+            let stream: BoxedStream = match tls_acceptor {
+                Some(tls_acceptor) => match tls_acceptor.accept(stream).await {
+                    Ok(tls_stream) => Box::new(tls_stream),
+                    Err(e) => {
+                        println!("TLS handshake with {:?} failed: {:?}", addr, e);
+                        return;
+                    }
+                },
+                None => Box::new(stream),
+            };
             match accept_async(stream).await {
-                Ok(mut ws_stream) => {
-                    if let Some(Ok(message)) = ws_stream.next().await {
-                        if let Ok(chatroom_id) = message.to_text().unwrap().parse::<u32>() {
-                            println!("Chat Id is: {:?}", chatroom_id);
-
-                            let current_chatroom = {
-                                let mut chat_rooms_lock = chat_rooms.lock().await;
-
-                                if let Some(chatroom) = chat_rooms_lock.get(&chatroom_id) {
-                                    println!("Chatroom already exists with id: {:?}, user1_id: {:?}, user2_id: {:?}",
-                                             chatroom.get_id(), chatroom.get_user1_id(), chatroom.get_user2_id());
-                                    chatroom.clone()
-                                } else {
-                                    let new_chatroom =
-                                        sql_interaction::get_chatroom_by_id(chatroom_id)
-                                            .await
-                                            .unwrap();
-                                    chat_rooms_lock.insert(chatroom_id, new_chatroom.clone());
-                                    new_chatroom
-                                }
-                            };
-
-                            // Handle the client connection
-                            _ = handle_single_client_connection(addr, ws_stream, current_chatroom)
-                                .await;
-                        } else {
-                            println!("Invalid Chat Id received");
-                        }
-                    } else {
-                        println!("No Chat Id received or error in receiving message");
+                Ok(ws_stream) => {
+                    if let Err(e) = handle_connection(state, addr, ws_stream).await {
+                        println!("Connection to {:?} closed with error: {:?}", addr, e);
                     }
                 }
                 Err(e) => {
@@ -71,13 +214,373 @@ pub async fn run() -> Result<(), Box<dyn Error + Send + Sync>> {
     }
 }
 
+/// Reads the next payload from the connection
+/// <br>Returns None if the connection was closed or the client speaks another protocol version
+async fn receive_payload(
+    addr: SocketAddr,
+    ws_stream: &mut WebSocketStream<BoxedStream>,
+) -> Result<Option<Payload>, Box<dyn Error + Send + Sync>> {
+    while let Some(message) = ws_stream.next().await {
+        let message = message?;
+        if !message.is_text() {
+            continue;
+        }
+
+        match Payload::from_json(message.to_text()?) {
+            Ok(payload) => return Ok(Some(payload)),
+            Err(ProtocolError::IncompatibleVersion(version)) => {
+                println!(
+                    "Client {:?} uses incompatible protocol version {}",
+                    addr, version
+                );
+                let error = ProtocolError::IncompatibleVersion(version).to_string();
+                send_payload(ws_stream, &Payload::error(&error)).await?;
+                ws_stream.close(None).await?;
+                return Ok(None);
+            }
+            Err(e) => {
+                println!("Invalid frame received from {:?}: {}", addr, e);
+                send_payload(ws_stream, &Payload::error("Invalid frame.")).await?;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Sends a payload to the client
+async fn send_payload(
+    ws_stream: &mut WebSocketStream<BoxedStream>,
+    payload: &Payload,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ws_stream.send(WsMessage::Text(payload.to_json())).await?;
+    Ok(())
+}
+
+/// Answers the requests of a client until the client joins a chatroom or disconnects
+async fn handle_connection(
+    state: Arc<ServerState>,
+    addr: SocketAddr,
+    mut ws_stream: WebSocketStream<BoxedStream>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Every client has to introduce itself first
+    // The session of the user that is logged in on this connection
+    let mut current_session: Option<Session> = match receive_payload(addr, &mut ws_stream).await? {
+        Some(Payload::Hello {
+            session_token: None,
+        }) => None,
+        Some(Payload::Hello {
+            session_token: Some(token),
+        }) => match storage::run_blocking(&state.storage, move |storage| {
+            Session::from_token(storage, &token)
+        })
+        .await?
+        {
+            Some(session) => Some(session),
+            None => {
+                send_payload(&mut ws_stream, &Payload::error("Invalid session.")).await?;
+                ws_stream.close(None).await?;
+                return Ok(());
+            }
+        },
+        Some(_) => {
+            send_payload(&mut ws_stream, &Payload::error("Expected hello.")).await?;
+            return Ok(());
+        }
+        None => return Ok(()),
+    };
+    send_payload(&mut ws_stream, &Payload::Ack).await?;
+
+    // The user of the connection is shown as online to friends while the connection is open
+    let mut tracked_user = TrackedUser::new(Arc::clone(&state));
+    tracked_user.set_user_id(
+        current_session
+            .as_ref()
+            .map(|session| session.user.get_id()),
+    );
+    let mut presence_updates = state.presence.subscribe();
+    let mut friend_ids = load_friend_ids(&state, tracked_user.user_id).await;
+
+    loop {
+        tokio::select! {
+            incoming = receive_payload(addr, &mut ws_stream) => {
+                let payload = match incoming? {
+                    Some(payload) => payload,
+                    None => break,
+                };
+                if let Some(user_id) = tracked_user.user_id {
+                    state.presence.mark_active(user_id);
+                }
+                match payload {
+                    Payload::Request(request) => {
+                        let response =
+                            request_handler::handle_request(&state, &mut current_session, request).await;
+                        send_payload(&mut ws_stream, &response).await?;
+                    }
+                    Payload::JoinRoom { chatroom_id } => {
+                        // Only logged in users can join chatrooms
+                        let user = match &current_session {
+                            Some(session) => session.user.clone(),
+                            None => {
+                                send_payload(&mut ws_stream, &Payload::error("You need to log in first."))
+                                    .await?;
+                                continue;
+                            }
+                        };
+                        println!("Chat Id is: {:?}", chatroom_id);
+
+                        // Users can join public rooms anytime, so the members are always loaded from the storage
+                        // <br>The cached chatroom is kept for its broadcast channel
+                        let stored_chatroom = storage::run_blocking(&state.storage, move |storage| storage.get_chatroom_by_id(chatroom_id)).await;
+                        let current_chatroom = match stored_chatroom {
+                            Ok(stored_chatroom) => {
+                                let mut chat_rooms_lock = state.chat_rooms.lock().await;
+                                let chatroom = chat_rooms_lock
+                                    .entry(chatroom_id)
+                                    .or_insert_with(|| stored_chatroom.clone());
+                                chatroom.set_members(stored_chatroom.get_members().clone());
+                                println!(
+                                    "Chatroom with id: {:?}, member ids: {:?}",
+                                    chatroom.get_id(),
+                                    chatroom.get_member_ids()
+                                );
+                                Some(chatroom.clone())
+                            }
+                            Err(_) => None,
+                        };
+
+                        // Chatrooms of other users are treated like chatrooms that do not exist
+                        match current_chatroom.filter(|chatroom| chatroom.is_member(user.get_id())) {
+                            Some(chatroom) => {
+                                send_payload(&mut ws_stream, &Payload::Ack).await?;
+
+                                // Handle the client connection
+                                return handle_single_client_connection(
+                                    addr, ws_stream, Arc::clone(&state), chatroom, user,
+                                )
+                                .await;
+                            }
+                            None => {
+                                send_payload(&mut ws_stream, &Payload::error("Chatroom not found.")).await?
+                            }
+                        }
+                    }
+                    _ => send_payload(&mut ws_stream, &Payload::error("Unexpected frame.")).await?,
+                }
+                // The user changes on login and logout
+                let user_id = current_session.as_ref().map(|session| session.user.get_id());
+                if user_id != tracked_user.user_id {
+                    tracked_user.set_user_id(user_id);
+                    friend_ids = load_friend_ids(&state, user_id).await;
+                }
+            }
+            update = presence_updates.recv() => {
+                match (update, tracked_user.user_id) {
+                    // Only friends see the presence of a user
+                    (Ok(PresenceUpdate::Presence(presence)), _) if friend_ids.contains(&presence.get_user_id()) => {
+                        send_payload(&mut ws_stream, &Payload::Presence(presence)).await?;
+                    }
+                    (Ok(PresenceUpdate::Friendship(user1_id, user2_id)), Some(user_id)) if user1_id == user_id => _ = friend_ids.insert(user2_id),
+                    (Ok(PresenceUpdate::Friendship(user1_id, user2_id)), Some(user_id)) if user2_id == user_id => _ = friend_ids.insert(user1_id),
+                    // New friendships may have been missed, so the friends are loaded again
+                    (Err(RecvError::Lagged(_)), user_id) => friend_ids = load_friend_ids(&state, user_id).await,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the ids of the friends of the user, whose presence is sent on the connection
+/// <br>Without a user nobody is a friend. If the friends can not be loaded, no presence is sent
+async fn load_friend_ids(state: &ServerState, user_id: Option<u32>) -> HashSet<u32> {
+    let Some(user_id) = user_id else {
+        return HashSet::new();
+    };
+    let friends = storage::run_blocking(&state.storage, move |storage| {
+        storage.get_friends_of_user(user_id)
+    })
+    .await;
+    match friends {
+        Ok(friends) => friends.iter().map(|friend| friend.get_id()).collect(),
+        Err(e) => {
+            eprintln!("Error loading friends: {}", e);
+            HashSet::new()
+        }
+    }
+}
+
+/// Keeps the presence of the user that is logged in on a connection up to date
+/// <br>The user goes offline when the last connection is closed, also if the connection ends with an error
+struct TrackedUser {
+    state: Arc<ServerState>,
+    user_id: Option<u32>,
+}
+
+impl TrackedUser {
+    fn new(state: Arc<ServerState>) -> Self {
+        TrackedUser {
+            state,
+            user_id: None,
+        }
+    }
+
+    fn set_user_id(&mut self, user_id: Option<u32>) {
+        if self.user_id == user_id {
+            return;
+        }
+        self.leave();
+        if let Some(user_id) = user_id {
+            self.state.presence.connect(user_id);
+        }
+        self.user_id = user_id;
+    }
+
+    fn leave(&mut self) {
+        if let Some(user_id) = self.user_id.take() {
+            if let Some(last_seen) = self.state.presence.disconnect(user_id) {
+                // Saved first, so that friends who ask right after the change get the new time
+                // The connection does not wait for it, it may be closing already
+                let state = Arc::clone(&self.state);
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = state.storage.set_last_seen(user_id, last_seen) {
+                        eprintln!("Error saving last seen time: {}", e);
+                    }
+                    state.presence.publish_offline(user_id, last_seen);
+                });
+            }
+        }
+    }
+}
+
+impl Drop for TrackedUser {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+/// Returns true if every other member of the chatroom can decrypt the message
+/// <br>In group chats the message has to contain a ratchet message for each other member, so that nobody is left out unnoticed
+fn reaches_every_member(chatroom: &ChatRoom, sender_id: u32, content: &str) -> bool {
+    match ratchet::parse_group_message(content) {
+        Some(messages) => {
+            let mut recipient_ids: Vec<u32> =
+                messages.iter().map(|(user_id, _)| *user_id).collect();
+            recipient_ids.sort_unstable();
+            let mut other_member_ids: Vec<u32> = chatroom
+                .get_other_members(sender_id)
+                .iter()
+                .map(|member| member.get_id())
+                .collect();
+            other_member_ids.sort_unstable();
+            recipient_ids == other_member_ids
+        }
+        None => !chatroom.is_group_chat(),
+    }
+}
+
+/// Reloads the members of the chatroom from the storage and returns true if they have changed
+async fn refresh_members(storage: &Arc<dyn Storage>, chatroom: &mut ChatRoom) -> bool {
+    let chatroom_id = *chatroom.get_id();
+    let stored_chatroom = storage::run_blocking(storage, move |storage| {
+        storage.get_chatroom_by_id(chatroom_id)
+    })
+    .await;
+    match stored_chatroom {
+        Ok(stored_chatroom) if stored_chatroom.get_member_ids() != chatroom.get_member_ids() => {
+            chatroom.set_members(stored_chatroom.get_members().clone());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Checks if the user may write the content to the chatroom, returns the error that is sent back otherwise
+/// <br>Muted members can still read, but not write. The server only relays ciphertext, it never sees what users write
+async fn check_written_content(
+    storage: &Arc<dyn Storage>,
+    chatroom: &mut ChatRoom,
+    user_id: u32,
+    content: &str,
+) -> Option<String> {
+    let chatroom_id = *chatroom.get_id();
+    let remaining_secs = storage::run_blocking(storage, move |storage| {
+        moderation::get_remaining_mute_secs(storage, chatroom_id, user_id)
+    })
+    .await;
+    match remaining_secs {
+        Ok(0) => {}
+        Ok(remaining_secs) => {
+            return Some(format!(
+                "You are muted in this chatroom for another {} minutes.",
+                remaining_secs.div_ceil(60)
+            ))
+        }
+        Err(e) => {
+            eprintln!("Error checking if user is muted: {}", e);
+            return Some("Internal server error.".to_string());
+        }
+    }
+    if !crypto::is_encrypted(content) {
+        return Some("Messages have to be end-to-end encrypted.".to_string());
+    }
+    if !reaches_every_member(chatroom, user_id, content) {
+        // Users may have joined the public room since this client joined it
+        let members_changed = refresh_members(storage, chatroom).await;
+        if !reaches_every_member(chatroom, user_id, content) {
+            let error = if members_changed {
+                "The members of the chatroom have changed. Please join the chatroom again."
+            } else {
+                "Messages have to be encrypted for every member of the chatroom."
+            };
+            return Some(error.to_string());
+        }
+    }
+    None
+}
+
+/// Sends the changed message to the user and everybody else in the chatroom, or tells the user why it was not changed
+async fn send_change_outcome(
+    ws_stream: &mut WebSocketStream<BoxedStream>,
+    chatroom: &ChatRoom,
+    addr: SocketAddr,
+    outcome: StorageResult<ChangeOutcome>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match outcome {
+        Ok(ChangeOutcome::Changed(chat_msg)) => {
+            send_payload(ws_stream, &Payload::MessageChanged(chat_msg.clone())).await?;
+            _ = chatroom.broadcast_event(addr, RoomEvent::MessageChanged(Box::new(chat_msg)));
+        }
+        Ok(ChangeOutcome::Rejected(message)) => {
+            send_payload(ws_stream, &Payload::error(&message)).await?;
+        }
+        Err(e) => {
+            eprintln!("Error changing message: {}", e);
+            send_payload(
+                ws_stream,
+                &Payload::error("The message could not be changed."),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
 /// This function handles the connection for each client.
 async fn handle_single_client_connection(
     addr: SocketAddr,
-    mut ws_stream: WebSocketStream<TcpStream>,
-    chatroom: ChatRoom,
+    mut ws_stream: WebSocketStream<BoxedStream>,
+    state: Arc<ServerState>,
+    mut chatroom: ChatRoom,
+    user: User,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    println!("Current count of receivers for this chatroom is: {:?}", chatroom.get_receiver().read().unwrap().len());
+    let storage = Arc::clone(&state.storage);
+    println!(
+        "Current count of receivers for this chatroom is: {:?}",
+        chatroom.get_receiver().read().unwrap().len()
+    );
     let mut chatroom_receiver = chatroom.get_sender().subscribe();
 
     // A continuous loop for concurrently performing two tasks:
@@ -85,42 +588,763 @@ async fn handle_single_client_connection(
     //(2) receiving messages on `bcast_rx` and sending them to the client.
     loop {
         tokio::select! {
-            incoming = ws_stream.next() => {
+            incoming = receive_payload(addr, &mut ws_stream) => {
+                let incoming = incoming?;
+                state.presence.mark_active(user.get_id());
                 match incoming {
-                    Some(Ok(msg)) => {
-
-                        if let Ok(msg) = msg.into_text(){
-                            let custom_msg = ChatMessage::new(addr, msg.to_string());
-                            println!("{}", custom_msg);
-                            _ = chatroom.broadcast_message(custom_msg.clone());
-
-                            // Spawn a task to handle the database interaction in the background
-                            let chatroom_id = *chatroom.get_id();
-                            let content = custom_msg.get_content().clone();
-                            tokio::task::spawn(async move {
-                                let result = sql_interaction::save_chat_message_to_database(chatroom_id, content).await;
-                                match result {
-                                    Ok(_) => println!("Message saved to database"),
-                                    Err(e) => eprintln!("Error saving message to database: {}", e),
+                    Some(Payload::ChatMessage(chat_msg)) => {
+                        // Messages can only be sent to the joined chatroom
+                        if chat_msg.get_chatroom_id() != *chatroom.get_id() {
+                            send_payload(&mut ws_stream, &Payload::error("Messages can only be sent to the joined chatroom.")).await?;
+                            continue;
+                        }
+                        if let Some(error) = check_written_content(&storage, &mut chatroom, user.get_id(), &chat_msg.get_content()).await {
+                            send_payload(&mut ws_stream, &Payload::error(&error)).await?;
+                            continue;
+                        }
+                        // The message is saved first, so that it is sent with its id and time
+                        // The sender is always the logged in user, whatever the client claims
+                        let chatroom_id = *chatroom.get_id();
+                        let sender_id = user.get_id();
+                        let content = chat_msg.get_content();
+                        let sent_at = helper_functions::get_sys_time_in_secs();
+                        let result = storage::run_blocking(&storage, move |storage| storage.save_chat_message_to_database(chatroom_id, sender_id, &content, sent_at)).await;
+                        let chat_msg = match result {
+                            Ok(chat_msg) => {
+                                println!("{:?}: {}", addr, chat_msg);
+                                chat_msg
+                            }
+                            Err(e) => {
+                                eprintln!("Error saving message to database: {}", e);
+                                send_payload(&mut ws_stream, &Payload::error("The message could not be saved.")).await?;
+                                continue;
+                            }
+                        };
+                        send_payload(&mut ws_stream, &Payload::MessageStored(chat_msg.clone())).await?;
+                        _ = chatroom.broadcast_message(addr, chat_msg);
+                    }
+                    Some(Payload::EditMessage { chatroom_id, message_id, content }) => {
+                        if chatroom_id != *chatroom.get_id() {
+                            send_payload(&mut ws_stream, &Payload::error("Messages can only be changed in the joined chatroom.")).await?;
+                            continue;
+                        }
+                        // The new content has to be readable by every member, like a new message
+                        if let Some(error) = check_written_content(&storage, &mut chatroom, user.get_id(), &content).await {
+                            send_payload(&mut ws_stream, &Payload::error(&error)).await?;
+                            continue;
+                        }
+                        let now = helper_functions::get_sys_time_in_secs();
+                        let editor = user.clone();
+                        let outcome = storage::run_blocking(&storage, move |storage| message_edits::edit_message(storage, chatroom_id, &editor, message_id, &content, now)).await;
+                        send_change_outcome(&mut ws_stream, &chatroom, addr, outcome).await?;
+                    }
+                    Some(Payload::DeleteMessage { chatroom_id, message_id }) => {
+                        if chatroom_id != *chatroom.get_id() {
+                            send_payload(&mut ws_stream, &Payload::error("Messages can only be changed in the joined chatroom.")).await?;
+                            continue;
+                        }
+                        let now = helper_functions::get_sys_time_in_secs();
+                        let editor = user.clone();
+                        let outcome = storage::run_blocking(&storage, move |storage| message_edits::delete_message(storage, chatroom_id, &editor, message_id, now)).await;
+                        send_change_outcome(&mut ws_stream, &chatroom, addr, outcome).await?;
+                    }
+                    Some(Payload::EditHistoryRequest { chatroom_id, message_id }) => {
+                        if chatroom_id != *chatroom.get_id() {
+                            send_payload(&mut ws_stream, &Payload::error("The edit history can only be read in the joined chatroom.")).await?;
+                            continue;
+                        }
+                        let edit_history = storage::run_blocking(&storage, move |storage| message_edits::get_edit_history(storage, chatroom_id, message_id)).await;
+                        match edit_history {
+                            Ok(Ok(edits)) => send_payload(&mut ws_stream, &Payload::EditHistory { message_id, edits }).await?,
+                            Ok(Err(message)) => send_payload(&mut ws_stream, &Payload::error(&message)).await?,
+                            Err(e) => {
+                                eprintln!("Error loading edit history: {}", e);
+                                send_payload(&mut ws_stream, &Payload::error("Internal server error.")).await?;
+                            }
+                        }
+                    }
+                    Some(Payload::Receipt(receipt)) => {
+                        // Receipts are not answered, the sender of the message is told if its status changed
+                        let (receipt_chatroom, reader) = (chatroom.clone(), user.clone());
+                        let saved = storage::run_blocking(&storage, move |storage| receipts::save_receipt(storage, &receipt_chatroom, &reader, receipt)).await;
+                        match saved {
+                            Ok(Some((sender_name, receipt))) => {
+                                _ = chatroom.broadcast_event(addr, RoomEvent::Receipt { sender_name, receipt });
+                            }
+                            Ok(None) => {}
+                            Err(e) => eprintln!("Error saving receipt: {}", e),
+                        }
+                    }
+                    Some(Payload::HistoryRequest { chatroom_id, before_message_id, keep_unread }) => {
+                        if chatroom_id != *chatroom.get_id() {
+                            send_payload(&mut ws_stream, &Payload::error("History can only be requested for the joined chatroom.")).await?;
+                            continue;
+                        }
+                        let (history_chatroom, reader) = (chatroom.clone(), user.clone());
+                        let history = storage::run_blocking(&storage, move |storage| receipts::read_history(storage, &history_chatroom, &reader, before_message_id, !keep_unread)).await;
+                        match history {
+                            Ok(history) => {
+                                let history_payload = Payload::History { messages: history.messages, receipts: history.receipts, has_more: history.has_more };
+                                send_payload(&mut ws_stream, &history_payload).await?;
+                                for (sender_name, receipt) in history.read {
+                                    _ = chatroom.broadcast_event(addr, RoomEvent::Receipt { sender_name, receipt });
                                 }
-                            });
-
-
+                            }
+                            Err(e) => {
+                                eprintln!("Error loading chat history: {}", e);
+                                send_payload(&mut ws_stream, &Payload::error("Internal server error.")).await?;
+                            }
+                        }
+                    }
+                    Some(Payload::Moderation { chatroom_id, action }) => {
+                        if chatroom_id != *chatroom.get_id() {
+                            send_payload(&mut ws_stream, &Payload::error("Moderation is only possible in the joined chatroom.")).await?;
+                            continue;
+                        }
+                        // The moderated copy of the chatroom replaces this one, it has the members after the action
+                        let (mut moderated_chatroom, moderator) = (chatroom.clone(), user.clone());
+                        let moderated = storage::run_blocking(&storage, move |storage| {
+                            let outcome = moderation::moderate(storage, &mut moderated_chatroom, &moderator, action);
+                            Ok((moderated_chatroom, outcome))
+                        }).await;
+                        let outcome = match moderated {
+                            Ok((moderated_chatroom, outcome)) => {
+                                chatroom = moderated_chatroom;
+                                outcome
+                            }
+                            Err(e) => Err(e),
+                        };
+                        match outcome {
+                            Ok(ModerationOutcome::Done { announcement, removed_user_id }) => {
+                                // Every action is announced to everybody in the chatroom, including the moderator
+                                println!("{:?}: {}", addr, announcement);
+                                _ = chatroom.broadcast_event(addr, RoomEvent::Announcement(announcement));
+                                if let Some(removed_user_id) = removed_user_id {
+                                    _ = chatroom.broadcast_event(addr, RoomEvent::MemberRemoved(removed_user_id));
+                                }
+                            }
+                            Ok(ModerationOutcome::Rejected(message)) => send_payload(&mut ws_stream, &Payload::error(&message)).await?,
+                            Err(e) => {
+                                eprintln!("Error moderating chatroom: {}", e);
+                                send_payload(&mut ws_stream, &Payload::error("Internal server error.")).await?;
+                            }
+                        }
+                    }
+                    Some(Payload::Typing { chatroom_id, is_typing }) => {
+                        // Typing is not answered, frames for other chatrooms are ignored
+                        if chatroom_id != *chatroom.get_id() {
+                            continue;
                         }
+                        // Muted members could not send what they are typing
+                        let user_id = user.get_id();
+                        if is_typing && !matches!(storage::run_blocking(&storage, move |storage| moderation::get_remaining_mute_secs(storage, chatroom_id, user_id)).await, Ok(0)) {
+                            continue;
                         }
-                        Some(Err(err)) => return Err(err.into()),
-                        None => return Ok(()),  // Stream ended
+                        _ = chatroom.broadcast_event(addr, RoomEvent::Typing { username: user.get_name().clone(), is_typing });
+                    }
+                    Some(_) => send_payload(&mut ws_stream, &Payload::error("Unexpected frame.")).await?,
+                    None => return Ok(()),  // Stream ended
                 }
             }
             msg = chatroom_receiver.recv() => {
-                if let Ok(chat_msg) = msg {
-
-                    if chat_msg.get_address() != addr { // Don't send message back to the sender
-                        ws_stream
-                    .send(WsMessage::Text(chat_msg.get_content())).await?;
+                match msg {
+                    // Don't send message back to the sender
+                    Ok((sender_addr, RoomEvent::Message(chat_msg))) if sender_addr != addr => {
+                        send_payload(&mut ws_stream, &Payload::ChatMessage(*chat_msg)).await?;
+                    }
+                    Ok((sender_addr, RoomEvent::MessageChanged(chat_msg))) if sender_addr != addr => {
+                        send_payload(&mut ws_stream, &Payload::MessageChanged(*chat_msg)).await?;
+                    }
+                    Ok((_, RoomEvent::Announcement(message))) => {
+                        send_payload(&mut ws_stream, &Payload::Announcement { message }).await?;
+                    }
+                    // Other devices of the typing user are not told either
+                    Ok((_, RoomEvent::Typing { username, is_typing })) if username != *user.get_name() => {
+                        send_payload(&mut ws_stream, &Payload::MemberTyping { username, is_typing }).await?;
+                    }
+                    // Every connection of the sender shows the status, also other devices
+                    Ok((_, RoomEvent::Receipt { sender_name, receipt })) if sender_name == *user.get_name() => {
+                        send_payload(&mut ws_stream, &Payload::Receipt(receipt)).await?;
+                    }
+                    // Kicked and banned members lose their subscription right away
+                    Ok((_, RoomEvent::MemberRemoved(user_id))) if user_id == user.get_id() => {
+                        let message = "You were removed from the chatroom.".to_string();
+                        send_payload(&mut ws_stream, &Payload::Removed { message }).await?;
+                        ws_stream.close(None).await?;
+                        return Ok(());
                     }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::IdentityKeyPair;
+    use crate::protocol::ModerationAction;
+    use crate::ratchet::RatchetSession;
+    use crate::structs::message::ChatMessage;
+    use crate::structs::presence::PresenceStatus;
+    use crate::structs::receipt::{Receipt, ReceiptStatus};
+    use crate::test_support::{get_chat_room, TestServer, TestStorageBuilder};
+
+    /// The server can not check the content of messages, so any session that encrypts is good enough
+    fn get_test_session(chatroom_id: u32) -> RatchetSession {
+        let other = IdentityKeyPair::generate().get_public_key_hex();
+        RatchetSession::new(&IdentityKeyPair::generate(), 1, &other, 2, chatroom_id).unwrap()
+    }
+
+    fn get_test_storage() -> Arc<dyn Storage> {
+        TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .user("antonia")
+            .friends("rino", "anton")
+            .chat_room("rino and anton's chat", &["rino", "anton"])
+            .message(1, "anton", "Guten Morgen, Rino!")
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_chat_between_two_users() {
+        let server = TestServer::start(get_test_storage()).await;
+        let (mut rino_connection, rino) = server.login("rino").await;
+        let (anton_connection, _) = server.login("anton").await;
+        let chatroom = get_chat_room(&mut rino_connection, "rino and anton's chat").await;
+        let chatroom_id = *chatroom.get_id();
+
+        // Anton joins first and waits for the history, so he is listening for new messages
+        let mut anton_chat = anton_connection.open_chat_connection().await.unwrap();
+        anton_chat.join_chat_room(chatroom_id).await.unwrap();
+        anton_chat.request_history(chatroom_id, None).await.unwrap();
+        match anton_chat.receive().await.unwrap() {
+            Some(Payload::History {
+                messages,
+                receipts,
+                has_more,
+            }) => {
+                assert!(!has_more);
+                assert_eq!(messages.len(), 1);
+                assert_eq!(messages[0].get_id(), Some(1));
+                assert_eq!(messages[0].get_sender_id(), Some(2));
+                assert_eq!(messages[0].to_string(), "anton: Guten Morgen, Rino!");
+                assert!(messages[0].get_sent_at().is_some());
+                assert_eq!(receipts, vec![Receipt::new(1, ReceiptStatus::Stored)]);
+            }
+            other => panic!("Expected history, got {:?}", other),
+        }
+
+        // The sender name is always the logged in user
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(chatroom_id).await.unwrap();
+        let encrypted = get_test_session(chatroom_id).encrypt("Hallo, Anton!");
+        rino_chat
+            .send_chat_message(ChatMessage::new(
+                chatroom_id,
+                "anton".to_string(),
+                encrypted.clone(),
+            ))
+            .await
+            .unwrap();
+
+        match anton_chat.receive().await.unwrap() {
+            Some(Payload::ChatMessage(message)) => {
+                assert_eq!(*message.get_sender_name(), rino.get_name().clone());
+                assert_eq!(message.get_sender_id(), Some(rino.get_id()));
+                assert_eq!(message.get_content(), encrypted);
+                assert!(message.get_sent_at().is_some());
+            }
+            other => panic!("Expected chat message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sender_gets_receipts() {
+        let server = TestServer::start(get_test_storage()).await;
+        let (rino_connection, _) = server.login("rino").await;
+        let (anton_connection, _) = server.login("anton").await;
+
+        let mut anton_chat = anton_connection.open_chat_connection().await.unwrap();
+        anton_chat.join_chat_room(1).await.unwrap();
+        anton_chat.request_history(1, None).await.unwrap();
+        assert!(matches!(
+            anton_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+
+        // Rino reads the history, so Anton is told that his message was read
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(1).await.unwrap();
+        rino_chat.request_history(1, None).await.unwrap();
+        assert!(matches!(
+            rino_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+        match anton_chat.receive().await.unwrap() {
+            Some(Payload::Receipt(receipt)) => {
+                assert_eq!(receipt, Receipt::new(1, ReceiptStatus::Read))
+            }
+            other => panic!("Expected receipt, got {:?}", other),
+        }
+
+        // The sender gets the id first, then the status changes of the message
+        let encrypted = get_test_session(1).encrypt("Hallo, Anton!");
+        rino_chat
+            .send_chat_message(ChatMessage::new(1, "rino".to_string(), encrypted.clone()))
+            .await
+            .unwrap();
+        let message_id = match rino_chat.receive().await.unwrap() {
+            Some(Payload::MessageStored(message)) => {
+                assert_eq!(message.get_content(), encrypted);
+                message.get_id().unwrap()
+            }
+            other => panic!("Expected stored message, got {:?}", other),
+        };
+        match anton_chat.receive().await.unwrap() {
+            Some(Payload::ChatMessage(message)) => assert_eq!(message.get_id(), Some(message_id)),
+            other => panic!("Expected chat message, got {:?}", other),
+        }
+        for status in [ReceiptStatus::Delivered, ReceiptStatus::Read] {
+            anton_chat
+                .send_receipt(Receipt::new(message_id, status))
+                .await
+                .unwrap();
+            match rino_chat.receive().await.unwrap() {
+                Some(Payload::Receipt(receipt)) => {
+                    assert_eq!(receipt, Receipt::new(message_id, status))
+                }
+                other => panic!("Expected receipt, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_edited_and_deleted_messages_are_sent_to_the_chatroom() {
+        let server = TestServer::start(get_test_storage()).await;
+        let (rino_connection, _) = server.login("rino").await;
+        let (anton_connection, _) = server.login("anton").await;
+
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(1).await.unwrap();
+        rino_chat.request_history(1, None).await.unwrap();
+        assert!(matches!(
+            rino_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+        let mut anton_chat = anton_connection.open_chat_connection().await.unwrap();
+        anton_chat.join_chat_room(1).await.unwrap();
+
+        // Rino can not change the message of anton
+        rino_chat.delete_message(1, 1).await.unwrap();
+        match rino_chat.receive().await.unwrap() {
+            Some(Payload::Error { message }) => {
+                assert_eq!(message, "You can only change your own messages.")
+            }
+            other => panic!("Expected error, got {:?}", other),
+        }
+
+        let encrypted = get_test_session(1).encrypt("Guten Tag, Rino!");
+        anton_chat
+            .edit_message(1, 1, encrypted.clone())
+            .await
+            .unwrap();
+        for chat in [&mut anton_chat, &mut rino_chat] {
+            match chat.receive().await.unwrap() {
+                Some(Payload::MessageChanged(message)) => {
+                    assert_eq!(message.get_id(), Some(1));
+                    assert_eq!(message.get_content(), encrypted);
+                    assert!(message.get_edited_at().is_some());
+                }
+                other => panic!("Expected changed message, got {:?}", other),
+            }
+        }
+
+        // Rino can read what the message said before the edit
+        rino_chat.request_edit_history(1, 1).await.unwrap();
+        match rino_chat.receive().await.unwrap() {
+            Some(Payload::EditHistory { message_id, edits }) => {
+                assert_eq!(message_id, 1);
+                assert_eq!(edits.len(), 1);
+                assert_eq!(edits[0].get_content(), "Guten Morgen, Rino!");
+            }
+            other => panic!("Expected edit history, got {:?}", other),
+        }
+
+        anton_chat.delete_message(1, 1).await.unwrap();
+        for chat in [&mut anton_chat, &mut rino_chat] {
+            match chat.receive().await.unwrap() {
+                Some(Payload::MessageChanged(message)) => {
+                    assert!(message.is_deleted());
+                    assert_eq!(message.get_content(), "");
+                }
+                other => panic!("Expected changed message, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_message_is_rejected() {
+        let storage = get_test_storage();
+        let server = TestServer::start(Arc::clone(&storage)).await;
+        let (rino_connection, _) = server.login("rino").await;
+
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(1).await.unwrap();
+        rino_chat
+            .send_chat_message(ChatMessage::new(
+                1,
+                "rino".to_string(),
+                "Hallo, Anton!".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            rino_chat.receive().await.unwrap(),
+            Some(Payload::Error { .. })
+        ));
+        // Only the message of the fixture is stored
+        assert_eq!(
+            storage.get_chat_messages_before(1, None, 10).unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_chat_is_sent_to_every_member() {
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .user("antonia")
+            .friends("rino", "anton")
+            .friends("rino", "antonia")
+            .chat_room("Group", &["rino", "anton", "antonia"])
+            .build();
+        let server = TestServer::start(Arc::clone(&storage)).await;
+        let (rino_connection, _) = server.login("rino").await;
+
+        // Anton and Antonia join first and wait for the history, so they are listening for new messages
+        let mut receiver_chats = Vec::new();
+        for username in ["anton", "antonia"] {
+            let (connection, _) = server.login(username).await;
+            let mut chat = connection.open_chat_connection().await.unwrap();
+            chat.join_chat_room(1).await.unwrap();
+            chat.request_history(1, None).await.unwrap();
+            assert!(matches!(
+                chat.receive().await.unwrap(),
+                Some(Payload::History { .. })
+            ));
+            receiver_chats.push(chat);
+        }
+
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(1).await.unwrap();
+
+        // A message that leaves out Antonia is rejected
+        let mut session = get_test_session(1);
+        let incomplete = ratchet::create_group_message(&[(2, session.encrypt("Hallo!"))]);
+        rino_chat
+            .send_chat_message(ChatMessage::new(1, "rino".to_string(), incomplete))
+            .await
+            .unwrap();
+        assert!(matches!(
+            rino_chat.receive().await.unwrap(),
+            Some(Payload::Error { .. })
+        ));
+
+        let encrypted = ratchet::create_group_message(&[
+            (2, session.encrypt("Hallo!")),
+            (3, session.encrypt("Hallo!")),
+        ]);
+        rino_chat
+            .send_chat_message(ChatMessage::new(1, "rino".to_string(), encrypted.clone()))
+            .await
+            .unwrap();
+
+        for chat in &mut receiver_chats {
+            match chat.receive().await.unwrap() {
+                Some(Payload::ChatMessage(message)) => {
+                    assert_eq!(*message.get_sender_name(), "rino".to_string());
+                    assert_eq!(message.get_content(), encrypted);
                 }
+                other => panic!("Expected chat message, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_joining_chatroom_of_other_users() {
+        let server = TestServer::start(get_test_storage()).await;
+        let (antonia_connection, _) = server.login("antonia").await;
+
+        let mut antonia_chat = antonia_connection.open_chat_connection().await.unwrap();
+        assert!(antonia_chat.join_chat_room(1).await.is_err());
+        assert!(antonia_chat.join_chat_room(42).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_public_room_with_member_who_joined_later() {
+        let storage = get_test_storage();
+        let server = TestServer::start(Arc::clone(&storage)).await;
+        let (mut rino_connection, _) = server.login("rino").await;
+        let (mut antonia_connection, _) = server.login("antonia").await;
+        let chatroom = rino_connection
+            .create_public_room("Lobby", Some("secret".to_string()))
+            .await
+            .unwrap();
+        let chatroom_id = *chatroom.get_id();
+
+        // Rino enters the room while nobody else is a member
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(chatroom_id).await.unwrap();
+
+        // Antonia can only enter the room after joining it with the password
+        let mut antonia_chat = antonia_connection.open_chat_connection().await.unwrap();
+        assert!(antonia_chat.join_chat_room(chatroom_id).await.is_err());
+        assert!(antonia_connection
+            .join_public_room(chatroom_id, Some("wrong".to_string()))
+            .await
+            .is_err());
+        antonia_connection
+            .join_public_room(chatroom_id, Some("secret".to_string()))
+            .await
+            .unwrap();
+        antonia_chat.join_chat_room(chatroom_id).await.unwrap();
+        antonia_chat
+            .request_history(chatroom_id, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            antonia_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+
+        // The message of Rino is for Antonia, who joined after Rino entered the room
+        let mut session = get_test_session(chatroom_id);
+        let encrypted = ratchet::create_group_message(&[(3, session.encrypt("Hallo!"))]);
+        rino_chat
+            .send_chat_message(ChatMessage::new(
+                chatroom_id,
+                "rino".to_string(),
+                encrypted.clone(),
+            ))
+            .await
+            .unwrap();
+        match antonia_chat.receive().await.unwrap() {
+            Some(Payload::ChatMessage(message)) => assert_eq!(message.get_content(), encrypted),
+            other => panic!("Expected chat message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kicked_member_leaves_the_chatroom() {
+        let storage = get_test_storage();
+        let server = TestServer::start(Arc::clone(&storage)).await;
+        let (mut rino_connection, _) = server.login("rino").await;
+        let (mut antonia_connection, _) = server.login("antonia").await;
+        let chatroom = rino_connection
+            .create_public_room("Lobby", None)
+            .await
+            .unwrap();
+        let chatroom_id = *chatroom.get_id();
+        antonia_connection
+            .join_public_room(chatroom_id, None)
+            .await
+            .unwrap();
+
+        let mut antonia_chat = antonia_connection.open_chat_connection().await.unwrap();
+        antonia_chat.join_chat_room(chatroom_id).await.unwrap();
+        antonia_chat
+            .request_history(chatroom_id, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            antonia_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(chatroom_id).await.unwrap();
+
+        // Antonia is no moderator
+        antonia_chat
+            .send_moderation(
+                chatroom_id,
+                ModerationAction::Kick {
+                    username: "rino".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            antonia_chat.receive().await.unwrap(),
+            Some(Payload::Error { .. })
+        ));
+
+        rino_chat
+            .send_moderation(
+                chatroom_id,
+                ModerationAction::Kick {
+                    username: "antonia".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        for chat in [&mut rino_chat, &mut antonia_chat] {
+            match chat.receive().await.unwrap() {
+                Some(Payload::Announcement { message }) => {
+                    assert_eq!(message, "antonia was kicked by rino.")
+                }
+                other => panic!("Expected announcement, got {:?}", other),
+            }
+        }
+        assert!(matches!(
+            antonia_chat.receive().await.unwrap(),
+            Some(Payload::Removed { .. })
+        ));
+        assert!(!storage
+            .get_chatroom_by_id(chatroom_id)
+            .unwrap()
+            .is_member(3));
+    }
+
+    #[tokio::test]
+    async fn test_muted_member_can_not_write() {
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("anton")
+            .user("antonia")
+            .friends("rino", "anton")
+            .friends("rino", "antonia")
+            .chat_room("Group", &["rino", "anton", "antonia"])
+            .build();
+        storage.mute_chat_member(1, 1, 60).unwrap();
+        let server = TestServer::start(Arc::clone(&storage)).await;
+        let (rino_connection, _) = server.login("rino").await;
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(1).await.unwrap();
+
+        let mut session = get_test_session(1);
+        let encrypted = ratchet::create_group_message(&[
+            (2, session.encrypt("Hallo!")),
+            (3, session.encrypt("Hallo!")),
+        ]);
+        rino_chat
+            .send_chat_message(ChatMessage::new(1, "rino".to_string(), encrypted))
+            .await
+            .unwrap();
+        match rino_chat.receive().await.unwrap() {
+            Some(Payload::Error { message }) => assert!(message.contains("muted")),
+            other => panic!("Expected error, got {:?}", other),
+        }
+        assert!(storage
+            .get_chat_messages_before(1, None, 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_typing_is_relayed_to_other_members() {
+        let server = TestServer::start(get_test_storage()).await;
+        let (rino_connection, _) = server.login("rino").await;
+        let (anton_connection, _) = server.login("anton").await;
+
+        let mut anton_chat = anton_connection.open_chat_connection().await.unwrap();
+        anton_chat.join_chat_room(1).await.unwrap();
+        anton_chat.request_history(1, None).await.unwrap();
+        assert!(matches!(
+            anton_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+
+        let mut rino_chat = rino_connection.open_chat_connection().await.unwrap();
+        rino_chat.join_chat_room(1).await.unwrap();
+        rino_chat.send_typing(1, true).await.unwrap();
+        match anton_chat.receive().await.unwrap() {
+            Some(Payload::MemberTyping {
+                username,
+                is_typing,
+            }) => {
+                assert_eq!(username, "rino");
+                assert!(is_typing);
+            }
+            other => panic!("Expected typing, got {:?}", other),
+        }
+
+        // The typing user is not told about its own typing
+        rino_chat.request_history(1, None).await.unwrap();
+        assert!(matches!(
+            rino_chat.receive().await.unwrap(),
+            Some(Payload::History { .. })
+        ));
+        // Rino read the message of Anton on the way
+        assert!(matches!(
+            anton_chat.receive().await.unwrap(),
+            Some(Payload::Receipt(_))
+        ));
+
+        rino_chat.send_typing(1, false).await.unwrap();
+        assert!(matches!(
+            anton_chat.receive().await.unwrap(),
+            Some(Payload::MemberTyping {
+                is_typing: false,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_friends_see_presence_changes() {
+        let storage = get_test_storage();
+        let server = TestServer::start(Arc::clone(&storage)).await;
+        let (mut anton_connection, _) = server.login("anton").await;
+        anton_connection.load_presence_of_friends().await.unwrap();
+        assert_eq!(
+            anton_connection.get_presence(1).unwrap().get_status(),
+            PresenceStatus::Offline
+        );
+
+        // The second request makes sure the login of Rino is finished on the server
+        let (mut rino_connection, _) = server.login("rino").await;
+        rino_connection.get_friends().await.unwrap();
+        match anton_connection.receive().await.unwrap() {
+            Some(Payload::Presence(presence)) => {
+                assert_eq!(presence.get_user_id(), 1);
+                assert_eq!(presence.get_status(), PresenceStatus::Online);
+            }
+            other => panic!("Expected presence, got {:?}", other),
+        }
+
+        drop(rino_connection);
+        match anton_connection.receive().await.unwrap() {
+            Some(Payload::Presence(presence)) => {
+                assert_eq!(presence.get_status(), PresenceStatus::Offline)
+            }
+            other => panic!("Expected presence, got {:?}", other),
+        }
+        assert!(storage.get_last_seen(1).unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_new_friends_see_presence_changes() {
+        let server = TestServer::start(get_test_storage()).await;
+        let (mut rino_connection, _) = server.login("rino").await;
+        rino_connection.create_new_friend_request(3).await.unwrap();
+
+        // Rino does not see Antonia going online, they are no friends yet
+        let (mut antonia_connection, _) = server.login("antonia").await;
+        antonia_connection.accept_friend_request(1).await.unwrap();
+
+        // The connection of Rino was told about the friendship before the change
+        drop(antonia_connection);
+        match rino_connection.receive().await.unwrap() {
+            Some(Payload::Presence(presence)) => {
+                assert_eq!(presence.get_user_id(), 3);
+                assert_eq!(presence.get_status(), PresenceStatus::Offline);
             }
+            other => panic!("Expected presence, got {:?}", other),
         }
     }
 }
@@ -0,0 +1,605 @@
+use crate::client;
+use crate::config::ClientConfig;
+use crate::helper_functions;
+use crate::protocol::{ClientRequest, ModerationAction, Payload, ServerResponse};
+use crate::structs::{
+    chat_room::ChatRoom, friend_request::FriendRequest, message::ChatMessage, presence::Presence,
+    receipt::Receipt, session_info::SessionInfo, user::User,
+};
+use crate::tls::BoxedStream;
+use futures_util::stream::StreamExt;
+use futures_util::SinkExt;
+use std::collections::HashMap;
+use std::error::Error;
+use tokio_websockets::{Message, WebSocketStream};
+
+/// Possible outcomes of a login attempt
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginResult {
+    LoggedIn(User),
+    WrongPassword {
+        tries_left: u32,
+    },
+    Blocked {
+        remaining_secs: u64,
+    },
+    /// The password is a one-time password, the login has to be repeated with a new password
+    PasswordChangeRequired,
+}
+
+/// Connection of a client to the server
+/// <br>All data the client needs is requested from the server through this connection,
+/// the client itself never talks to the database
+pub struct ServerConnection {
+    ws_stream: WebSocketStream<BoxedStream>,
+    /// Settings of the client, used to open further connections to the same server
+    config: ClientConfig,
+    /// Token of the session of the logged in user, used to authenticate further connections
+    session_token: Option<String>,
+    /// Latest known presence of the friends of the logged in user by user id
+    /// <br>The server sends changes at any time, they are picked up while waiting for answers
+    presences: HashMap<u32, Presence>,
+}
+
+impl ServerConnection {
+    /// Opens a new connection to the configured server and introduces the client
+    pub async fn connect(config: &ClientConfig) -> Result<ServerConnection, Box<dyn Error>> {
+        ServerConnection::connect_with_session(config, None).await
+    }
+
+    /// Opens a new connection to the server that is authenticated with the session token
+    async fn connect_with_session(
+        config: &ClientConfig,
+        session_token: Option<String>,
+    ) -> Result<ServerConnection, Box<dyn Error>> {
+        let ws_stream = client::create_websocket_connection(config).await?;
+        let mut connection = ServerConnection {
+            ws_stream,
+            config: config.clone(),
+            session_token: session_token.clone(),
+            presences: HashMap::new(),
+        };
+
+        connection.send(&Payload::Hello { session_token }).await?;
+        match connection.receive().await? {
+            Some(Payload::Ack) => Ok(connection),
+            Some(Payload::Error { message }) => Err(message.into()),
+            Some(other) => Err(unexpected_payload(other)),
+            None => Err("Connection to server closed".into()),
+        }
+    }
+
+    /// Returns the token of the session of the logged in user
+    pub fn get_session_token(&self) -> Option<&String> {
+        self.session_token.as_ref()
+    }
+
+    /// Opens another connection to the server as the user that is logged in on this connection
+    /// <br>Used for joining a chatroom, which takes over the whole connection
+    pub async fn open_chat_connection(&self) -> Result<ServerConnection, Box<dyn Error>> {
+        match &self.session_token {
+            Some(session_token) => {
+                ServerConnection::connect_with_session(&self.config, Some(session_token.clone()))
+                    .await
+            }
+            None => Err("You need to log in first.".into()),
+        }
+    }
+
+    /// Sends a payload to the server
+    pub async fn send(&mut self, payload: &Payload) -> Result<(), Box<dyn Error>> {
+        self.ws_stream
+            .send(Message::text(payload.to_json()))
+            .await?;
+        Ok(())
+    }
+
+    /// Waits for the next payload of the server
+    /// <br>Returns None if the server closed the connection
+    pub async fn receive(&mut self) -> Result<Option<Payload>, Box<dyn Error>> {
+        loop {
+            match self.ws_stream.next().await {
+                Some(Ok(msg)) => {
+                    if let Some(text) = msg.as_text() {
+                        return Ok(Some(Payload::from_json(text)?));
+                    }
+                }
+                Some(Err(err)) => return Err(err.into()),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Sends a payload and waits for the answer of the server
+    /// <br>An `Error` of the server is returned as error
+    async fn send_and_receive(&mut self, payload: &Payload) -> Result<Payload, Box<dyn Error>> {
+        self.send(payload).await?;
+        loop {
+            match self.receive().await? {
+                Some(Payload::Presence(presence)) => {
+                    self.presences.insert(presence.get_user_id(), presence);
+                }
+                Some(Payload::Error { message }) => return Err(message.into()),
+                Some(answer) => return Ok(answer),
+                None => return Err("Connection to server closed".into()),
+            }
+        }
+    }
+
+    /// Sends a request to the server and waits for its response
+    async fn send_request(&mut self, request: ClientRequest) -> Result<Payload, Box<dyn Error>> {
+        self.send_and_receive(&Payload::Request(request)).await
+    }
+
+    ///Returns true if a username exists
+    pub async fn check_if_username_exists(
+        &mut self,
+        username: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let request = ClientRequest::CheckIfUsernameExists {
+            username: username.to_string(),
+        };
+        match self.send_request(request).await? {
+            Payload::Response(ServerResponse::UsernameExists(exists)) => Ok(exists),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns if a user is currently blocked or not
+    pub async fn check_if_user_isblocked(
+        &mut self,
+        username: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let request = ClientRequest::CheckIfUserIsBlocked {
+            username: username.to_string(),
+        };
+        match self.send_request(request).await? {
+            Payload::Response(ServerResponse::UserIsBlocked(is_blocked)) => Ok(is_blocked),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Tries to log in with the username and the hashed password
+    /// <br>On success, all further requests on this connection are made as the logged in user
+    /// <br>If `remember_me` is set, the session stays valid for a longer time.
+    /// `new_password_hash` replaces a one-time password, see `LoginResult::PasswordChangeRequired`
+    pub async fn login(
+        &mut self,
+        username: &str,
+        password_hash: &str,
+        remember_me: bool,
+        new_password_hash: Option<&str>,
+    ) -> Result<LoginResult, Box<dyn Error>> {
+        let request = ClientRequest::Login {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            device_name: helper_functions::get_device_name(),
+            remember_me,
+            new_password_hash: new_password_hash.map(str::to_string),
+        };
+        match self.send_request(request).await? {
+            Payload::Response(ServerResponse::LoginSucceeded {
+                user,
+                session_token,
+            }) => {
+                self.session_token = Some(session_token);
+                Ok(LoginResult::LoggedIn(user))
+            }
+            Payload::Response(ServerResponse::LoginFailed { tries_left }) => {
+                Ok(LoginResult::WrongPassword { tries_left })
+            }
+            Payload::Response(ServerResponse::AccountBlocked { remaining_secs }) => {
+                Ok(LoginResult::Blocked { remaining_secs })
+            }
+            Payload::Response(ServerResponse::PasswordChangeRequired) => {
+                Ok(LoginResult::PasswordChangeRequired)
+            }
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Creates a new account and logs in as the new user
+    pub async fn sign_up(
+        &mut self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<User, Box<dyn Error>> {
+        let request = ClientRequest::SignUp {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            device_name: helper_functions::get_device_name(),
+        };
+        match self.send_request(request).await? {
+            Payload::Response(ServerResponse::SignedUp {
+                user,
+                session_token,
+            }) => {
+                self.session_token = Some(session_token);
+                Ok(user)
+            }
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Logs in with the token of an earlier login
+    pub async fn resume_session(&mut self, session_token: &str) -> Result<User, Box<dyn Error>> {
+        let request = ClientRequest::ResumeSession {
+            session_token: session_token.to_string(),
+        };
+        match self.send_request(request).await? {
+            Payload::Response(ServerResponse::LoginSucceeded {
+                user,
+                session_token,
+            }) => {
+                self.session_token = Some(session_token);
+                Ok(user)
+            }
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Logs out the current user of this connection
+    pub async fn logout(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.send_request(ClientRequest::Logout).await? {
+            Payload::Ack => {
+                self.session_token = None;
+                Ok(())
+            }
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns all active sessions of the current user
+    pub async fn get_sessions(&mut self) -> Result<Vec<SessionInfo>, Box<dyn Error>> {
+        match self.send_request(ClientRequest::GetSessions).await? {
+            Payload::Response(ServerResponse::Sessions(sessions)) => Ok(sessions),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Ends a session of the current user, e.g. on a lost device
+    pub async fn revoke_session(&mut self, session_id: u32) -> Result<(), Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::RevokeSession { session_id })
+            .await?
+        {
+            Payload::Ack => Ok(()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Searches for users that match the search string and are no friends of the current user yet
+    pub async fn get_user_by_name_with_contains_search(
+        &mut self,
+        search_string: String,
+    ) -> Result<Vec<User>, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::SearchUsers { search_string })
+            .await?
+        {
+            Payload::Response(ServerResponse::Users(users)) => Ok(users),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns true if the current user is friends with the given user
+    pub async fn check_if_friends_with(&mut self, user_id: u32) -> Result<bool, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::CheckIfFriends { user_id })
+            .await?
+        {
+            Payload::Response(ServerResponse::AreFriends(are_friends)) => Ok(are_friends),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Sends a friend request from the current user to the receiver
+    pub async fn create_new_friend_request(
+        &mut self,
+        receiver_id: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::SendFriendRequest { receiver_id })
+            .await?
+        {
+            Payload::Ack => Ok(()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns all open friend requests of the current user
+    pub async fn get_friend_requests(&mut self) -> Result<Vec<FriendRequest>, Box<dyn Error>> {
+        match self.send_request(ClientRequest::GetFriendRequests).await? {
+            Payload::Response(ServerResponse::FriendRequests(requests)) => Ok(requests),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Accepts the friend request of the sender to the current user
+    pub async fn accept_friend_request(&mut self, sender_id: u32) -> Result<(), Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::AcceptFriendRequest { sender_id })
+            .await?
+        {
+            Payload::Ack => Ok(()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Declines the friend request of the sender to the current user
+    pub async fn decline_friend_request(&mut self, sender_id: u32) -> Result<(), Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::DeclineFriendRequest { sender_id })
+            .await?
+        {
+            Payload::Ack => Ok(()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns all chatrooms of the current user
+    pub async fn search_for_chatrooms(&mut self) -> Result<Vec<ChatRoom>, Box<dyn Error>> {
+        match self.send_request(ClientRequest::GetChatRooms).await? {
+            Payload::Response(ServerResponse::ChatRooms(chatrooms)) => Ok(chatrooms),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns all friends of the current user
+    pub async fn get_friends(&mut self) -> Result<Vec<User>, Box<dyn Error>> {
+        match self.send_request(ClientRequest::GetFriends).await? {
+            Payload::Response(ServerResponse::Users(friends)) => Ok(friends),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Asks for the presence of all friends, afterwards the server keeps sending changes
+    pub async fn load_presence_of_friends(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.send_request(ClientRequest::GetPresence).await? {
+            Payload::Response(ServerResponse::Presences(presences)) => {
+                for presence in presences {
+                    self.presences.insert(presence.get_user_id(), presence);
+                }
+                Ok(())
+            }
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns the number of unread messages by chatroom id, the server counts offline messages as delivered afterwards
+    pub async fn get_unread_counts(&mut self) -> Result<HashMap<u32, u32>, Box<dyn Error>> {
+        match self.send_request(ClientRequest::GetUnreadCounts).await? {
+            Payload::Response(ServerResponse::UnreadCounts(unread_counts)) => Ok(unread_counts),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns the latest known presence of a friend, None if it was not loaded yet
+    pub fn get_presence(&self, user_id: u32) -> Option<&Presence> {
+        self.presences.get(&user_id)
+    }
+
+    ///Creates a group chat of the current user and the friends with the ids
+    pub async fn create_group_chat(
+        &mut self,
+        name: &str,
+        member_ids: Vec<u32>,
+    ) -> Result<ChatRoom, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::CreateGroupChat {
+                name: name.to_string(),
+                member_ids,
+            })
+            .await?
+        {
+            Payload::Response(ServerResponse::ChatRoom(chatroom)) => Ok(chatroom),
+            Payload::Error { message } => Err(message.into()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Creates a public room, the password hash is None for rooms everybody can join
+    pub async fn create_public_room(
+        &mut self,
+        name: &str,
+        password_hash: Option<String>,
+    ) -> Result<ChatRoom, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::CreatePublicRoom {
+                name: name.to_string(),
+                password_hash,
+            })
+            .await?
+        {
+            Payload::Response(ServerResponse::ChatRoom(chatroom)) => Ok(chatroom),
+            Payload::Error { message } => Err(message.into()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns the public rooms whose name contains the search string and that the current user is not a member of
+    pub async fn search_public_rooms(
+        &mut self,
+        search_string: &str,
+    ) -> Result<Vec<ChatRoom>, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::SearchPublicRooms {
+                search_string: search_string.to_string(),
+            })
+            .await?
+        {
+            Payload::Response(ServerResponse::ChatRooms(chatrooms)) => Ok(chatrooms),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Makes the current user a member of the public room
+    pub async fn join_public_room(
+        &mut self,
+        chatroom_id: u32,
+        password_hash: Option<String>,
+    ) -> Result<ChatRoom, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::JoinPublicRoom {
+                chatroom_id,
+                password_hash,
+            })
+            .await?
+        {
+            Payload::Response(ServerResponse::ChatRoom(chatroom)) => Ok(chatroom),
+            Payload::Error { message } => Err(message.into()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Publishes the public identity key of the current user
+    pub async fn publish_identity_key(&mut self, public_key: &str) -> Result<(), Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::PublishIdentityKey {
+                public_key: public_key.to_string(),
+            })
+            .await?
+        {
+            Payload::Ack => Ok(()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Returns the public identity key of the user, None if the user did not publish a key yet
+    pub async fn get_identity_key(
+        &mut self,
+        user_id: u32,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        match self
+            .send_request(ClientRequest::GetIdentityKey { user_id })
+            .await?
+        {
+            Payload::Response(ServerResponse::IdentityKey { public_key, .. }) => Ok(public_key),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Turns this connection into a chat connection for the chatroom
+    pub async fn join_chat_room(&mut self, chatroom_id: u32) -> Result<(), Box<dyn Error>> {
+        match self
+            .send_and_receive(&Payload::JoinRoom { chatroom_id })
+            .await?
+        {
+            Payload::Ack => Ok(()),
+            other => Err(unexpected_payload(other)),
+        }
+    }
+
+    ///Asks for a page of the messages of the joined chatroom, the newest page without a message id
+    /// <br>The messages arrive as `History` payload
+    pub async fn request_history(
+        &mut self,
+        chatroom_id: u32,
+        before_message_id: Option<u32>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::HistoryRequest {
+            chatroom_id,
+            before_message_id,
+            keep_unread: false,
+        })
+        .await
+    }
+
+    ///Asks for a page of the messages of the joined chatroom like `request_history`, but nothing is read
+    /// <br>No receipts are sent for the messages, the chatroom keeps its unread messages
+    pub async fn request_history_without_reading(
+        &mut self,
+        chatroom_id: u32,
+        before_message_id: Option<u32>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::HistoryRequest {
+            chatroom_id,
+            before_message_id,
+            keep_unread: true,
+        })
+        .await
+    }
+
+    ///Sends a message to the joined chatroom
+    pub async fn send_chat_message(&mut self, message: ChatMessage) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::ChatMessage(message)).await
+    }
+
+    ///Moderates a member of the joined chatroom
+    /// <br>The server answers with an `Announcement` to the chatroom or an `Error`
+    pub async fn send_moderation(
+        &mut self,
+        chatroom_id: u32,
+        action: ModerationAction,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::Moderation {
+            chatroom_id,
+            action,
+        })
+        .await
+    }
+
+    ///Replaces the content of an own message of the joined chatroom with the encrypted content
+    pub async fn edit_message(
+        &mut self,
+        chatroom_id: u32,
+        message_id: u32,
+        content: String,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::EditMessage {
+            chatroom_id,
+            message_id,
+            content,
+        })
+        .await
+    }
+
+    ///Deletes an own message of the joined chatroom
+    pub async fn delete_message(
+        &mut self,
+        chatroom_id: u32,
+        message_id: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::DeleteMessage {
+            chatroom_id,
+            message_id,
+        })
+        .await
+    }
+
+    ///Asks for the contents a message of the joined chatroom had before it was edited
+    pub async fn request_edit_history(
+        &mut self,
+        chatroom_id: u32,
+        message_id: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::EditHistoryRequest {
+            chatroom_id,
+            message_id,
+        })
+        .await
+    }
+
+    ///Tells the server that the user received or read a message of another member
+    pub async fn send_receipt(&mut self, receipt: Receipt) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::Receipt(receipt)).await
+    }
+
+    ///Tells the other members of the joined chatroom that the user started or stopped typing
+    pub async fn send_typing(
+        &mut self,
+        chatroom_id: u32,
+        is_typing: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(&Payload::Typing {
+            chatroom_id,
+            is_typing,
+        })
+        .await
+    }
+}
+
+/// Creates the error for a payload that does not fit the request
+fn unexpected_payload(payload: Payload) -> Box<dyn Error> {
+    format!("Unexpected answer from server: {:?}", payload).into()
+}
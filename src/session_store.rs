@@ -0,0 +1,139 @@
+use crate::helper_functions::encode_hex;
+use crate::structs::user::User;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the file the session token is stored in, inside the home directory of the user
+const SESSION_FILE_NAME: &str = ".chatclient_session";
+
+/// Returns the path of the file the session token is stored in
+pub fn get_session_file_path() -> PathBuf {
+    get_path_in_home_directory(SESSION_FILE_NAME)
+}
+
+/// Returns the path of a file inside the home directory of the user
+/// <br>Falls back to the working directory if there is no home directory
+pub fn get_path_in_home_directory(file_name: &str) -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match home {
+        Some(home) => Path::new(&home).join(file_name),
+        None => PathBuf::from(file_name),
+    }
+}
+
+/// Returns the username as it is put into the names of the files of the user
+/// <br>Names from the server are not trusted: a name that is no valid username, for example with `/` or `..`,
+/// is replaced by its SHA-256 hash, so that it can not point outside the home directory
+pub fn get_username_for_file_name(username: &str) -> String {
+    if User::is_valid_name(username) {
+        username.to_string()
+    } else {
+        encode_hex(&Sha256::digest(username.as_bytes()))
+    }
+}
+
+/// Returns the stored session token, if there is one
+pub fn load_session_token(path: &Path) -> Option<String> {
+    let token = fs::read_to_string(path).ok()?;
+    let token = token.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
+/// Stores the session token for automatic login on the next start
+/// <br>The file can only be read by the current user
+pub fn save_session_token(path: &Path, token: &str) -> io::Result<()> {
+    write_private_file(path, token)
+}
+
+/// Replaces the content of the file, which can only be read by the current user afterwards
+pub fn write_private_file(path: &Path, content: &str) -> io::Result<()> {
+    open_private_file(path, true)?.write_all(content.as_bytes())
+}
+
+/// Creates the file if it does not exist, afterwards it can only be read by the current user
+/// <br>The content of an existing file is kept
+pub fn create_private_file(path: &Path) -> io::Result<()> {
+    open_private_file(path, false).map(|_| ())
+}
+
+fn open_private_file(path: &Path, truncate: bool) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(truncate);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let file = options.open(path)?;
+    // The mode is only applied to new files, so restrict files that existed before as well
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(file)
+}
+
+/// Deletes the stored session token
+pub fn delete_session_token(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chatclient_session_test_{}", name))
+    }
+
+    #[test]
+    fn test_save_and_load_session_token() {
+        let path = get_test_path("save_and_load");
+        save_session_token(&path, "token").unwrap();
+        assert_eq!(load_session_token(&path), Some("token".to_string()));
+        delete_session_token(&path).unwrap();
+        assert_eq!(load_session_token(&path), None);
+    }
+
+    #[test]
+    fn test_get_username_for_file_name() {
+        assert_eq!(get_username_for_file_name("anton"), "anton");
+        for username in ["../../.bashrc", "a/b", "anton\n"] {
+            let file_name = get_username_for_file_name(username);
+            assert_eq!(file_name.len(), 64);
+            assert!(file_name.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+    }
+
+    #[test]
+    fn test_delete_missing_session_token() {
+        let path = get_test_path("missing");
+        assert!(delete_session_token(&path).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_session_file_is_only_readable_by_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = get_test_path("permissions");
+        fs::write(&path, "old token").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        save_session_token(&path, "token").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        delete_session_token(&path).unwrap();
+    }
+}
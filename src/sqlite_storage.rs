@@ -0,0 +1,1730 @@
+use crate::chat_export::{ChatExport, RestoredUser};
+use crate::helper_functions;
+use crate::migrations::{self, Migration};
+use crate::storage::{self, ChatMemberRow, ChatMessageRow, Storage, StorageResult};
+use crate::structs::chat_room::ChatRoom;
+use crate::structs::friend_request::FriendRequest;
+use crate::structs::message::{ChatMessage, MessageEdit};
+use crate::structs::receipt::ReceiptStatus;
+use crate::structs::room_role::RoomRole;
+use crate::structs::session_info::SessionInfo;
+use crate::structs::user::User;
+use rusqlite::{named_params, Connection, OptionalExtension, ToSql};
+use std::sync::{Mutex, MutexGuard};
+
+/// Creates the table that remembers which migrations were applied
+const CREATE_SCHEMA_VERSION_TABLE: &str = r"
+CREATE TABLE IF NOT EXISTS schema_version (
+    Version INTEGER PRIMARY KEY,
+    Name VARCHAR(100) NOT NULL,
+    AppliedAt INTEGER NOT NULL
+);
+";
+
+/// Embedded SQLite database as storage of the server
+/// <br>Needs no database server, so the server can run on any machine
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Opens the SQLite database file, it is created if it does not exist
+    /// <br>The schema has to be created by `src migrate`
+    pub fn open(path: &str) -> StorageResult<SqliteStorage> {
+        SqliteStorage::from_connection(Connection::open(path)?)
+    }
+
+    /// Opens a SQLite database that only lives in memory and is lost when the server stops
+    /// <br>The database is empty, so all migrations are applied right away
+    pub fn open_in_memory() -> StorageResult<SqliteStorage> {
+        let storage = SqliteStorage::from_connection(Connection::open_in_memory()?)?;
+        migrations::migrate(&storage)?;
+        Ok(storage)
+    }
+
+    fn from_connection(conn: Connection) -> StorageResult<SqliteStorage> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        Ok(SqliteStorage {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Runs raw SQL, used by tests to create data of old schema versions
+    #[cfg(test)]
+    pub fn execute_batch(&self, sql: &str) -> StorageResult<()> {
+        self.get_dbconn()?.execute_batch(sql)?;
+        Ok(())
+    }
+
+    /// Returns the chatrooms with all of their members
+    /// <br>`condition` filters and orders the rows, it has to order them by chatroom id
+    fn query_chatrooms(
+        &self,
+        condition: &str,
+        params: &[(&str, &dyn ToSql)],
+    ) -> StorageResult<Vec<ChatRoom>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(&format!(
+            r"SELECT {} FROM chats
+            JOIN chat_members ON chat_members.Chat_Id = chats.Id
+            JOIN users ON users.Id = chat_members.User_Id
+            {}",
+            storage::CHAT_MEMBER_COLUMNS,
+            condition
+        ))?;
+        let rows = statement
+            .query_map(params, |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<ChatMemberRow>, _>>()?;
+        Ok(storage::group_chat_member_rows(rows))
+    }
+
+    /// Returns the messages together with the name of their sender
+    /// <br>`condition` joins further tables, filters and orders the rows
+    fn query_chat_messages(
+        &self,
+        condition: &str,
+        params: &[(&str, &dyn ToSql)],
+    ) -> StorageResult<Vec<ChatMessage>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(&format!(
+            r"SELECT {} FROM chat_messages
+            LEFT JOIN users ON users.Id = chat_messages.Sender_Id
+            {}",
+            storage::CHAT_MESSAGE_COLUMNS,
+            condition
+        ))?;
+        let rows = statement
+            .query_map(params, |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get(8)?,
+                ))
+            })?
+            .collect::<Result<Vec<ChatMessageRow>, _>>()?;
+        Ok(rows
+            .into_iter()
+            .map(storage::chat_message_from_row)
+            .collect())
+    }
+
+    fn get_dbconn(&self) -> StorageResult<MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| "Database connection is poisoned".into())
+    }
+}
+
+/// Changes the schema and its version together or not at all
+/// <br>The foreign keys are checked before the changes are committed
+fn apply_migration_in_transaction(
+    conn: &mut Connection,
+    migration: &Migration,
+) -> StorageResult<()> {
+    let transaction = conn.transaction()?;
+    transaction.execute_batch(CREATE_SCHEMA_VERSION_TABLE)?;
+    transaction.execute_batch(migration.sqlite)?;
+    let has_foreign_key_violations = transaction
+        .prepare("PRAGMA foreign_key_check")?
+        .exists([])?;
+    if has_foreign_key_violations {
+        return Err(format!(
+            "Migration {:04}_{} violates foreign keys",
+            migration.version, migration.name
+        )
+        .into());
+    }
+    transaction.execute(
+        "INSERT INTO schema_version (Version, Name, AppliedAt) VALUES (:version, :name, :applied_at)",
+        named_params! {
+            ":version": migration.version,
+            ":name": migration.name,
+            ":applied_at": helper_functions::get_sys_time_in_secs(),
+        },
+    )?;
+    transaction.commit()?;
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn get_schema_version(&self) -> StorageResult<u32> {
+        let conn = self.get_dbconn()?;
+        let table_exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !table_exists {
+            return Ok(0);
+        }
+        let version: Option<u32> =
+            conn.query_row("SELECT MAX(Version) FROM schema_version", [], |row| {
+                row.get(0)
+            })?;
+        Ok(version.unwrap_or(0))
+    }
+
+    fn apply_migration(&self, migration: &Migration) -> StorageResult<()> {
+        let mut conn = self.get_dbconn()?;
+        // Migrations that rebuild a table need the foreign keys off, they can only be switched outside of a transaction
+        // See https://www.sqlite.org/lang_altertable.html#otheralter
+        conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+        let result = apply_migration_in_transaction(&mut conn, migration);
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        result
+    }
+
+    fn check_if_username_exists(&self, name: &str) -> StorageResult<bool> {
+        let conn = self.get_dbconn()?;
+        let exists = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE UserName = :username)",
+            named_params! { ":username": name },
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    fn get_password_hash_of_user(&self, username: &str) -> StorageResult<Option<String>> {
+        let conn = self.get_dbconn()?;
+        let result = conn
+            .query_row(
+                "SELECT Password FROM users WHERE UserName = :username",
+                named_params! { ":username": username },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result)
+    }
+
+    fn update_password_hash_of_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE users SET Password = :password WHERE UserName = :username",
+            named_params! { ":password": password_hash, ":username": username },
+        )?;
+        Ok(())
+    }
+
+    fn save_new_user_to_database_after_signup(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "INSERT INTO users (UserName, Password, BlockedUntil) VALUES (:username, :password, 0)",
+            named_params! { ":username": username, ":password": password },
+        )?;
+        Ok(())
+    }
+
+    fn get_user_from_database_by_name(&self, username: &str) -> StorageResult<User> {
+        let conn = self.get_dbconn()?;
+        let user = conn
+            .query_row(
+                "SELECT Id, UserName FROM users WHERE UserName = :username",
+                named_params! { ":username": username },
+                |row| Ok(User::new(row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        user.ok_or_else(|| "User not found".into())
+    }
+
+    fn set_user_isblocked(&self, username: &str, duration: u64) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE users SET BlockedUntil = :blocked_until WHERE UserName = :username",
+            named_params! {
+                ":blocked_until": helper_functions::get_sys_time_in_secs() + duration,
+                ":username": username,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn get_user_is_blockeduntil(&self, username: &str) -> StorageResult<u64> {
+        let conn = self.get_dbconn()?;
+        let result: Option<u64> = conn
+            .query_row(
+                "SELECT BlockedUntil FROM users WHERE UserName = :username",
+                named_params! { ":username": username },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.unwrap_or(0))
+    }
+
+    fn check_if_user_isblocked(&self, username: &str) -> StorageResult<bool> {
+        let blocked_until = self.get_user_is_blockeduntil(username)?;
+        //User is blocked if BlockedUntil time is larger that current time
+        Ok(blocked_until > helper_functions::get_sys_time_in_secs())
+    }
+
+    fn set_last_seen(&self, user_id: u32, last_seen: u64) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE users SET LastSeen = :last_seen WHERE Id = :user_id",
+            named_params! { ":last_seen": last_seen, ":user_id": user_id },
+        )?;
+        Ok(())
+    }
+
+    fn get_last_seen(&self, user_id: u32) -> StorageResult<u64> {
+        let conn = self.get_dbconn()?;
+        let result: Option<u64> = conn
+            .query_row(
+                "SELECT LastSeen FROM users WHERE Id = :user_id",
+                named_params! { ":user_id": user_id },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.unwrap_or(0))
+    }
+
+    fn set_identity_key(&self, user_id: u32, public_key: &str) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE users SET IdentityKey = :public_key WHERE Id = :user_id",
+            named_params! { ":public_key": public_key, ":user_id": user_id },
+        )?;
+        Ok(())
+    }
+
+    fn get_identity_key(&self, user_id: u32) -> StorageResult<Option<String>> {
+        let conn = self.get_dbconn()?;
+        let result: Option<Option<String>> = conn
+            .query_row(
+                "SELECT IdentityKey FROM users WHERE Id = :user_id",
+                named_params! { ":user_id": user_id },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.flatten())
+    }
+
+    fn get_user_by_name_with_contains_search(
+        &self,
+        search_string: &str,
+        current_user_id: u32,
+    ) -> StorageResult<Vec<User>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            r"SELECT users.Id, users.UserName
+            FROM users
+            LEFT JOIN friends
+            ON (users.Id = friends.User1_Id AND friends.User2_Id = :current_user_id)
+            OR (users.Id = friends.User2_Id AND friends.User1_Id = :current_user_id)
+            WHERE users.UserName LIKE :search_string
+            AND users.Id != :current_user_id
+            AND friends.Id IS NULL",
+        )?;
+        let users = statement
+            .query_map(
+                named_params! {
+                    ":search_string": format!("%{}%", search_string),
+                    ":current_user_id": current_user_id,
+                },
+                |row| Ok(User::new(row.get(0)?, row.get(1)?)),
+            )?
+            .collect::<Result<Vec<User>, _>>()?;
+        Ok(users)
+    }
+
+    fn search_for_chatrooms_of_user(&self, user_id: u32) -> StorageResult<Vec<ChatRoom>> {
+        self.query_chatrooms(
+            r"JOIN chat_members AS own ON own.Chat_Id = chats.Id AND own.User_Id = :user_id
+            ORDER BY chats.Id, users.Id",
+            named_params! { ":user_id": user_id },
+        )
+    }
+
+    fn create_new_chatroom(
+        &self,
+        member_ids: &[u32],
+        chatroom_name: &str,
+    ) -> StorageResult<ChatRoom> {
+        let chatroom_id = {
+            let mut conn = self.get_dbconn()?;
+            // The chatroom is only created together with all of its members
+            let transaction = conn.transaction()?;
+            transaction.execute(
+                "INSERT INTO chats (ChatName) VALUES (:chatname)",
+                named_params! { ":chatname": chatroom_name },
+            )?;
+            let chatroom_id = transaction.last_insert_rowid() as u32;
+            for member_id in member_ids {
+                transaction.execute(
+                    "INSERT INTO chat_members (Chat_Id, User_Id) VALUES (:chatroom_id, :user_id)",
+                    named_params! { ":chatroom_id": chatroom_id, ":user_id": member_id },
+                )?;
+            }
+            transaction.commit()?;
+            chatroom_id
+        };
+        self.get_chatroom_by_id(chatroom_id)
+    }
+
+    fn create_public_chatroom(
+        &self,
+        creator_id: u32,
+        chatroom_name: &str,
+        password_hash: Option<&str>,
+    ) -> StorageResult<Option<ChatRoom>> {
+        let chatroom_id = {
+            let mut conn = self.get_dbconn()?;
+            let transaction = conn.transaction()?;
+            let result = transaction.execute(
+                "INSERT INTO chats (ChatName, IsPublic, PasswordHash) VALUES (:chatname, TRUE, :password_hash)",
+                named_params! { ":chatname": chatroom_name, ":password_hash": password_hash },
+            );
+            // Another room got the name since it was checked
+            match result {
+                Err(rusqlite::Error::SqliteFailure(e, _))
+                    if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+                {
+                    return Ok(None)
+                }
+                result => result?,
+            };
+            let chatroom_id = transaction.last_insert_rowid() as u32;
+            transaction.execute(
+                "INSERT INTO chat_members (Chat_Id, User_Id, Role) VALUES (:chatroom_id, :user_id, :role)",
+                named_params! {
+                    ":chatroom_id": chatroom_id,
+                    ":user_id": creator_id,
+                    ":role": RoomRole::Owner.as_str(),
+                },
+            )?;
+            transaction.commit()?;
+            chatroom_id
+        };
+        self.get_chatroom_by_id(chatroom_id).map(Some)
+    }
+
+    fn check_if_public_chatroom_name_exists(&self, chatroom_name: &str) -> StorageResult<bool> {
+        let conn = self.get_dbconn()?;
+        let exists = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM chats WHERE IsPublic AND ChatName = :chatname)",
+            named_params! { ":chatname": chatroom_name },
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    fn search_public_chatrooms(
+        &self,
+        search_string: &str,
+        current_user_id: u32,
+    ) -> StorageResult<Vec<ChatRoom>> {
+        self.query_chatrooms(
+            r"WHERE chats.IsPublic AND chats.ChatName LIKE :search_string
+            AND NOT EXISTS(SELECT 1 FROM chat_members AS own WHERE own.Chat_Id = chats.Id AND own.User_Id = :current_user_id)
+            ORDER BY chats.Id, users.Id",
+            named_params! {
+                ":search_string": format!("%{}%", search_string),
+                ":current_user_id": current_user_id,
+            },
+        )
+    }
+
+    fn get_chatroom_password_hash(&self, chatroom_id: u32) -> StorageResult<Option<String>> {
+        let conn = self.get_dbconn()?;
+        let result: Option<Option<String>> = conn
+            .query_row(
+                "SELECT PasswordHash FROM chats WHERE Id = :chatroom_id",
+                named_params! { ":chatroom_id": chatroom_id },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.flatten())
+    }
+
+    fn join_public_chatroom(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+        max_members: u32,
+    ) -> StorageResult<bool> {
+        let mut conn = self.get_dbconn()?;
+        let transaction = conn.transaction()?;
+        let member_count: u32 = transaction.query_row(
+            "SELECT COUNT(*) FROM chat_members WHERE Chat_Id = :chatroom_id",
+            named_params! { ":chatroom_id": chatroom_id },
+            |row| row.get(0),
+        )?;
+        if member_count >= max_members {
+            return Ok(false);
+        }
+        transaction.execute(
+            "INSERT INTO chat_members (Chat_Id, User_Id) VALUES (:chatroom_id, :user_id)",
+            named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+        )?;
+        transaction.commit()?;
+        Ok(true)
+    }
+
+    fn get_chat_member_role(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+    ) -> StorageResult<Option<RoomRole>> {
+        let conn = self.get_dbconn()?;
+        let result: Option<String> = conn
+            .query_row(
+                "SELECT Role FROM chat_members WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+                named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.map(|role| RoomRole::from_db(&role)))
+    }
+
+    fn set_chat_member_role(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+        role: RoomRole,
+    ) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE chat_members SET Role = :role WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+            named_params! {
+                ":role": role.as_str(),
+                ":chatroom_id": chatroom_id,
+                ":user_id": user_id,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn remove_chat_member(&self, chatroom_id: u32, user_id: u32) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "DELETE FROM chat_members WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+            named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+        )?;
+        Ok(())
+    }
+
+    fn ban_chat_member(&self, chatroom_id: u32, user_id: u32) -> StorageResult<()> {
+        let mut conn = self.get_dbconn()?;
+        let transaction = conn.transaction()?;
+        transaction.execute(
+            "DELETE FROM chat_members WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+            named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+        )?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO chat_bans (Chat_Id, User_Id) VALUES (:chatroom_id, :user_id)",
+            named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn check_if_banned_from_chatroom(&self, chatroom_id: u32, user_id: u32) -> StorageResult<bool> {
+        let conn = self.get_dbconn()?;
+        let exists = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM chat_bans WHERE Chat_Id = :chatroom_id AND User_Id = :user_id)",
+            named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+            |row| row.get(0),
+        )?;
+        Ok(exists)
+    }
+
+    fn mute_chat_member(&self, chatroom_id: u32, user_id: u32, duration: u64) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE chat_members SET MutedUntil = :muted_until WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+            named_params! {
+                ":muted_until": helper_functions::get_sys_time_in_secs() + duration,
+                ":chatroom_id": chatroom_id,
+                ":user_id": user_id,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn get_chat_member_muted_until(&self, chatroom_id: u32, user_id: u32) -> StorageResult<u64> {
+        let conn = self.get_dbconn()?;
+        let result: Option<u64> = conn
+            .query_row(
+                "SELECT MutedUntil FROM chat_members WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+                named_params! { ":chatroom_id": chatroom_id, ":user_id": user_id },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.unwrap_or(0))
+    }
+
+    fn get_chatroom_by_id(&self, chatroom_id: u32) -> StorageResult<ChatRoom> {
+        self.query_chatrooms(
+            "WHERE chats.Id = :chatroom_id ORDER BY users.Id",
+            named_params! { ":chatroom_id": chatroom_id },
+        )?
+        .pop()
+        .ok_or_else(|| "Chatroom not found".into())
+    }
+
+    fn create_new_friend_request(&self, sender_id: u32, receiver_id: u32) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "INSERT INTO friend_requests (Sender_Id, Receiver_Id, Accepted) VALUES (:sender_id, :receiver_id, FALSE)",
+            named_params! { ":sender_id": sender_id, ":receiver_id": receiver_id },
+        )?;
+        Ok(())
+    }
+
+    fn create_friends(&self, user1_id: u32, user2_id: u32) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "INSERT INTO friends (User1_Id, User2_Id) VALUES (:user1_id, :user2_id)",
+            named_params! { ":user1_id": user1_id, ":user2_id": user2_id },
+        )?;
+        Ok(())
+    }
+
+    fn get_friend_requests_by_user_id(&self, user_id: u32) -> StorageResult<Vec<FriendRequest>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            r"SELECT fr.Sender_Id, fr.Receiver_Id, fr.Accepted, u.UserName
+            FROM friend_requests fr
+            INNER JOIN users u ON fr.Sender_Id = u.Id AND fr.Accepted = FALSE AND fr.Receiver_Id = :user_id",
+        )?;
+        let friend_requests = statement
+            .query_map(named_params! { ":user_id": user_id }, |row| {
+                Ok(FriendRequest::new(
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<FriendRequest>, _>>()?;
+        Ok(friend_requests)
+    }
+
+    fn accept_friend_request_for_user(
+        &self,
+        sender_id: u32,
+        receiver_id: u32,
+    ) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE friend_requests SET Accepted = TRUE WHERE Sender_Id = :sender_id AND Receiver_Id = :receiver_id",
+            named_params! { ":sender_id": sender_id, ":receiver_id": receiver_id },
+        )?;
+        Ok(())
+    }
+
+    fn delete_friend_request(&self, sender_id: u32, receiver_id: u32) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "DELETE FROM friend_requests WHERE Sender_Id = :sender_id AND Receiver_Id = :receiver_id",
+            named_params! { ":sender_id": sender_id, ":receiver_id": receiver_id },
+        )?;
+        Ok(())
+    }
+
+    fn get_friends_of_user(&self, user_id: u32) -> StorageResult<Vec<User>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            r"SELECT users.Id, users.UserName
+            FROM users
+            JOIN friends
+            ON (users.Id = friends.User1_Id AND friends.User2_Id = :user_id)
+            OR (users.Id = friends.User2_Id AND friends.User1_Id = :user_id)
+            ORDER BY users.UserName",
+        )?;
+        let users = statement
+            .query_map(named_params! { ":user_id": user_id }, |row| {
+                Ok(User::new(row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<User>, _>>()?;
+        Ok(users)
+    }
+
+    fn check_if_two_users_are_friends(&self, user1_id: u32, user2_id: u32) -> StorageResult<bool> {
+        let conn = self.get_dbconn()?;
+        let are_friends = conn.query_row(
+            r"SELECT EXISTS(SELECT 1 FROM friends WHERE (User1_Id = :user1_id AND User2_Id = :user2_id) OR (User1_Id = :user2_id AND User2_Id = :user1_id))",
+            named_params! { ":user1_id": user1_id, ":user2_id": user2_id },
+            |row| row.get(0),
+        )?;
+        Ok(are_friends)
+    }
+
+    fn save_chat_message_to_database(
+        &self,
+        chatroom_id: u32,
+        sender_id: u32,
+        content: &str,
+        sent_at: u64,
+    ) -> StorageResult<ChatMessage> {
+        let message_id = {
+            let conn = self.get_dbconn()?;
+            conn.execute(
+                r"INSERT INTO chat_messages (Chat_Id, Sender_Id, Message, SentAt, RoomMessageId)
+                SELECT :chatroom_id, :sender_id, :message, :sent_at, COALESCE(MAX(RoomMessageId), 0) + 1
+                FROM chat_messages WHERE Chat_Id = :chatroom_id",
+                named_params! {
+                    ":chatroom_id": chatroom_id,
+                    ":sender_id": sender_id,
+                    ":message": content,
+                    ":sent_at": sent_at,
+                },
+            )?;
+            conn.last_insert_rowid() as u32
+        };
+        self.get_chat_message_by_id(message_id)?
+            .ok_or_else(|| "The saved message could not be found".into())
+    }
+
+    fn get_chat_messages_before(
+        &self,
+        chatroom_id: u32,
+        before_message_id: Option<u32>,
+        limit: u32,
+    ) -> StorageResult<Vec<ChatMessage>> {
+        let mut messages = self.query_chat_messages(
+            r"WHERE chat_messages.Chat_Id = :chatroom_id AND (:before_id IS NULL OR chat_messages.Id < :before_id)
+            ORDER BY chat_messages.Id DESC LIMIT :limit",
+            named_params! {
+                ":chatroom_id": chatroom_id,
+                ":before_id": before_message_id,
+                ":limit": limit,
+            },
+        )?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    fn get_chat_message_by_id(&self, message_id: u32) -> StorageResult<Option<ChatMessage>> {
+        let messages = self.query_chat_messages(
+            "WHERE chat_messages.Id = :message_id",
+            named_params! { ":message_id": message_id },
+        )?;
+        Ok(messages.into_iter().next())
+    }
+
+    fn edit_chat_message(
+        &self,
+        message_id: u32,
+        sender_id: u32,
+        sent_after: u64,
+        content: &str,
+        edited_at: u64,
+    ) -> StorageResult<Option<ChatMessage>> {
+        {
+            let mut conn = self.get_dbconn()?;
+            // The old content is only kept if the new one was saved
+            let transaction = conn.transaction()?;
+            let condition = named_params! {
+                ":message_id": message_id,
+                ":sender_id": sender_id,
+                ":sent_after": sent_after,
+            };
+            let old_content: Option<String> = transaction
+                .query_row(
+                    &format!(
+                        "SELECT Message FROM chat_messages WHERE {}",
+                        storage::CHANGEABLE_MESSAGE
+                    ),
+                    condition,
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(old_content) = old_content else {
+                return Ok(None);
+            };
+            transaction.execute(
+                "INSERT INTO chat_message_edits (Message_Id, Message, EditedAt) VALUES (?1, ?2, ?3)",
+                rusqlite::params![message_id, old_content, edited_at],
+            )?;
+            let changed_rows = transaction.execute(
+                &format!(
+                    "UPDATE chat_messages SET Message = :message, EditedAt = :edited_at WHERE {}",
+                    storage::CHANGEABLE_MESSAGE
+                ),
+                named_params! {
+                    ":message_id": message_id,
+                    ":sender_id": sender_id,
+                    ":sent_after": sent_after,
+                    ":message": content,
+                    ":edited_at": edited_at,
+                },
+            )?;
+            if changed_rows != 1 {
+                return Ok(None);
+            }
+            transaction.commit()?;
+        }
+        self.get_chat_message_by_id(message_id)
+    }
+
+    fn delete_chat_message(
+        &self,
+        message_id: u32,
+        sender_id: u32,
+        sent_after: u64,
+        deleted_at: u64,
+    ) -> StorageResult<Option<ChatMessage>> {
+        {
+            let mut conn = self.get_dbconn()?;
+            let transaction = conn.transaction()?;
+            let changed_rows = transaction.execute(
+                &format!(
+                    "UPDATE chat_messages SET Message = '', DeletedAt = :deleted_at WHERE {}",
+                    storage::CHANGEABLE_MESSAGE
+                ),
+                named_params! {
+                    ":message_id": message_id,
+                    ":sender_id": sender_id,
+                    ":sent_after": sent_after,
+                    ":deleted_at": deleted_at,
+                },
+            )?;
+            if changed_rows != 1 {
+                return Ok(None);
+            }
+            transaction.execute(
+                "DELETE FROM chat_message_edits WHERE Message_Id = ?1",
+                [message_id],
+            )?;
+            transaction.commit()?;
+        }
+        self.get_chat_message_by_id(message_id)
+    }
+
+    fn get_chat_message_edits(&self, message_id: u32) -> StorageResult<Vec<MessageEdit>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            "SELECT Message, EditedAt FROM chat_message_edits WHERE Message_Id = ?1 ORDER BY Id",
+        )?;
+        let edits = statement
+            .query_map([message_id], |row| {
+                Ok(MessageEdit::new(row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<MessageEdit>, _>>()?;
+        Ok(edits)
+    }
+
+    fn save_message_receipt(
+        &self,
+        message_id: u32,
+        user_id: u32,
+        status: ReceiptStatus,
+    ) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "INSERT INTO message_receipts (Message_Id, User_Id, Status) VALUES (:message_id, :user_id, :status)
+             ON CONFLICT (Message_Id, User_Id) DO UPDATE SET Status = excluded.Status WHERE Status != 'read'",
+            named_params! { ":message_id": message_id, ":user_id": user_id, ":status": status.as_str() },
+        )?;
+        Ok(())
+    }
+
+    fn get_message_receipts(&self, message_id: u32) -> StorageResult<Vec<(u32, ReceiptStatus)>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            "SELECT User_Id, Status FROM message_receipts WHERE Message_Id = :message_id ORDER BY User_Id",
+        )?;
+        let receipts = statement
+            .query_map(named_params! { ":message_id": message_id }, |row| {
+                let status: String = row.get(1)?;
+                Ok((row.get(0)?, ReceiptStatus::from_db(&status)))
+            })?
+            .collect::<Result<Vec<(u32, ReceiptStatus)>, _>>()?;
+        Ok(receipts)
+    }
+
+    fn set_last_read_message(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+        message_id: u32,
+    ) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE chat_members SET LastReadMessageId = MAX(LastReadMessageId, :message_id) WHERE Chat_Id = :chatroom_id AND User_Id = :user_id",
+            named_params! { ":message_id": message_id, ":chatroom_id": chatroom_id, ":user_id": user_id },
+        )?;
+        Ok(())
+    }
+
+    fn get_unread_counts(&self, user_id: u32) -> StorageResult<Vec<(u32, u32)>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            "SELECT chat_messages.Chat_Id, COUNT(*) FROM chat_messages
+             JOIN chat_members ON chat_members.Chat_Id = chat_messages.Chat_Id AND chat_members.User_Id = :user_id
+             WHERE chat_messages.Id > chat_members.LastReadMessageId AND chat_messages.Sender_Id IS NOT :user_id
+             GROUP BY chat_messages.Chat_Id ORDER BY chat_messages.Chat_Id",
+        )?;
+        let counts = statement
+            .query_map(named_params! { ":user_id": user_id }, |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<Vec<(u32, u32)>, _>>()?;
+        Ok(counts)
+    }
+
+    fn get_undelivered_messages(&self, user_id: u32) -> StorageResult<Vec<ChatMessage>> {
+        self.query_chat_messages(
+            r"JOIN chat_members ON chat_members.Chat_Id = chat_messages.Chat_Id AND chat_members.User_Id = :user_id
+            WHERE chat_messages.Id > chat_members.LastReadMessageId AND chat_messages.Sender_Id IS NOT :user_id
+            AND NOT EXISTS (SELECT 1 FROM message_receipts WHERE message_receipts.Message_Id = chat_messages.Id AND message_receipts.User_Id = :user_id)
+            ORDER BY chat_messages.Id",
+            named_params! { ":user_id": user_id },
+        )
+    }
+
+    fn get_message_receipts_for_messages(
+        &self,
+        chatroom_id: u32,
+        first_message_id: u32,
+        last_message_id: u32,
+    ) -> StorageResult<Vec<(u32, u32, ReceiptStatus)>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            "SELECT message_receipts.Message_Id, message_receipts.User_Id, message_receipts.Status FROM message_receipts
+             JOIN chat_messages ON chat_messages.Id = message_receipts.Message_Id
+             WHERE message_receipts.Message_Id BETWEEN :first_message_id AND :last_message_id AND chat_messages.Chat_Id = :chatroom_id
+             ORDER BY message_receipts.Message_Id, message_receipts.User_Id",
+        )?;
+        let receipts = statement
+            .query_map(
+                named_params! {
+                    ":chatroom_id": chatroom_id,
+                    ":first_message_id": first_message_id,
+                    ":last_message_id": last_message_id,
+                },
+                |row| {
+                    let status: String = row.get(2)?;
+                    Ok((row.get(0)?, row.get(1)?, ReceiptStatus::from_db(&status)))
+                },
+            )?
+            .collect::<Result<Vec<(u32, u32, ReceiptStatus)>, _>>()?;
+        Ok(receipts)
+    }
+
+    fn create_session(
+        &self,
+        user_id: u32,
+        token_hash: &str,
+        device_name: &str,
+        expires_at: u64,
+    ) -> StorageResult<u32> {
+        let conn = self.get_dbconn()?;
+        let now = helper_functions::get_sys_time_in_secs();
+
+        // Remove sessions that are not valid anymore before adding the new one
+        conn.execute(
+            "DELETE FROM sessions WHERE ExpiresAt <= :now",
+            named_params! { ":now": now },
+        )?;
+        conn.execute(
+            r"INSERT INTO sessions (User_Id, TokenHash, DeviceName, CreatedAt, LastSeen, ExpiresAt)
+            VALUES (:user_id, :token_hash, :device_name, :now, :now, :expires_at)",
+            named_params! {
+                ":user_id": user_id,
+                ":token_hash": token_hash,
+                ":device_name": device_name,
+                ":now": now,
+                ":expires_at": expires_at,
+            },
+        )?;
+        Ok(conn.last_insert_rowid() as u32)
+    }
+
+    fn get_session_by_token_hash(&self, token_hash: &str) -> StorageResult<Option<(u32, User)>> {
+        let conn = self.get_dbconn()?;
+        let session = conn
+            .query_row(
+                r"SELECT sessions.Id, users.Id, users.UserName FROM sessions
+                JOIN users ON users.Id = sessions.User_Id
+                WHERE sessions.TokenHash = :token_hash AND sessions.ExpiresAt > :now",
+                named_params! {
+                    ":token_hash": token_hash,
+                    ":now": helper_functions::get_sys_time_in_secs(),
+                },
+                |row| Ok((row.get(0)?, User::new(row.get(1)?, row.get(2)?))),
+            )
+            .optional()?;
+        Ok(session)
+    }
+
+    fn update_session_last_seen(&self, session_id: u32) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE sessions SET LastSeen = :now WHERE Id = :session_id",
+            named_params! {
+                ":now": helper_functions::get_sys_time_in_secs(),
+                ":session_id": session_id,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn get_sessions_of_user(
+        &self,
+        user_id: u32,
+        current_session_id: u32,
+    ) -> StorageResult<Vec<SessionInfo>> {
+        let conn = self.get_dbconn()?;
+        let mut statement = conn.prepare(
+            r"SELECT Id, DeviceName, CreatedAt, LastSeen, ExpiresAt FROM sessions
+            WHERE User_Id = :user_id AND ExpiresAt > :now ORDER BY LastSeen DESC",
+        )?;
+        let sessions = statement
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                    ":now": helper_functions::get_sys_time_in_secs(),
+                },
+                |row| {
+                    let id: u32 = row.get(0)?;
+                    Ok(SessionInfo::new(
+                        id,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        id == current_session_id,
+                    ))
+                },
+            )?
+            .collect::<Result<Vec<SessionInfo>, _>>()?;
+        Ok(sessions)
+    }
+
+    fn delete_session(&self, session_id: u32, user_id: u32) -> StorageResult<bool> {
+        let conn = self.get_dbconn()?;
+        let deleted = conn.execute(
+            "DELETE FROM sessions WHERE Id = :session_id AND User_Id = :user_id",
+            named_params! { ":session_id": session_id, ":user_id": user_id },
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn restore_chat_export(
+        &self,
+        export: &ChatExport,
+        new_users: &[RestoredUser],
+        new_friendships: &[(u32, u32)],
+    ) -> StorageResult<()> {
+        let mut conn = self.get_dbconn()?;
+        // Nothing is kept if any part of the export can not be saved
+        let transaction = conn.transaction()?;
+        for new_user in new_users {
+            transaction.execute(
+                r"INSERT INTO users (Id, UserName, Password, BlockedUntil, IdentityKey, MustChangePassword)
+                VALUES (:id, :username, :password, 0, :identity_key, TRUE)",
+                named_params! {
+                    ":id": new_user.user.get_id(),
+                    ":username": new_user.user.get_name(),
+                    ":password": new_user.password_hash,
+                    ":identity_key": new_user.identity_key,
+                },
+            )?;
+        }
+        for (user1_id, user2_id) in new_friendships {
+            transaction.execute(
+                "INSERT INTO friends (User1_Id, User2_Id) VALUES (:user1_id, :user2_id)",
+                named_params! { ":user1_id": user1_id, ":user2_id": user2_id },
+            )?;
+        }
+
+        let chatroom = &export.chatroom;
+        transaction.execute(
+            "INSERT INTO chats (Id, ChatName, IsPublic) VALUES (:chatroom_id, :chatname, :is_public)",
+            named_params! {
+                ":chatroom_id": chatroom.id,
+                ":chatname": chatroom.name,
+                ":is_public": chatroom.is_public,
+            },
+        )?;
+        for member in &chatroom.members {
+            transaction.execute(
+                "INSERT INTO chat_members (Chat_Id, User_Id, Role) VALUES (:chatroom_id, :user_id, :role)",
+                named_params! {
+                    ":chatroom_id": chatroom.id,
+                    ":user_id": member.id,
+                    ":role": member.role.as_str(),
+                },
+            )?;
+        }
+        for message in &export.messages {
+            transaction.execute(
+                r"INSERT INTO chat_messages (Id, Chat_Id, Sender_Id, Message, SentAt, RoomMessageId, EditedAt, DeletedAt)
+                VALUES (:id, :chatroom_id, :sender_id, :message, :sent_at, :room_message_id, :edited_at, :deleted_at)",
+                named_params! {
+                    ":id": message.id,
+                    ":chatroom_id": chatroom.id,
+                    ":sender_id": message.sender_id,
+                    ":message": message.content,
+                    ":sent_at": message.sent_at,
+                    ":room_message_id": message.room_message_id,
+                    ":edited_at": message.edited_at,
+                    ":deleted_at": message.deleted_at,
+                },
+            )?;
+            for edit in &message.edits {
+                transaction.execute(
+                    r"INSERT INTO chat_message_edits (Message_Id, Message, EditedAt)
+                    VALUES (:message_id, :message, :edited_at)",
+                    named_params! {
+                        ":message_id": message.id,
+                        ":message": edit.get_content(),
+                        ":edited_at": edit.get_replaced_at(),
+                    },
+                )?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn check_if_password_change_required(&self, username: &str) -> StorageResult<bool> {
+        let conn = self.get_dbconn()?;
+        let result: Option<bool> = conn
+            .query_row(
+                "SELECT MustChangePassword FROM users WHERE UserName = :username",
+                named_params! { ":username": username },
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(result.unwrap_or(false))
+    }
+
+    fn change_required_password(&self, username: &str, password_hash: &str) -> StorageResult<()> {
+        let conn = self.get_dbconn()?;
+        conn.execute(
+            "UPDATE users SET Password = :password, MustChangePassword = FALSE WHERE UserName = :username",
+            named_params! { ":password": password_hash, ":username": username },
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Creates a database with the users anton, rino and antonia, where anton and rino are friends
+    fn get_test_storage() -> SqliteStorage {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        for name in ["anton", "rino", "antonia"] {
+            storage
+                .save_new_user_to_database_after_signup(name, "password")
+                .unwrap();
+        }
+        storage.create_friends(1, 2).unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_check_if_username_exists() {
+        let storage = get_test_storage();
+        assert!(storage.check_if_username_exists("rino").unwrap());
+        assert!(!storage
+            .check_if_username_exists("KeinGültigerUsername")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_update_password_hash_of_user() {
+        let storage = get_test_storage();
+        storage
+            .update_password_hash_of_user("rino", "new password")
+            .unwrap();
+        assert_eq!(
+            storage.get_password_hash_of_user("rino").unwrap(),
+            Some("new password".to_string())
+        );
+        assert_eq!(storage.get_password_hash_of_user("nobody").unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_user_from_database_by_name() {
+        let storage = get_test_storage();
+        let user = storage.get_user_from_database_by_name("rino").unwrap();
+        assert_eq!(user, User::new(2, "rino".to_string()));
+        assert!(storage.get_user_from_database_by_name("nobody").is_err());
+    }
+
+    #[test]
+    fn test_set_user_isblocked() {
+        let storage = get_test_storage();
+        assert!(!storage.check_if_user_isblocked("rino").unwrap());
+        storage.set_user_isblocked("rino", 35).unwrap();
+        assert!(storage.check_if_user_isblocked("rino").unwrap());
+        assert!(
+            storage.get_user_is_blockeduntil("rino").unwrap()
+                > helper_functions::get_sys_time_in_secs()
+        );
+        assert!(!storage.check_if_user_isblocked("nobody").unwrap());
+    }
+
+    #[test]
+    fn test_identity_key() {
+        let storage = get_test_storage();
+        assert_eq!(storage.get_identity_key(2).unwrap(), None);
+        storage.set_identity_key(2, "abc").unwrap();
+        assert_eq!(
+            storage.get_identity_key(2).unwrap(),
+            Some("abc".to_string())
+        );
+        storage.set_identity_key(2, "def").unwrap();
+        assert_eq!(
+            storage.get_identity_key(2).unwrap(),
+            Some("def".to_string())
+        );
+        assert_eq!(storage.get_identity_key(99).unwrap(), None);
+    }
+
+    #[test]
+    fn test_last_seen() {
+        let storage = get_test_storage();
+        assert_eq!(storage.get_last_seen(2).unwrap(), 0);
+        storage.set_last_seen(2, 1000).unwrap();
+        assert_eq!(storage.get_last_seen(2).unwrap(), 1000);
+        assert_eq!(storage.get_last_seen(99).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_user_by_name_with_contains_search() {
+        let storage = get_test_storage();
+        // anton is already a friend of rino, so only antonia is found
+        let users = storage
+            .get_user_by_name_with_contains_search("anton", 2)
+            .unwrap();
+        assert_eq!(users, vec![User::new(3, "antonia".to_string())]);
+    }
+
+    #[test]
+    fn test_friend_requests() {
+        let storage = get_test_storage();
+        storage.create_new_friend_request(3, 2).unwrap();
+        let requests = storage.get_friend_requests_by_user_id(2).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].get_sender_id(), 3);
+
+        storage.accept_friend_request_for_user(3, 2).unwrap();
+        assert!(storage
+            .get_friend_requests_by_user_id(2)
+            .unwrap()
+            .is_empty());
+
+        storage.create_new_friend_request(2, 1).unwrap();
+        storage.delete_friend_request(2, 1).unwrap();
+        assert!(storage
+            .get_friend_requests_by_user_id(1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_if_two_users_are_friends() {
+        let storage = get_test_storage();
+        assert!(storage.check_if_two_users_are_friends(2, 1).unwrap());
+        assert!(!storage.check_if_two_users_are_friends(1, 3).unwrap());
+    }
+
+    #[test]
+    fn test_get_friends_of_user() {
+        let storage = get_test_storage();
+        storage.create_friends(3, 2).unwrap();
+        assert_eq!(
+            storage.get_friends_of_user(2).unwrap(),
+            vec![
+                User::new(1, "anton".to_string()),
+                User::new(3, "antonia".to_string())
+            ]
+        );
+        assert!(storage.get_friends_of_user(99).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_chatrooms() {
+        let storage = get_test_storage();
+        let chatroom = storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        assert_eq!(*chatroom.get_name(), "TestChat1".to_string());
+        assert_eq!(chatroom.get_members()[1], User::new(2, "rino".to_string()));
+
+        assert_eq!(storage.search_for_chatrooms_of_user(2).unwrap().len(), 1);
+        assert!(storage.search_for_chatrooms_of_user(3).unwrap().is_empty());
+        assert_eq!(
+            storage
+                .get_chatroom_by_id(*chatroom.get_id())
+                .unwrap()
+                .get_member_ids(),
+            vec![1, 2]
+        );
+        assert!(storage.get_chatroom_by_id(999999).is_err());
+    }
+
+    #[test]
+    fn test_group_chatroom() {
+        let storage = get_test_storage();
+        storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        let group = storage.create_new_chatroom(&[2, 1, 3], "Group").unwrap();
+        assert_eq!(group.get_member_ids(), vec![1, 2, 3]);
+
+        let chatrooms = storage.search_for_chatrooms_of_user(3).unwrap();
+        assert_eq!(chatrooms.len(), 1);
+        assert_eq!(chatrooms[0].get_member_ids(), vec![1, 2, 3]);
+        assert_eq!(storage.search_for_chatrooms_of_user(1).unwrap().len(), 2);
+
+        // A chatroom with an unknown member is not created at all
+        assert!(storage.create_new_chatroom(&[1, 99], "Broken").is_err());
+        assert_eq!(storage.search_for_chatrooms_of_user(1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_public_chatrooms() {
+        let storage = get_test_storage();
+        storage
+            .create_new_chatroom(&[1, 2], "Lobby of friends")
+            .unwrap();
+        let lobby = storage
+            .create_public_chatroom(1, "Lobby", None)
+            .unwrap()
+            .unwrap();
+        let secret = storage
+            .create_public_chatroom(2, "Secret lobby", Some("hash"))
+            .unwrap()
+            .unwrap();
+        // Names of public rooms are unique, other chatrooms may have the same name
+        assert!(storage
+            .create_public_chatroom(3, "Lobby", None)
+            .unwrap()
+            .is_none());
+        assert!(storage.create_new_chatroom(&[1, 3], "Lobby").is_ok());
+        assert!(lobby.is_public());
+        assert!(!lobby.has_password());
+        assert!(secret.has_password());
+        assert_eq!(secret.get_member_ids(), vec![2]);
+
+        assert!(storage
+            .check_if_public_chatroom_name_exists("Lobby")
+            .unwrap());
+        assert!(!storage
+            .check_if_public_chatroom_name_exists("Lobby of friends")
+            .unwrap());
+
+        // Only public rooms the user is no member of are found
+        let found = storage.search_public_chatrooms("lobby", 1).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].get_name(), "Secret lobby".to_string());
+        assert_eq!(
+            storage.search_public_chatrooms("lobby", 3).unwrap().len(),
+            2
+        );
+
+        assert_eq!(
+            storage
+                .get_chatroom_password_hash(*secret.get_id())
+                .unwrap(),
+            Some("hash".to_string())
+        );
+        assert_eq!(
+            storage.get_chatroom_password_hash(*lobby.get_id()).unwrap(),
+            None
+        );
+
+        assert!(storage
+            .join_public_chatroom(*secret.get_id(), 3, 10)
+            .unwrap());
+        assert_eq!(
+            storage
+                .get_chatroom_by_id(*secret.get_id())
+                .unwrap()
+                .get_member_ids(),
+            vec![2, 3]
+        );
+        assert!(storage
+            .join_public_chatroom(*secret.get_id(), 3, 10)
+            .is_err());
+        // Full rooms get no new members
+        assert!(!storage
+            .join_public_chatroom(*secret.get_id(), 4, 2)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_chat_member_roles_and_bans() {
+        let storage = get_test_storage();
+        let lobby = storage
+            .create_public_chatroom(1, "Lobby", None)
+            .unwrap()
+            .unwrap();
+        let chatroom_id = *lobby.get_id();
+        assert!(storage.join_public_chatroom(chatroom_id, 2, 10).unwrap());
+
+        assert_eq!(
+            storage.get_chat_member_role(chatroom_id, 1).unwrap(),
+            Some(RoomRole::Owner)
+        );
+        assert_eq!(
+            storage.get_chat_member_role(chatroom_id, 2).unwrap(),
+            Some(RoomRole::Member)
+        );
+        assert_eq!(storage.get_chat_member_role(chatroom_id, 3).unwrap(), None);
+        storage
+            .set_chat_member_role(chatroom_id, 2, RoomRole::Moderator)
+            .unwrap();
+        assert_eq!(
+            storage.get_chat_member_role(chatroom_id, 2).unwrap(),
+            Some(RoomRole::Moderator)
+        );
+
+        assert_eq!(
+            storage.get_chat_member_muted_until(chatroom_id, 2).unwrap(),
+            0
+        );
+        storage.mute_chat_member(chatroom_id, 2, 60).unwrap();
+        assert!(
+            storage.get_chat_member_muted_until(chatroom_id, 2).unwrap()
+                > helper_functions::get_sys_time_in_secs()
+        );
+
+        assert!(storage.join_public_chatroom(chatroom_id, 3, 10).unwrap());
+        storage.remove_chat_member(chatroom_id, 2).unwrap();
+        storage.ban_chat_member(chatroom_id, 3).unwrap();
+        assert_eq!(
+            storage
+                .get_chatroom_by_id(chatroom_id)
+                .unwrap()
+                .get_member_ids(),
+            vec![1]
+        );
+        assert!(!storage
+            .check_if_banned_from_chatroom(chatroom_id, 2)
+            .unwrap());
+        assert!(storage
+            .check_if_banned_from_chatroom(chatroom_id, 3)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_chat_messages() {
+        let storage = get_test_storage();
+        let chatroom = storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        storage
+            .save_chat_message_to_database(*chatroom.get_id(), 2, "Hallo, Anton!", 1000)
+            .unwrap();
+        storage
+            .save_chat_message_to_database(*chatroom.get_id(), 1, "Guten Morgen, Rino!", 1000)
+            .unwrap();
+
+        let messages = storage
+            .get_chat_messages_before(*chatroom.get_id(), None, 10)
+            .unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                ChatMessage::from_database(
+                    1,
+                    1,
+                    1,
+                    Some((2, "rino".to_string())),
+                    1000,
+                    "Hallo, Anton!".to_string()
+                ),
+                ChatMessage::from_database(
+                    2,
+                    2,
+                    1,
+                    Some((1, "anton".to_string())),
+                    1000,
+                    "Guten Morgen, Rino!".to_string()
+                )
+            ]
+        );
+
+        // Every chatroom counts its messages on its own
+        let other_chatroom = storage.create_new_chatroom(&[1, 3], "TestChat2").unwrap();
+        let message = storage
+            .save_chat_message_to_database(*other_chatroom.get_id(), 3, "Hallo!", 2000)
+            .unwrap();
+        assert_eq!(message.get_id(), Some(3));
+        assert_eq!(
+            message,
+            ChatMessage::from_database(
+                3,
+                1,
+                *other_chatroom.get_id(),
+                Some((3, "antonia".to_string())),
+                2000,
+                "Hallo!".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_save_concurrent_chat_messages() {
+        let storage = Arc::new(get_test_storage());
+        let chatroom_id = *storage
+            .create_new_chatroom(&[1, 2], "TestChat1")
+            .unwrap()
+            .get_id();
+        let threads = (0..8)
+            .map(|i| {
+                let storage = Arc::clone(&storage);
+                std::thread::spawn(move || {
+                    storage
+                        .save_chat_message_to_database(chatroom_id, 1 + i % 2, "Hallo!", 1000)
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut numbers = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap().get_room_message_id().unwrap())
+            .collect::<Vec<u32>>();
+        numbers.sort();
+        assert_eq!(numbers, (1..=8).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_chat_messages_before() {
+        let storage = get_test_storage();
+        let chatroom = storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        let chatroom_id = *chatroom.get_id();
+        for content in ["Eins", "Zwei", "Drei", "Vier", "Fünf"] {
+            storage
+                .save_chat_message_to_database(chatroom_id, 1, content, 1000)
+                .unwrap();
+        }
+        let get_contents = |before_message_id, limit| -> Vec<String> {
+            storage
+                .get_chat_messages_before(chatroom_id, before_message_id, limit)
+                .unwrap()
+                .iter()
+                .map(|message| message.get_content())
+                .collect()
+        };
+
+        assert_eq!(get_contents(None, 2), vec!["Vier", "Fünf"]);
+        assert_eq!(get_contents(Some(4), 2), vec!["Zwei", "Drei"]);
+        assert_eq!(get_contents(Some(2), 2), vec!["Eins"]);
+        assert!(get_contents(Some(1), 2).is_empty());
+        assert!(storage
+            .get_chat_messages_before(99, None, 2)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_unread_messages() {
+        let storage = get_test_storage();
+        let chatroom = storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        let chatroom_id = *chatroom.get_id();
+        let first_id = storage
+            .save_chat_message_to_database(chatroom_id, 2, "Hallo, Anton!", 1000)
+            .unwrap()
+            .get_id()
+            .unwrap();
+        let second_id = storage
+            .save_chat_message_to_database(chatroom_id, 2, "Bist du da?", 1000)
+            .unwrap()
+            .get_id()
+            .unwrap();
+        storage
+            .save_chat_message_to_database(chatroom_id, 1, "Ja!", 1000)
+            .unwrap();
+
+        // Own messages are never unread
+        assert_eq!(
+            storage.get_unread_counts(1).unwrap(),
+            vec![(chatroom_id, 2)]
+        );
+        assert_eq!(
+            storage.get_unread_counts(2).unwrap(),
+            vec![(chatroom_id, 1)]
+        );
+        assert_eq!(storage.get_unread_counts(3).unwrap(), vec![]);
+
+        storage
+            .save_message_receipt(first_id, 1, ReceiptStatus::Delivered)
+            .unwrap();
+        let undelivered = storage.get_undelivered_messages(1).unwrap();
+        assert_eq!(undelivered.len(), 1);
+        assert_eq!(undelivered[0].get_id(), Some(second_id));
+
+        storage
+            .set_last_read_message(chatroom_id, 1, second_id)
+            .unwrap();
+        storage
+            .set_last_read_message(chatroom_id, 1, first_id)
+            .unwrap();
+        assert_eq!(storage.get_unread_counts(1).unwrap(), vec![]);
+        assert!(storage.get_undelivered_messages(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_message_receipts() {
+        let storage = get_test_storage();
+        let chatroom = storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        let chatroom_id = *chatroom.get_id();
+        let first_id = storage
+            .save_chat_message_to_database(chatroom_id, 2, "Hallo, Anton!", 1000)
+            .unwrap()
+            .get_id()
+            .unwrap();
+        let second_id = storage
+            .save_chat_message_to_database(chatroom_id, 2, "Bist du da?", 1000)
+            .unwrap()
+            .get_id()
+            .unwrap();
+        assert_eq!(second_id, first_id + 1);
+        let message = storage.get_chat_message_by_id(second_id).unwrap().unwrap();
+        assert_eq!(message.get_chatroom_id(), chatroom_id);
+        assert_eq!(*message.get_sender_name(), "rino".to_string());
+        assert_eq!(storage.get_chat_message_by_id(99).unwrap(), None);
+
+        storage
+            .save_message_receipt(first_id, 2, ReceiptStatus::Delivered)
+            .unwrap();
+        storage
+            .save_message_receipt(first_id, 2, ReceiptStatus::Read)
+            .unwrap();
+        storage
+            .save_message_receipt(second_id, 2, ReceiptStatus::Delivered)
+            .unwrap();
+        // A message that was read stays read
+        storage
+            .save_message_receipt(first_id, 2, ReceiptStatus::Delivered)
+            .unwrap();
+
+        assert_eq!(
+            storage.get_message_receipts(first_id).unwrap(),
+            vec![(2, ReceiptStatus::Read)]
+        );
+        assert_eq!(
+            storage
+                .get_message_receipts_for_messages(chatroom_id, first_id, second_id)
+                .unwrap(),
+            vec![
+                (first_id, 2, ReceiptStatus::Read),
+                (second_id, 2, ReceiptStatus::Delivered)
+            ]
+        );
+        assert_eq!(
+            storage
+                .get_message_receipts_for_messages(chatroom_id, second_id, second_id)
+                .unwrap(),
+            vec![(second_id, 2, ReceiptStatus::Delivered)]
+        );
+    }
+
+    #[test]
+    fn test_edit_and_delete_chat_messages() {
+        let storage = get_test_storage();
+        let chatroom = storage.create_new_chatroom(&[1, 2], "TestChat1").unwrap();
+        let message_id = storage
+            .save_chat_message_to_database(*chatroom.get_id(), 2, "Hallo, Anton!", 1000)
+            .unwrap()
+            .get_id()
+            .unwrap();
+
+        storage
+            .edit_chat_message(message_id, 2, 900, "Hallo Anton!", 1100)
+            .unwrap()
+            .unwrap();
+        let message = storage
+            .edit_chat_message(message_id, 2, 900, "Hallo, Anton :)", 1200)
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.get_content(), "Hallo, Anton :)");
+        assert_eq!(message.get_edited_at(), Some(1200));
+        assert_eq!(message.get_deleted_at(), None);
+        assert_eq!(
+            storage.get_chat_message_edits(message_id).unwrap(),
+            vec![
+                MessageEdit::new("Hallo, Anton!".to_string(), 1100),
+                MessageEdit::new("Hallo Anton!".to_string(), 1200)
+            ]
+        );
+
+        // Nothing is written for another sender or a message sent before the edit window
+        assert!(storage
+            .edit_chat_message(message_id, 1, 900, "Hallo?", 1250)
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .delete_chat_message(message_id, 2, 1001, 1250)
+            .unwrap()
+            .is_none());
+        assert_eq!(storage.get_chat_message_edits(message_id).unwrap().len(), 2);
+
+        // Only a tombstone without any of the contents is left
+        let message = storage
+            .delete_chat_message(message_id, 2, 900, 1300)
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.get_content(), "");
+        assert_eq!(message.get_deleted_at(), Some(1300));
+        assert!(storage
+            .get_chat_message_edits(message_id)
+            .unwrap()
+            .is_empty());
+        // Deleted messages can not be changed anymore
+        assert!(storage
+            .edit_chat_message(message_id, 2, 900, "Hallo!", 1400)
+            .unwrap()
+            .is_none());
+        let messages = storage
+            .get_chat_messages_before(*chatroom.get_id(), None, 10)
+            .unwrap();
+        assert_eq!(messages, vec![message]);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let storage = get_test_storage();
+        let expires_at = helper_functions::get_sys_time_in_secs() + 60;
+        let session_id = storage
+            .create_session(2, "hash", "laptop", expires_at)
+            .unwrap();
+        storage
+            .create_session(2, "expired hash", "phone", 1)
+            .unwrap();
+
+        let (id, user) = storage.get_session_by_token_hash("hash").unwrap().unwrap();
+        assert_eq!(id, session_id);
+        assert_eq!(user, User::new(2, "rino".to_string()));
+        assert!(storage
+            .get_session_by_token_hash("expired hash")
+            .unwrap()
+            .is_none());
+
+        let sessions = storage.get_sessions_of_user(2, session_id).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].is_current());
+
+        // Sessions of other users can not be deleted
+        assert!(!storage.delete_session(session_id, 1).unwrap());
+        assert!(storage.delete_session(session_id, 2).unwrap());
+        assert!(storage.get_session_by_token_hash("hash").unwrap().is_none());
+    }
+}
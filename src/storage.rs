@@ -0,0 +1,466 @@
+use crate::chat_export::{ChatExport, RestoredUser};
+use crate::migrations::Migration;
+use crate::sql_interaction::MySqlStorage;
+use crate::sqlite_storage::SqliteStorage;
+use crate::structs::chat_room::ChatRoom;
+use crate::structs::friend_request::FriendRequest;
+use crate::structs::message::{ChatMessage, MessageEdit};
+use crate::structs::receipt::ReceiptStatus;
+use crate::structs::room_role::RoomRole;
+use crate::structs::session_info::SessionInfo;
+use crate::structs::user::User;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Database that is used if no other database is configured
+/// <br>A SQLite file in the working directory, so that no database credentials are built into the program
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://chat.db";
+
+pub type StorageResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Everything the server saves permanently
+/// <br>Implemented by every database backend, the server only talks to the database through this trait
+pub trait Storage: Send + Sync {
+    ///Returns the version of the database schema, 0 if no migration was applied yet
+    fn get_schema_version(&self) -> StorageResult<u32>;
+
+    ///Changes the database schema by the migration and saves the new version
+    fn apply_migration(&self, migration: &Migration) -> StorageResult<()>;
+
+    ///Returns true if a username exists in database
+    fn check_if_username_exists(&self, name: &str) -> StorageResult<bool>;
+
+    ///Returns the password hash that is saved for the user with the username
+    /// <br>Returns None if there is no user with the username
+    fn get_password_hash_of_user(&self, username: &str) -> StorageResult<Option<String>>;
+
+    ///Replaces the password hash of the user with the username
+    fn update_password_hash_of_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> StorageResult<()>;
+
+    ///Saves a new user with the username und the password that is hashed already
+    fn save_new_user_to_database_after_signup(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> StorageResult<()>;
+
+    ///Returns the user with the username
+    fn get_user_from_database_by_name(&self, username: &str) -> StorageResult<User>;
+
+    ///Blocks user for a certain amount of time
+    fn set_user_isblocked(&self, username: &str, duration: u64) -> StorageResult<()>;
+
+    ///Returns until when a user is blocked, 0 if the user is unknown
+    fn get_user_is_blockeduntil(&self, username: &str) -> StorageResult<u64>;
+
+    ///Returns if a user is currently blocked or not
+    fn check_if_user_isblocked(&self, username: &str) -> StorageResult<bool>;
+
+    ///Saves when the user was last active
+    fn set_last_seen(&self, user_id: u32, last_seen: u64) -> StorageResult<()>;
+
+    ///Returns when the user was last active, 0 if the user was never seen
+    fn get_last_seen(&self, user_id: u32) -> StorageResult<u64>;
+
+    ///Saves the public identity key of the user, replaces a key that was saved before
+    fn set_identity_key(&self, user_id: u32, public_key: &str) -> StorageResult<()>;
+
+    ///Returns the public identity key of the user, None if the user did not publish a key yet
+    fn get_identity_key(&self, user_id: u32) -> StorageResult<Option<String>>;
+
+    ///Searches for users that match the search string and are no friends of the current user
+    fn get_user_by_name_with_contains_search(
+        &self,
+        search_string: &str,
+        current_user_id: u32,
+    ) -> StorageResult<Vec<User>>;
+
+    ///Returns all chatrooms the user is a member of
+    fn search_for_chatrooms_of_user(&self, user_id: u32) -> StorageResult<Vec<ChatRoom>>;
+
+    ///Creates a chatroom with the users as members and returns it
+    fn create_new_chatroom(
+        &self,
+        member_ids: &[u32],
+        chatroom_name: &str,
+    ) -> StorageResult<ChatRoom>;
+
+    ///Creates a public room with the user as its first member and returns it
+    /// <br>`password_hash` is the bcrypt hash of the room password, None for rooms without password.
+    /// Returns None if there is a public room with the name already, the names are unique in the database
+    fn create_public_chatroom(
+        &self,
+        creator_id: u32,
+        chatroom_name: &str,
+        password_hash: Option<&str>,
+    ) -> StorageResult<Option<ChatRoom>>;
+
+    ///Returns true if there is a public room with the name
+    fn check_if_public_chatroom_name_exists(&self, chatroom_name: &str) -> StorageResult<bool>;
+
+    ///Searches for public rooms that match the search string and the user is no member of yet
+    fn search_public_chatrooms(
+        &self,
+        search_string: &str,
+        current_user_id: u32,
+    ) -> StorageResult<Vec<ChatRoom>>;
+
+    ///Returns the password hash of the chatroom, None if the chatroom has no password
+    fn get_chatroom_password_hash(&self, chatroom_id: u32) -> StorageResult<Option<String>>;
+
+    ///Adds the user to the members of the public room, if it has less than `max_members` members
+    /// <br>Returns false if the room is full. The members are counted in the same transaction,
+    /// so that users who join at the same time can not take the same place
+    fn join_public_chatroom(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+        max_members: u32,
+    ) -> StorageResult<bool>;
+
+    ///Returns the role of the user in the chatroom, None if the user is no member
+    fn get_chat_member_role(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+    ) -> StorageResult<Option<RoomRole>>;
+
+    ///Changes the role of a member of the chatroom
+    fn set_chat_member_role(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+        role: RoomRole,
+    ) -> StorageResult<()>;
+
+    ///Removes the user from the members of the chatroom
+    fn remove_chat_member(&self, chatroom_id: u32, user_id: u32) -> StorageResult<()>;
+
+    ///Removes the user from the members of the chatroom and saves that the user must not join it again
+    fn ban_chat_member(&self, chatroom_id: u32, user_id: u32) -> StorageResult<()>;
+
+    ///Returns true if the user was banned from the chatroom
+    fn check_if_banned_from_chatroom(&self, chatroom_id: u32, user_id: u32) -> StorageResult<bool>;
+
+    ///Mutes a member of the chatroom for a certain amount of seconds
+    fn mute_chat_member(&self, chatroom_id: u32, user_id: u32, duration: u64) -> StorageResult<()>;
+
+    ///Returns until when a member of the chatroom is muted, 0 if the member was never muted
+    fn get_chat_member_muted_until(&self, chatroom_id: u32, user_id: u32) -> StorageResult<u64>;
+
+    ///Returns the chatroom with the id
+    fn get_chatroom_by_id(&self, chatroom_id: u32) -> StorageResult<ChatRoom>;
+
+    ///Saves a friend request that is not accepted yet
+    fn create_new_friend_request(&self, sender_id: u32, receiver_id: u32) -> StorageResult<()>;
+
+    ///Saves that the two users are friends
+    fn create_friends(&self, user1_id: u32, user2_id: u32) -> StorageResult<()>;
+
+    ///Returns all friend requests to the user that are not accepted yet
+    fn get_friend_requests_by_user_id(&self, user_id: u32) -> StorageResult<Vec<FriendRequest>>;
+
+    ///Marks the friend request as accepted
+    fn accept_friend_request_for_user(&self, sender_id: u32, receiver_id: u32)
+        -> StorageResult<()>;
+
+    ///Deletes the friend request
+    fn delete_friend_request(&self, sender_id: u32, receiver_id: u32) -> StorageResult<()>;
+
+    ///Returns all friends of the user, ordered by name
+    fn get_friends_of_user(&self, user_id: u32) -> StorageResult<Vec<User>>;
+
+    ///Returns true if the two users are friends
+    fn check_if_two_users_are_friends(&self, user1_id: u32, user2_id: u32) -> StorageResult<bool>;
+
+    ///Saves a message the user sent to a chatroom, it gets the next number of the chatroom
+    /// <br>Returns the saved message with its id, number and the time it was sent at
+    fn save_chat_message_to_database(
+        &self,
+        chatroom_id: u32,
+        sender_id: u32,
+        content: &str,
+        sent_at: u64,
+    ) -> StorageResult<ChatMessage>;
+
+    ///Returns up to `limit` messages of a chatroom that were written before the message with the id, in the order they were written
+    /// <br>Without a message id the newest messages are returned
+    fn get_chat_messages_before(
+        &self,
+        chatroom_id: u32,
+        before_message_id: Option<u32>,
+        limit: u32,
+    ) -> StorageResult<Vec<ChatMessage>>;
+
+    ///Returns the message with the id, None if there is none
+    fn get_chat_message_by_id(&self, message_id: u32) -> StorageResult<Option<ChatMessage>>;
+
+    ///Replaces the content of a message, the content it had before is kept in the edit history
+    /// <br>Only a message of the sender that was sent at `sent_after` or later and is not deleted is changed,
+    /// the condition is checked in the same statement as the change. Returns the edited message, None if nothing was changed
+    fn edit_chat_message(
+        &self,
+        message_id: u32,
+        sender_id: u32,
+        sent_after: u64,
+        content: &str,
+        edited_at: u64,
+    ) -> StorageResult<Option<ChatMessage>>;
+
+    ///Marks a message as deleted and removes its content together with its edit history
+    /// <br>Only changes the message under the same condition as `edit_chat_message`, returns the deleted message, None if nothing was changed
+    fn delete_chat_message(
+        &self,
+        message_id: u32,
+        sender_id: u32,
+        sent_after: u64,
+        deleted_at: u64,
+    ) -> StorageResult<Option<ChatMessage>>;
+
+    ///Returns the contents a message had before it was edited, the oldest first
+    fn get_chat_message_edits(&self, message_id: u32) -> StorageResult<Vec<MessageEdit>>;
+
+    ///Saves that the member received or read the message
+    /// <br>A message that was read stays read
+    fn save_message_receipt(
+        &self,
+        message_id: u32,
+        user_id: u32,
+        status: ReceiptStatus,
+    ) -> StorageResult<()>;
+
+    ///Returns the receipts of the message as user id and status
+    fn get_message_receipts(&self, message_id: u32) -> StorageResult<Vec<(u32, ReceiptStatus)>>;
+
+    ///Saves that the member read the messages of the chatroom up to the message with the id
+    /// <br>The marker never goes back to an older message
+    fn set_last_read_message(
+        &self,
+        chatroom_id: u32,
+        user_id: u32,
+        message_id: u32,
+    ) -> StorageResult<()>;
+
+    ///Returns the number of unread messages of other members for every chatroom of the user as chatroom id and count
+    /// <br>Chatrooms without unread messages are left out
+    fn get_unread_counts(&self, user_id: u32) -> StorageResult<Vec<(u32, u32)>>;
+
+    ///Returns the unread messages of other members the user has no receipt for yet, in the order they were written
+    fn get_undelivered_messages(&self, user_id: u32) -> StorageResult<Vec<ChatMessage>>;
+
+    ///Returns the receipts of the messages of the chatroom with ids from first to last message id as message id, user id and status
+    /// <br>Only the receipts of one page of the history are loaded, however big the chatroom is
+    fn get_message_receipts_for_messages(
+        &self,
+        chatroom_id: u32,
+        first_message_id: u32,
+        last_message_id: u32,
+    ) -> StorageResult<Vec<(u32, u32, ReceiptStatus)>>;
+
+    ///Saves a new session of a user and returns its id
+    /// <br>Expired sessions are removed on the way
+    fn create_session(
+        &self,
+        user_id: u32,
+        token_hash: &str,
+        device_name: &str,
+        expires_at: u64,
+    ) -> StorageResult<u32>;
+
+    ///Returns the id of the session and its user if the session exists and is not expired
+    fn get_session_by_token_hash(&self, token_hash: &str) -> StorageResult<Option<(u32, User)>>;
+
+    ///Sets the time a session was last used to now
+    fn update_session_last_seen(&self, session_id: u32) -> StorageResult<()>;
+
+    ///Returns all sessions of a user that are not expired
+    /// <br>The session with the id `current_session_id` is marked as current session
+    fn get_sessions_of_user(
+        &self,
+        user_id: u32,
+        current_session_id: u32,
+    ) -> StorageResult<Vec<SessionInfo>>;
+
+    ///Deletes a session of a user
+    /// <br>Returns false if the user has no session with this id
+    fn delete_session(&self, session_id: u32, user_id: u32) -> StorageResult<bool>;
+
+    ///Saves the chatroom of an export with the ids it had, its members, messages and edit history in one transaction
+    /// <br>`new_users` are created with their one-time password and have to change it on their next login,
+    /// `new_friendships` are the friendships of the export the database does not have yet
+    fn restore_chat_export(
+        &self,
+        export: &ChatExport,
+        new_users: &[RestoredUser],
+        new_friendships: &[(u32, u32)],
+    ) -> StorageResult<()>;
+
+    ///Checks if the user still has to replace a one-time password
+    fn check_if_password_change_required(&self, username: &str) -> StorageResult<bool>;
+
+    ///Replaces the one-time password of the user with the password hash of the password the user chose
+    fn change_required_password(&self, username: &str, password_hash: &str) -> StorageResult<()>;
+}
+
+/// Opens the database the URL points to
+/// <br>Supported are `mysql://...` and `sqlite://<path>` (`sqlite::memory:` for a database that only lives in memory)
+pub fn open_storage(url: &str) -> StorageResult<Arc<dyn Storage>> {
+    if url.starts_with("mysql://") {
+        Ok(Arc::new(MySqlStorage::connect(url)?))
+    } else if url == "sqlite::memory:" {
+        Ok(Arc::new(SqliteStorage::open_in_memory()?))
+    } else if let Some(path) = url.strip_prefix("sqlite://") {
+        Ok(Arc::new(SqliteStorage::open(path)?))
+    } else {
+        Err(format!("Unsupported database URL: {}", url).into())
+    }
+}
+
+/// Runs work with the storage on the threads for blocking tasks and waits for its result
+/// <br>The database calls block, so the server never makes them on its async threads,
+/// where a slow query would hold up every other connection
+pub async fn run_blocking<T, F>(storage: &Arc<dyn Storage>, work: F) -> StorageResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn Storage) -> StorageResult<T> + Send + 'static,
+{
+    let storage = Arc::clone(storage);
+    tokio::task::spawn_blocking(move || work(storage.as_ref())).await?
+}
+
+/// Row of a query that returns chatrooms together with their members
+/// <br>Chatroom id, chatroom name, is public, has password, member id, member name
+pub type ChatMemberRow = (u32, String, bool, bool, u32, String);
+
+/// Columns of the chats and users tables a `ChatMemberRow` is read from
+pub const CHAT_MEMBER_COLUMNS: &str =
+    "chats.Id, chats.ChatName, chats.IsPublic, chats.PasswordHash IS NOT NULL, users.Id, users.UserName";
+
+/// Row of a query that returns chat messages
+/// <br>Message id, number in the chatroom, chatroom id, sender id, sender name, sent at, edited at, deleted at, content
+pub type ChatMessageRow = (
+    u32,
+    u32,
+    u32,
+    Option<u32>,
+    Option<String>,
+    u64,
+    Option<u64>,
+    Option<u64>,
+    String,
+);
+
+/// Condition on the chat_messages table for a message the sender may still change
+/// <br>Needs the named parameters `message_id`, `sender_id` and `sent_after`
+pub const CHANGEABLE_MESSAGE: &str =
+    "Id = :message_id AND Sender_Id = :sender_id AND SentAt >= :sent_after AND DeletedAt IS NULL";
+
+/// Columns of the chat_messages and users tables a `ChatMessageRow` is read from, users is joined on the sender
+pub const CHAT_MESSAGE_COLUMNS: &str = "chat_messages.Id, chat_messages.RoomMessageId, chat_messages.Chat_Id, chat_messages.Sender_Id, users.UserName, chat_messages.SentAt, chat_messages.EditedAt, chat_messages.DeletedAt, chat_messages.Message";
+
+/// Builds the message of a row, messages without a known sender have no sender
+pub fn chat_message_from_row(row: ChatMessageRow) -> ChatMessage {
+    let (
+        id,
+        room_message_id,
+        chatroom_id,
+        sender_id,
+        sender_name,
+        sent_at,
+        edited_at,
+        deleted_at,
+        content,
+    ) = row;
+    ChatMessage::from_database(
+        id,
+        room_message_id,
+        chatroom_id,
+        sender_id.zip(sender_name),
+        sent_at,
+        content,
+    )
+    .with_changes(edited_at, deleted_at)
+}
+
+/// Builds the chatrooms from rows that are ordered by chatroom id
+pub fn group_chat_member_rows(rows: Vec<ChatMemberRow>) -> Vec<ChatRoom> {
+    let mut chatrooms: Vec<(ChatMemberRow, Vec<User>)> = Vec::new();
+    for row in rows {
+        let member = User::new(row.4, row.5.clone());
+        match chatrooms.last_mut() {
+            Some((first_row, members)) if first_row.0 == row.0 => members.push(member),
+            _ => chatrooms.push((row, vec![member])),
+        }
+    }
+    chatrooms
+        .into_iter()
+        .map(|((id, name, is_public, has_password, _, _), members)| {
+            if is_public {
+                ChatRoom::new_public(id, name, members, has_password)
+            } else {
+                ChatRoom::new(id, name, members)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_chat_member_rows() {
+        let rows = vec![
+            (
+                1,
+                "TestChat1".to_string(),
+                false,
+                false,
+                1,
+                "anton".to_string(),
+            ),
+            (
+                1,
+                "TestChat1".to_string(),
+                false,
+                false,
+                2,
+                "rino".to_string(),
+            ),
+            (2, "Group".to_string(), false, false, 1, "anton".to_string()),
+            (2, "Group".to_string(), false, false, 2, "rino".to_string()),
+            (
+                2,
+                "Group".to_string(),
+                false,
+                false,
+                3,
+                "antonia".to_string(),
+            ),
+            (3, "Lobby".to_string(), true, true, 2, "rino".to_string()),
+        ];
+        let chatrooms = group_chat_member_rows(rows);
+        assert_eq!(chatrooms.len(), 3);
+        assert_eq!(chatrooms[0].get_member_ids(), vec![1, 2]);
+        assert_eq!(*chatrooms[1].get_name(), "Group".to_string());
+        assert_eq!(chatrooms[1].get_member_ids(), vec![1, 2, 3]);
+        assert!(!chatrooms[1].is_public());
+        assert!(chatrooms[2].is_public());
+        assert!(chatrooms[2].has_password());
+    }
+
+    #[test]
+    fn test_open_storage_sqlite_in_memory() {
+        let storage = open_storage("sqlite::memory:").unwrap();
+        assert!(!storage.check_if_username_exists("rino").unwrap());
+    }
+
+    #[test]
+    fn test_open_storage_unsupported_url() {
+        assert!(open_storage("postgres://localhost/chatclient").is_err());
+    }
+}
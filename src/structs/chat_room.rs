@@ -1,33 +1,105 @@
+use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use tokio::sync::broadcast::error::SendError;
 
-use crate::{sql_interaction, structs::message::ChatMessage};
-use colored::Colorize;
+use crate::structs::message::ChatMessage;
+use crate::structs::receipt::Receipt;
+use crate::structs::user::User;
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::{channel, Receiver, Sender};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "ChatRoomData", into = "ChatRoomData")]
 pub struct ChatRoom {
     id: u32,
     name: String,
-    user1_id: u32,
-    user2_id: u32,
-    bcast_sender: Sender<ChatMessage>,
-    bcast_receiver: Arc<RwLock<Receiver<ChatMessage>>>,
+    members: Vec<User>,
+    /// Public rooms can be found by name and joined by every user
+    is_public: bool,
+    has_password: bool,
+    bcast_sender: Sender<BroadcastMessage>,
+    bcast_receiver: Arc<RwLock<Receiver<BroadcastMessage>>>,
+}
+
+/// Event in the broadcast channel, together with the address of the connection that caused it
+pub type BroadcastMessage = (SocketAddr, RoomEvent);
+
+/// Everything the connections of a chatroom are told about
+#[derive(Debug, Clone)]
+pub enum RoomEvent {
+    /// Boxed, so that the other events stay small
+    Message(Box<ChatMessage>),
+    /// A message was edited or deleted
+    MessageChanged(Box<ChatMessage>),
+    /// Message of the server to everybody in the chatroom
+    Announcement(String),
+    /// The member with the id was kicked or banned, its connections have to leave the chatroom
+    MemberRemoved(u32),
+    /// The member with the name started or stopped typing
+    Typing { username: String, is_typing: bool },
+    /// The status of a message changed, only the sender is told
+    Receipt {
+        sender_name: String,
+        receipt: Receipt,
+    },
+}
+
+/// The part of a chatroom that is sent between server and client
+/// <br>The broadcast channel only exists locally and is recreated on deserialization
+#[derive(Serialize, Deserialize)]
+struct ChatRoomData {
+    id: u32,
+    name: String,
+    members: Vec<User>,
+    is_public: bool,
+    has_password: bool,
+}
+
+impl From<ChatRoomData> for ChatRoom {
+    fn from(data: ChatRoomData) -> Self {
+        if data.is_public {
+            ChatRoom::new_public(data.id, data.name, data.members, data.has_password)
+        } else {
+            ChatRoom::new(data.id, data.name, data.members)
+        }
+    }
+}
+
+impl From<ChatRoom> for ChatRoomData {
+    fn from(chatroom: ChatRoom) -> Self {
+        ChatRoomData {
+            id: chatroom.id,
+            name: chatroom.name,
+            members: chatroom.members,
+            is_public: chatroom.is_public,
+            has_password: chatroom.has_password,
+        }
+    }
 }
 
 impl ChatRoom {
-    pub fn new(id: u32, name: String, user1_id: u32, user2_id: u32) -> Self {
-        let (bcast_tx, bcast_rx) = channel::<ChatMessage>(16);
+    pub fn new(id: u32, name: String, members: Vec<User>) -> Self {
+        let (bcast_tx, bcast_rx) = channel::<BroadcastMessage>(16);
         ChatRoom {
             id,
             name,
-            user1_id,
-            user2_id,
+            members,
+            is_public: false,
+            has_password: false,
             bcast_sender: bcast_tx,
             bcast_receiver: Arc::new(RwLock::new(bcast_rx)),
         }
     }
 
+    ///Creates a public room, which every user can find by its name
+    pub fn new_public(id: u32, name: String, members: Vec<User>, has_password: bool) -> Self {
+        ChatRoom {
+            is_public: true,
+            has_password,
+            ..ChatRoom::new(id, name, members)
+        }
+    }
+
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -36,51 +108,71 @@ impl ChatRoom {
         &self.id
     }
 
-    pub fn get_sender(&self) -> Sender<ChatMessage> {
+    pub fn get_sender(&self) -> Sender<BroadcastMessage> {
         self.bcast_sender.clone()
     }
 
-    pub fn get_receiver(&self) -> Arc<RwLock<Receiver<ChatMessage>>> {
+    pub fn get_receiver(&self) -> Arc<RwLock<Receiver<BroadcastMessage>>> {
         self.bcast_receiver.clone()
     }
 
-    pub fn get_user1_id(&self) -> u32 {
-        self.user1_id
+    pub fn get_members(&self) -> &Vec<User> {
+        &self.members
     }
 
-    pub fn get_user2_id(&self) -> u32 {
-        self.user2_id
+    ///Replaces the members, e.g. after a user joined the public room
+    /// <br>The broadcast channel is kept, so connected clients stay subscribed
+    pub fn set_members(&mut self, members: Vec<User>) {
+        self.members = members;
     }
 
-    pub fn broadcast_message(&self, message: ChatMessage) -> Result<usize, SendError<ChatMessage>> {
-        self.bcast_sender.send(message)
+    pub fn get_member_ids(&self) -> Vec<u32> {
+        self.members.iter().map(|member| member.get_id()).collect()
     }
 
-    pub async fn print_chat_history(
-        &mut self,
-        current_client: String,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let msgs = sql_interaction::get_chat_messages_for_chatroom_from_database(self.id).await;
+    pub fn is_public(&self) -> bool {
+        self.is_public
+    }
 
-        for msg in msgs.as_ref().unwrap() {
-            // Split the message into sender and content
-            let parts: Vec<String> = msg.splitn(2, ':').map(|s| s.to_string()).collect();
+    ///Returns true if users need a password to join the public room
+    pub fn has_password(&self) -> bool {
+        self.has_password
+    }
 
-            if parts.len() == 2 {
-                let sender = parts[0].trim();
-                let content = parts[1].trim();
+    ///Returns true if the messages of the chatroom are encrypted for every member on its own
+    /// <br>These are chatrooms with more than two members and public rooms, which can get new members anytime
+    pub fn is_group_chat(&self) -> bool {
+        self.is_public || self.members.len() > 2
+    }
 
-                // Check if the current client sent the message
-                if sender == current_client {
-                    println!("{}: {}", sender.bold().purple(), content);
-                } else {
-                    println!("{}: {}", sender.bold().cyan(), content);
-                }
-            } else {
-                eprintln!("Message does not contain a colon: {}", msg);
-            }
-        }
-        Ok(())
+    ///Returns true if the user is one of the members of the chatroom
+    pub fn is_member(&self, user_id: u32) -> bool {
+        self.members.iter().any(|member| member.get_id() == user_id)
+    }
+
+    ///Returns all members of the chatroom except the user
+    pub fn get_other_members(&self, user_id: u32) -> Vec<User> {
+        self.members
+            .iter()
+            .filter(|member| member.get_id() != user_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn broadcast_message(
+        &self,
+        sender_address: SocketAddr,
+        message: ChatMessage,
+    ) -> Result<usize, SendError<BroadcastMessage>> {
+        self.broadcast_event(sender_address, RoomEvent::Message(Box::new(message)))
+    }
+
+    pub fn broadcast_event(
+        &self,
+        sender_address: SocketAddr,
+        event: RoomEvent,
+    ) -> Result<usize, SendError<BroadcastMessage>> {
+        self.bcast_sender.send((sender_address, event))
     }
 }
 
@@ -88,63 +180,87 @@ impl ChatRoom {
 mod tests {
     use super::*;
 
+    fn get_members(ids: &[u32]) -> Vec<User> {
+        ids.iter()
+            .map(|id| User::new(*id, format!("user{}", id)))
+            .collect()
+    }
+
     #[test]
     fn test_get_chatroom_name() {
-        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2);
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
         assert_eq!(*chatroom.get_name(), "Chatroom 1".to_string());
     }
 
     #[test]
     fn test_get_chatroom_id() {
-        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2);
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
         assert_eq!(*chatroom.get_id(), 1);
     }
 
     #[test]
     fn test_get_sender() {
-        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2);
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
         let sender = chatroom.get_sender();
         assert_eq!(sender.receiver_count(), 1);
     }
 
     #[test]
     fn test_broadcast_message_positive() {
-        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2);
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
         let sender_addr = std::net::SocketAddr::new(
             std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
             8080,
         );
         let content = "Hello, Bob!".to_string();
 
-        let chat_message: ChatMessage = ChatMessage::new(sender_addr, content.clone());
-        let result = chatroom.broadcast_message(chat_message);
+        let chat_message: ChatMessage = ChatMessage::new(1, "Alice".to_string(), content.clone());
+        let result = chatroom.broadcast_message(sender_addr, chat_message);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 1);
     }
 
-    #[tokio::test]
-    async fn test_print_chat_history_positive() {
-        let mut chatroom = ChatRoom::new(1, "TestChat1".to_string(), 1, 2);
-        let result = chatroom.print_chat_history("rino".to_string()).await;
-        assert!(result.is_ok());
+    #[test]
+    fn test_get_member_ids() {
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
+        assert_eq!(chatroom.get_member_ids(), vec![1, 2]);
+        assert!(!chatroom.is_group_chat());
     }
 
-    #[tokio::test]
-    async fn test_print_chat_history_missing_colon_in_message() {
-        let mut chatroom = ChatRoom::new(2, "TestChat2".to_string(), 1, 3);
-        let result = chatroom.print_chat_history("anton".to_string()).await;
-        assert!(result.is_ok());
+    #[test]
+    fn test_group_chat_members() {
+        let chatroom = ChatRoom::new(1, "Group".to_string(), get_members(&[1, 2, 3]));
+        assert!(chatroom.is_group_chat());
+        assert!(chatroom.is_member(3));
+        assert!(!chatroom.is_member(4));
+        assert_eq!(chatroom.get_other_members(2), get_members(&[1, 3]));
+    }
+
+    #[test]
+    fn test_public_room() {
+        let chatroom = ChatRoom::new_public(1, "Lobby".to_string(), get_members(&[1]), true);
+        assert!(chatroom.is_public());
+        assert!(chatroom.has_password());
+        assert!(chatroom.is_group_chat());
+        assert!(chatroom.get_other_members(1).is_empty());
+
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
+        assert!(!chatroom.is_public());
+        assert!(!chatroom.has_password());
     }
 
     #[test]
-    fn test_get_user1_id() {
-        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2);
-        assert_eq!(chatroom.get_user1_id(), 1);
+    fn test_is_member() {
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
+        assert!(chatroom.is_member(1));
+        assert!(chatroom.is_member(2));
+        assert!(!chatroom.is_member(3));
     }
 
     #[test]
-    fn test_get_user2_id() {
-        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), 1, 2);
-        assert_eq!(chatroom.get_user2_id(), 2);
+    fn test_get_other_members() {
+        let chatroom = ChatRoom::new(1, "Chatroom 1".to_string(), get_members(&[1, 2]));
+        assert_eq!(chatroom.get_other_members(1), get_members(&[2]));
+        assert_eq!(chatroom.get_other_members(2), get_members(&[1]));
     }
 }
@@ -1,7 +1,8 @@
 use super::{chat_room::ChatRoom, user::User};
-use crate::sql_interaction;
+use crate::server_connection::ServerConnection;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FriendRequest {
     sender_id: u32,
     _receiver_id: u32,
@@ -33,35 +34,24 @@ impl FriendRequest {
         self.sender_id
     }
 
-    pub async fn accept_friend_request(&self, receiving_user: User) -> Option<ChatRoom> {
-        _ = sql_interaction::accept_friend_request_for_user(
-            self.get_sender_id(),
-            receiving_user.get_id(),
-        )
-        .await;
-
-        _ = sql_interaction::create_friends(self.get_sender_id(), receiving_user.get_id()).await;
-
-        let chatroom_name = format!(
-            "{} and {}'s chat",
-            receiving_user.get_name(),
-            self.get_display_name()
-        );
-        // create new chatroom to show in "Join an existing chatroom"
-        let _ = sql_interaction::create_new_chatroom(
-            receiving_user.get_id(),
-            self.get_sender_id(),
-            chatroom_name,
-        )
-        .await;
+    pub async fn accept_friend_request(
+        &self,
+        connection: &mut ServerConnection,
+        _receiving_user: User,
+    ) -> Option<ChatRoom> {
+        // The server marks the request as accepted, creates the friendship and
+        // the new chatroom to show in "Join an existing chatroom"
+        _ = connection.accept_friend_request(self.get_sender_id()).await;
         None // theoretically, we could return the chatroom directly to immediately join it, but instead we return to the chat menu
     }
 
     pub async fn decline_friend_request(
         &self,
-        current_user_id: u32,
+        connection: &mut ServerConnection,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        _ = sql_interaction::delete_friend_request(self.get_sender_id(), current_user_id).await;
+        connection
+            .decline_friend_request(self.get_sender_id())
+            .await?;
         println!("Friend request declined!");
 
         Ok(())
@@ -71,6 +61,7 @@ impl FriendRequest {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{TestServer, TestStorageBuilder};
 
     #[test]
     fn test_get_display_name() {
@@ -87,18 +78,48 @@ mod tests {
     #[tokio::test]
     async fn test_accepting_friend_request() {
         // Accept rinos request to antonia
-        let antonia = User::new(4, "Antonia".to_string());
-        let friend_request = FriendRequest::new(2, 4, false, "rino".to_string());
-        let chatroom = friend_request.accept_friend_request(antonia.clone()).await;
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("antonia")
+            .friend_request("rino", "antonia")
+            .build();
+        let server = TestServer::start(storage).await;
+        let (mut connection, antonia) = server.login("antonia").await;
+
+        let friend_request = FriendRequest::new(1, 2, false, "rino".to_string());
+        let chatroom = friend_request
+            .accept_friend_request(&mut connection, antonia.clone())
+            .await;
         assert!(chatroom.is_none());
+
+        // The request is answered and the new chatroom can be joined
+        assert!(connection.get_friend_requests().await.unwrap().is_empty());
+        assert!(connection.check_if_friends_with(1).await.unwrap());
+        let chatrooms = connection.search_for_chatrooms().await.unwrap();
+        assert_eq!(chatrooms.len(), 1);
+        assert_eq!(
+            *chatrooms[0].get_name(),
+            "antonia and rino's chat".to_string()
+        );
     }
 
     #[tokio::test]
     async fn test_declining_friend_request() {
         // Declining rinos friend request to testuser
-        // let rino = sql_interaction::get_user_from_database_by_id(2).await.unwrap();
-        let friend_request = FriendRequest::new(2, 3, false, "rino".to_string());
-        let res = friend_request.decline_friend_request(3).await;
+        let storage = TestStorageBuilder::new()
+            .user("rino")
+            .user("testuser")
+            .friend_request("rino", "testuser")
+            .build();
+        let server = TestServer::start(storage).await;
+        let (mut connection, _) = server.login("testuser").await;
+
+        let friend_request = FriendRequest::new(1, 2, false, "rino".to_string());
+        let res = friend_request.decline_friend_request(&mut connection).await;
         assert!(res.is_ok());
+
+        assert!(connection.get_friend_requests().await.unwrap().is_empty());
+        assert!(!connection.check_if_friends_with(1).await.unwrap());
+        assert!(connection.search_for_chatrooms().await.unwrap().is_empty());
     }
 }
@@ -1,31 +1,150 @@
+use serde::{Deserialize, Serialize};
 use std::fmt;
-#[derive(Debug, Clone)]
+
+/// Message written in a chatroom, shared by client and server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ChatMessage {
-    sender_address: std::net::SocketAddr,
+    /// Assigned by the server when the message is saved, None for messages the client is about to send
+    #[serde(default)]
+    id: Option<u32>,
+    /// Number of the message within its chatroom, counted from 1 without gaps
+    #[serde(default)]
+    room_message_id: Option<u32>,
+    chatroom_id: u32,
+    /// Id of the user who sent the message, None for messages the client is about to send and old messages without a known sender
+    #[serde(default)]
+    sender_id: Option<u32>,
+    sender_name: String,
+    /// Seconds since UNIX EPOCH the server saved the message
+    #[serde(default)]
+    sent_at: Option<u64>,
+    /// Seconds since UNIX EPOCH the sender last edited the message, None if it was never edited
+    #[serde(default)]
+    edited_at: Option<u64>,
+    /// Seconds since UNIX EPOCH the sender deleted the message, the content of deleted messages is empty
+    #[serde(default)]
+    deleted_at: Option<u64>,
     content: String,
 }
 
 impl ChatMessage {
-    pub fn new(sender_addr : std::net::SocketAddr, content: String) -> ChatMessage {
+    pub fn new(chatroom_id: u32, sender_name: String, content: String) -> ChatMessage {
         ChatMessage {
-            sender_address : sender_addr,
+            id: None,
+            room_message_id: None,
+            chatroom_id,
+            sender_id: None,
+            sender_name,
+            sent_at: None,
+            edited_at: None,
+            deleted_at: None,
             content,
         }
     }
 
-    pub fn get_address(&self) -> std::net::SocketAddr {
-        self.sender_address
+    ///Creates a message from a row of the database
+    /// <br>Old messages without a known sender have no sender id and an empty sender name
+    pub fn from_database(
+        id: u32,
+        room_message_id: u32,
+        chatroom_id: u32,
+        sender: Option<(u32, String)>,
+        sent_at: u64,
+        content: String,
+    ) -> ChatMessage {
+        let (sender_id, sender_name) = match sender {
+            Some((sender_id, sender_name)) => (Some(sender_id), sender_name),
+            None => (None, String::new()),
+        };
+        ChatMessage {
+            id: Some(id),
+            room_message_id: Some(room_message_id),
+            chatroom_id,
+            sender_id,
+            sender_name,
+            sent_at: Some(sent_at),
+            edited_at: None,
+            deleted_at: None,
+            content,
+        }
+    }
+
+    ///Sets when the message was edited and deleted, as it is stored in the database
+    pub fn with_changes(mut self, edited_at: Option<u64>, deleted_at: Option<u64>) -> ChatMessage {
+        self.edited_at = edited_at;
+        self.deleted_at = deleted_at;
+        self
+    }
+
+    pub fn get_id(&self) -> Option<u32> {
+        self.id
+    }
+
+    pub fn get_room_message_id(&self) -> Option<u32> {
+        self.room_message_id
+    }
+
+    pub fn get_chatroom_id(&self) -> u32 {
+        self.chatroom_id
+    }
+
+    pub fn get_sender_id(&self) -> Option<u32> {
+        self.sender_id
+    }
+
+    pub fn get_sent_at(&self) -> Option<u64> {
+        self.sent_at
+    }
+
+    pub fn get_edited_at(&self) -> Option<u64> {
+        self.edited_at
+    }
+
+    pub fn get_deleted_at(&self) -> Option<u64> {
+        self.deleted_at
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    pub fn get_sender_name(&self) -> &String {
+        &self.sender_name
     }
 
     pub fn get_content(&self) -> String {
         self.content.clone()
     }
+}
 
+/// Content a message had before it was edited, as it is kept in the edit history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageEdit {
+    content: String,
+    /// Seconds since UNIX EPOCH the content was replaced
+    replaced_at: u64,
+}
+
+impl MessageEdit {
+    pub fn new(content: String, replaced_at: u64) -> MessageEdit {
+        MessageEdit {
+            content,
+            replaced_at,
+        }
+    }
+
+    pub fn get_content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn get_replaced_at(&self) -> u64 {
+        self.replaced_at
+    }
 }
 
 impl fmt::Display for ChatMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.sender_address, self.content)
+        write!(f, "{}: {}", self.sender_name, self.content)
     }
 }
 
@@ -35,48 +154,69 @@ mod tests {
 
     #[test]
     fn test_message_new() {
-        let sender_addr = std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)), 8080);
         let content = "Hello, Bob!".to_string();
 
-        let chat_message: ChatMessage =
-            ChatMessage::new(sender_addr, content.clone());
+        let chat_message: ChatMessage = ChatMessage::new(1, "Alice".to_string(), content.clone());
 
         // Assert to check if ChatMessage struct is constructed properly
-        assert_eq!(chat_message.sender_address, sender_addr);
+        assert_eq!(chat_message.chatroom_id, 1);
+        assert_eq!(chat_message.sender_name, "Alice".to_string());
         assert_eq!(chat_message.content, content);
-
     }
+
     #[test]
     fn test_to_string() {
-        let sender_addr = std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)), 8080);
         let content = "Hello, Bob!".to_string();
 
-        let chat_message: ChatMessage =
-            ChatMessage::new(sender_addr, content.clone());
+        let chat_message: ChatMessage = ChatMessage::new(1, "Alice".to_string(), content.clone());
 
-        assert_eq!(chat_message.to_string(), format!("{}: {}", sender_addr, content));
+        assert_eq!(chat_message.to_string(), format!("Alice: {}", content));
     }
 
-        #[test]
+    #[test]
     fn test_get_content() {
-        let sender_addr = std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)), 8080);
         let content = "Hello, Bob!".to_string();
 
-        let chat_message: ChatMessage =
-            ChatMessage::new(sender_addr, content.clone());
+        let chat_message: ChatMessage = ChatMessage::new(1, "Alice".to_string(), content.clone());
 
         assert_eq!(chat_message.get_content(), content);
     }
 
-            #[test]
-    fn test_get_sender_address() {
-        let sender_addr = std::net::SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)), 8080);
-        let content = "Hello, Bob!".to_string();
-
+    #[test]
+    fn test_get_sender_name() {
         let chat_message: ChatMessage =
-            ChatMessage::new(sender_addr, content.clone());
+            ChatMessage::new(1, "Alice".to_string(), "Hello, Bob!".to_string());
+
+        assert_eq!(*chat_message.get_sender_name(), "Alice".to_string());
+    }
 
-        assert_eq!(chat_message.get_address(), sender_addr);
+    #[test]
+    fn test_from_database() {
+        let chat_message = ChatMessage::from_database(
+            4,
+            2,
+            1,
+            Some((7, "Alice".to_string())),
+            1000,
+            "Hello: Bob!".to_string(),
+        );
+        assert_eq!(chat_message.get_id(), Some(4));
+        assert_eq!(chat_message.room_message_id, Some(2));
+        assert_eq!(chat_message.get_sender_id(), Some(7));
+        assert_eq!(*chat_message.get_sender_name(), "Alice".to_string());
+        assert_eq!(chat_message.get_sent_at(), Some(1000));
+        assert_eq!(chat_message.to_string(), "Alice: Hello: Bob!");
+
+        let without_sender = ChatMessage::from_database(5, 3, 1, None, 1000, "Hello!".to_string());
+        assert_eq!(without_sender.get_sender_id(), None);
+        assert_eq!(*without_sender.get_sender_name(), String::new());
     }
 
+    #[test]
+    fn test_get_chatroom_id() {
+        let chat_message: ChatMessage =
+            ChatMessage::new(1, "Alice".to_string(), "Hello, Bob!".to_string());
+
+        assert_eq!(chat_message.get_chatroom_id(), 1);
+    }
 }
@@ -0,0 +1,82 @@
+use crate::helper_functions;
+use serde::{Deserialize, Serialize};
+
+/// Whether a user is connected to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresenceStatus {
+    Online,
+    /// Connected, but did not do anything for a while
+    Away,
+    Offline,
+}
+
+/// Presence of a user as it is shown to the friends of the user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    user_id: u32,
+    status: PresenceStatus,
+    /// Seconds since UNIX EPOCH the user was last active, 0 if the user was never seen
+    last_seen: u64,
+}
+
+impl Presence {
+    // Constructor
+    pub fn new(user_id: u32, status: PresenceStatus, last_seen: u64) -> Presence {
+        Presence {
+            user_id,
+            status,
+            last_seen,
+        }
+    }
+
+    pub fn get_user_id(&self) -> u32 {
+        self.user_id
+    }
+
+    pub fn get_status(&self) -> PresenceStatus {
+        self.status
+    }
+
+    ///Returns the marker that is shown next to the user, with the last seen time if the user is not online
+    /// <br>`now` is the current time in seconds since UNIX EPOCH
+    pub fn describe(&self, now: u64) -> String {
+        let last_seen = if self.last_seen == 0 {
+            String::new()
+        } else {
+            format!(
+                ", last seen {}",
+                helper_functions::format_time_ago(now.saturating_sub(self.last_seen))
+            )
+        };
+        match self.status {
+            PresenceStatus::Online => "● online".to_string(),
+            PresenceStatus::Away => format!("◐ away{}", last_seen),
+            PresenceStatus::Offline => format!("○ offline{}", last_seen),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            Presence::new(1, PresenceStatus::Online, 1000).describe(1000),
+            "● online"
+        );
+        assert_eq!(
+            Presence::new(1, PresenceStatus::Away, 1000).describe(1000 + 6 * 60),
+            "◐ away, last seen 6 min ago"
+        );
+        assert_eq!(
+            Presence::new(1, PresenceStatus::Offline, 1000).describe(1000 + 3 * 3600),
+            "○ offline, last seen 3 h ago"
+        );
+        assert_eq!(
+            Presence::new(1, PresenceStatus::Offline, 0).describe(1000),
+            "○ offline"
+        );
+    }
+}
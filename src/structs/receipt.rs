@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// How far a message got on its way to the other members of the chatroom
+/// <br>Statuses are ordered, a message never goes back to a lower status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    /// The server saved the message
+    Stored,
+    /// The client of the member received the message
+    Delivered,
+    /// The member saw the message
+    Read,
+}
+
+impl ReceiptStatus {
+    ///Returns the name the status is saved with in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReceiptStatus::Stored => "stored",
+            ReceiptStatus::Delivered => "delivered",
+            ReceiptStatus::Read => "read",
+        }
+    }
+
+    ///Returns the status with the name from the database, unknown names are treated as delivered
+    pub fn from_db(name: &str) -> ReceiptStatus {
+        match name {
+            "read" => ReceiptStatus::Read,
+            "stored" => ReceiptStatus::Stored,
+            _ => ReceiptStatus::Delivered,
+        }
+    }
+
+    ///Returns the mark that is shown behind an own message, always two characters wide
+    pub fn get_mark(&self) -> &'static str {
+        match self {
+            ReceiptStatus::Stored => "✓ ",
+            ReceiptStatus::Delivered | ReceiptStatus::Read => "✓✓",
+        }
+    }
+}
+
+/// Status of a message
+/// <br>Clients send it for messages of other members, the server sends it to the sender of the message
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    message_id: u32,
+    status: ReceiptStatus,
+}
+
+impl Receipt {
+    pub fn new(message_id: u32, status: ReceiptStatus) -> Receipt {
+        Receipt { message_id, status }
+    }
+
+    pub fn get_message_id(&self) -> u32 {
+        self.message_id
+    }
+
+    pub fn get_status(&self) -> ReceiptStatus {
+        self.status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_names_round_trip() {
+        for status in [
+            ReceiptStatus::Stored,
+            ReceiptStatus::Delivered,
+            ReceiptStatus::Read,
+        ] {
+            assert_eq!(ReceiptStatus::from_db(status.as_str()), status);
+        }
+        assert!(ReceiptStatus::Read > ReceiptStatus::Delivered);
+        assert!(ReceiptStatus::Delivered > ReceiptStatus::Stored);
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+/// Role of a member in a chatroom
+/// <br>Roles are ordered, members can only moderate members with a lower role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RoomRole {
+    Member,
+    /// Can kick, ban and mute members
+    Moderator,
+    /// Creator of the chatroom, can also make members moderators
+    Owner,
+}
+
+impl RoomRole {
+    ///Returns the name the role is saved with in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomRole::Member => "member",
+            RoomRole::Moderator => "moderator",
+            RoomRole::Owner => "owner",
+        }
+    }
+
+    ///Returns the role with the name from the database, unknown names are treated as member
+    pub fn from_db(name: &str) -> RoomRole {
+        match name {
+            "owner" => RoomRole::Owner,
+            "moderator" => RoomRole::Moderator,
+            _ => RoomRole::Member,
+        }
+    }
+
+    ///Returns true if the role allows to kick, ban and mute members
+    pub fn can_moderate(&self) -> bool {
+        *self >= RoomRole::Moderator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_names_round_trip() {
+        for role in [RoomRole::Member, RoomRole::Moderator, RoomRole::Owner] {
+            assert_eq!(RoomRole::from_db(role.as_str()), role);
+        }
+        assert_eq!(RoomRole::from_db("admin"), RoomRole::Member);
+    }
+
+    #[test]
+    fn test_roles_are_ordered() {
+        assert!(RoomRole::Owner > RoomRole::Moderator);
+        assert!(RoomRole::Moderator > RoomRole::Member);
+        assert!(RoomRole::Moderator.can_moderate());
+        assert!(!RoomRole::Member.can_moderate());
+    }
+}
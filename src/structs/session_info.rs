@@ -0,0 +1,104 @@
+use crate::helper_functions;
+use serde::{Deserialize, Serialize};
+
+/// Active login of a user on one device, as it is shown in the sessions menu
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    id: u32,
+    device_name: String,
+    created_at: u64,
+    last_seen: u64,
+    expires_at: u64,
+    is_current: bool,
+}
+
+impl SessionInfo {
+    // Constructor
+    pub fn new(
+        id: u32,
+        device_name: String,
+        created_at: u64,
+        last_seen: u64,
+        expires_at: u64,
+        is_current: bool,
+    ) -> SessionInfo {
+        SessionInfo {
+            id,
+            device_name,
+            created_at,
+            last_seen,
+            expires_at,
+            is_current,
+        }
+    }
+
+    pub fn get_id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn get_device_name(&self) -> &String {
+        &self.device_name
+    }
+
+    ///Returns true if this is the session of the connection that requested the list
+    pub fn is_current(&self) -> bool {
+        self.is_current
+    }
+
+    ///Returns the line that is shown for the session in the sessions menu
+    /// <br>`now` is the current time in seconds since UNIX EPOCH
+    pub fn describe(&self, now: u64) -> String {
+        let mut description = format!(
+            "{} - last seen {}",
+            self.device_name,
+            helper_functions::format_time_ago(now.saturating_sub(self.last_seen))
+        );
+        if self.is_current {
+            description.push_str(" (this device)");
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_info_new() {
+        let session = SessionInfo::new(1, "laptop".to_string(), 10, 20, 30, true);
+        assert_eq!(session.get_id(), 1);
+        assert_eq!(*session.get_device_name(), "laptop".to_string());
+        assert_eq!(session.created_at, 10);
+        assert_eq!(session.last_seen, 20);
+        assert_eq!(session.expires_at, 30);
+        assert!(session.is_current());
+    }
+
+    #[test]
+    fn test_describe() {
+        let session = SessionInfo::new(1, "laptop".to_string(), 0, 1000, 0, false);
+        assert_eq!(session.describe(1010), "laptop - last seen just now");
+        assert_eq!(
+            session.describe(1000 + 5 * 60),
+            "laptop - last seen 5 min ago"
+        );
+        assert_eq!(
+            session.describe(1000 + 3 * 3600),
+            "laptop - last seen 3 h ago"
+        );
+        assert_eq!(
+            session.describe(1000 + 2 * 86400),
+            "laptop - last seen 2 days ago"
+        );
+    }
+
+    #[test]
+    fn test_describe_current_session() {
+        let session = SessionInfo::new(1, "laptop".to_string(), 0, 1000, 0, true);
+        assert_eq!(
+            session.describe(1000),
+            "laptop - last seen just now (this device)"
+        );
+    }
+}
@@ -1,4 +1,12 @@
-#[derive(Debug, PartialEq, Clone, Eq)]
+use serde::{Deserialize, Serialize};
+
+/// Minimum number of characters of a username
+pub const MIN_NAME_LENGTH: usize = 3;
+
+/// Maximum number of characters of a username
+pub const MAX_NAME_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq, Clone, Eq, Serialize, Deserialize)]
 pub struct User {
     id: u32,
     name: String,
@@ -17,6 +25,14 @@ impl User {
     pub fn get_id(&self) -> u32 {
         self.id
     }
+
+    /// Returns true if the name can be used as username: 3 to 32 letters or digits
+    /// <br>Usernames become part of file names on the devices, so they never contain `/`, `.` or control characters
+    pub fn is_valid_name(name: &str) -> bool {
+        let length = name.chars().count();
+        (MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&length)
+            && name.chars().all(char::is_alphanumeric)
+    }
 }
 
 #[cfg(test)]
@@ -41,4 +57,16 @@ mod tests {
         let user = User::new(1, "Alice".to_string());
         assert_eq!(user.get_id(), 1);
     }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(User::is_valid_name("Alice"));
+        assert!(User::is_valid_name("Jürgen42"));
+        assert!(!User::is_valid_name("Al"));
+        assert!(!User::is_valid_name(&"a".repeat(MAX_NAME_LENGTH + 1)));
+        assert!(!User::is_valid_name("../Alice"));
+        assert!(!User::is_valid_name("Al/ice"));
+        assert!(!User::is_valid_name("Alice\n"));
+        assert!(!User::is_valid_name("Alice\u{7}"));
+    }
 }
@@ -0,0 +1,195 @@
+use crate::config::{ClientConfig, ServerConfig};
+use crate::helper_functions;
+use crate::login::hash_password;
+use crate::server;
+use crate::server_connection::{LoginResult, ServerConnection};
+use crate::sqlite_storage::SqliteStorage;
+use crate::storage::Storage;
+use crate::structs::chat_room::ChatRoom;
+use crate::structs::user::User;
+use crate::tls;
+use rcgen::CertifiedKey;
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+/// Password of every user that is created by the fixture builder
+pub const TEST_PASSWORD: &str = "Ifuckingloverust1";
+
+/// Lowest cost bcrypt allows, so that creating and logging in test users is fast
+const TEST_BCRYPT_COST: u32 = 4;
+
+/// Builds a database in memory that contains exactly the data a test needs
+/// <br>Users are referred to by their name, ids are given out in the order the users are added
+pub struct TestStorageBuilder {
+    storage: SqliteStorage,
+}
+
+impl Default for TestStorageBuilder {
+    fn default() -> Self {
+        TestStorageBuilder::new()
+    }
+}
+
+impl TestStorageBuilder {
+    // Constructor
+    pub fn new() -> TestStorageBuilder {
+        TestStorageBuilder {
+            storage: SqliteStorage::open_in_memory().unwrap(),
+        }
+    }
+
+    ///Adds a user with the password `TEST_PASSWORD`
+    pub fn user(self, username: &str) -> TestStorageBuilder {
+        let password_hash = bcrypt::hash(hash_password(TEST_PASSWORD), TEST_BCRYPT_COST).unwrap();
+        self.storage
+            .save_new_user_to_database_after_signup(username, &password_hash)
+            .unwrap();
+        self
+    }
+
+    ///Makes the two users friends
+    pub fn friends(self, username1: &str, username2: &str) -> TestStorageBuilder {
+        let (user1, user2) = (self.get_user(username1), self.get_user(username2));
+        self.storage
+            .create_friends(user1.get_id(), user2.get_id())
+            .unwrap();
+        self
+    }
+
+    ///Adds a friend request that is not answered yet
+    pub fn friend_request(self, sender: &str, receiver: &str) -> TestStorageBuilder {
+        let (sender, receiver) = (self.get_user(sender), self.get_user(receiver));
+        self.storage
+            .create_new_friend_request(sender.get_id(), receiver.get_id())
+            .unwrap();
+        self
+    }
+
+    ///Adds a chatroom with the users as members
+    pub fn chat_room(self, name: &str, usernames: &[&str]) -> TestStorageBuilder {
+        let member_ids: Vec<u32> = usernames
+            .iter()
+            .map(|username| self.get_user(username).get_id())
+            .collect();
+        self.storage.create_new_chatroom(&member_ids, name).unwrap();
+        self
+    }
+
+    ///Adds a message to the chatroom with the id, sent now
+    pub fn message(self, chatroom_id: u32, sender: &str, content: &str) -> TestStorageBuilder {
+        let sender_id = self.get_user(sender).get_id();
+        self.storage
+            .save_chat_message_to_database(
+                chatroom_id,
+                sender_id,
+                content,
+                helper_functions::get_sys_time_in_secs(),
+            )
+            .unwrap();
+        self
+    }
+
+    pub fn build(self) -> Arc<dyn Storage> {
+        Arc::new(self.storage)
+    }
+
+    fn get_user(&self, username: &str) -> User {
+        self.storage
+            .get_user_from_database_by_name(username)
+            .unwrap()
+    }
+}
+
+/// Server that runs inside the test on a random free port
+/// <br>The server is stopped when it is dropped
+pub struct TestServer {
+    client_config: ClientConfig,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    ///Starts a server that uses the storage
+    pub async fn start(storage: Arc<dyn Storage>) -> TestServer {
+        TestServer::start_with_tls_acceptor(storage, None).await
+    }
+
+    ///Starts a server that uses the storage and TLS with a new self-signed certificate
+    pub async fn start_with_tls(storage: Arc<dyn Storage>) -> TestServer {
+        let CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+        let tls_acceptor = tls::create_acceptor(vec![cert.der().clone()], key).unwrap();
+        TestServer::start_with_tls_acceptor(storage, Some(tls_acceptor)).await
+    }
+
+    async fn start_with_tls_acceptor(
+        storage: Arc<dyn Storage>,
+        tls_acceptor: Option<TlsAcceptor>,
+    ) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let scheme = if tls_acceptor.is_some() { "wss" } else { "ws" };
+
+        // Every server gets its own known servers file, so that no test sees the pins of another test
+        let known_servers_file =
+            std::env::temp_dir().join(format!("chatclient_known_servers_{}", addr.port()));
+        _ = std::fs::remove_file(&known_servers_file);
+        let client_config = ClientConfig {
+            server_uri: format!("{}://{}", scheme, addr),
+            known_servers_file,
+        };
+
+        let handle = tokio::spawn(async move {
+            _ = server::serve(listener, storage, ServerConfig::default(), tls_acceptor).await;
+        });
+        TestServer {
+            client_config,
+            handle,
+        }
+    }
+
+    ///Returns the settings a client needs to connect to this server
+    pub fn get_client_config(&self) -> &ClientConfig {
+        &self.client_config
+    }
+
+    ///Opens a new connection to the server
+    pub async fn connect(&self) -> ServerConnection {
+        ServerConnection::connect(&self.client_config)
+            .await
+            .unwrap()
+    }
+
+    ///Opens a new connection and logs in the user with `TEST_PASSWORD`
+    pub async fn login(&self, username: &str) -> (ServerConnection, User) {
+        let mut connection = self.connect().await;
+        match connection
+            .login(username, &hash_password(TEST_PASSWORD), false, None)
+            .await
+            .unwrap()
+        {
+            LoginResult::LoggedIn(user) => (connection, user),
+            other => panic!("Login of {} failed: {:?}", username, other),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+        _ = std::fs::remove_file(&self.client_config.known_servers_file);
+    }
+}
+/// Returns the chatroom of the user with the name
+pub async fn get_chat_room(connection: &mut ServerConnection, name: &str) -> ChatRoom {
+    connection
+        .search_for_chatrooms()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|chatroom| chatroom.get_name() == name)
+        .unwrap()
+}
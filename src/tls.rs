@@ -0,0 +1,349 @@
+use crate::session_store;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Name of the file the pinned server certificates are stored in, inside the home directory of the user
+const KNOWN_SERVERS_FILE_NAME: &str = ".chatclient_known_servers";
+
+/// Connection to the other side, either plain TCP or TCP with TLS
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+fn get_crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// Returns the SHA-256 fingerprint of a certificate as hex string
+pub fn get_certificate_fingerprint(certificate: &CertificateDer<'_>) -> String {
+    Sha256::digest(certificate.as_ref())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Creates the TLS acceptor of the server from the certificate chain and its private key
+pub fn create_acceptor(
+    certificates: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<TlsAcceptor, Box<dyn Error + Send + Sync>> {
+    let config = rustls::ServerConfig::builder_with_provider(get_crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Creates the TLS acceptor of the server from a certificate file and a key file in PEM format
+pub fn load_acceptor(
+    certificate_path: &Path,
+    key_path: &Path,
+) -> Result<TlsAcceptor, Box<dyn Error + Send + Sync>> {
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(open(certificate_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(format!("No certificate found in {}", certificate_path.display()).into());
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(open(key_path)?))?
+        .ok_or_else(|| format!("No private key found in {}", key_path.display()))?;
+    create_acceptor(certificates, key)
+}
+
+fn open(path: &Path) -> Result<fs::File, Box<dyn Error + Send + Sync>> {
+    fs::File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e).into())
+}
+
+/// Accepts the certificate of the server if it matches the pinned fingerprint
+/// <br>Without a pinned fingerprint every certificate is accepted (trust on first use),
+/// the fingerprint that was seen is remembered so that it can be pinned afterwards
+#[derive(Debug)]
+struct PinningVerifier {
+    pinned_fingerprint: Option<String>,
+    seen_fingerprint: Mutex<Option<String>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = get_certificate_fingerprint(end_entity);
+        *self.seen_fingerprint.lock().unwrap() = Some(fingerprint.clone());
+        match &self.pinned_fingerprint {
+            Some(pinned) if *pinned != fingerprint => Err(rustls::Error::General(
+                "Certificate does not match the pinned fingerprint".to_string(),
+            )),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    // The signatures are checked as usual, so the server has to own the key of the certificate
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            certificate,
+            signature,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            certificate,
+            signature,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Opens a TLS connection to the server and checks its certificate against the known servers file
+/// <br>The certificate of a server that was never seen before is pinned
+pub async fn connect(
+    host: &str,
+    port: u16,
+    known_servers_path: &Path,
+) -> Result<BoxedStream, Box<dyn Error>> {
+    let server = format!("{}:{}", host, port);
+    let pinned_fingerprint = load_pinned_fingerprint(known_servers_path, &server);
+    let provider = get_crypto_provider();
+    let verifier = Arc::new(PinningVerifier {
+        pinned_fingerprint: pinned_fingerprint.clone(),
+        seen_fingerprint: Mutex::new(None),
+        provider: Arc::clone(&provider),
+    });
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let server_name = ServerName::try_from(host.to_string())?;
+    let seen_fingerprint = || verifier.seen_fingerprint.lock().unwrap().clone();
+    let tls_stream = match TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+    {
+        Ok(tls_stream) => tls_stream,
+        Err(e) => {
+            return match (pinned_fingerprint, seen_fingerprint()) {
+                (Some(pinned), Some(seen)) if pinned != seen => Err(format!(
+                    "The certificate of {} has changed! This could be a man-in-the-middle attack.\n\
+                     Expected fingerprint: {}\nReceived fingerprint: {}\n\
+                     If the server really got a new certificate, remove its line from {}",
+                    server,
+                    pinned,
+                    seen,
+                    known_servers_path.display()
+                )
+                .into()),
+                _ => Err(e.into()),
+            };
+        }
+    };
+
+    if let (None, Some(seen)) = (pinned_fingerprint, seen_fingerprint()) {
+        println!(
+            "First connection to {}, trusting its certificate with the fingerprint {}",
+            server, seen
+        );
+        save_pinned_fingerprint(known_servers_path, &server, &seen)?;
+    }
+    Ok(Box::new(tls_stream))
+}
+
+/// Returns true if the host is this device, the only host connections without TLS are allowed to
+/// <br>IPv6 addresses may be given in brackets, as in URIs
+pub fn is_loopback_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Returns the path of the file the pinned server certificates are stored in
+pub fn get_known_servers_file_path() -> PathBuf {
+    session_store::get_path_in_home_directory(KNOWN_SERVERS_FILE_NAME)
+}
+
+/// Returns the pinned fingerprint of the server, if the client connected to it before
+/// <br>Every line of the file is `<host>:<port> <fingerprint>`
+pub fn load_pinned_fingerprint(path: &Path, server: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    content.lines().find_map(|line| {
+        let (line_server, fingerprint) = line.trim().split_once(' ')?;
+        (line_server == server).then(|| fingerprint.trim().to_string())
+    })
+}
+
+/// Pins the fingerprint of the server
+/// <br>Only the current user can change the file, so that no other user of the device can pin a certificate of their own
+pub fn save_pinned_fingerprint(path: &Path, server: &str, fingerprint: &str) -> io::Result<()> {
+    let mut content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("{} {}\n", server, fingerprint));
+    session_store::write_private_file(path, &content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_connection::ServerConnection;
+    use crate::test_support::{TestServer, TestStorageBuilder};
+
+    fn get_test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chatclient_known_servers_test_{}", name));
+        _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_is_loopback_host() {
+        assert!(is_loopback_host("localhost"));
+        assert!(is_loopback_host("127.0.0.1"));
+        assert!(is_loopback_host("[::1]"));
+        assert!(!is_loopback_host("10.8.13.39"));
+        assert!(!is_loopback_host("chat.example.com"));
+        assert!(!is_loopback_host("localhost.example.com"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_known_servers_file_is_only_writable_by_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = get_test_path("permissions");
+        fs::write(&path, "localhost:8000 abc").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+
+        save_pinned_fingerprint(&path, "localhost:8001", "def").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // The pins that were there before are kept
+        assert_eq!(
+            load_pinned_fingerprint(&path, "localhost:8000"),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            load_pinned_fingerprint(&path, "localhost:8001"),
+            Some("def".to_string())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load_pinned_fingerprint() {
+        let path = get_test_path("save_and_load");
+        assert_eq!(load_pinned_fingerprint(&path, "localhost:8000"), None);
+
+        save_pinned_fingerprint(&path, "localhost:8000", "abc").unwrap();
+        save_pinned_fingerprint(&path, "localhost:8001", "def").unwrap();
+        assert_eq!(
+            load_pinned_fingerprint(&path, "localhost:8000"),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            load_pinned_fingerprint(&path, "localhost:8001"),
+            Some("def".to_string())
+        );
+        assert_eq!(load_pinned_fingerprint(&path, "localhost:8002"), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_certificate_fingerprint() {
+        let certificate = CertificateDer::from(b"certificate".to_vec());
+        let fingerprint = get_certificate_fingerprint(&certificate);
+        assert_eq!(fingerprint.len(), 64);
+        assert_ne!(
+            fingerprint,
+            get_certificate_fingerprint(&CertificateDer::from(b"other".to_vec()))
+        );
+    }
+
+    fn get_test_storage() -> Arc<dyn crate::storage::Storage> {
+        TestStorageBuilder::new().user("rino").build()
+    }
+
+    #[tokio::test]
+    async fn test_login_over_tls_pins_certificate() {
+        let server = TestServer::start_with_tls(get_test_storage()).await;
+        let config = server.get_client_config();
+        let uri = config.server_uri.parse::<http::Uri>().unwrap();
+        let server_name = format!("{}:{}", uri.host().unwrap(), uri.port_u16().unwrap());
+        assert_eq!(
+            load_pinned_fingerprint(&config.known_servers_file, &server_name),
+            None
+        );
+
+        let (_, user) = server.login("rino").await;
+        assert_eq!(*user.get_name(), "rino".to_string());
+        let pinned = load_pinned_fingerprint(&config.known_servers_file, &server_name);
+        assert!(pinned.is_some());
+
+        // The pinned certificate is accepted on the next connection without pinning it again
+        server.connect().await;
+        let content = fs::read_to_string(&config.known_servers_file).unwrap();
+        assert_eq!(content.lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_changed_certificate_is_rejected() {
+        let server = TestServer::start_with_tls(get_test_storage()).await;
+        let config = server.get_client_config();
+        let uri = config.server_uri.parse::<http::Uri>().unwrap();
+        let server_name = format!("{}:{}", uri.host().unwrap(), uri.port_u16().unwrap());
+        save_pinned_fingerprint(&config.known_servers_file, &server_name, &"0".repeat(64)).unwrap();
+
+        let result = ServerConnection::connect(config).await;
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("man-in-the-middle"));
+    }
+
+    #[test]
+    fn test_load_acceptor_missing_file() {
+        let path = get_test_path("missing_certificate");
+        assert!(load_acceptor(&path, &path).is_err());
+    }
+}